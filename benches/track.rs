@@ -0,0 +1,134 @@
+//! Lookups and edits on a long flow. Lookups should scale with the number of entries
+//! rather than the length of the flow, and updating the ticks with the ticks changed.
+//!
+//! Run with `cargo bench --bench track`
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use solo_composer_engine::state::entries::key_signature::{KeySignature, KeySignatureMode};
+use solo_composer_engine::state::entries::time_signature::{
+    Reflow, TimeSignature, TimeSignatureDrawType,
+};
+use solo_composer_engine::state::entries::tone::{Articulation, Tone};
+use solo_composer_engine::state::score::flow::Flow;
+use solo_composer_engine::state::score::notation::measures;
+use solo_composer_engine::state::score::track::Track;
+use solo_composer_engine::utils::duration::{Duration, NoteDuration};
+use solo_composer_engine::utils::pitch::{Accidental, Pitch};
+use solo_composer_engine::utils::velocity::Velocity;
+
+const BARS: u32 = 2000;
+
+/// 2000 bars of 4/4 with a quarter note on every beat, the time and key changing
+/// every 100 bars
+fn long_flow() -> Flow {
+    let mut flow = Flow::new();
+    let bar = NoteDuration::Whole.to_ticks(flow.subdivisions);
+    let beat = NoteDuration::Quarter.to_ticks(flow.subdivisions);
+    flow.length = bar * BARS;
+
+    for i in 0..BARS / 100 {
+        let tick = i * 100 * bar;
+        flow.master.insert(TimeSignature::new(
+            format!("time{}", i),
+            tick,
+            4,
+            NoteDuration::Quarter,
+            TimeSignatureDrawType::Normal,
+            None,
+        ));
+        flow.master.insert(KeySignature::new(
+            format!("key{}", i),
+            tick,
+            KeySignatureMode::Major,
+            (i % 7) as i8,
+        ));
+    }
+
+    let mut track = Track::new();
+    for tick in (0..flow.length).step_by(beat as usize) {
+        track.insert(Tone::new(
+            format!("tone{}", tick),
+            tick,
+            Duration::new(beat),
+            Pitch::new(60 + (tick / beat % 12) as u8, Accidental::Natural),
+            Velocity::new(100),
+            Articulation::None,
+            None,
+        ));
+    }
+    flow.tracks.insert(track.key.clone(), track);
+
+    flow
+}
+
+fn lookups(c: &mut Criterion) {
+    let flow = long_flow();
+    let track = flow.tracks.values().next().unwrap();
+    let end = flow.length - 1;
+    let bar = NoteDuration::Whole.to_ticks(flow.subdivisions);
+
+    c.bench_function("time signature on or before the end", |b| {
+        b.iter(|| {
+            flow.master
+                .get_time_signature_on_or_before_tick(black_box(end))
+        })
+    });
+    c.bench_function("time signature after the last", |b| {
+        b.iter(|| {
+            flow.master
+                .get_time_signature_after_tick(black_box(end - 100 * bar), flow.length)
+        })
+    });
+    c.bench_function("key signature on or before the end", |b| {
+        b.iter(|| {
+            flow.master
+                .get_key_signature_on_or_before_tick(black_box(end))
+        })
+    });
+    c.bench_function("tones in a bar", |b| {
+        let start = bar * BARS / 2;
+        b.iter(|| {
+            track
+                .entries_in_range(black_box(start..start + bar))
+                .count()
+        })
+    });
+    c.bench_function("measures of the flow", |b| {
+        b.iter(|| measures(&flow.master, black_box(flow.length), flow.subdivisions))
+    });
+}
+
+fn edits(c: &mut Criterion) {
+    let flow = long_flow();
+
+    c.bench_function("reflow the last time signature", |b| {
+        b.iter_with_setup(
+            || flow.clone(),
+            |mut flow| {
+                let tick = flow.length - 1;
+                flow.reflow(black_box(tick), Reflow::Pad);
+                flow
+            },
+        )
+    });
+}
+
+fn ticks(c: &mut Criterion) {
+    let flow = long_flow();
+    let ticks = flow.calc_ticks();
+    let last_bar = flow.length - NoteDuration::Whole.to_ticks(flow.subdivisions);
+
+    c.bench_function("calculate the ticks", |b| b.iter(|| flow.calc_ticks()));
+    c.bench_function("update the ticks of the last bar", |b| {
+        b.iter_with_setup(
+            || ticks.clone(),
+            |mut ticks| {
+                flow.update_ticks(&mut ticks, black_box(last_bar));
+                ticks
+            },
+        )
+    });
+}
+
+criterion_group!(benches, lookups, edits, ticks);
+criterion_main!(benches);
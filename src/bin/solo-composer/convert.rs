@@ -0,0 +1,142 @@
+use crate::CliResult;
+use solo_composer_engine::state::Engine;
+use std::path::Path;
+
+#[derive(Debug, PartialEq)]
+pub enum Format {
+    Midi,
+    MusicXml,
+    LilyPond,
+    Abc,
+    Json,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> CliResult<Format> {
+        match name.to_lowercase().as_str() {
+            "midi" | "mid" => Ok(Format::Midi),
+            "musicxml" | "xml" => Ok(Format::MusicXml),
+            "lilypond" | "ly" => Ok(Format::LilyPond),
+            "abc" => Ok(Format::Abc),
+            "json" => Ok(Format::Json),
+            // the engine lays out the score but leaves the drawing to the UI
+            "svg" | "pdf" | "png" => Err(format!(
+                "{} isn't supported, the engine doesn't draw scores. Convert to lilypond and engrave that instead",
+                name
+            )),
+            _ => Err(format!("unknown format '{}'", name)),
+        }
+    }
+
+    /// The format for a file's extension
+    pub fn from_path(path: &str) -> CliResult<Format> {
+        match Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+        {
+            Some(extension) => Format::from_name(extension),
+            None => Err(format!("can't tell the format of {}, use --format", path)),
+        }
+    }
+}
+
+/// Number the files of a score with many flows, eg. score.musicxml => score-2.musicxml
+fn numbered(path: &str, number: usize) -> String {
+    let path = Path::new(path);
+    let stem = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("");
+    let name = match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) => format!("{}-{}.{}", stem, number, extension),
+        None => format!("{}-{}", stem, number),
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+/// Convert the score, or a single flow of it, returning the files to write.
+///
+/// MIDI and ABC hold every flow in one file, MusicXML is written as a file per flow.
+/// LilyPond and JSON are always the whole score.
+pub fn convert(
+    engine: &Engine,
+    format: Format,
+    flow: Option<&str>,
+    path: &str,
+) -> CliResult<Vec<(String, Vec<u8>)>> {
+    let score = engine.score();
+    let flows: Vec<&String> = match flow {
+        Some(flow) => vec![score
+            .flows
+            .order
+            .iter()
+            .find(|key| *key == flow)
+            .ok_or_else(|| format!("there is no flow '{}'", flow))?],
+        None => score.flows.order.iter().collect(),
+    };
+    let whole_score = |name: &str| match flow {
+        Some(_) => Err(format!(
+            "{} is written for the whole score, leave out --flow",
+            name
+        )),
+        None => Ok(()),
+    };
+
+    let file = |bytes: Vec<u8>| vec![(String::from(path), bytes)];
+    match format {
+        Format::Midi => match flow {
+            Some(flow) => Ok(file(engine.export_midi(flow).map_err(|e| e.to_string())?)),
+            None => Ok(file(engine.export_score_midi())),
+        },
+        Format::MusicXml => {
+            let mut documents = Vec::new();
+            for flow_key in flows.iter() {
+                documents.push(
+                    engine
+                        .export_musicxml(flow_key)
+                        .map_err(|e| e.to_string())?,
+                );
+            }
+            if documents.len() == 1 {
+                return Ok(file(documents.remove(0).into_bytes()));
+            }
+            Ok(documents
+                .into_iter()
+                .enumerate()
+                .map(|(i, document)| (numbered(path, i + 1), document.into_bytes()))
+                .collect())
+        }
+        Format::LilyPond => {
+            whole_score("lilypond")?;
+            Ok(file(engine.export_lilypond().into_bytes()))
+        }
+        Format::Abc => {
+            // an ABC file can hold many tunes, separated by a blank line
+            let mut tunes = Vec::new();
+            for flow_key in flows.iter() {
+                tunes.push(engine.export_abc(flow_key).map_err(|e| e.to_string())?);
+            }
+            Ok(file(tunes.join("\n").into_bytes()))
+        }
+        Format::Json => {
+            whole_score("json")?;
+            let json = serde_json::to_string_pretty(score).map_err(|e| e.to_string())?;
+            Ok(file(json.into_bytes()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_formats() {
+        assert_eq!(Format::from_path("out/score.mid"), Ok(Format::Midi));
+        assert_eq!(Format::from_path("score.MusicXML"), Ok(Format::MusicXml));
+        assert_eq!(Format::from_name("ly"), Ok(Format::LilyPond));
+        assert!(Format::from_path("score.svg").is_err());
+        assert!(Format::from_path("score").is_err());
+        assert_eq!(numbered("out/score.musicxml", 2), "out/score-2.musicxml");
+    }
+}
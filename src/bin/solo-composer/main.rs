@@ -0,0 +1,185 @@
+//! `solo-composer`, convert, summarise, validate and edit scores without a browser.
+
+mod convert;
+mod script;
+mod summary;
+
+use solo_composer_engine::state::listener::{SystemClock, Update};
+use solo_composer_engine::state::Engine;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::process;
+
+const USAGE: &str = "usage: solo-composer <command> [options]
+
+commands:
+  convert <score.json> <output> [--format <format>] [--flow <flow>]
+      write the score as midi, musicxml, lilypond, abc or json. The format
+      defaults to the extension of the output.
+  summary <score.json>
+      list the flows, players and instruments with bar counts and durations
+  validate <score.json>
+      check the score is consistent, exits with 1 if it isn't
+  edit <score.json> <script> [--output <score.json>]
+      apply the edits in a script, writing the score to stdout by default
+
+flows are given by key or by number, eg. --flow 2";
+
+/// A failure, reported to the user as is
+pub type CliResult<T> = Result<T, String>;
+
+/// An engine with nothing listening to it
+pub fn engine() -> Engine {
+    Engine::create(Box::new(SystemClock), Box::new(|_: Update| ()))
+}
+
+fn load(path: &str) -> CliResult<Engine> {
+    let text = fs::read_to_string(path).map_err(|e| format!("can't read {}: {}", path, e))?;
+    let doc = serde_json::from_str(&text).map_err(|e| format!("{} isn't JSON: {}", path, e))?;
+    let mut engine = engine();
+    engine
+        .load(doc)
+        .map_err(|e| format!("can't load {}: {}", path, e))?;
+    Ok(engine)
+}
+
+pub fn write(path: &str, bytes: &[u8]) -> CliResult<()> {
+    fs::write(path, bytes).map_err(|e| format!("can't write {}: {}", path, e))
+}
+
+/// Find a flow by key or by its number in the score, from 1
+pub fn find_flow(engine: &Engine, reference: &str) -> CliResult<String> {
+    let order = &engine.score().flows.order;
+    if order.iter().any(|key| key == reference) {
+        return Ok(String::from(reference));
+    }
+    match reference.parse::<usize>() {
+        Ok(number) if number > 0 && number <= order.len() => Ok(order[number - 1].clone()),
+        _ => Err(format!("there is no flow '{}'", reference)),
+    }
+}
+
+/// The positional arguments and `--name value` options of a command
+struct Args {
+    positional: Vec<String>,
+    options: HashMap<String, String>,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> CliResult<Args> {
+        let mut positional = Vec::new();
+        let mut options = HashMap::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let name = match arg.as_str() {
+                "-o" => "output",
+                "-f" => "format",
+                arg => match arg.strip_prefix("--") {
+                    Some(name) => name,
+                    None => {
+                        positional.push(arg.to_string());
+                        continue;
+                    }
+                },
+            };
+            match args.next() {
+                Some(value) => options.insert(String::from(name), value.clone()),
+                None => return Err(format!("--{} needs a value", name)),
+            };
+        }
+        Ok(Args {
+            positional,
+            options,
+        })
+    }
+
+    fn option(&self, name: &str) -> Option<&str> {
+        self.options.get(name).map(|value| value.as_str())
+    }
+
+    /// The positional arguments, which there must be exactly `count` of
+    fn expect(&self, count: usize) -> CliResult<&[String]> {
+        if self.positional.len() == count {
+            Ok(&self.positional)
+        } else {
+            Err(String::from(USAGE))
+        }
+    }
+}
+
+/// Run a command, returning the exit code
+fn run(command: &str, args: Args) -> CliResult<i32> {
+    match command {
+        "convert" => {
+            let paths = args.expect(2)?;
+            let engine = load(&paths[0])?;
+            let format = match args.option("format") {
+                Some(name) => convert::Format::from_name(name)?,
+                None => convert::Format::from_path(&paths[1])?,
+            };
+            let flow = match args.option("flow") {
+                Some(flow) => Some(find_flow(&engine, flow)?),
+                None => None,
+            };
+            for (path, bytes) in convert::convert(&engine, format, flow.as_deref(), &paths[1])? {
+                write(&path, &bytes)?;
+                println!("wrote {}", path);
+            }
+            Ok(0)
+        }
+        "summary" => {
+            let paths = args.expect(1)?;
+            let engine = load(&paths[0])?;
+            print!("{}", summary::summary(&engine));
+            Ok(0)
+        }
+        "validate" => {
+            let paths = args.expect(1)?;
+            let engine = load(&paths[0])?;
+            let problems = engine.validate();
+            for problem in problems.iter() {
+                println!("{}", problem);
+            }
+            if problems.is_empty() {
+                println!("{} is valid", paths[0]);
+                Ok(0)
+            } else {
+                Ok(1)
+            }
+        }
+        "edit" => {
+            let paths = args.expect(2)?;
+            let mut engine = load(&paths[0])?;
+            let text = fs::read_to_string(&paths[1])
+                .map_err(|e| format!("can't read {}: {}", paths[1], e))?;
+            script::run(&mut engine, &text)?;
+            let json = serde_json::to_string_pretty(engine.score()).map_err(|e| e.to_string())?;
+            match args.option("output") {
+                Some(path) => write(path, json.as_bytes())?,
+                None => println!("{}", json),
+            }
+            Ok(0)
+        }
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            Ok(0)
+        }
+        _ => Err(String::from(USAGE)),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.split_first() {
+        Some((command, rest)) => Args::parse(rest).and_then(|args| run(command, args)),
+        None => Err(String::from(USAGE)),
+    };
+    match result {
+        Ok(code) => process::exit(code),
+        Err(message) => {
+            eprintln!("{}", message);
+            process::exit(1);
+        }
+    }
+}
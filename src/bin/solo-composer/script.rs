@@ -0,0 +1,476 @@
+//! Edit scripts, one command per line:
+//!
+//! ```text
+//! # comments start with a hash
+//! set_title "Symphony No. 1"
+//! player = create_player solo
+//! violin = create_instrument strings.violin
+//! assign_instrument $player $violin
+//! assign_player flow:1 $player
+//! create_tone flow:1 $violin 0 16 69
+//! ```
+//!
+//! `name = command ...` keeps the key the command returns as `$name`. Flows can also be
+//! given by number, `flow:1` being the first. The whole script is one transaction, so
+//! if a line fails the score is left unchanged.
+
+use crate::{find_flow, CliResult};
+use solo_composer_engine::state::entries::gradual_tempo::{
+    GradualTempoType, TempoCurve, TempoTarget,
+};
+use solo_composer_engine::state::entries::key_signature::KeySignatureMode;
+use solo_composer_engine::state::entries::time_signature::{Reflow, TimeSignatureDrawType};
+use solo_composer_engine::state::entries::tone::Articulation;
+use solo_composer_engine::state::score::player::PlayerType;
+use solo_composer_engine::state::Engine;
+use solo_composer_engine::utils::duration::NoteDuration;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Split a line into words, keeping "quoted text" together
+fn tokenize(line: &str) -> CliResult<Vec<String>> {
+    let mut words = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+        if c == '#' {
+            break;
+        }
+        let mut word = String::new();
+        if c == '"' {
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some(c) => word.push(c),
+                        None => return Err(String::from("unfinished escape")),
+                    },
+                    Some(c) => word.push(c),
+                    None => return Err(String::from("unclosed quote")),
+                }
+            }
+        } else {
+            word.push(c);
+            while let Some(c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                word.push(*c);
+                chars.next();
+            }
+        }
+        words.push(word);
+    }
+    Ok(words)
+}
+
+struct Script<'a> {
+    engine: &'a mut Engine,
+    variables: HashMap<String, String>,
+}
+
+impl<'a> Script<'a> {
+    /// Resolve a `$name` to the key it holds
+    fn key(&self, word: &str) -> CliResult<String> {
+        match word.strip_prefix('$') {
+            Some(name) => match self.variables.get(name) {
+                Some(key) => Ok(key.clone()),
+                None => Err(format!("${} isn't set", name)),
+            },
+            None => Ok(String::from(word)),
+        }
+    }
+
+    fn flow(&self, word: &str) -> CliResult<String> {
+        let word = self.key(word)?;
+        find_flow(self.engine, word.strip_prefix("flow:").unwrap_or(&word))
+    }
+
+    /// A track to write tones to, either a track key or an instrument, which
+    /// writes to the first track of its first stave
+    fn track(&self, flow_key: &str, word: &str) -> CliResult<String> {
+        let key = self.key(word)?;
+        let score = self.engine.score();
+        let instrument = match score.instruments.get(&key) {
+            Some(instrument) => instrument,
+            None => return Ok(key),
+        };
+        instrument
+            .staves
+            .first()
+            .and_then(|stave_key| score.flows.by_key.get(flow_key)?.staves.get(stave_key))
+            .and_then(|stave| stave.tracks.first().cloned())
+            .ok_or_else(|| format!("instrument '{}' isn't in flow '{}'", key, flow_key))
+    }
+
+    /// Run a command, returning the key it created if any
+    fn command(&mut self, words: &[String]) -> CliResult<Option<String>> {
+        let (command, args) = match words.split_first() {
+            Some((command, args)) => (command.as_str(), args),
+            None => return Ok(None),
+        };
+        let arg = |i: usize| -> CliResult<&str> {
+            args.get(i)
+                .map(|arg| arg.as_str())
+                .ok_or_else(|| format!("{} needs more arguments", command))
+        };
+
+        match command {
+            "set_title" => {
+                self.engine.set_title(arg(0)?.to_string());
+                Ok(None)
+            }
+            "set_subtitle" => {
+                self.engine.set_subtitle(arg(0)?.to_string());
+                Ok(None)
+            }
+            "set_composer" => {
+                self.engine.set_composer(arg(0)?.to_string());
+                Ok(None)
+            }
+            "set_arranger" => {
+                self.engine.set_arranger(arg(0)?.to_string());
+                Ok(None)
+            }
+            "set_lyricist" => {
+                self.engine.set_lyricist(arg(0)?.to_string());
+                Ok(None)
+            }
+            "set_copyright" => {
+                self.engine.set_copyright(arg(0)?.to_string());
+                Ok(None)
+            }
+            "create_flow" => Ok(Some(self.engine.create_flow())),
+            "create_player" => {
+                let player_type = match arg(0)? {
+                    "solo" => PlayerType::Solo,
+                    "section" => PlayerType::Section,
+                    other => return Err(format!("unknown player type '{}'", other)),
+                };
+                Ok(Some(self.engine.create_player(player_type)))
+            }
+            "create_instrument" => self
+                .engine
+                .create_instrument(arg(0)?)
+                .map(Some)
+                .map_err(err),
+            "assign_instrument" => {
+                let player_key = self.key(arg(0)?)?;
+                let instrument_key = self.key(arg(1)?)?;
+                self.engine
+                    .assign_instrument(&player_key, &instrument_key)
+                    .map_err(err)?;
+                Ok(None)
+            }
+            "assign_player" => {
+                let flow_key = self.flow(arg(0)?)?;
+                let player_key = self.key(arg(1)?)?;
+                self.engine
+                    .assign_player(&flow_key, &player_key)
+                    .map_err(err)?;
+                Ok(None)
+            }
+            "rename_flow" => {
+                let flow_key = self.flow(arg(0)?)?;
+                self.engine.rename_flow(&flow_key, arg(1)?).map_err(err)?;
+                Ok(None)
+            }
+            "set_flow_length" => {
+                let flow_key = self.flow(arg(0)?)?;
+                self.engine
+                    .set_flow_length(&flow_key, number(arg(1)?)?)
+                    .map_err(err)?;
+                Ok(None)
+            }
+            "set_flow_subdivisions" => {
+                let flow_key = self.flow(arg(0)?)?;
+                self.engine
+                    .set_flow_subdivisions(&flow_key, number(arg(1)?)?)
+                    .map_err(err)?;
+                Ok(None)
+            }
+            "remove_flow" => {
+                let flow_key = self.flow(arg(0)?)?;
+                self.engine.remove_flow(&flow_key).map_err(err)?;
+                Ok(None)
+            }
+            // insert_bars <flow> <bar> <count>, bars are numbered from 1
+            "insert_bars" | "delete_bars" => {
+                let flow_key = self.flow(arg(0)?)?;
+                let bar: u32 = number(arg(1)?)?;
+                if bar == 0 {
+                    return Err(String::from("bars are numbered from 1"));
+                }
+                let count = number(arg(2)?)?;
+                if command == "insert_bars" {
+                    self.engine.insert_bars(&flow_key, bar - 1, count)
+                } else {
+                    self.engine.delete_bars(&flow_key, bar - 1, count)
+                }
+                .map_err(err)?;
+                Ok(None)
+            }
+            // create_time_signature <flow> <tick> <beats> <beat type>
+            "create_time_signature" => {
+                let flow_key = self.flow(arg(0)?)?;
+                self.engine
+                    .create_time_signature(
+                        &flow_key,
+                        number(arg(1)?)?,
+                        number(arg(2)?)?,
+                        beat_type(arg(3)?)?,
+                        TimeSignatureDrawType::Normal,
+                        None,
+                    )
+                    .map(Some)
+                    .map_err(err)
+            }
+            // set_pickup <flow> <time signature> <ticks>, padding the last bar to fit
+            "set_pickup" => {
+                let flow_key = self.flow(arg(0)?)?;
+                let entry_key = self.key(arg(1)?)?;
+                self.engine
+                    .set_time_signature_pickup(&flow_key, &entry_key, number(arg(2)?)?, Reflow::Pad)
+                    .map_err(err)?;
+                Ok(None)
+            }
+            // create_key_signature <flow> <tick> major|minor|open <sharps, negative for flats>
+            "create_key_signature" => {
+                let flow_key = self.flow(arg(0)?)?;
+                let mode = match arg(2)? {
+                    "major" => KeySignatureMode::Major,
+                    "minor" => KeySignatureMode::Minor,
+                    "open" => KeySignatureMode::Open,
+                    other => return Err(format!("unknown key signature mode '{}'", other)),
+                };
+                self.engine
+                    .create_key_signature(&flow_key, number(arg(1)?)?, mode, number(arg(3)?)?)
+                    .map(Some)
+                    .map_err(err)
+            }
+            // create_absolute_tempo <flow> <tick> <bpm> [beat type, dotted as 4.] [text]
+            "create_absolute_tempo" => {
+                let flow_key = self.flow(arg(0)?)?;
+                let bpm = number(arg(2)?)?;
+                if bpm == 0 {
+                    return Err(String::from("the bpm must be above zero"));
+                }
+                let (beat, dotted) = match args.get(3) {
+                    Some(beat) => {
+                        let undotted = beat.trim_end_matches('.');
+                        match beat.len() - undotted.len() {
+                            dots @ 0..=2 => (beat_type(undotted)?, dots as u8),
+                            _ => return Err(format!("'{}' has too many dots", beat)),
+                        }
+                    }
+                    None => (NoteDuration::Quarter, 0),
+                };
+                let text = args.get(4).map(|text| text.as_str()).unwrap_or("");
+                self.engine
+                    .create_absolute_tempo(
+                        &flow_key,
+                        number(arg(1)?)?,
+                        text,
+                        beat,
+                        dotted,
+                        bpm,
+                        false,
+                        !text.is_empty(),
+                        true,
+                    )
+                    .map(Some)
+                    .map_err(err)
+            }
+            // create_gradual_tempo <flow> <tick> <duration> rit|accel|a_tempo|tempo_primo [bpm or percent%]
+            "create_gradual_tempo" => {
+                let flow_key = self.flow(arg(0)?)?;
+                let tempo_type = match arg(3)? {
+                    "rit" => GradualTempoType::Ritardando,
+                    "accel" => GradualTempoType::Accelerando,
+                    "a_tempo" => GradualTempoType::ATempo,
+                    "tempo_primo" => GradualTempoType::TempoPrimo,
+                    other => return Err(format!("unknown gradual tempo '{}'", other)),
+                };
+                let target = match args.get(4) {
+                    Some(target) => match target.strip_suffix('%') {
+                        Some(percent) => Some(TempoTarget::Percent(number(percent)?)),
+                        None => Some(TempoTarget::Bpm(number(target)?)),
+                    },
+                    None => None,
+                };
+                self.engine
+                    .create_gradual_tempo(
+                        &flow_key,
+                        number(arg(1)?)?,
+                        number(arg(2)?)?,
+                        tempo_type,
+                        TempoCurve::Linear,
+                        target,
+                    )
+                    .map(Some)
+                    .map_err(err)
+            }
+            // create_tone <flow> <track or instrument> <tick> <duration> <pitch> [velocity]
+            "create_tone" => {
+                let flow_key = self.flow(arg(0)?)?;
+                let track_key = self.track(&flow_key, arg(1)?)?;
+                let velocity = match args.get(5) {
+                    Some(velocity) => number(velocity)?,
+                    None => 100,
+                };
+                self.engine
+                    .create_tone(
+                        &flow_key,
+                        &track_key,
+                        number(arg(2)?)?,
+                        number(arg(3)?)?,
+                        number(arg(4)?)?,
+                        velocity,
+                        Articulation::None,
+                    )
+                    .map(Some)
+                    .map_err(err)
+            }
+            _ => Err(format!("unknown command '{}'", command)),
+        }
+    }
+}
+
+fn err(error: solo_composer_engine::error::Error) -> String {
+    error.to_string()
+}
+
+fn number<T: FromStr>(word: &str) -> CliResult<T> {
+    word.parse()
+        .map_err(|_| format!("'{}' isn't a valid number", word))
+}
+
+/// A beat type written as a note value, eg. 4 for quarters
+fn beat_type(word: &str) -> CliResult<NoteDuration> {
+    match word {
+        "1" => Ok(NoteDuration::Whole),
+        "2" => Ok(NoteDuration::Half),
+        "4" => Ok(NoteDuration::Quarter),
+        "8" => Ok(NoteDuration::Eighth),
+        "16" => Ok(NoteDuration::Sixteenth),
+        "32" => Ok(NoteDuration::ThirtySecond),
+        _ => Err(format!("'{}' isn't a beat type", word)),
+    }
+}
+
+/// Run a script against the engine as a single undoable edit
+pub fn run(engine: &mut Engine, text: &str) -> CliResult<()> {
+    engine.begin_transaction(Some(String::from("Run script")));
+    let mut script = Script {
+        engine,
+        variables: HashMap::new(),
+    };
+
+    for (i, line) in text.lines().enumerate() {
+        let result = tokenize(line).and_then(|words| match words.get(1).map(|w| w.as_str()) {
+            Some("=") => {
+                let key = script.command(&words[2..])?;
+                let key = key.ok_or_else(|| format!("{} doesn't return a key", words[2]))?;
+                script.variables.insert(words[0].clone(), key);
+                Ok(())
+            }
+            _ => script.command(&words).map(|_| ()),
+        });
+        if let Err(message) = result {
+            script.engine.rollback();
+            return Err(format!("line {}: {}", i + 1, message));
+        }
+    }
+
+    script.engine.commit().map_err(err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solo_composer_engine::state::entries::Entry;
+
+    fn engine() -> Engine {
+        let mut engine = crate::engine();
+        let doc = serde_json::from_str(include_str!("../../../tests/fixtures/score_v2.json"));
+        engine.load(doc.unwrap()).unwrap();
+        engine
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize(r#"set_title "Symphony \"No. 1\"" # the title"#),
+            Ok(vec![
+                String::from("set_title"),
+                String::from("Symphony \"No. 1\""),
+            ])
+        );
+        assert!(tokenize(r#"set_title "Symphony"#).is_err());
+    }
+
+    #[test]
+    fn test_run() {
+        let mut engine = engine();
+        let script = r#"
+            set_title "Partita"
+            flow = create_flow
+            rename_flow $flow "Gigue"
+            player = create_player solo
+            flute = create_instrument woodwinds.flute
+            assign_instrument $player $flute
+            assign_player flow:2 $player
+            time = create_time_signature $flow 0 6 8
+            create_absolute_tempo $flow 0 60 4. Allegro
+            create_gradual_tempo $flow 8 8 rit 80%
+            insert_bars $flow 2 2
+            delete_bars flow:2 3 1
+            set_pickup $flow $time 8
+            create_tone flow:2 $flute 0 8 72
+        "#;
+        run(&mut engine, script).unwrap();
+
+        let score = engine.score();
+        assert_eq!(score.meta.title, "Partita");
+        let flow = score.flows.by_key.get(&score.flows.order[1]).unwrap();
+        assert_eq!(flow.title, "Gigue");
+        assert_eq!(flow.length, 104); // an eighth pickup then two bars of 6/8
+        let tones = flow
+            .tracks
+            .values()
+            .flat_map(|track| track.entries.by_key.values())
+            .filter(|entry| matches!(entry, Entry::Tone(_)))
+            .count();
+        assert_eq!(tones, 1);
+        match flow.master.get_absolute_tempo_at_tick(0) {
+            Some(tempo) => assert_eq!((tempo.bpm, tempo.dotted), (60, 1)),
+            None => panic!("the tempo wasn't created"),
+        }
+        assert_eq!(engine.undo_label(), Some(String::from("Run script")));
+    }
+
+    #[test]
+    fn test_run_failure() {
+        let mut engine = engine();
+        let script = "set_title \"Partita\"\ncreate_tone flow:1 missing 0 8 72\n";
+        assert!(run(&mut engine, script)
+            .unwrap_err()
+            .starts_with("line 2: "));
+        assert_eq!(engine.score().meta.title, "Sonata");
+        assert_eq!(
+            run(&mut engine, "explode"),
+            Err(String::from("line 1: unknown command 'explode'"))
+        );
+        assert!(run(&mut engine, "insert_bars flow:1 0 1").is_err());
+        assert_eq!(
+            run(
+                &mut engine,
+                "set_title \"Partita\"\ncreate_absolute_tempo flow:1 0 0"
+            ),
+            Err(String::from("line 2: the bpm must be above zero"))
+        );
+        assert_eq!(engine.score().meta.title, "Sonata");
+    }
+}
@@ -0,0 +1,97 @@
+use solo_composer_engine::state::score::notation::measures;
+use solo_composer_engine::state::score::player::PlayerType;
+use solo_composer_engine::state::Engine;
+use std::fmt::Write;
+
+/// Describe the flows, players and instruments of a score
+pub fn summary(engine: &Engine) -> String {
+    let score = engine.score();
+    let mut out = String::new();
+    let title = if score.meta.title.is_empty() {
+        "Untitled"
+    } else {
+        &score.meta.title
+    };
+    let _ = writeln!(out, "{}", title);
+    if !score.meta.composer.is_empty() {
+        let _ = writeln!(out, "{}", score.meta.composer);
+    }
+
+    let _ = writeln!(out, "\nflows:");
+    for (i, flow) in score
+        .flows
+        .order
+        .iter()
+        .filter_map(|key| score.flows.by_key.get(key))
+        .enumerate()
+    {
+        let bars = measures(&flow.master, flow.length, flow.subdivisions).len();
+        let _ = writeln!(
+            out,
+            "  {}. {} ({}): {} {}, {:.1} seconds",
+            i + 1,
+            if flow.title.is_empty() {
+                "Untitled"
+            } else {
+                &flow.title
+            },
+            flow.key,
+            bars,
+            if bars == 1 { "bar" } else { "bars" },
+            engine.flow_duration_seconds(&flow.key).unwrap_or(0.0)
+        );
+    }
+
+    let _ = writeln!(out, "\nplayers:");
+    for (i, player) in score
+        .players
+        .order
+        .iter()
+        .filter_map(|key| score.players.by_key.get(key))
+        .enumerate()
+    {
+        let style = score.config.auto_count.style(player.player_type);
+        let instruments: Vec<String> = player
+            .instruments
+            .iter()
+            .filter_map(|key| score.instruments.get(key))
+            .map(|instrument| match instrument.count {
+                Some(count) => format!("{} {}", instrument.long_name, style.format(count)),
+                None => instrument.long_name.clone(),
+            })
+            .collect();
+        let _ = writeln!(
+            out,
+            "  {}. {} ({}): {}",
+            i + 1,
+            match player.player_type {
+                PlayerType::Solo => "solo",
+                PlayerType::Section => "section",
+            },
+            player.key,
+            if instruments.is_empty() {
+                String::from("no instruments")
+            } else {
+                instruments.join(", ")
+            }
+        );
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary() {
+        let mut engine = crate::engine();
+        let doc = serde_json::from_str(include_str!("../../../tests/fixtures/score_v2.json"));
+        engine.load(doc.unwrap()).unwrap();
+        let summary = summary(&engine);
+        assert!(summary.starts_with("Sonata\nA. Composer\n"));
+        assert!(summary.contains("  1. Allegro (flow0000000001): 1 bar, 2.0 seconds\n"));
+        assert!(summary.contains(": Violin\n"));
+    }
+}
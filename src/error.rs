@@ -0,0 +1,117 @@
+use std::fmt;
+
+/// Everything that can go wrong when operating on the engine.
+///
+/// These are thrown to JS as an `Error` with a `code` property so the UI can
+/// report what went wrong.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    UnknownFlow(String),
+    UnknownTrack(String),
+    UnknownEntry(String),
+    UnknownPlayer(String),
+    UnknownInstrument(String),
+    UnknownStave(String),
+    UnknownDef(String),
+    InvalidTick(u32),
+    InvalidKeySignature(i8),
+    InvalidIndex(usize),
+    InvalidTuplet(u8, u8),
+    InvalidSubdivisions(u8),
+    UnrepresentableEntries(u8, Vec<String>),
+    InvalidTempo(String),
+    NoTransaction,
+    SchemaMismatch(String),
+    InvalidFile(String),
+    UnsupportedExport(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// A stable code for the error, exposed to JS
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::UnknownFlow(_) => "UNKNOWN_FLOW",
+            Error::UnknownTrack(_) => "UNKNOWN_TRACK",
+            Error::UnknownEntry(_) => "UNKNOWN_ENTRY",
+            Error::UnknownPlayer(_) => "UNKNOWN_PLAYER",
+            Error::UnknownInstrument(_) => "UNKNOWN_INSTRUMENT",
+            Error::UnknownStave(_) => "UNKNOWN_STAVE",
+            Error::UnknownDef(_) => "UNKNOWN_DEF",
+            Error::InvalidTick(_) => "INVALID_TICK",
+            Error::InvalidKeySignature(_) => "INVALID_KEY_SIGNATURE",
+            Error::InvalidIndex(_) => "INVALID_INDEX",
+            Error::InvalidTuplet(_, _) => "INVALID_TUPLET",
+            Error::InvalidSubdivisions(_) => "INVALID_SUBDIVISIONS",
+            Error::UnrepresentableEntries(_, _) => "UNREPRESENTABLE_ENTRIES",
+            Error::InvalidTempo(_) => "INVALID_TEMPO",
+            Error::NoTransaction => "NO_TRANSACTION",
+            Error::SchemaMismatch(_) => "SCHEMA_MISMATCH",
+            Error::InvalidFile(_) => "INVALID_FILE",
+            Error::UnsupportedExport(_) => "UNSUPPORTED_EXPORT",
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::UnknownFlow(key) => write!(f, "unknown flow: {}", key),
+            Error::UnknownTrack(key) => write!(f, "unknown track: {}", key),
+            Error::UnknownEntry(key) => write!(f, "unknown entry: {}", key),
+            Error::UnknownPlayer(key) => write!(f, "unknown player: {}", key),
+            Error::UnknownInstrument(key) => write!(f, "unknown instrument: {}", key),
+            Error::UnknownStave(key) => write!(f, "unknown stave: {}", key),
+            Error::UnknownDef(id) => write!(f, "unknown instrument def: {}", id),
+            Error::InvalidTick(tick) => write!(f, "invalid tick: {}", tick),
+            Error::InvalidKeySignature(offset) => {
+                write!(f, "invalid key signature: {} accidentals", offset)
+            }
+            Error::InvalidIndex(index) => write!(f, "invalid index: {}", index),
+            Error::InvalidTuplet(actual, normal) => {
+                write!(f, "invalid tuplet: {}:{}", actual, normal)
+            }
+            Error::InvalidSubdivisions(subdivisions) => {
+                write!(f, "invalid subdivisions: {}", subdivisions)
+            }
+            Error::UnrepresentableEntries(subdivisions, keys) => write!(
+                f,
+                "entries can't be represented in {} subdivisions: {}",
+                subdivisions,
+                keys.join(", ")
+            ),
+            Error::InvalidTempo(message) => write!(f, "invalid tempo: {}", message),
+            Error::NoTransaction => write!(f, "no transaction in progress"),
+            Error::SchemaMismatch(message) => write!(f, "schema mismatch: {}", message),
+            Error::InvalidFile(message) => write!(f, "invalid file: {}", message),
+            Error::UnsupportedExport(message) => write!(f, "unsupported export: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_code() {
+        assert_eq!(Error::UnknownFlow(String::from("a")).code(), "UNKNOWN_FLOW");
+        assert_eq!(Error::InvalidTick(4).code(), "INVALID_TICK");
+    }
+
+    #[test]
+    fn test_message() {
+        assert_eq!(
+            Error::UnknownTrack(String::from("abc")).to_string(),
+            "unknown track: abc"
+        );
+        assert_eq!(
+            Error::UnrepresentableEntries(4, vec![String::from("a"), String::from("b")])
+                .to_string(),
+            "entries can't be represented in 4 subdivisions: a, b"
+        );
+    }
+}
@@ -0,0 +1,556 @@
+use crate::error::{Error, Result};
+use crate::formats::abc::{key_alters, key_name, length, LETTERS};
+use crate::formats::untokenize;
+use crate::state::entries::absolute_tempo::AbsoluteTempo;
+use crate::state::entries::barline::BarlineType;
+use crate::state::entries::clef::{Clef, ClefDrawType};
+use crate::state::entries::key_signature::KeySignatureMode;
+use crate::state::entries::time_signature::{TimeSignature, TimeSignatureDrawType};
+use crate::state::entries::tone::{Articulation, Tone};
+use crate::state::entries::tuplet::Tuplet;
+use crate::state::entries::Entry;
+use crate::state::score::flow::Flow;
+use crate::state::score::notation::{measures, notate, Notation};
+use crate::state::score::stave::Stave;
+use crate::state::score::track::Track;
+use crate::state::score::Score;
+use crate::state::Engine;
+use crate::utils::duration::{NoteDuration, WrittenDuration};
+use crate::utils::pitch::{Accidental, Pitch};
+use std::collections::HashMap;
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+/// Bars written on each line of the tune body
+const BARS_PER_LINE: usize = 4;
+
+/// The meter as written in `M:`
+fn meter(time_signature: Option<&TimeSignature>) -> String {
+    let time_signature = match time_signature {
+        Some(time_signature) if time_signature.beats > 0 => time_signature,
+        _ => return String::from("none"),
+    };
+    match time_signature.draw_type {
+        TimeSignatureDrawType::CommonTime => return String::from("C"),
+        TimeSignatureDrawType::SplitCommonTime => return String::from("C|"),
+        _ => (),
+    }
+
+    let beats = time_signature.beats;
+    let groupings = &time_signature.groupings;
+    let complex = beats > 4 && beats % 3 != 0;
+    if complex
+        && groupings.len() > 1
+        && groupings.iter().map(|beats| u32::from(*beats)).sum::<u32>() == u32::from(beats)
+    {
+        let groupings: Vec<String> = groupings.iter().map(|beats| beats.to_string()).collect();
+        format!(
+            "({})/{}",
+            groupings.join("+"),
+            time_signature.beat_type.to_int()
+        )
+    } else {
+        format!("{}/{}", beats, time_signature.beat_type.to_int())
+    }
+}
+
+/// A note value as a fraction of a whole note, eg. a dotted quarter is 3/8
+fn fraction(base: &NoteDuration, dots: u8) -> (u32, u32) {
+    let power = 2u32.pow(u32::from(dots));
+    let numerator = power * 2 - 1;
+    let denominator = u32::from(base.to_int()) * power;
+    // dotted values are already in lowest terms, this only reduces the whole note
+    match numerator % 2 == 0 && denominator % 2 == 0 {
+        true => (numerator / 2, denominator / 2),
+        false => (numerator, denominator),
+    }
+}
+
+/// The length of a written duration in units of the default eighth note
+fn note_length(duration: &WrittenDuration) -> String {
+    let (numerator, denominator) = fraction(&duration.base, duration.dots);
+    let numerator = numerator * 8;
+    let divisor = gcd(numerator, denominator);
+    length(numerator / divisor, denominator / divisor)
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+fn tempo(tempo: &AbsoluteTempo) -> String {
+    let (numerator, denominator) = fraction(&tempo.beat_type, tempo.dotted);
+    let speed = format!("{}/{}={}", numerator, denominator, tempo.bpm);
+    if tempo.text_visible && !tempo.text.is_empty() {
+        format!(
+            "\"{}\" {}",
+            untokenize(&tempo.text).replace('"', "'"),
+            speed
+        )
+    } else {
+        speed
+    }
+}
+
+/// The clef as written in `K:`, treble is the default so is left out
+fn clef(clef: Option<&Clef>) -> Option<&'static str> {
+    let clef = clef?;
+    match (clef.draw_as, clef.offset) {
+        (ClefDrawType::G, _) if clef.pitch.int < 67 => Some("treble-8"),
+        (ClefDrawType::G, _) => None,
+        (ClefDrawType::F, _) => Some("bass"),
+        (ClefDrawType::C, offset) if offset > 0 => Some("tenor"),
+        (ClefDrawType::C, _) => Some("alto"),
+        (ClefDrawType::Percussion, _) => Some("perc"),
+        (ClefDrawType::Hidden, _) => Some("none"),
+    }
+}
+
+/// The key as written in `K:`, with the clef if there is one
+fn key(master: &Track, stave: &Stave, tick: u32) -> String {
+    let key = match master.get_key_signature_on_or_before_tick(tick) {
+        Some(key) => key_name(key.offset, key.mode),
+        None => key_name(0, KeySignatureMode::Major),
+    };
+    let current = stave
+        .master
+        .entries
+        .by_key
+        .values()
+        .filter_map(|entry| match entry {
+            Entry::Clef(clef) if clef.tick <= tick => Some(clef),
+            _ => None,
+        })
+        .max_by_key(|clef| clef.tick);
+    let clef = clef(current);
+    match clef {
+        Some(clef) => format!("{} clef={}", key, clef),
+        None => key,
+    }
+}
+
+fn barline(barline_type: BarlineType) -> &'static str {
+    match barline_type {
+        BarlineType::Normal => "|",
+        BarlineType::Double => "||",
+        BarlineType::Final => "|]",
+        BarlineType::StartRepeat => "|:",
+        BarlineType::EndRepeat => ":|",
+        BarlineType::EndStartRepeat => "::",
+    }
+}
+
+/// The master entries at a tick
+fn master_at(master: &Track, tick: u32) -> impl Iterator<Item = &Entry> {
+    master
+        .entries
+        .by_tick
+        .get(&tick)
+        .into_iter()
+        .flatten()
+        .filter_map(move |key| master.entries.by_key.get(key))
+}
+
+/// The inline fields changing the meter, key or tempo within the tune
+fn inline_fields(flow: &Flow, stave: &Stave, tick: u32) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut key_changed = false;
+    for entry in master_at(&flow.master, tick) {
+        match entry {
+            Entry::TimeSignature(time_signature) => {
+                out.push(format!("[M:{}]", meter(Some(time_signature))))
+            }
+            Entry::KeySignature(_) => key_changed = true,
+            _ => (),
+        }
+    }
+    key_changed |= master_at(&stave.master, tick).any(|entry| matches!(entry, Entry::Clef(_)));
+    if key_changed {
+        out.push(format!("[K:{}]", key(&flow.master, stave, tick)));
+    }
+    if let Some(entry) = flow.master.get_absolute_tempo_at_tick(tick) {
+        out.push(format!("[Q:{}]", tempo(entry)));
+    }
+    out
+}
+
+/// Writes notes with accidentals relative to the key and those earlier in the bar
+struct Spelling {
+    key: [i8; 7],
+    bar: HashMap<(usize, u8), i8>,
+}
+
+impl Spelling {
+    fn note(&mut self, pitch: &Pitch) -> String {
+        let pitch = pitch.written();
+        let alter = match pitch.accidental {
+            Accidental::DoubleSharp => 2,
+            Accidental::Sharp => 1,
+            Accidental::Natural => 0,
+            Accidental::Flat => -1,
+            Accidental::DoubleFlat => -2,
+        };
+        let letter = LETTERS
+            .iter()
+            .position(|letter| letter.to_string() == pitch.letter())
+            .unwrap_or(0);
+        let octave = pitch.octave();
+
+        let current = match self.bar.get(&(letter, octave)) {
+            Some(alter) => *alter,
+            None => self.key[letter],
+        };
+        let accidental = if alter == current {
+            ""
+        } else {
+            self.bar.insert((letter, octave), alter);
+            match alter {
+                2 => "^^",
+                1 => "^",
+                -1 => "_",
+                -2 => "__",
+                _ => "=",
+            }
+        };
+
+        // middle C is C, the octave above is c
+        let name = if octave > 4 {
+            format!(
+                "{}{}",
+                LETTERS[letter].to_ascii_lowercase(),
+                "'".repeat(usize::from(octave - 5))
+            )
+        } else {
+            format!("{}{}", LETTERS[letter], ",".repeat(usize::from(4 - octave)))
+        };
+        format!("{}{}", accidental, name)
+    }
+}
+
+fn decoration(articulation: Articulation) -> &'static str {
+    match articulation {
+        Articulation::None => "",
+        Articulation::Staccato => ".",
+        Articulation::Staccatissimo => "!wedge!",
+        Articulation::Tenuto => "!tenuto!",
+        Articulation::StaccatoTenuto => ".!tenuto!",
+    }
+}
+
+/// The ABC tuplet marker, the short form is used where it means the same thing
+fn tuplet(tuplet: &Tuplet, notes: usize) -> String {
+    let default = match tuplet.actual {
+        2 | 4 | 8 => Some(3),
+        3 | 6 => Some(2),
+        _ => None,
+    };
+    if default == Some(tuplet.normal) && notes == usize::from(tuplet.actual) {
+        format!("({}", tuplet.actual)
+    } else {
+        format!("({}:{}:{}", tuplet.actual, tuplet.normal, notes)
+    }
+}
+
+/// Write a note, chord or rest
+fn notation(
+    spelling: &mut Spelling,
+    track: &Track,
+    notation: &Notation,
+    tied_from: bool,
+    measure_rest: bool,
+) -> String {
+    let mut tones: Vec<&Tone> = notation
+        .tones
+        .iter()
+        .filter_map(|tone_key| match track.entries.by_key.get(tone_key) {
+            Some(Entry::Tone(tone)) => Some(tone),
+            _ => None,
+        })
+        .collect();
+    tones.sort_by_key(|tone| tone.pitch.int);
+
+    let written = note_length(&notation.duration);
+    let mut token = String::new();
+    // articulations are only written at the start of tied notes
+    if !tied_from {
+        if let Some(tone) = tones
+            .iter()
+            .find(|tone| !matches!(tone.articulation, Articulation::None))
+        {
+            token.push_str(decoration(tone.articulation));
+        }
+    }
+    match tones.len() {
+        0 if measure_rest => token.push('Z'),
+        0 => token.push_str(&format!("z{}", written)),
+        1 => token.push_str(&format!("{}{}", spelling.note(&tones[0].pitch), written)),
+        _ => {
+            let names: Vec<String> = tones
+                .iter()
+                .map(|tone| spelling.note(&tone.pitch))
+                .collect();
+            token.push_str(&format!("[{}]{}", names.join(""), written));
+        }
+    }
+    if notation.tie && !tones.is_empty() {
+        token.push('-');
+    }
+    token
+}
+
+/// Short notes within a beat group are beamed by writing them without a space
+fn beamed(flow: &Flow, previous: &Notation, current: &Notation) -> bool {
+    let short = |notation: &Notation| {
+        !notation.is_rest() && notation.duration.base.to_int() >= NoteDuration::Eighth.to_int()
+    };
+    let boundary = match flow
+        .master
+        .get_time_signature_on_or_before_tick(current.tick)
+    {
+        Some(time_signature) if time_signature.beats > 0 => {
+            time_signature.is_on_grouping_boundry(current.tick, flow.subdivisions)
+        }
+        _ => current.tick % u32::from(flow.subdivisions) == 0,
+    };
+    short(previous) && short(current) && !boundary
+}
+
+fn write_header(out: &mut Vec<String>, score: &Score, flow: &Flow, stave: &Stave, number: usize) {
+    out.push(format!("X:{}", number));
+    let meta = &score.meta;
+    if !meta.title.is_empty() {
+        out.push(format!("T:{}", untokenize(&meta.title)));
+    }
+    if !flow.title.is_empty() && flow.title != meta.title {
+        out.push(format!("T:{}", untokenize(&flow.title)));
+    }
+    if !meta.composer.is_empty() {
+        out.push(format!("C:{}", untokenize(&meta.composer)));
+    }
+    out.push(format!(
+        "M:{}",
+        meter(flow.master.get_time_signature_at_tick(0))
+    ));
+    out.push(String::from("L:1/8"));
+    if let Some(entry) = flow.master.get_absolute_tempo_at_tick(0) {
+        out.push(format!("Q:{}", tempo(entry)));
+    }
+    // the key ends the header
+    out.push(format!("K:{}", key(&flow.master, stave, 0)));
+}
+
+/// Write the tune body bar by bar, `BARS_PER_LINE` bars to a line
+fn write_body(out: &mut Vec<String>, flow: &Flow, stave: &Stave, track: &Track) {
+    let notations = notate(track, &flow.master, flow.length, flow.subdivisions);
+    let tuplets: HashMap<&String, &Tuplet> = track
+        .get_tuplets()
+        .into_iter()
+        .map(|tuplet| (&tuplet.key, tuplet))
+        .collect();
+
+    let mut spelling = Spelling {
+        key: [0; 7],
+        bar: HashMap::new(),
+    };
+    let mut line = String::new();
+    if let Some(Entry::Barline(entry)) = master_at(&flow.master, 0).find(|entry| {
+        matches!(entry, Entry::Barline(barline) if matches!(barline.barline_type, BarlineType::StartRepeat))
+    }) {
+        line.push_str(barline(entry.barline_type));
+    }
+
+    let mut cursor = 0;
+    for (i, (start, end)) in measures(&flow.master, flow.length, flow.subdivisions)
+        .into_iter()
+        .enumerate()
+    {
+        spelling.bar.clear();
+        if let Some(key) = flow.master.get_key_signature_on_or_before_tick(start) {
+            spelling.key = match key.mode {
+                KeySignatureMode::Open => [0; 7],
+                _ => key_alters(key.offset),
+            };
+        }
+        let open_time = flow
+            .master
+            .get_time_signature_on_or_before_tick(start)
+            .is_none_or(|time_signature| time_signature.beats == 0);
+
+        let mut tuplet_key: Option<&String> = None;
+        let mut previous: Option<&Notation> = None;
+        while let Some(current) = notations.get(cursor) {
+            if current.tick >= end {
+                break;
+            }
+            if current.tick > 0 {
+                let fields = inline_fields(flow, stave, current.tick);
+                if !fields.is_empty() {
+                    line.push_str(&fields.join(""));
+                    previous = None;
+                }
+            }
+            if current.tuplet.as_ref() != tuplet_key {
+                tuplet_key = current.tuplet.as_ref();
+                if let Some(entry) = tuplet_key.and_then(|key| tuplets.get(key)) {
+                    let notes = notations[cursor..]
+                        .iter()
+                        .take_while(|notation| notation.tuplet == current.tuplet)
+                        .count();
+                    if !line.is_empty() && !line.ends_with(['|', ':', ']']) {
+                        line.push(' ');
+                    }
+                    line.push_str(&tuplet(entry, notes));
+                    previous = None;
+                }
+            }
+
+            let joined = previous.is_some_and(|previous| beamed(flow, previous, current));
+            if !joined && previous.is_some() {
+                line.push(' ');
+            }
+            let tied_from = cursor > 0 && notations[cursor - 1].tie;
+            let measure_rest = !open_time && current.tick == start && current.length == end - start;
+            line.push_str(&notation(
+                &mut spelling,
+                track,
+                current,
+                tied_from,
+                measure_rest,
+            ));
+            previous = Some(current);
+            cursor += 1;
+        }
+
+        let barline_type = master_at(&flow.master, end).find_map(|entry| match entry {
+            Entry::Barline(entry) => Some(entry.barline_type),
+            _ => None,
+        });
+        let symbol = match barline_type {
+            Some(barline_type) => barline(barline_type),
+            None if end == flow.length => barline(BarlineType::Final),
+            None => barline(BarlineType::Normal),
+        };
+        line.push_str(symbol);
+
+        if (i + 1) % BARS_PER_LINE == 0 || end == flow.length {
+            out.push(line);
+            line = String::new();
+        } else {
+            line.push(' ');
+        }
+    }
+    if !line.trim().is_empty() {
+        out.push(String::from(line.trim_end()));
+    }
+}
+
+/// Write a flow as an ABC tune. ABC has a single stave so the flow must have only one,
+/// only the first voice of the stave is written.
+pub fn export(score: &Score, flow: &Flow, number: usize) -> Result<String> {
+    let stave = match flow.staves.len() {
+        1 => flow.staves.values().next(),
+        _ => None,
+    };
+    let stave = match stave {
+        Some(stave) => stave,
+        None => {
+            return Err(Error::UnsupportedExport(String::from(
+                "ABC can only be written for flows with a single stave",
+            )))
+        }
+    };
+
+    let mut out = Vec::new();
+    write_header(&mut out, score, flow, stave, number);
+    if let Some(track) = stave.tracks.first().and_then(|key| flow.tracks.get(key)) {
+        write_body(&mut out, flow, stave, track);
+    }
+    out.push(String::new());
+    Ok(out.join("\n"))
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl Engine {
+    /// Export a flow with a single stave as an ABC tune
+    pub fn export_abc(&self, flow_key: &str) -> Result<String> {
+        let score = &self.state.score;
+        let flow = match score.flows.by_key.get(flow_key) {
+            Some(flow) => flow,
+            None => return Err(Error::UnknownFlow(String::from(flow_key))),
+        };
+        let number = score
+            .flows
+            .order
+            .iter()
+            .position(|key| key == flow_key)
+            .unwrap_or(0);
+        export(score, flow, number + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::abc::import::plan;
+    use crate::formats::plan::Fraction;
+
+    fn score() -> Score {
+        serde_json::from_str(include_str!("../../../tests/fixtures/score_v2.json")).unwrap()
+    }
+
+    #[test]
+    fn test_export() {
+        let score = score();
+        let flow = &score.flows.by_key["flow0000000001"];
+        let abc = export(&score, flow, 1).unwrap();
+
+        assert_eq!(
+            abc,
+            "X:1\nT:Sonata\nT:Allegro\nC:A. Composer\nM:none\nL:1/8\nQ:\"Allegro\" 1/4=120\nK:C\nA2 ._B2 z4|]\n"
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let score = score();
+        let flow = &score.flows.by_key["flow0000000001"];
+        let plan = plan(&export(&score, flow, 1).unwrap()).unwrap();
+
+        let movement = &plan.movements[0];
+        assert_eq!(movement.title, Some(String::from("Sonata")));
+        let tones: Vec<(u8, Fraction)> = movement.parts[0]
+            .tones
+            .iter()
+            .map(|tone| (tone.pitch, tone.start))
+            .collect();
+        assert_eq!(
+            tones,
+            vec![(69, Fraction::zero()), (70, Fraction::new(1, 1))]
+        );
+    }
+
+    #[test]
+    fn test_meter_and_lengths() {
+        assert_eq!(fraction(&NoteDuration::Quarter, 1), (3, 8));
+        assert_eq!(fraction(&NoteDuration::Whole, 0), (1, 1));
+        assert_eq!(
+            note_length(&WrittenDuration {
+                base: NoteDuration::Sixteenth,
+                dots: 0
+            }),
+            "/"
+        );
+        assert_eq!(
+            note_length(&WrittenDuration {
+                base: NoteDuration::Quarter,
+                dots: 1
+            }),
+            "3"
+        );
+        assert_eq!(meter(None), "none");
+    }
+}
@@ -0,0 +1,1223 @@
+use crate::error::{Error, Result};
+use crate::formats::abc::{key_alters, tonic_fifths, LETTERS, STEPS};
+use crate::formats::plan::{
+    Fraction, ImportReport, ImportedMeta, Movement, Plan, PlannedClef, PlannedPart, PlannedTempo,
+    PlannedTimeSignature, PlannedTone, PlannedTuplet, Warnings,
+};
+use crate::state::entries::clef::ClefDrawType;
+use crate::state::entries::key_signature::KeySignatureMode;
+use crate::state::entries::time_signature::TimeSignatureDrawType;
+use crate::state::entries::tone::Articulation;
+use crate::state::score::instrument::defs::{get_def, get_def_by_program};
+use crate::state::Engine;
+use crate::utils::duration::NoteDuration;
+use std::collections::HashMap;
+use std::ops::{Add, Mul, Sub};
+
+/// Tunes have no dynamics unless they are marked, this is a forte
+const DEFAULT_VELOCITY: u8 = 90;
+
+/// Every tune is played by the same instrument
+const PART_ID: &str = "abc";
+
+fn invalid(message: &str) -> Error {
+    Error::InvalidFile(format!("abc {}", message))
+}
+
+/// The note value and dots of a length given as a fraction of a whole note, eg. 3/8
+fn beat(numerator: u64, denominator: u64) -> Option<(NoteDuration, u8)> {
+    let fraction = Fraction::new(numerator, denominator);
+    let (base, dots) = match fraction.numerator {
+        1 => (fraction.denominator, 0),
+        3 => (fraction.denominator / 2, 1),
+        7 => (fraction.denominator / 4, 2),
+        _ => return None,
+    };
+    let base = match base {
+        1 => NoteDuration::Whole,
+        2 => NoteDuration::Half,
+        4 => NoteDuration::Quarter,
+        8 => NoteDuration::Eighth,
+        16 => NoteDuration::Sixteenth,
+        32 => NoteDuration::ThirtySecond,
+        _ => return None,
+    };
+    Some((base, dots))
+}
+
+/// A fraction such as 1/8, as written in `L:` and `Q:`
+fn parse_fraction(text: &str) -> Option<(u64, u64)> {
+    let mut parts = text.trim().splitn(2, '/');
+    let numerator = parts.next()?.trim().parse().ok()?;
+    let denominator = match parts.next() {
+        Some(denominator) => denominator.trim().parse().ok()?,
+        None => 1,
+    };
+    if numerator == 0 || denominator == 0 {
+        return None;
+    }
+    Some((numerator, denominator))
+}
+
+/// Read a time signature from `M:`, eg. 6/8, C, C| or (2+3)/8
+fn parse_meter(text: &str) -> Option<PlannedTimeSignature> {
+    let text = text.trim();
+    let time_signature = |beats, beat_type, draw_type, groupings| PlannedTimeSignature {
+        start: Fraction::zero(),
+        beats,
+        beat_type,
+        draw_type,
+        groupings,
+    };
+    match text {
+        "" | "none" => {
+            return Some(time_signature(
+                0,
+                NoteDuration::Quarter,
+                TimeSignatureDrawType::Hidden,
+                None,
+            ))
+        }
+        "C" => {
+            return Some(time_signature(
+                4,
+                NoteDuration::Quarter,
+                TimeSignatureDrawType::CommonTime,
+                None,
+            ))
+        }
+        "C|" => {
+            return Some(time_signature(
+                2,
+                NoteDuration::Half,
+                TimeSignatureDrawType::SplitCommonTime,
+                None,
+            ))
+        }
+        _ => (),
+    }
+
+    let (beats, beat_type) = text.split_once('/')?;
+    let groups: Vec<u8> = beats
+        .trim_matches(|c| c == '(' || c == ')' || c == ' ')
+        .split('+')
+        .map(|beat| beat.trim().parse().ok())
+        .collect::<Option<Vec<u8>>>()?;
+    let total: u32 = groups.iter().map(|beat| u32::from(*beat)).sum();
+    let beat_type = match beat_type
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|beat_type| beat(1, beat_type))
+    {
+        Some((beat_type, 0)) => beat_type,
+        _ => return None,
+    };
+    if total == 0 || total > u32::from(u8::MAX) {
+        return None;
+    }
+    let groupings = if groups.len() > 1 { Some(groups) } else { None };
+    Some(time_signature(
+        total as u8,
+        beat_type,
+        TimeSignatureDrawType::Normal,
+        groupings,
+    ))
+}
+
+/// A clef named in `K:` or `V:`, eg. bass or treble-8
+fn parse_clef(name: &str) -> Option<PlannedClef> {
+    let name = name.trim_start_matches("clef=");
+    let (name, octave) = match name.strip_suffix("-8").or_else(|| name.strip_suffix("8")) {
+        Some(name) => (name, -12),
+        None => match name.strip_suffix("+8") {
+            Some(name) => (name, 12),
+            None => (name, 0),
+        },
+    };
+    let (draw_as, pitch, line) = match name {
+        "treble" | "G" => (ClefDrawType::G, 67, 2),
+        "bass" | "F" => (ClefDrawType::F, 53, 4),
+        "alto" | "C" => (ClefDrawType::C, 60, 3),
+        "tenor" => (ClefDrawType::C, 60, 4),
+        "perc" | "percussion" | "P" => (ClefDrawType::Percussion, 60, 3),
+        "none" => (ClefDrawType::Hidden, 67, 2),
+        _ => return None,
+    };
+    Some(PlannedClef {
+        stave: 0,
+        start: Fraction::zero(),
+        pitch: (pitch + octave) as u8,
+        offset: (line - 3) * 2,
+        draw_as,
+    })
+}
+
+/// The sharps (+) or flats (-) of a mode relative to its major key
+fn mode_offset(mode: &str) -> Option<i8> {
+    let mode = mode.to_lowercase();
+    if mode.is_empty() {
+        return Some(0);
+    }
+    if mode == "m" {
+        return Some(-3);
+    }
+    match mode.get(..3).unwrap_or(&mode) {
+        "maj" | "ion" => Some(0),
+        "min" | "aeo" => Some(-3),
+        "mix" => Some(-1),
+        "dor" => Some(-2),
+        "phr" => Some(-4),
+        "lyd" => Some(1),
+        "loc" => Some(-5),
+        _ => None,
+    }
+}
+
+/// A tuplet that is still collecting its notes
+struct OpenTuplet {
+    start: Fraction,
+    remaining: u32,
+    actual: u8,
+    normal: u8,
+}
+
+/// The last note, chord or rest, which a broken rhythm changes the length of
+struct LastNote {
+    start: Fraction,
+    duration: Fraction,
+    tones: Vec<usize>,
+}
+
+/// A pitch read from the body with whether it is tied to the next note
+struct ReadPitch {
+    pitch: u8,
+    tie: bool,
+}
+
+/// Reads a single tune, its header and then its body a line at a time
+struct Tune<'a> {
+    warnings: &'a mut Warnings,
+    titles: Vec<String>,
+    composers: Vec<String>,
+    program: Option<u8>,
+    in_header: bool,
+
+    position: Fraction,
+    unit: Option<Fraction>, // in quarters
+    bar_length: Option<Fraction>,
+    compound: bool,
+    key: [i8; 7],
+    bar_alters: HashMap<(usize, i32), i8>,
+    velocity: u8,
+    articulation: Articulation,
+    broken: Option<Fraction>,
+    ties: Vec<usize>,
+    last: Option<LastNote>,
+    tuplet: Option<OpenTuplet>,
+    bars: Vec<Fraction>,
+
+    // only the first voice is read
+    voice: Option<String>,
+    skipping: bool,
+
+    tones: Vec<PlannedTone>,
+    tuplets: Vec<PlannedTuplet>,
+    clefs: Vec<PlannedClef>,
+    time_signatures: Vec<PlannedTimeSignature>,
+    key_signatures: Vec<(Fraction, i8, KeySignatureMode)>,
+    tempos: Vec<PlannedTempo>,
+}
+
+impl<'a> Tune<'a> {
+    fn new(warnings: &'a mut Warnings) -> Self {
+        Self {
+            warnings,
+            titles: Vec::new(),
+            composers: Vec::new(),
+            program: None,
+            in_header: true,
+            position: Fraction::zero(),
+            unit: None,
+            bar_length: None,
+            compound: false,
+            key: [0; 7],
+            bar_alters: HashMap::new(),
+            velocity: DEFAULT_VELOCITY,
+            articulation: Articulation::None,
+            broken: None,
+            ties: Vec::new(),
+            last: None,
+            tuplet: None,
+            bars: Vec::new(),
+            voice: None,
+            skipping: false,
+            tones: Vec::new(),
+            tuplets: Vec::new(),
+            clefs: Vec::new(),
+            time_signatures: Vec::new(),
+            key_signatures: Vec::new(),
+            tempos: Vec::new(),
+        }
+    }
+
+    fn warn(&mut self, element: &str, message: &str) {
+        let bar = (self.bars.len() + 1).to_string();
+        self.warnings.push(element, None, Some(&bar), message);
+    }
+
+    /// The unit note length, which defaults from the meter
+    fn unit(&mut self) -> Fraction {
+        match self.unit {
+            Some(unit) => unit,
+            None => {
+                // meters shorter than 3/4 default to sixteenths
+                let unit = match self.bar_length {
+                    Some(bar_length) if bar_length < Fraction::new(3, 1) => Fraction::new(1, 4),
+                    _ => Fraction::new(1, 2),
+                };
+                self.unit = Some(unit);
+                unit
+            }
+        }
+    }
+
+    fn line(&mut self, line: &str) {
+        let line = line.trim_end();
+        if let Some(directive) = line.strip_prefix("%%") {
+            self.directive(directive);
+            return;
+        }
+        if line.starts_with('%') || line.is_empty() {
+            return;
+        }
+
+        let chars: Vec<char> = line.chars().collect();
+        if chars.len() > 1 && chars[0].is_ascii_alphabetic() && chars[1] == ':' {
+            let value: String = chars[2..].iter().collect();
+            let value = match value.find('%') {
+                Some(i) => &value[..i],
+                None => &value,
+            };
+            self.field(chars[0], value.trim());
+            return;
+        }
+
+        // a tune without a key starts its body here
+        if self.in_header {
+            self.start_body();
+        }
+        self.music(&chars);
+    }
+
+    fn directive(&mut self, directive: &str) {
+        let words: Vec<&str> = directive.split_whitespace().collect();
+        if let ["MIDI", "program", .., program] = words.as_slice() {
+            if let Ok(program) = program.parse::<u8>() {
+                self.program.get_or_insert(program.min(127));
+            }
+        }
+    }
+
+    fn start_body(&mut self) {
+        self.in_header = false;
+        self.unit();
+    }
+
+    fn field(&mut self, name: char, value: &str) {
+        match name {
+            'T' if self.in_header => self.titles.push(String::from(value)),
+            'C' if self.in_header => self.composers.push(String::from(value)),
+            'M' => match parse_meter(value) {
+                Some(mut time_signature) => {
+                    self.bar_length = match time_signature.beats {
+                        0 => None,
+                        beats => Some(Fraction::new(
+                            4 * u64::from(beats),
+                            u64::from(time_signature.beat_type.to_int()),
+                        )),
+                    };
+                    self.compound = time_signature.beats > 3 && time_signature.beats % 3 == 0;
+                    time_signature.start = self.position;
+                    self.time_signatures
+                        .retain(|other| other.start != time_signature.start);
+                    self.time_signatures.push(time_signature);
+                }
+                None => self.warn("M:", "unsupported meter"),
+            },
+            'L' => match parse_fraction(value) {
+                Some((numerator, denominator)) => {
+                    self.unit = Some(Fraction::new(4 * numerator, denominator))
+                }
+                None => self.warn("L:", "unsupported unit note length"),
+            },
+            'Q' => self.tempo(value),
+            'K' => {
+                self.key(value);
+                if self.in_header {
+                    self.start_body();
+                }
+            }
+            'V' => self.voice(value),
+            'w' | 'W' => self.warn("w:", "lyrics are not imported"),
+            'P' if !self.in_header => self.warn("P:", "parts are not supported"),
+            'I' => self.directive(value),
+            // information fields, such as the origin or source, have nowhere to go
+            _ => (),
+        }
+    }
+
+    fn tempo(&mut self, value: &str) {
+        // the text is quoted and may come before or after the speed
+        let mut text = Vec::new();
+        let mut speed = String::new();
+        for (i, part) in value.split('"').enumerate() {
+            if i % 2 == 1 {
+                text.push(part.trim());
+            } else {
+                speed.push_str(part);
+                speed.push(' ');
+            }
+        }
+        let text = text.join(" ");
+        let speed = speed.trim();
+
+        let (beats, bpm) = match speed.split_once('=') {
+            Some((beats, bpm)) => (beats.trim(), bpm.trim()),
+            None => ("", speed),
+        };
+        let bpm = match bpm.parse::<f64>() {
+            Ok(bpm) if bpm > 0.0 => bpm.round() as u32,
+            _ => {
+                self.warn("Q:", "tempos without a speed are not imported");
+                return;
+            }
+        };
+
+        let beat = if beats.is_empty() {
+            let unit = self.unit();
+            beat(unit.numerator, unit.denominator * 4)
+        } else if beats.split_whitespace().count() > 1 {
+            None
+        } else {
+            parse_fraction(beats).and_then(|(numerator, denominator)| beat(numerator, denominator))
+        };
+        let (beat_type, dotted) = match beat {
+            Some(beat) => beat,
+            None => {
+                self.warn("Q:", "unsupported tempo beat");
+                return;
+            }
+        };
+
+        let start = self.position;
+        self.tempos.retain(|other| other.start != start);
+        self.tempos.push(PlannedTempo {
+            start,
+            text_visible: !text.is_empty(),
+            text,
+            beat_type,
+            dotted,
+            bpm,
+            parenthesis_visible: false,
+            bpm_visible: true,
+        });
+    }
+
+    fn key(&mut self, value: &str) {
+        let mut words = value.split_whitespace().peekable();
+        let mut key = None;
+
+        match words.peek() {
+            Some(&"none") => {
+                words.next();
+                key = Some((0, KeySignatureMode::Open));
+            }
+            Some(&"HP") | Some(&"Hp") => {
+                words.next();
+                self.warn("K:", "bagpipe keys are not supported");
+            }
+            Some(word) if word.starts_with(|c: char| ('A'..='G').contains(&c)) => {
+                let word = words.next().unwrap_or("");
+                let mut chars = word.chars();
+                let letter = chars.next().unwrap_or('C');
+                let rest: String = chars.collect();
+                let (alter, mode) = if let Some(mode) = rest.strip_prefix('#') {
+                    (1, String::from(mode))
+                } else if let Some(mode) = rest.strip_prefix('b') {
+                    (-1, String::from(mode))
+                } else {
+                    (0, rest)
+                };
+                // the mode may be a separate word, eg. G minor
+                let mode = match words.peek() {
+                    Some(word)
+                        if mode.is_empty()
+                            && mode_offset(word).is_some()
+                            && !word.contains('=') =>
+                    {
+                        String::from(words.next().unwrap_or(""))
+                    }
+                    _ => mode,
+                };
+
+                match (tonic_fifths(letter, alter), mode_offset(&mode)) {
+                    (Some(fifths), Some(offset)) if (-7..=7).contains(&(fifths + offset)) => {
+                        let mode = if offset == -3 {
+                            KeySignatureMode::Minor
+                        } else {
+                            KeySignatureMode::Major
+                        };
+                        key = Some((fifths + offset, mode));
+                    }
+                    _ => self.warn("K:", "unsupported key"),
+                }
+            }
+            // a key with only a clef is C major
+            _ => key = Some((0, KeySignatureMode::Major)),
+        }
+
+        for word in words {
+            if word.starts_with('^') || word.starts_with('_') || word.starts_with('=') {
+                self.warn("K:", "explicit key accidentals are not supported");
+            } else if let Some(mut clef) = parse_clef(word) {
+                clef.start = self.position;
+                self.clefs.retain(|other| other.start != clef.start);
+                self.clefs.push(clef);
+            }
+        }
+
+        if let Some((offset, mode)) = key {
+            self.key = key_alters(offset);
+            let start = self.position;
+            self.key_signatures.retain(|other| other.0 != start);
+            self.key_signatures.push((start, offset, mode));
+        }
+    }
+
+    fn voice(&mut self, value: &str) {
+        let id = String::from(value.split_whitespace().next().unwrap_or(""));
+        match &self.voice {
+            None => {
+                self.voice = Some(id);
+                // the first voice's clef is that of the stave
+                for word in value.split_whitespace().skip(1) {
+                    if let Some(mut clef) = parse_clef(word) {
+                        clef.start = self.position;
+                        self.clefs.retain(|other| other.start != clef.start);
+                        self.clefs.push(clef);
+                    }
+                }
+            }
+            Some(first) => {
+                self.skipping = *first != id;
+                if self.skipping {
+                    self.warn("V:", "only the first voice is imported");
+                }
+            }
+        }
+    }
+
+    fn decoration(&mut self, name: &str) {
+        match name {
+            "staccato" | "." => self.articulation = Articulation::Staccato,
+            "tenuto" => {
+                self.articulation = match self.articulation {
+                    Articulation::Staccato => Articulation::StaccatoTenuto,
+                    _ => Articulation::Tenuto,
+                }
+            }
+            "wedge" | "staccatissimo" => self.articulation = Articulation::Staccatissimo,
+            "pppp" | "ppp" => self.velocity = 20,
+            "pp" => self.velocity = 35,
+            "p" => self.velocity = 50,
+            "mp" => self.velocity = 65,
+            "mf" => self.velocity = 80,
+            "f" => self.velocity = 95,
+            "ff" => self.velocity = 110,
+            "fff" | "ffff" => self.velocity = 125,
+            name => self.warn(&format!("!{}!", name), "decoration not supported"),
+        }
+    }
+
+    /// Read an accidental, letter and octave marks, returning the MIDI number
+    fn pitch(&mut self, chars: &[char], i: &mut usize) -> Option<u8> {
+        let mut explicit = None;
+        while let Some(c) = chars.get(*i) {
+            let alter = match c {
+                '^' => 1,
+                '_' => -1,
+                '=' => 0,
+                _ => break,
+            };
+            explicit = Some(explicit.unwrap_or(0) + alter);
+            *i += 1;
+        }
+
+        let c = *chars.get(*i)?;
+        let letter = LETTERS.iter().position(|l| *l == c.to_ascii_uppercase())?;
+        let mut octave: i32 = if c.is_ascii_lowercase() { 5 } else { 4 };
+        *i += 1;
+        while let Some(c) = chars.get(*i) {
+            match c {
+                '\'' => octave += 1,
+                ',' => octave -= 1,
+                _ => break,
+            }
+            *i += 1;
+        }
+
+        // accidentals last until the end of the bar
+        let alter = match explicit {
+            Some(alter) => {
+                self.bar_alters.insert((letter, octave), alter);
+                alter
+            }
+            None => match self.bar_alters.get(&(letter, octave)) {
+                Some(alter) => *alter,
+                None => self.key[letter],
+            },
+        };
+        let int = (octave + 1) * 12 + i32::from(STEPS[letter]) + i32::from(alter);
+        if (0..128).contains(&int) {
+            Some(int as u8)
+        } else {
+            None
+        }
+    }
+
+    /// Read a length multiplier, eg. 3, /2, // or 3/2
+    fn length(chars: &[char], i: &mut usize) -> Fraction {
+        let number = |i: &mut usize| {
+            let start = *i;
+            while chars.get(*i).is_some_and(|c| c.is_ascii_digit()) {
+                *i += 1;
+            }
+            chars[start..*i]
+                .iter()
+                .collect::<String>()
+                .parse::<u64>()
+                .ok()
+        };
+
+        let numerator = number(i).unwrap_or(1);
+        let mut denominator = 1;
+        while chars.get(*i) == Some(&'/') {
+            *i += 1;
+            denominator *= number(i).unwrap_or(2);
+        }
+        Fraction::new(numerator, denominator)
+    }
+
+    /// Add a note, chord or rest at the current position
+    fn note(&mut self, pitches: Vec<ReadPitch>, length: Fraction) {
+        let mut duration = self.unit().mul(length);
+        if let Some(broken) = self.broken.take() {
+            duration = duration.mul(broken);
+        }
+        if let Some(tuplet) = &mut self.tuplet {
+            duration = duration.mul(Fraction::new(
+                u64::from(tuplet.normal),
+                u64::from(tuplet.actual),
+            ));
+            tuplet.remaining = tuplet.remaining.saturating_sub(1);
+        }
+
+        let start = self.position;
+        let mut indexes = Vec::new();
+        let mut ties = Vec::new();
+        for read in pitches {
+            // tied notes carry on the tone they are tied from
+            let tied = self.ties.iter().copied().find(|&index| {
+                let tone = &self.tones[index];
+                tone.pitch == read.pitch && tone.start.add(tone.duration) == start
+            });
+            let index = match tied {
+                Some(index) => {
+                    let tone = &mut self.tones[index];
+                    tone.duration = tone.duration.add(duration);
+                    index
+                }
+                None => {
+                    self.tones.push(PlannedTone {
+                        stave: 0,
+                        start,
+                        duration,
+                        pitch: read.pitch,
+                        velocity: self.velocity,
+                        articulation: self.articulation,
+                    });
+                    self.tones.len() - 1
+                }
+            };
+            if read.tie {
+                ties.push(index);
+            }
+            indexes.push(index);
+        }
+
+        self.ties = ties;
+        self.articulation = Articulation::None;
+        self.position = start.add(duration);
+        self.last = Some(LastNote {
+            start,
+            duration,
+            tones: indexes,
+        });
+
+        if let Some(tuplet) = &self.tuplet {
+            if tuplet.remaining == 0 {
+                self.tuplets.push(PlannedTuplet {
+                    stave: 0,
+                    start: tuplet.start,
+                    end: self.position,
+                    actual: tuplet.actual,
+                    normal: tuplet.normal,
+                });
+                self.tuplet = None;
+            }
+        }
+    }
+
+    /// Dotted rhythms written as > or <, the first note is lengthened and the next shortened
+    fn broken_rhythm(&mut self, count: u32, longer_first: bool) {
+        let last = match &mut self.last {
+            Some(last) => last,
+            None => return,
+        };
+        let power = 2u64.pow(count.min(3));
+        let long = Fraction::new(power * 2 - 1, power);
+        let short = Fraction::new(1, power);
+        let (first, next) = if longer_first {
+            (long, short)
+        } else {
+            (short, long)
+        };
+
+        let duration = last.duration.mul(first);
+        for index in last.tones.iter() {
+            let tone = &mut self.tones[*index];
+            tone.duration = tone.duration.sub(last.duration).add(duration);
+        }
+        self.position = last.start.add(duration);
+        last.duration = duration;
+        self.broken = Some(next);
+    }
+
+    fn tuplet_start(&mut self, chars: &[char], i: &mut usize) {
+        let mut numbers = [None; 3];
+        for (n, number) in numbers.iter_mut().enumerate() {
+            if n > 0 {
+                if chars.get(*i) != Some(&':') {
+                    break;
+                }
+                *i += 1;
+            }
+            let start = *i;
+            while chars.get(*i).is_some_and(|c| c.is_ascii_digit()) {
+                *i += 1;
+            }
+            *number = chars[start..*i]
+                .iter()
+                .collect::<String>()
+                .parse::<u8>()
+                .ok();
+        }
+
+        let actual = numbers[0].unwrap_or(3);
+        let normal = numbers[1].unwrap_or(match actual {
+            2 | 4 | 8 => 3,
+            3 | 6 => 2,
+            _ if self.compound => 3,
+            _ => 2,
+        });
+        let remaining = numbers[2].unwrap_or(actual);
+        if actual < 2 || normal == 0 {
+            self.warn("(", "unsupported tuplet");
+            return;
+        }
+        self.tuplet = Some(OpenTuplet {
+            start: self.position,
+            remaining: u32::from(remaining),
+            actual,
+            normal,
+        });
+    }
+
+    fn barline(&mut self, text: &str) {
+        if text.contains(':') {
+            self.warn("|:", "repeats are not supported");
+        }
+        self.bar_alters.clear();
+        if self.bars.last() != Some(&self.position) {
+            self.bars.push(self.position);
+        }
+    }
+
+    /// Read a line of the tune body
+    fn music(&mut self, chars: &[char]) {
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+
+            // inline fields such as [K:D] may switch voice so are read even when skipping
+            if c == '['
+                && chars.get(i + 2) == Some(&':')
+                && chars.get(i + 1).is_some_and(|c| c.is_ascii_alphabetic())
+            {
+                let end = chars[i..]
+                    .iter()
+                    .position(|c| *c == ']')
+                    .map_or(chars.len(), |end| i + end);
+                let value: String = chars[i + 3..end].iter().collect();
+                self.field(chars[i + 1], value.trim());
+                i = end + 1;
+                continue;
+            }
+            if c == '%' {
+                break;
+            }
+            if self.skipping {
+                i += 1;
+                continue;
+            }
+
+            match c {
+                '"' => {
+                    let end = chars[i + 1..]
+                        .iter()
+                        .position(|c| *c == '"')
+                        .map_or(chars.len(), |end| i + 1 + end);
+                    let text: String = chars[i + 1..end.min(chars.len())].iter().collect();
+                    if !text.starts_with(['^', '_', '<', '>', '@']) {
+                        self.warn("\"", "chord symbols are not imported");
+                    }
+                    i = end + 1;
+                }
+                '!' | '+' => {
+                    let end = chars[i + 1..]
+                        .iter()
+                        .position(|d| *d == c)
+                        .map_or(chars.len(), |end| i + 1 + end);
+                    let name: String = chars[i + 1..end.min(chars.len())].iter().collect();
+                    self.decoration(&name);
+                    i = end + 1;
+                }
+                '.' => {
+                    self.decoration(".");
+                    i += 1;
+                }
+                '~' | 'H' | 'L' | 'M' | 'O' | 'P' | 'S' | 'T' | 'u' | 'v' | 'J' | 'R' => {
+                    self.warn(&c.to_string(), "decoration not supported");
+                    i += 1;
+                }
+                '{' => {
+                    self.warn("{", "grace notes are left out");
+                    i = chars[i..]
+                        .iter()
+                        .position(|c| *c == '}')
+                        .map_or(chars.len(), |end| i + end + 1);
+                }
+                '(' => {
+                    i += 1;
+                    if chars.get(i).is_some_and(|c| c.is_ascii_digit()) {
+                        self.tuplet_start(chars, &mut i);
+                    }
+                }
+                '|' | ':' => {
+                    let start = i;
+                    while i < chars.len()
+                        && (matches!(chars[i], '|' | ':')
+                            || (chars[i] == ']' && chars[i - 1] == '|'))
+                    {
+                        i += 1;
+                    }
+                    let text: String = chars[start..i].iter().collect();
+                    self.barline(&text);
+                    if chars.get(i).is_some_and(|c| c.is_ascii_digit())
+                        || chars.get(i) == Some(&'[')
+                            && chars.get(i + 1).is_some_and(|c| c.is_ascii_digit())
+                    {
+                        self.warn("[1", "endings are not supported");
+                        while i < chars.len()
+                            && (chars[i].is_ascii_digit() || matches!(chars[i], '[' | ',' | '-'))
+                        {
+                            i += 1;
+                        }
+                    }
+                }
+                '[' if chars.get(i + 1) == Some(&'|') => {
+                    i += 2;
+                    self.barline("[|");
+                }
+                '[' if chars.get(i + 1).is_some_and(|c| c.is_ascii_digit()) => {
+                    self.warn("[1", "endings are not supported");
+                    i += 1;
+                    while chars
+                        .get(i)
+                        .is_some_and(|c| c.is_ascii_digit() || *c == ',' || *c == '-')
+                    {
+                        i += 1;
+                    }
+                }
+                '[' => {
+                    // a chord lasts as long as its first note
+                    i += 1;
+                    let mut pitches = Vec::new();
+                    let mut length = None;
+                    while i < chars.len() && chars[i] != ']' {
+                        let start = i;
+                        match self.pitch(chars, &mut i) {
+                            Some(pitch) => {
+                                let note_length = Tune::length(chars, &mut i);
+                                length.get_or_insert(note_length);
+                                let tie = chars.get(i) == Some(&'-');
+                                if tie {
+                                    i += 1;
+                                }
+                                pitches.push(ReadPitch { pitch, tie });
+                            }
+                            None => i = start + 1,
+                        }
+                    }
+                    i += 1;
+                    let length = length
+                        .unwrap_or_else(|| Fraction::new(1, 1))
+                        .mul(Tune::length(chars, &mut i));
+                    if chars.get(i) == Some(&'-') {
+                        i += 1;
+                        for pitch in pitches.iter_mut() {
+                            pitch.tie = true;
+                        }
+                    }
+                    if !pitches.is_empty() {
+                        self.note(pitches, length);
+                    }
+                }
+                'A'..='G' | 'a'..='g' | '^' | '_' | '=' => {
+                    let start = i;
+                    match self.pitch(chars, &mut i) {
+                        Some(pitch) => {
+                            let length = Tune::length(chars, &mut i);
+                            let tie = chars.get(i) == Some(&'-');
+                            if tie {
+                                i += 1;
+                            }
+                            self.note(vec![ReadPitch { pitch, tie }], length);
+                        }
+                        None => i = start + 1,
+                    }
+                }
+                'z' | 'x' => {
+                    i += 1;
+                    let length = Tune::length(chars, &mut i);
+                    self.note(Vec::new(), length);
+                }
+                'Z' | 'X' => {
+                    i += 1;
+                    let bars = Tune::length(chars, &mut i);
+                    match self.bar_length {
+                        Some(bar_length) => {
+                            for _ in 0..bars.numerator {
+                                self.position = self.position.add(bar_length);
+                            }
+                            self.ties.clear();
+                        }
+                        None => self.warn("Z", "multi-measure rests need a meter"),
+                    }
+                }
+                '>' | '<' => {
+                    let start = i;
+                    while chars.get(i) == Some(&c) {
+                        i += 1;
+                    }
+                    self.broken_rhythm((i - start) as u32, c == '>');
+                }
+                _ => i += 1,
+            }
+        }
+    }
+
+    /// Lay out the tune as a flow
+    fn finish(self) -> (Vec<String>, Vec<String>, Option<u8>, Movement) {
+        let mut movement = Movement {
+            title: self.titles.first().cloned(),
+            length: Fraction::zero(),
+            time_signatures: self.time_signatures,
+            key_signatures: self.key_signatures,
+            tempos: self.tempos,
+            parts: Vec::new(),
+        };
+        let mut tones = self.tones;
+        let mut tuplets = self.tuplets;
+        let mut clefs = self.clefs;
+        let mut end = self.position;
+        if let Some(bar) = self.bars.last() {
+            end = end.max(*bar);
+        }
+
+        // a short first bar is a pickup, which is written as a full bar
+        let first_bar = movement
+            .time_signatures
+            .first()
+            .filter(|time_signature| time_signature.start.is_zero());
+        let bar_length = first_bar.and_then(|time_signature| match time_signature.beats {
+            0 => None,
+            beats => Some(Fraction::new(
+                4 * u64::from(beats),
+                u64::from(time_signature.beat_type.to_int()),
+            )),
+        });
+        if let (Some(bar_length), Some(first)) = (bar_length, self.bars.first()) {
+            if !first.is_zero() && *first < bar_length {
+                self.warnings.push(
+                    "|",
+                    None,
+                    Some("1"),
+                    "the pickup bar is written as a full bar",
+                );
+                let offset = bar_length.sub(*first);
+                let shift = |start: &mut Fraction| {
+                    if !start.is_zero() {
+                        *start = start.add(offset);
+                    }
+                };
+                tones
+                    .iter_mut()
+                    .for_each(|tone| tone.start = tone.start.add(offset));
+                tuplets.iter_mut().for_each(|tuplet| {
+                    tuplet.start = tuplet.start.add(offset);
+                    tuplet.end = tuplet.end.add(offset);
+                });
+                clefs.iter_mut().for_each(|clef| shift(&mut clef.start));
+                movement
+                    .time_signatures
+                    .iter_mut()
+                    .for_each(|entry| shift(&mut entry.start));
+                movement
+                    .key_signatures
+                    .iter_mut()
+                    .for_each(|entry| shift(&mut entry.0));
+                movement
+                    .tempos
+                    .iter_mut()
+                    .for_each(|entry| shift(&mut entry.start));
+                end = end.add(offset);
+            }
+        }
+
+        // fill the last bar
+        let last = movement.time_signatures.last();
+        let mut length = last.map_or(Fraction::zero(), |time_signature| time_signature.start);
+        match last.filter(|time_signature| time_signature.beats > 0) {
+            Some(time_signature) => {
+                let bar_length = Fraction::new(
+                    4 * u64::from(time_signature.beats),
+                    u64::from(time_signature.beat_type.to_int()),
+                );
+                while length < end || length.is_zero() {
+                    length = length.add(bar_length);
+                }
+            }
+            None => length = end.max(Fraction::new(4, 1)),
+        }
+        movement.length = length;
+
+        let def = self
+            .program
+            .and_then(get_def_by_program)
+            .or_else(|| get_def("keyboard.piano"));
+        if let Some(def) = def {
+            movement.parts.push(PlannedPart {
+                id: String::from(PART_ID),
+                name: String::from(def.long_name),
+                def,
+                tones,
+                tuplets,
+                clefs,
+            });
+        }
+
+        (self.titles, self.composers, self.program, movement)
+    }
+}
+
+/// The lines of each tune, a tune starts at `X:` and ends at a blank line.
+/// Text without any `X:` is read as a single tune.
+fn tunes(text: &str) -> Vec<Vec<&str>> {
+    let lines: Vec<&str> = text.lines().collect();
+    if !lines.iter().any(|line| line.starts_with("X:")) {
+        return vec![lines];
+    }
+
+    let mut tunes = Vec::new();
+    let mut current: Option<Vec<&str>> = None;
+    for line in lines {
+        if line.starts_with("X:") {
+            tunes.extend(current.take());
+            current = Some(Vec::new());
+        } else if line.trim().is_empty() {
+            tunes.extend(current.take());
+        } else if let Some(tune) = &mut current {
+            tune.push(line);
+        }
+    }
+    tunes.extend(current);
+    tunes
+}
+
+/// Read ABC text into flows to create, each tune becomes a flow
+pub fn plan(text: &str) -> Result<Plan> {
+    let mut warnings = Warnings::default();
+    let mut meta = ImportedMeta::default();
+    let mut movements = Vec::new();
+
+    for lines in tunes(text) {
+        let mut tune = Tune::new(&mut warnings);
+        for line in lines {
+            tune.line(line);
+        }
+        let (titles, composers, _, movement) = tune.finish();
+        if movement.parts.iter().all(|part| part.tones.is_empty())
+            && movement.length <= Fraction::new(4, 1)
+            && titles.is_empty()
+        {
+            continue;
+        }
+
+        // the score takes its details from the first tune
+        if movements.is_empty() {
+            meta.title = titles.first().cloned();
+            meta.subtitle = titles.get(1).cloned();
+            if !composers.is_empty() {
+                meta.composer = Some(composers.join(", "));
+            }
+        }
+        movements.push(movement);
+    }
+
+    if movements.is_empty() {
+        return Err(invalid("has no tunes"));
+    }
+
+    Ok(Plan {
+        meta,
+        movements,
+        warnings: warnings.list,
+    })
+}
+
+impl Engine {
+    /// Import tunes written in ABC notation, each tune becomes a new flow played by a
+    /// single instrument.
+    ///
+    /// Returns the new flows and a list of warnings for anything that couldn't be imported.
+    pub fn import_abc(&mut self, text: &str) -> Result<ImportReport> {
+        let plan = plan(text)?;
+
+        self.begin_transaction(Some(String::from("Import ABC")));
+        // the listener is sent the whole state once we are done rather than every step
+        self.patches.resync();
+        let report = self.create_planned_flows(&plan);
+        self.commit()?;
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TUNE: &str = "X:1
+T:The Kesh
+T:Jig
+C:Trad.
+M:6/8
+L:1/8
+Q:3/8=100
+K:G
+%%MIDI program 73
+D|:G3 GAB|!p!A3 ABd|(3efg [GB]2 _B>c|e3 z3|Z:|
+w:la la
+";
+
+    #[test]
+    fn test_plan() {
+        let plan = plan(TUNE).unwrap();
+        assert_eq!(plan.meta.title, Some(String::from("The Kesh")));
+        assert_eq!(plan.meta.subtitle, Some(String::from("Jig")));
+        assert_eq!(plan.meta.composer, Some(String::from("Trad.")));
+
+        let movement = &plan.movements[0];
+        assert_eq!(movement.time_signatures[0].beats, 6);
+        assert_eq!(movement.key_signatures[0].1, 1);
+        assert_eq!(movement.tempos[0].bpm, 100);
+        assert_eq!(movement.tempos[0].dotted, 1);
+
+        // the D pickup is padded to a full bar, each bar is 3 quarters
+        let part = &movement.parts[0];
+        assert_eq!(part.def.id, "woodwinds.flute");
+        assert_eq!(part.tones[0].pitch, 62);
+        assert_eq!(part.tones[0].start, Fraction::new(5, 2));
+        assert_eq!(part.tones[1].start, Fraction::new(3, 1));
+        assert_eq!(part.tones[1].duration, Fraction::new(3, 2));
+
+        // F is sharp in G, the dynamic sets the velocity
+        let a = part
+            .tones
+            .iter()
+            .find(|tone| tone.start == Fraction::new(6, 1))
+            .unwrap();
+        assert_eq!(a.pitch, 69);
+        assert_eq!(a.velocity, 50);
+
+        // a triplet, a chord and a dotted pair
+        assert_eq!(part.tuplets.len(), 1);
+        assert_eq!(part.tuplets[0].start, Fraction::new(9, 1));
+        assert_eq!(part.tuplets[0].end, Fraction::new(10, 1));
+        let chord: Vec<u8> = part
+            .tones
+            .iter()
+            .filter(|tone| tone.start == Fraction::new(10, 1))
+            .map(|tone| tone.pitch)
+            .collect();
+        assert_eq!(chord, vec![67, 71]);
+        let b = part.tones.iter().find(|tone| tone.pitch == 70).unwrap();
+        assert_eq!(b.duration, Fraction::new(3, 4));
+
+        // the multi-bar rest fills a bar, the length is rounded to whole bars
+        assert_eq!(movement.length, Fraction::new(18, 1));
+        let elements: Vec<&str> = plan
+            .warnings
+            .iter()
+            .map(|warning| warning.element.as_str())
+            .collect();
+        assert!(elements.contains(&"|:"));
+        assert!(elements.contains(&"w:"));
+    }
+
+    #[test]
+    fn test_ties_and_accidentals() {
+        let tune = plan("M:4/4\nL:1/4\nK:F\n^c c B- B | c2 [C2E2]- [CE] z|").unwrap();
+        let part = &tune.movements[0].parts[0];
+        let tones: Vec<(u8, Fraction)> = part
+            .tones
+            .iter()
+            .map(|tone| (tone.pitch, tone.duration))
+            .collect();
+        assert_eq!(
+            tones,
+            vec![
+                (73, Fraction::new(1, 1)),
+                (73, Fraction::new(1, 1)),
+                (70, Fraction::new(2, 1)),
+                (72, Fraction::new(2, 1)),
+                (60, Fraction::new(3, 1)),
+                (64, Fraction::new(3, 1)),
+            ]
+        );
+        assert_eq!(tune.movements[0].key_signatures[0].1, -1);
+        assert!(plan("").is_err());
+    }
+}
@@ -0,0 +1,93 @@
+pub mod export;
+pub mod import;
+
+use crate::state::entries::key_signature::KeySignatureMode;
+
+/// The note letters in order from C, as they are indexed below
+pub const LETTERS: [char; 7] = ['C', 'D', 'E', 'F', 'G', 'A', 'B'];
+
+/// The semitones of each letter above C
+pub const STEPS: [u8; 7] = [0, 2, 4, 5, 7, 9, 11];
+
+/// The position of each letter in the circle of fifths, from C
+const FIFTHS: [i8; 7] = [0, 2, 4, -1, 1, 3, 5];
+
+/// The alteration of each letter (C to B) in a key with `fifths` sharps (+) or flats (-)
+pub fn key_alters(fifths: i8) -> [i8; 7] {
+    let mut alters = [0; 7];
+    for (i, fifth) in FIFTHS.iter().enumerate() {
+        // sharps are added from F (-1) and flats from B (5)
+        if fifths > 0 && *fifth < fifths - 1 {
+            alters[i] = 1;
+        }
+        if fifths < 0 && *fifth > 5 + fifths {
+            alters[i] = -1;
+        }
+    }
+    alters
+}
+
+/// The number of sharps (+) or flats (-) of the major key on a tonic, eg. ("B", -1) => -2
+pub fn tonic_fifths(letter: char, alter: i8) -> Option<i8> {
+    let i = LETTERS
+        .iter()
+        .position(|l| *l == letter.to_ascii_uppercase())?;
+    Some(FIFTHS[i] + alter * 7)
+}
+
+/// The ABC name of a key signature, eg. "Bb" or "F#m"
+pub fn key_name(offset: i8, mode: KeySignatureMode) -> String {
+    let fifths = match mode {
+        KeySignatureMode::Major => offset,
+        // the relative minor is three fifths below
+        KeySignatureMode::Minor => offset + 3,
+        KeySignatureMode::Open => return String::from("none"),
+    };
+    let i = FIFTHS
+        .iter()
+        .position(|fifth| (fifths - fifth) % 7 == 0)
+        .unwrap_or(0);
+    let accidental = match (fifths - FIFTHS[i]) / 7 {
+        2 => "##",
+        1 => "#",
+        -1 => "b",
+        -2 => "bb",
+        _ => "",
+    };
+    let mode = match mode {
+        KeySignatureMode::Minor => "m",
+        _ => "",
+    };
+    format!("{}{}{}", LETTERS[i], accidental, mode)
+}
+
+/// An ABC note length multiplier, eg. 3/2, omitting a numerator or denominator of 1
+pub fn length(numerator: u32, denominator: u32) -> String {
+    match (numerator, denominator) {
+        (1, 1) => String::new(),
+        (numerator, 1) => numerator.to_string(),
+        (1, 2) => String::from("/"),
+        (1, denominator) => format!("/{}", denominator),
+        (numerator, denominator) => format!("{}/{}", numerator, denominator),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys() {
+        assert_eq!(key_alters(0), [0; 7]);
+        assert_eq!(key_alters(2), [1, 0, 0, 1, 0, 0, 0]);
+        assert_eq!(key_alters(-3), [0, 0, -1, 0, 0, -1, -1]);
+        assert_eq!(tonic_fifths('B', -1), Some(-2));
+        assert_eq!(tonic_fifths('f', 1), Some(6));
+        assert_eq!(key_name(-2, KeySignatureMode::Major), "Bb");
+        assert_eq!(key_name(3, KeySignatureMode::Minor), "F#m");
+        assert_eq!(key_name(0, KeySignatureMode::Minor), "Am");
+        assert_eq!(length(3, 2), "3/2");
+        assert_eq!(length(1, 4), "/4");
+        assert_eq!(length(2, 1), "2");
+    }
+}
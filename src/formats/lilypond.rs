@@ -0,0 +1,807 @@
+use crate::formats::{part_name, untokenize};
+use crate::state::entries::absolute_tempo::AbsoluteTempo;
+use crate::state::entries::barline::BarlineType;
+use crate::state::entries::clef::{Clef, ClefDrawType};
+use crate::state::entries::key_signature::KeySignatureMode;
+use crate::state::entries::time_signature::{TimeSignature, TimeSignatureDrawType};
+use crate::state::entries::tone::{Articulation, Tone};
+use crate::state::entries::tuplet::Tuplet;
+use crate::state::entries::Entry;
+use crate::state::score::engrave::{Bracketing, Engrave, LayoutType};
+use crate::state::score::flow::Flow;
+use crate::state::score::instrument::defs::get_def;
+use crate::state::score::instrument::Instrument;
+use crate::state::score::notation::{measures, notate, Notation};
+use crate::state::score::stave::Stave;
+use crate::state::score::track::Track;
+use crate::state::score::Score;
+use crate::state::Engine;
+use crate::utils::duration::WrittenDuration;
+use crate::utils::pitch::{Accidental, Pitch};
+use std::collections::HashMap;
+use std::ops::Range;
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+/// The LilyPond version the output is written for
+const VERSION: &str = "2.24.0";
+
+/// Builds the document a line at a time, indenting nested blocks
+struct Writer {
+    out: String,
+    depth: usize,
+}
+
+impl Writer {
+    fn new() -> Self {
+        Self {
+            out: String::new(),
+            depth: 0,
+        }
+    }
+
+    fn line(&mut self, text: &str) {
+        if !text.is_empty() {
+            self.out.push_str(&"  ".repeat(self.depth));
+            self.out.push_str(text);
+        }
+        self.out.push('\n');
+    }
+
+    /// Write a line that opens a block
+    fn open(&mut self, text: &str) {
+        self.line(text);
+        self.depth += 1;
+    }
+
+    /// Write a line that closes a block
+    fn close(&mut self, text: &str) {
+        self.depth = self.depth.saturating_sub(1);
+        self.line(text);
+    }
+
+    /// Write a line that closes one block and opens another, eg. `} <<`
+    fn reopen(&mut self, text: &str) {
+        self.close(text);
+        self.depth += 1;
+    }
+
+    fn finish(self) -> String {
+        self.out
+    }
+}
+
+/// A LilyPond string, with accidental tokens written as symbols
+fn quote(text: &str) -> String {
+    let text = untokenize(text).replace('\\', "\\\\").replace('"', "\\\"");
+    format!("\"{}\"", text)
+}
+
+/// The note name of a pitch in the default (Dutch) note names, eg. bes'
+fn note_name(pitch: &Pitch) -> String {
+    let pitch = pitch.written();
+    let suffix = match pitch.accidental {
+        Accidental::DoubleSharp => "isis",
+        Accidental::Sharp => "is",
+        Accidental::Natural => "",
+        Accidental::Flat => "es",
+        Accidental::DoubleFlat => "eses",
+    };
+    // c with no marks is the octave below middle C
+    let octave = i32::from(pitch.octave()) - 3;
+    let marks = if octave > 0 {
+        "'".repeat(octave as usize)
+    } else {
+        ",".repeat(octave.unsigned_abs() as usize)
+    };
+    format!("{}{}{}", pitch.letter().to_lowercase(), suffix, marks)
+}
+
+fn duration(duration: &WrittenDuration) -> String {
+    format!(
+        "{}{}",
+        duration.base.to_int(),
+        ".".repeat(duration.dots as usize)
+    )
+}
+
+fn articulation(articulation: Articulation) -> &'static str {
+    match articulation {
+        Articulation::None => "",
+        Articulation::Staccato => "-.",
+        Articulation::Staccatissimo => "-!",
+        Articulation::Tenuto => "--",
+        Articulation::StaccatoTenuto => "-_",
+    }
+}
+
+fn key(offset: i8, mode: KeySignatureMode) -> String {
+    const MAJOR: [&str; 15] = [
+        "ces", "ges", "des", "aes", "ees", "bes", "f", "c", "g", "d", "a", "e", "b", "fis", "cis",
+    ];
+    const MINOR: [&str; 15] = [
+        "aes", "ees", "bes", "f", "c", "g", "d", "a", "e", "b", "fis", "cis", "gis", "dis", "ais",
+    ];
+    let i = (offset.clamp(-7, 7) + 7) as usize;
+    match mode {
+        KeySignatureMode::Major => format!("\\key {} \\major", MAJOR[i]),
+        KeySignatureMode::Minor => format!("\\key {} \\minor", MINOR[i]),
+        // an open key has no accidentals
+        KeySignatureMode::Open => String::from("\\key c \\major"),
+    }
+}
+
+/// A metered time signature, open time is written as a cadenza
+fn time(time_signature: &TimeSignature) -> String {
+    let beats = time_signature.beats;
+    let fraction = format!("{}/{}", beats, time_signature.beat_type.to_int());
+
+    // complex time signatures keep their groupings
+    let groupings = &time_signature.groupings;
+    let complex = beats > 4 && beats % 3 != 0;
+    let fraction = if complex
+        && groupings.len() > 1
+        && groupings.iter().map(|beats| u32::from(*beats)).sum::<u32>() == u32::from(beats)
+    {
+        let groupings: Vec<String> = groupings.iter().map(|beats| beats.to_string()).collect();
+        format!("{} {}", groupings.join(","), fraction)
+    } else {
+        fraction
+    };
+
+    match time_signature.draw_type {
+        TimeSignatureDrawType::Hidden => {
+            format!("\\once \\omit Staff.TimeSignature \\time {}", fraction)
+        }
+        TimeSignatureDrawType::Normal => format!("\\numericTimeSignature \\time {}", fraction),
+        TimeSignatureDrawType::CommonTime | TimeSignatureDrawType::SplitCommonTime => {
+            format!("\\defaultTimeSignature \\time {}", fraction)
+        }
+    }
+}
+
+fn clef(clef: &Clef, stave: &Stave) -> Option<String> {
+    // the offset is in half spaces from the middle of the stave
+    let lines = stave.lines.iter().filter(|line| **line == 1).count() as i8;
+    let line = (lines + 1) / 2 + clef.offset / 2;
+
+    let (name, pitch) = match (clef.draw_as, line) {
+        (ClefDrawType::Hidden, _) => return None,
+        (ClefDrawType::Percussion, _) => return Some(String::from("\\clef percussion")),
+        (ClefDrawType::G, 1) => ("french", 67),
+        (ClefDrawType::G, _) => ("treble", 67),
+        (ClefDrawType::F, 3) => ("varbaritone", 53),
+        (ClefDrawType::F, 5) => ("subbass", 53),
+        (ClefDrawType::F, _) => ("bass", 53),
+        (ClefDrawType::C, 1) => ("soprano", 60),
+        (ClefDrawType::C, 2) => ("mezzosoprano", 60),
+        (ClefDrawType::C, 4) => ("tenor", 60),
+        (ClefDrawType::C, 5) => ("baritone", 60),
+        (ClefDrawType::C, _) => ("alto", 60),
+    };
+
+    // octave clefs, eg. the tenor G clef
+    let octaves = (i16::from(clef.pitch.int) - pitch) / 12;
+    Some(match octaves {
+        0 => format!("\\clef {}", name),
+        octaves if octaves < 0 => format!("\\clef \"{}_{}\"", name, 1 + 7 * octaves.abs()),
+        octaves => format!("\\clef \"{}^{}\"", name, 1 + 7 * octaves),
+    })
+}
+
+fn tempo(tempo: &AbsoluteTempo) -> String {
+    let beat = format!(
+        "{}{}",
+        tempo.beat_type.to_int(),
+        ".".repeat(tempo.dotted as usize)
+    );
+    // LilyPond puts the metronome mark in brackets when there is text, even if it is empty
+    let text = if tempo.text_visible && !tempo.text.is_empty() {
+        Some(quote(&tempo.text))
+    } else if tempo.parenthesis_visible {
+        Some(String::from("\"\""))
+    } else {
+        None
+    };
+
+    // without a printed metronome mark, still set the tempo for playback
+    let playback = format!(
+        "\\set Score.tempoWholesPerMinute = #(ly:make-moment {}/4)",
+        tempo.normalized_bpm.round()
+    );
+    match (text, tempo.bpm_visible) {
+        (Some(text), true) => format!("\\tempo {} {} = {}", text, beat, tempo.bpm),
+        (None, true) => format!("\\tempo {} = {}", beat, tempo.bpm),
+        (Some(text), false) if tempo.text_visible => format!("\\tempo {} {}", text, playback),
+        _ => playback,
+    }
+}
+
+fn barline(barline_type: BarlineType) -> &'static str {
+    match barline_type {
+        BarlineType::Normal => "\\bar \"|\"",
+        BarlineType::Double => "\\bar \"||\"",
+        BarlineType::Final => "\\bar \"|.\"",
+        BarlineType::StartRepeat => "\\bar \".|:\"",
+        BarlineType::EndRepeat => "\\bar \":|.\"",
+        BarlineType::EndStartRepeat => "\\bar \":..:\"",
+    }
+}
+
+/// An instrument with its name as it is shown in the score
+struct Part<'a> {
+    instrument: &'a Instrument,
+    family: &'a str,
+    name: String,
+    short_name: String,
+    staves: Vec<&'a Stave>,
+}
+
+/// Collect the instruments in the flow in score order
+fn parts<'a>(score: &'a Score, flow: &'a Flow) -> Vec<Part<'a>> {
+    let mut parts = Vec::new();
+    for player_key in score.players.order.iter() {
+        if !flow.players.contains(player_key) {
+            continue;
+        }
+        let player = match score.players.by_key.get(player_key) {
+            Some(player) => player,
+            None => continue,
+        };
+        let style = score.config.auto_count.style(player.player_type);
+        for instrument_key in player.instruments.iter() {
+            let instrument = match score.instruments.get(instrument_key) {
+                Some(instrument) => instrument,
+                None => continue,
+            };
+            parts.push(Part {
+                instrument,
+                family: get_def(&instrument.id).map_or("", |def| def.path[0]),
+                name: part_name(&instrument.long_name, instrument.count, style),
+                short_name: part_name(&instrument.short_name, instrument.count, style),
+                staves: instrument
+                    .staves
+                    .iter()
+                    .filter_map(|stave_key| flow.staves.get(stave_key))
+                    .collect(),
+            });
+        }
+    }
+    parts
+}
+
+/// A run of parts, which is bracketed if `bracket` is set
+#[derive(Debug, PartialEq)]
+struct Group {
+    bracket: bool,
+    range: Range<usize>,
+}
+
+/// Split parts, described as (family, name, stave count), into brackets and sub-brackets.
+///
+/// Orchestral bracketing brackets each family. Small ensembles only bracket a family
+/// when there are more than two of them, eg. a string quartet but not a pair of flutes.
+/// Within a bracket, adjacent numbered instruments of the same name are sub-bracketed.
+fn brackets(parts: &[(&str, &str, usize)], engrave: &Engrave) -> Vec<(Group, Vec<Group>)> {
+    // runs of adjacent items with the same key
+    let runs = |range: Range<usize>, same: &dyn Fn(usize, usize) -> bool| {
+        let mut out: Vec<Range<usize>> = Vec::new();
+        for i in range {
+            match out.last_mut() {
+                Some(run) if same(run.start, i) => run.end = i + 1,
+                _ => out.push(i..i + 1),
+            }
+        }
+        out
+    };
+
+    let families = match engrave.bracketing {
+        Bracketing::None => runs(0..parts.len(), &|_, _| false),
+        _ => runs(0..parts.len(), &|a, b| parts[a].0 == parts[b].0),
+    };
+
+    families
+        .into_iter()
+        .map(|range| {
+            let count = range.len();
+            let single_stave = count == 1 && parts[range.start].2 == 1;
+            let bracket = match engrave.bracketing {
+                Bracketing::None => false,
+                Bracketing::Orchestral => {
+                    count > 1 || (single_stave && engrave.bracket_single_staves)
+                }
+                Bracketing::SmallEnsemble => count > 2,
+            };
+
+            let subs = runs(range.clone(), &|a, b| parts[a].1 == parts[b].1)
+                .into_iter()
+                .map(|sub| Group {
+                    bracket: bracket && engrave.sub_bracket && sub.len() > 1 && sub.len() < count,
+                    range: sub,
+                })
+                .collect();
+            (Group { bracket, range }, subs)
+        })
+        .collect()
+}
+
+/// The commands written before the notes in a voice, as (tick, command)
+fn commands(flow: &Flow, stave: &Stave, first_voice: bool, conductor: bool) -> Vec<(u32, String)> {
+    let mut out = Vec::new();
+    if first_voice {
+        for entry in stave.master.entries.by_key.values() {
+            if let Entry::Clef(entry) = entry {
+                if let Some(command) = clef(entry, stave) {
+                    out.push((entry.tick, command));
+                }
+            }
+        }
+        for entry in flow.master.entries.by_key.values() {
+            match entry {
+                Entry::KeySignature(entry) => out.push((entry.tick, key(entry.offset, entry.mode))),
+                Entry::AbsoluteTempo(entry) if conductor => out.push((entry.tick, tempo(entry))),
+                _ => (),
+            }
+        }
+    }
+    // clefs, then keys, then tempos at the same tick
+    out.sort_by_key(|(tick, _)| *tick);
+    out
+}
+
+/// The master entries at a tick
+fn master_at(master: &Track, tick: u32) -> impl Iterator<Item = &Entry> {
+    master
+        .entries
+        .by_tick
+        .get(&tick)
+        .into_iter()
+        .flatten()
+        .filter_map(move |key| master.entries.by_key.get(key))
+}
+
+/// Write a note, chord or rest
+fn notation(
+    tokens: &mut Vec<String>,
+    track: &Track,
+    notation: &Notation,
+    tied_from: bool,
+    measure_rest: bool,
+) {
+    let mut tones: Vec<&Tone> = notation
+        .tones
+        .iter()
+        .filter_map(|tone_key| match track.entries.by_key.get(tone_key) {
+            Some(Entry::Tone(tone)) => Some(tone),
+            _ => None,
+        })
+        .collect();
+    tones.sort_by_key(|tone| tone.pitch.int);
+
+    let written = duration(&notation.duration);
+    let mut token = match tones.len() {
+        0 if measure_rest => format!("R{}", written),
+        0 => format!("r{}", written),
+        1 => format!("{}{}", note_name(&tones[0].pitch), written),
+        _ => {
+            let names: Vec<String> = tones.iter().map(|tone| note_name(&tone.pitch)).collect();
+            format!("<{}>{}", names.join(" "), written)
+        }
+    };
+    if !tones.is_empty() {
+        // articulations are only written at the start of tied notes
+        if !tied_from {
+            if let Some(tone) = tones
+                .iter()
+                .find(|tone| !matches!(tone.articulation, Articulation::None))
+            {
+                token.push_str(articulation(tone.articulation));
+            }
+        }
+        if notation.tie {
+            token.push_str(" ~");
+        }
+    }
+    tokens.push(token);
+}
+
+/// Write a voice bar by bar, one line per bar
+fn write_voice(
+    ly: &mut Writer,
+    flow: &Flow,
+    track: &Track,
+    commands: &[(u32, String)],
+    first_voice: bool,
+    last_flow_barline: BarlineType,
+) {
+    let notations = notate(track, &flow.master, flow.length, flow.subdivisions);
+    let tuplets: HashMap<&String, &Tuplet> = track
+        .get_tuplets()
+        .into_iter()
+        .map(|tuplet| (&tuplet.key, tuplet))
+        .collect();
+
+    let mut cursor = 0;
+    let mut command = 0;
+    let mut open_time = false;
+    for (start, end) in measures(&flow.master, flow.length, flow.subdivisions) {
+        let mut tokens: Vec<String> = Vec::new();
+
+        if first_voice {
+            for entry in master_at(&flow.master, start) {
+                match entry {
+                    Entry::TimeSignature(time_signature) if time_signature.beats == 0 => {
+                        if !open_time {
+                            tokens.push(String::from("\\cadenzaOn"));
+                        }
+                        open_time = true;
+                    }
+                    Entry::TimeSignature(time_signature) => {
+                        if open_time {
+                            tokens.push(String::from("\\cadenzaOff"));
+                        }
+                        open_time = false;
+                        tokens.push(time(time_signature));
+                        if let Some((beats, beat_type)) =
+                            time_signature.pickup_beats(flow.subdivisions)
+                        {
+                            tokens.push(format!("\\partial {}*{}", beat_type.to_int(), beats));
+                        }
+                    }
+                    Entry::Barline(entry) => {
+                        if let BarlineType::StartRepeat = entry.barline_type {
+                            tokens.push(String::from(barline(entry.barline_type)));
+                        }
+                    }
+                    _ => (),
+                }
+            }
+        } else {
+            open_time = master_at(&flow.master, start).fold(open_time, |open, entry| match entry {
+                Entry::TimeSignature(time_signature) => time_signature.beats == 0,
+                _ => open,
+            });
+        }
+
+        let mut tuplet: Option<&String> = None;
+        while let Some(current) = notations.get(cursor) {
+            if current.tick >= end {
+                break;
+            }
+            while let Some((tick, text)) = commands.get(command) {
+                if *tick > current.tick {
+                    break;
+                }
+                tokens.push(text.clone());
+                command += 1;
+            }
+
+            if tuplet.is_some() && current.tuplet.as_ref() != tuplet {
+                tokens.push(String::from("}"));
+                tuplet = None;
+            }
+            if let Some(tuplet_key) = &current.tuplet {
+                if tuplet.is_none() {
+                    if let Some(entry) = tuplets.get(tuplet_key) {
+                        tokens.push(format!("\\tuplet {}/{} {{", entry.actual, entry.normal));
+                        tuplet = Some(tuplet_key);
+                    }
+                }
+            }
+
+            let tied_from = cursor > 0 && notations[cursor - 1].tie;
+            let measure_rest = !open_time && current.tick == start && current.length == end - start;
+            notation(&mut tokens, track, current, tied_from, measure_rest);
+            cursor += 1;
+        }
+        if tuplet.is_some() {
+            tokens.push(String::from("}"));
+        }
+
+        // the bar line, there are no bar checks in open time
+        let barline_type = master_at(&flow.master, end).find_map(|entry| match entry {
+            Entry::Barline(entry) => match entry.barline_type {
+                BarlineType::StartRepeat => None,
+                barline_type => Some(barline_type),
+            },
+            _ => None,
+        });
+        match barline_type {
+            Some(barline_type) => tokens.push(String::from(barline(barline_type))),
+            None if end == flow.length => tokens.push(String::from(barline(last_flow_barline))),
+            None if open_time => tokens.push(String::from(barline(BarlineType::Normal))),
+            None => (),
+        }
+        if !open_time {
+            tokens.push(String::from("|"));
+        }
+        ly.line(&tokens.join(" "));
+    }
+}
+
+fn write_staff(
+    ly: &mut Writer,
+    flow: &Flow,
+    part: &Part,
+    stave: &Stave,
+    engrave: &Engrave,
+    names: bool,
+    conductor: bool,
+) {
+    let lines = stave.lines.iter().filter(|line| **line == 1).count();
+    if names || lines != 5 {
+        ly.open("\\new Staff \\with {");
+        if names {
+            ly.line(&format!("instrumentName = {}", quote(&part.name)));
+            ly.line(&format!(
+                "shortInstrumentName = {}",
+                quote(&part.short_name)
+            ));
+        }
+        if lines != 5 {
+            ly.line(&format!("\\override StaffSymbol.line-count = #{}", lines));
+        }
+        ly.reopen("} <<");
+    } else {
+        ly.open("\\new Staff <<");
+    }
+
+    let tracks: Vec<&Track> = stave
+        .tracks
+        .iter()
+        .filter_map(|track_key| flow.tracks.get(track_key))
+        .collect();
+    const VOICES: [&str; 4] = ["\\voiceOne", "\\voiceTwo", "\\voiceThree", "\\voiceFour"];
+    for (i, track) in tracks.iter().enumerate() {
+        ly.open("\\new Voice {");
+        if tracks.len() > 1 {
+            if let Some(voice) = VOICES.get(i) {
+                ly.line(voice);
+            }
+        }
+        let commands = commands(flow, stave, i == 0, conductor);
+        write_voice(
+            ly,
+            flow,
+            track,
+            &commands,
+            i == 0,
+            engrave.final_barline_type,
+        );
+        ly.close("}");
+    }
+
+    ly.close(">>");
+}
+
+/// Instruments with more than one stave are braced together with a single name
+fn write_part(ly: &mut Writer, flow: &Flow, part: &Part, engrave: &Engrave, conductor: bool) {
+    if part.staves.len() == 1 {
+        write_staff(ly, flow, part, part.staves[0], engrave, true, conductor);
+        return;
+    }
+
+    ly.open("\\new PianoStaff \\with {");
+    ly.line(&format!("instrumentName = {}", quote(&part.name)));
+    ly.line(&format!(
+        "shortInstrumentName = {}",
+        quote(&part.short_name)
+    ));
+    ly.reopen("} <<");
+    for (i, stave) in part.staves.iter().enumerate() {
+        write_staff(ly, flow, part, stave, engrave, false, conductor && i == 0);
+    }
+    ly.close(">>");
+}
+
+fn write_header(ly: &mut Writer, score: &Score) {
+    let meta = &score.meta;
+    let fields = [
+        ("title", &meta.title),
+        ("subtitle", &meta.subtitle),
+        ("composer", &meta.composer),
+        ("arranger", &meta.arranger),
+        ("poet", &meta.lyricist),
+        ("copyright", &meta.copyright),
+    ];
+
+    ly.open("\\header {");
+    for (name, value) in fields.iter() {
+        if !value.is_empty() {
+            ly.line(&format!("{} = {}", name, quote(value)));
+        }
+    }
+    ly.line("tagline = ##f");
+    ly.close("}");
+}
+
+fn write_score(ly: &mut Writer, score: &Score, flow: &Flow, engrave: &Engrave) {
+    let parts = parts(score, flow);
+    let described: Vec<(&str, &str, usize)> = parts
+        .iter()
+        .map(|part| {
+            (
+                part.family,
+                part.instrument.long_name.as_str(),
+                part.staves.len(),
+            )
+        })
+        .collect();
+
+    ly.open("\\score {");
+    ly.open("<<");
+    let mut conductor = true;
+    for (group, subs) in brackets(&described, engrave) {
+        if group.bracket {
+            ly.open("\\new StaffGroup <<");
+        }
+        for sub in subs {
+            if sub.bracket {
+                ly.open("\\new StaffGroup \\with {");
+                ly.line("systemStartDelimiter = #'SystemStartSquare");
+                ly.reopen("} <<");
+            }
+            for part in parts[sub.range]
+                .iter()
+                .filter(|part| !part.staves.is_empty())
+            {
+                // tempo marks are written once, in the top stave
+                write_part(ly, flow, part, engrave, conductor);
+                conductor = false;
+            }
+            if sub.bracket {
+                ly.close(">>");
+            }
+        }
+        if group.bracket {
+            ly.close(">>");
+        }
+    }
+    ly.close(">>");
+
+    if !flow.title.is_empty() {
+        ly.open("\\header {");
+        ly.line(&format!("piece = {}", quote(&flow.title)));
+        ly.close("}");
+    }
+    ly.line("\\layout { }");
+    ly.close("}");
+}
+
+/// Write the score as a LilyPond document with a `\score` for each flow
+pub fn export(score: &Score) -> String {
+    let default = Engrave::new(LayoutType::Score, String::from("Score"));
+    let engrave = score
+        .engrave
+        .values()
+        .find(|engrave| matches!(engrave.layout_type, LayoutType::Score))
+        .unwrap_or(&default);
+
+    let mut ly = Writer::new();
+    ly.line(&format!("\\version \"{}\"", VERSION));
+    ly.line("");
+    write_header(&mut ly, score);
+    for flow_key in score.flows.order.iter() {
+        if let Some(flow) = score.flows.by_key.get(flow_key) {
+            ly.line("");
+            write_score(&mut ly, score, flow, engrave);
+        }
+    }
+    ly.finish()
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl Engine {
+    /// Export the score as a LilyPond (.ly) document
+    pub fn export_lilypond(&self) -> String {
+        export(&self.state.score)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::score::config::AutoCountStyle;
+
+    fn score() -> Score {
+        serde_json::from_str(include_str!("../../tests/fixtures/score_v2.json")).unwrap()
+    }
+
+    #[test]
+    fn test_export() {
+        let ly = export(&score());
+
+        assert!(ly.starts_with("\\version \"2.24.0\""));
+        assert!(ly.contains("title = \"Sonata\""));
+        assert!(ly.contains("composer = \"A. Composer\""));
+        assert!(ly.contains("piece = \"Allegro\""));
+        assert!(ly.contains("instrumentName = \"Violin\""));
+        assert!(ly.contains("\\clef treble"));
+        assert!(ly.contains("\\tempo \"Allegro\" 4 = 120"));
+
+        // open time, then the A and the Bb (stored with an invalid spelling)
+        assert!(ly.contains(
+            "\\cadenzaOn \\clef treble \\tempo \"Allegro\" 4 = 120 a'4 bes'4-. r2 \\bar \"|.\""
+        ));
+    }
+
+    #[test]
+    fn test_pickup() {
+        let mut score = score();
+        let flow = score.flows.by_key.get_mut("flow0000000001").unwrap();
+        for entry in flow.master.entries.by_key.values_mut() {
+            if let Entry::TimeSignature(time_signature) = entry {
+                time_signature.beats = 4;
+                time_signature.draw_type = TimeSignatureDrawType::Normal;
+                time_signature.pickup = 16;
+            }
+        }
+        let ly = export(&score);
+        assert!(ly.contains("\\numericTimeSignature \\time 4/4 \\partial 4*1 "));
+    }
+
+    #[test]
+    fn test_brackets() {
+        let engrave = Engrave {
+            bracketing: Bracketing::Orchestral,
+            sub_bracket: true,
+            bracket_single_staves: false,
+            ..score().engrave["engraveScore01"].clone()
+        };
+        let parts = [
+            ("Woodwinds", "Flute", 1),
+            ("Keyboards", "Piano", 2),
+            ("Strings", "Violin", 1),
+            ("Strings", "Violin", 1),
+            ("Strings", "Viola", 1),
+        ];
+        let groups = brackets(&parts, &engrave);
+        assert_eq!(groups.len(), 3);
+        assert!(!groups[0].0.bracket);
+        assert!(!groups[1].0.bracket);
+        assert_eq!(
+            groups[2].0,
+            Group {
+                bracket: true,
+                range: 2..5
+            }
+        );
+        assert_eq!(
+            groups[2].1,
+            vec![
+                Group {
+                    bracket: true,
+                    range: 2..4
+                },
+                Group {
+                    bracket: false,
+                    range: 4..5
+                },
+            ]
+        );
+
+        let engrave = Engrave {
+            bracketing: Bracketing::None,
+            ..engrave
+        };
+        assert!(brackets(&parts, &engrave)
+            .iter()
+            .all(|(group, _)| !group.bracket));
+    }
+
+    #[test]
+    fn test_names() {
+        assert_eq!(note_name(&Pitch::new(60, Accidental::Natural)), "c'");
+        assert_eq!(note_name(&Pitch::new(70, Accidental::Flat)), "bes'");
+        assert_eq!(note_name(&Pitch::new(42, Accidental::Sharp)), "fis,");
+        assert_eq!(
+            part_name("Violin", Some(2), AutoCountStyle::Roman),
+            "Violin II"
+        );
+        assert_eq!(
+            part_name("Horn in F", Some(4), AutoCountStyle::Arabic),
+            "Horn in F 4"
+        );
+        assert_eq!(key(-2, KeySignatureMode::Minor), "\\key g \\minor");
+    }
+}
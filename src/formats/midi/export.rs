@@ -0,0 +1,361 @@
+use crate::error::{Error, Result};
+use crate::formats::midi::{assign_channel, write_chunk, write_vlq, PPQ};
+use crate::state::entries::key_signature::KeySignatureMode;
+use crate::state::entries::Entry;
+use crate::state::score::flow::Flow;
+use crate::state::score::instrument::defs::get_program;
+use crate::state::score::instrument::Instrument;
+use crate::state::score::Score;
+use crate::state::Engine;
+use crate::utils::duration::NoteDuration;
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+// meta event types
+const META_MARKER: u8 = 0x06;
+const META_TRACK_NAME: u8 = 0x03;
+const META_END_OF_TRACK: u8 = 0x2F;
+const META_TEMPO: u8 = 0x51;
+const META_TIME_SIGNATURE: u8 = 0x58;
+const META_KEY_SIGNATURE: u8 = 0x59;
+
+// the order events are written in when they fall on the same tick
+const ORDER_META: u8 = 0;
+const ORDER_CONTROL: u8 = 1;
+const ORDER_NOTE_OFF: u8 = 2;
+const ORDER_NOTE_ON: u8 = 3;
+
+struct Event {
+    tick: u32,
+    order: u8,
+    data: Vec<u8>,
+}
+
+#[derive(Default)]
+struct MidiTrack {
+    events: Vec<Event>,
+}
+
+impl MidiTrack {
+    fn push(&mut self, tick: u32, order: u8, data: Vec<u8>) {
+        self.events.push(Event { tick, order, data });
+    }
+
+    fn meta(&mut self, tick: u32, kind: u8, data: &[u8]) {
+        let mut bytes = vec![0xFF, kind];
+        write_vlq(&mut bytes, data.len() as u32);
+        bytes.extend(data);
+        self.push(tick, ORDER_META, bytes);
+    }
+
+    /// Write the track chunk, events are written as the time since the previous event
+    fn into_bytes(mut self) -> Vec<u8> {
+        self.events.sort_by_key(|event| (event.tick, event.order));
+
+        let mut data = Vec::new();
+        let mut last = 0;
+        for event in self.events.iter() {
+            write_vlq(&mut data, event.tick - last);
+            data.extend(&event.data);
+            last = event.tick;
+        }
+        data.extend(&[0x00, 0xFF, META_END_OF_TRACK, 0x00]);
+
+        let mut out = Vec::new();
+        write_chunk(&mut out, b"MTrk", &data);
+        out
+    }
+}
+
+/// Each engine track becomes a MIDI track. When exporting several flows the
+/// tracks are matched up by their instrument, stave and position in the stave.
+struct Lane<'a> {
+    stave_key: &'a str,
+    voice: usize,
+    channel: u8,
+    audible: bool,
+    track: MidiTrack,
+}
+
+/// Convert a tick in a flow to MIDI ticks
+fn to_midi_ticks(tick: u32, subdivisions: u8) -> u32 {
+    (u64::from(tick) * u64::from(PPQ) / u64::from(subdivisions.max(1))) as u32
+}
+
+/// Set up the MIDI tracks for an instruments staves
+fn create_lanes<'a>(
+    instrument: &'a Instrument,
+    flows: &[&Flow],
+    channel: u8,
+    audible: bool,
+    lanes: &mut Vec<Lane<'a>>,
+) {
+    for stave_key in instrument.staves.iter() {
+        let voices = flows
+            .iter()
+            .filter_map(|flow| flow.staves.get(stave_key))
+            .map(|stave| stave.tracks.len())
+            .max()
+            .unwrap_or(0);
+
+        for voice in 0..voices {
+            let mut track = MidiTrack::default();
+            track.meta(0, META_TRACK_NAME, instrument.long_name.as_bytes());
+            track.push(
+                0,
+                ORDER_CONTROL,
+                vec![0xB0 | channel, 0x07, instrument.volume.min(127)],
+            );
+            if let Some(program) = get_program(&instrument.id) {
+                track.push(0, ORDER_CONTROL, vec![0xC0 | channel, program]);
+            }
+            lanes.push(Lane {
+                stave_key,
+                voice,
+                channel,
+                audible,
+                track,
+            });
+        }
+    }
+}
+
+/// Write the flows one after another as a type 1 standard MIDI file.
+///
+/// The first track holds the tempo, time and key signatures of each flow,
+/// followed by a track for every engine track in score order.
+pub fn export(score: &Score, flows: &[&Flow]) -> Vec<u8> {
+    let mut conductor = MidiTrack::default();
+    conductor.meta(0, META_TRACK_NAME, score.meta.title.as_bytes());
+
+    // muted instruments, or those not soloed when something is, keep their tracks but are silent
+    let any_solo = score.instruments.values().any(|instrument| instrument.solo);
+
+    let mut lanes = Vec::new();
+    let mut next_channel = 0;
+    for player_key in score.players.order.iter() {
+        let player = match score.players.by_key.get(player_key) {
+            Some(player) => player,
+            None => continue,
+        };
+        for instrument_key in player.instruments.iter() {
+            let instrument = match score.instruments.get(instrument_key) {
+                Some(instrument) => instrument,
+                None => continue,
+            };
+            let channel = assign_channel(&instrument.instrument_type, &mut next_channel);
+            let audible = !instrument.mute && (!any_solo || instrument.solo);
+            create_lanes(instrument, flows, channel, audible, &mut lanes);
+        }
+    }
+
+    let mut offset = 0;
+    for flow in flows.iter() {
+        let to_ticks = |tick: u32| offset + to_midi_ticks(tick, flow.subdivisions);
+
+        if flows.len() > 1 {
+            conductor.meta(offset, META_MARKER, flow.title.as_bytes());
+        }
+
+        // gradual tempo changes are played as a step every sixteenth
+        let step = NoteDuration::Sixteenth.to_ticks(flow.subdivisions);
+        for (tick, bpm) in flow.calc_tempo_map().changes(step) {
+            let micros = (60_000_000.0 / bpm).round() as u32;
+            conductor.meta(to_ticks(tick), META_TEMPO, &micros.to_be_bytes()[1..]);
+        }
+
+        for entry in flow.master.entries.by_key.values() {
+            match entry {
+                Entry::TimeSignature(time_signature) if time_signature.beats > 0 => {
+                    // midi has no pickups so the pickup is a short bar of its own
+                    let mut bars = vec![(
+                        time_signature.tick,
+                        time_signature.beats,
+                        time_signature.beat_type.clone(),
+                    )];
+                    if let Some((beats, beat_type)) = time_signature.pickup_beats(flow.subdivisions)
+                    {
+                        bars[0].0 += time_signature.pickup;
+                        bars.insert(0, (time_signature.tick, beats, beat_type));
+                    }
+                    for (tick, beats, beat_type) in bars {
+                        let beat_type = beat_type.to_int();
+                        conductor.meta(
+                            to_ticks(tick),
+                            META_TIME_SIGNATURE,
+                            &[
+                                beats,
+                                beat_type.trailing_zeros() as u8,
+                                96 / beat_type, // midi clocks per beat
+                                8,
+                            ],
+                        );
+                    }
+                }
+                Entry::KeySignature(key_signature)
+                    if key_signature.mode != KeySignatureMode::Open =>
+                {
+                    conductor.meta(
+                        to_ticks(key_signature.tick),
+                        META_KEY_SIGNATURE,
+                        &[
+                            key_signature.offset as u8,
+                            (key_signature.mode == KeySignatureMode::Minor) as u8,
+                        ],
+                    );
+                }
+                _ => (),
+            }
+        }
+
+        for lane in lanes.iter_mut().filter(|lane| lane.audible) {
+            let track = flow
+                .staves
+                .get(lane.stave_key)
+                .and_then(|stave| stave.tracks.get(lane.voice))
+                .and_then(|track_key| flow.tracks.get(track_key));
+            let track = match track {
+                Some(track) => track,
+                None => continue,
+            };
+
+            for entry in track.entries.by_key.values() {
+                if let Entry::Tone(tone) = entry {
+                    let pitch = tone.pitch.int.min(127);
+                    let velocity = tone.velocity.int.clamp(1, 127);
+                    lane.track.push(
+                        to_ticks(tone.tick),
+                        ORDER_NOTE_ON,
+                        vec![0x90 | lane.channel, pitch, velocity],
+                    );
+                    lane.track.push(
+                        to_ticks(tone.tick + tone.duration.int),
+                        ORDER_NOTE_OFF,
+                        vec![0x80 | lane.channel, pitch, 0],
+                    );
+                }
+            }
+        }
+
+        offset = to_ticks(flow.length);
+    }
+
+    let mut header = Vec::new();
+    header.extend(&1u16.to_be_bytes()); // format 1, simultaneous tracks
+    header.extend(&(lanes.len() as u16 + 1).to_be_bytes());
+    header.extend(&PPQ.to_be_bytes());
+
+    let mut out = Vec::new();
+    write_chunk(&mut out, b"MThd", &header);
+    out.extend(conductor.into_bytes());
+    for lane in lanes {
+        out.extend(lane.track.into_bytes());
+    }
+    out
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl Engine {
+    /// Export a flow as a standard MIDI file
+    pub fn export_midi(&self, flow_key: &str) -> Result<Vec<u8>> {
+        match self.state.score.flows.by_key.get(flow_key) {
+            Some(flow) => Ok(export(&self.state.score, &[flow])),
+            None => Err(Error::UnknownFlow(String::from(flow_key))),
+        }
+    }
+
+    /// Export every flow, one after another, as a single standard MIDI file
+    pub fn export_score_midi(&self) -> Vec<u8> {
+        let score = &self.state.score;
+        let flows: Vec<&Flow> = score
+            .flows
+            .order
+            .iter()
+            .filter_map(|flow_key| score.flows.by_key.get(flow_key))
+            .collect();
+        export(score, &flows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn score() -> Score {
+        serde_json::from_str(include_str!("../../../tests/fixtures/score_v2.json")).unwrap()
+    }
+
+    /// Split the file into its chunks
+    fn chunks(bytes: &[u8]) -> Vec<(&[u8], &[u8])> {
+        let mut chunks = Vec::new();
+        let mut i = 0;
+        while i < bytes.len() {
+            let length =
+                u32::from_be_bytes([bytes[i + 4], bytes[i + 5], bytes[i + 6], bytes[i + 7]]);
+            let end = i + 8 + length as usize;
+            chunks.push((&bytes[i..i + 4], &bytes[i + 8..end]));
+            i = end;
+        }
+        chunks
+    }
+
+    #[test]
+    fn test_export_flow() {
+        let score = score();
+        let flow = &score.flows.by_key["flow0000000001"];
+        let bytes = export(&score, &[flow]);
+        let chunks = chunks(&bytes);
+
+        assert_eq!(chunks[0].0, b"MThd");
+        assert_eq!(chunks[0].1, &[0, 1, 0, 2, 0x03, 0xC0]);
+        assert_eq!(chunks.len(), 3);
+
+        // tempo of 120 bpm is 500000 micro seconds per quarter
+        let conductor = chunks[1].1;
+        assert!(conductor
+            .windows(6)
+            .any(|bytes| bytes == [0xFF, META_TEMPO, 0x03, 0x07, 0xA1, 0x20]));
+
+        // the instrument track is named and plays the A then Bb
+        let track = chunks[2].1;
+        assert!(track.windows(6).any(|bytes| bytes == b"Violin"));
+        assert!(track.windows(2).any(|bytes| bytes == [0xC0, 40]));
+        assert!(track.windows(3).any(|bytes| bytes == [0x90, 69, 100]));
+        assert!(track.windows(3).any(|bytes| bytes == [0x90, 70, 100]));
+        assert!(track.ends_with(&[0xFF, META_END_OF_TRACK, 0x00]));
+    }
+
+    #[test]
+    fn test_pickup() {
+        let mut score = score();
+        let flow = score.flows.by_key.get_mut("flow0000000001").unwrap();
+        for entry in flow.master.entries.by_key.values_mut() {
+            if let Entry::TimeSignature(time_signature) = entry {
+                time_signature.beats = 4;
+                time_signature.beat_type = NoteDuration::Quarter;
+                time_signature.pickup = 8;
+            }
+        }
+        let flow = &score.flows.by_key["flow0000000001"];
+        let bytes = export(&score, &[flow]);
+        let conductor = chunks(&bytes)[1].1;
+
+        // an eighth pickup is a bar of 1/8 then 4/4 from the first barline
+        let pickup = [0xFF, META_TIME_SIGNATURE, 0x04, 1, 3, 12, 8];
+        let common = [0xFF, META_TIME_SIGNATURE, 0x04, 4, 2, 24, 8];
+        let find = |event: &[u8]| conductor.windows(7).position(|bytes| bytes == event);
+        assert!(find(&pickup).unwrap() < find(&common).unwrap());
+    }
+
+    #[test]
+    fn test_muted_instrument() {
+        let mut score = score();
+        for instrument in score.instruments.values_mut() {
+            instrument.mute = true;
+        }
+        let flow = &score.flows.by_key["flow0000000001"];
+        let bytes = export(&score, &[flow]);
+        let track = chunks(&bytes)[2].1;
+        assert!(!track.windows(3).any(|bytes| bytes[0] == 0x90));
+    }
+}
@@ -0,0 +1,700 @@
+use crate::error::{Error, Result};
+use crate::formats::midi::PERCUSSION_CHANNEL;
+use crate::state::entries::key_signature::KeySignatureMode;
+use crate::state::entries::time_signature::TimeSignatureDrawType;
+use crate::state::entries::tone::Articulation;
+use crate::state::score::instrument::defs::{get_def_by_drum, get_def_by_program, InstrumentDef};
+use crate::state::score::player::PlayerType;
+use crate::state::Engine;
+use crate::utils::duration::NoteDuration;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::TryFrom;
+
+fn invalid(message: &str) -> Error {
+    Error::InvalidFile(format!("midi {}", message))
+}
+
+/// Reads big endian values from a slice of bytes
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.position >= self.bytes.len()
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8]> {
+        let end = self.position + length;
+        if end > self.bytes.len() {
+            return Err(invalid("ended unexpectedly"));
+        }
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Read a variable length quantity, at most 4 bytes long
+    fn vlq(&mut self) -> Result<u32> {
+        let mut value = 0;
+        for _ in 0..4 {
+            let byte = self.u8()?;
+            value = (value << 7) | u32::from(byte & 0x7F);
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(invalid("variable length quantity is too long"))
+    }
+}
+
+/// The events we care about, everything else is skipped
+#[derive(Debug, Clone, PartialEq)]
+pub enum MidiEvent {
+    NoteOn {
+        channel: u8,
+        pitch: u8,
+        velocity: u8,
+    },
+    NoteOff {
+        channel: u8,
+        pitch: u8,
+    },
+    ProgramChange {
+        channel: u8,
+        program: u8,
+    },
+    Tempo(u32),             // micro seconds per quarter
+    TimeSignature(u8, u8),  // beats, beat type as a power of 2
+    KeySignature(i8, bool), // sharps (+) or flats (-), minor
+    TrackName(String),
+}
+
+pub struct MidiFile {
+    pub division: u16, // ticks per quarter
+    pub tracks: Vec<Vec<(u32, MidiEvent)>>,
+}
+
+/// Read a standard MIDI file into absolutely timed events
+pub fn parse(bytes: &[u8]) -> Result<MidiFile> {
+    let mut reader = Reader::new(bytes);
+    if reader.take(4)? != b"MThd" {
+        return Err(invalid("header not found"));
+    }
+    let length = reader.u32()? as usize;
+    let mut header = Reader::new(reader.take(length)?);
+    let _format = header.u16()?;
+    let count = header.u16()?;
+    let division = header.u16()?;
+    if division == 0 || division & 0x8000 != 0 {
+        return Err(invalid("SMPTE time division is not supported"));
+    }
+
+    let mut tracks = Vec::new();
+    while !reader.is_empty() && tracks.len() < usize::from(count) {
+        let id = reader.take(4)?;
+        let length = reader.u32()? as usize;
+        let data = reader.take(length)?;
+        // unknown chunks are allowed and ignored
+        if id == b"MTrk" {
+            tracks.push(parse_track(data)?);
+        }
+    }
+
+    Ok(MidiFile { division, tracks })
+}
+
+fn parse_track(data: &[u8]) -> Result<Vec<(u32, MidiEvent)>> {
+    let mut reader = Reader::new(data);
+    let mut events = Vec::new();
+    let mut tick: u32 = 0;
+    let mut running_status = None;
+
+    while !reader.is_empty() {
+        tick = tick.saturating_add(reader.vlq()?);
+
+        let mut status = reader.u8()?;
+        let mut first = None;
+        if status < 0x80 {
+            // running status, the status byte is left out and this is the first data byte
+            first = Some(status);
+            status = running_status.ok_or_else(|| invalid("event has no status"))?;
+        }
+
+        match status {
+            0xFF => {
+                let kind = reader.u8()?;
+                let length = reader.vlq()? as usize;
+                let data = reader.take(length)?;
+                let event = match kind {
+                    0x2F => break,
+                    0x03 => Some(MidiEvent::TrackName(
+                        String::from_utf8_lossy(data).into_owned(),
+                    )),
+                    0x51 if length >= 3 => Some(MidiEvent::Tempo(u32::from_be_bytes([
+                        0, data[0], data[1], data[2],
+                    ]))),
+                    0x58 if length >= 2 => Some(MidiEvent::TimeSignature(data[0], data[1])),
+                    0x59 if length >= 2 => {
+                        Some(MidiEvent::KeySignature(data[0] as i8, data[1] == 1))
+                    }
+                    _ => None,
+                };
+                if let Some(event) = event {
+                    events.push((tick, event));
+                }
+            }
+            0xF0 | 0xF7 => {
+                let length = reader.vlq()? as usize;
+                reader.take(length)?;
+            }
+            0x80..=0xEF => {
+                running_status = Some(status);
+                let channel = status & 0x0F;
+                let data1 = match first {
+                    Some(data1) => data1,
+                    None => reader.u8()?,
+                };
+                let event = match status & 0xF0 {
+                    0x80 => {
+                        reader.u8()?;
+                        Some(MidiEvent::NoteOff {
+                            channel,
+                            pitch: data1,
+                        })
+                    }
+                    0x90 => match reader.u8()? {
+                        0 => Some(MidiEvent::NoteOff {
+                            channel,
+                            pitch: data1,
+                        }),
+                        velocity => Some(MidiEvent::NoteOn {
+                            channel,
+                            pitch: data1,
+                            velocity,
+                        }),
+                    },
+                    0xC0 => Some(MidiEvent::ProgramChange {
+                        channel,
+                        program: data1,
+                    }),
+                    0xD0 => None,
+                    _ => {
+                        reader.u8()?;
+                        None
+                    }
+                };
+                if let Some(event) = event {
+                    events.push((tick, event));
+                }
+            }
+            _ => return Err(invalid("unknown status")),
+        }
+    }
+
+    Ok(events)
+}
+
+/// A note as found in the file, in the file's ticks
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct MidiNote {
+    pub track: usize,
+    pub channel: u8,
+    pub pitch: u8,
+    pub velocity: u8,
+    pub tick: u32,
+    pub length: u32,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub enum DropReason {
+    TooShort,     // shorter than a tick of the flow once quantised
+    Duplicate,    // the same pitch already starts on the quantised tick
+    Unterminated, // there was no note off
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct DroppedNote {
+    pub note: MidiNote,
+    pub reason: DropReason,
+}
+
+/// Returned from `import_midi()` so the user can be told what was changed
+#[derive(Debug, Serialize)]
+pub struct MidiImportReport {
+    pub flow_key: String,
+    pub snapped: Vec<MidiNote>,
+    pub dropped: Vec<DroppedNote>,
+}
+
+/// A tone quantised to the ticks of the flow
+pub struct PlannedTone {
+    pub stave: usize,
+    pub tick: u32,
+    pub duration: u32,
+    pub pitch: u8,
+    pub velocity: u8,
+}
+
+/// The notes of a MIDI track and channel, played by one instrument
+pub struct Part {
+    pub def: &'static InstrumentDef,
+    pub tones: Vec<PlannedTone>,
+}
+
+/// Everything needed to create the flow, worked out before touching the score
+pub struct Plan {
+    pub title: Option<String>,
+    pub length: u32,
+    pub parts: Vec<Part>,
+    pub tempos: BTreeMap<u32, f64>, // quarters per minute
+    pub time_signatures: BTreeMap<u32, (u8, NoteDuration)>,
+    pub key_signatures: BTreeMap<u32, (i8, bool)>,
+    pub snapped: Vec<MidiNote>,
+    pub dropped: Vec<DroppedNote>,
+}
+
+/// Pair up the note ons and offs, notes of the same pitch are ended first in first out
+fn notes(file: &MidiFile) -> (Vec<MidiNote>, Vec<DroppedNote>) {
+    let mut notes = Vec::new();
+    let mut dropped = Vec::new();
+
+    for (track, events) in file.tracks.iter().enumerate() {
+        let mut sounding: HashMap<(u8, u8), Vec<(u32, u8)>> = HashMap::new();
+        for (tick, event) in events.iter() {
+            match event {
+                MidiEvent::NoteOn {
+                    channel,
+                    pitch,
+                    velocity,
+                } => sounding
+                    .entry((*channel, *pitch))
+                    .or_default()
+                    .push((*tick, *velocity)),
+                MidiEvent::NoteOff { channel, pitch } => {
+                    if let Some(started) = sounding.get_mut(&(*channel, *pitch)) {
+                        if !started.is_empty() {
+                            let (start, velocity) = started.remove(0);
+                            notes.push(MidiNote {
+                                track,
+                                channel: *channel,
+                                pitch: *pitch,
+                                velocity,
+                                tick: start,
+                                length: tick - start,
+                            });
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        for ((channel, pitch), started) in sounding {
+            for (tick, velocity) in started {
+                dropped.push(DroppedNote {
+                    note: MidiNote {
+                        track,
+                        channel,
+                        pitch,
+                        velocity,
+                        tick,
+                        length: 0,
+                    },
+                    reason: DropReason::Unterminated,
+                });
+            }
+        }
+    }
+
+    notes.sort_by_key(|note| (note.track, note.tick, note.channel, note.pitch));
+    (notes, dropped)
+}
+
+/// Work out the flow for a MIDI file, quantising everything to `subdivisions` per quarter
+pub fn plan(file: &MidiFile, subdivisions: u8) -> Result<Plan> {
+    let division = u64::from(file.division);
+    let subdivisions = u64::from(subdivisions);
+    let quantise = |tick: u32| {
+        u32::try_from((u64::from(tick) * subdivisions + division / 2) / division)
+            .map_err(|_| invalid("event is too far into the flow"))
+    };
+    let is_exact = |tick: u32| (u64::from(tick) * subdivisions) % division == 0;
+
+    let mut title = None;
+    let mut programs: HashMap<(usize, u8), u8> = HashMap::new();
+    let mut tempos = BTreeMap::new();
+    let mut time_signatures = BTreeMap::new();
+    let mut key_signatures = BTreeMap::new();
+    let mut length = 1;
+
+    for (track, events) in file.tracks.iter().enumerate() {
+        for (tick, event) in events.iter() {
+            let tick = quantise(*tick)?;
+            match event {
+                MidiEvent::TrackName(name) if track == 0 && title.is_none() => {
+                    title = Some(name.clone())
+                }
+                MidiEvent::ProgramChange { channel, program } => {
+                    programs.entry((track, *channel)).or_insert(*program);
+                }
+                MidiEvent::Tempo(micros) if *micros > 0 => {
+                    tempos.insert(tick, 60_000_000.0 / f64::from(*micros));
+                    length = length.max(tick.saturating_add(1));
+                }
+                MidiEvent::TimeSignature(beats, power) => {
+                    let beat_type = match power {
+                        0 => NoteDuration::Whole,
+                        1 => NoteDuration::Half,
+                        2 => NoteDuration::Quarter,
+                        3 => NoteDuration::Eighth,
+                        4 => NoteDuration::Sixteenth,
+                        5 => NoteDuration::ThirtySecond,
+                        _ => continue,
+                    };
+                    time_signatures.insert(tick, (*beats, beat_type));
+                    length = length.max(tick.saturating_add(1));
+                }
+                MidiEvent::KeySignature(offset, minor) if (-7..=7).contains(offset) => {
+                    key_signatures.insert(tick, (*offset, *minor));
+                    length = length.max(tick.saturating_add(1));
+                }
+                _ => (),
+            }
+        }
+    }
+
+    // type 0 files put everything in one track so fall back to any program on the channel
+    let program = |track: usize, channel: u8| {
+        programs.get(&(track, channel)).cloned().or_else(|| {
+            programs
+                .iter()
+                .filter(|((_, other), _)| *other == channel)
+                .min_by_key(|((track, _), _)| *track)
+                .map(|(_, program)| *program)
+        })
+    };
+
+    let (notes, mut dropped) = notes(file);
+    let mut snapped = Vec::new();
+    let mut parts: Vec<Part> = Vec::new();
+    let mut part_keys: Vec<String> = Vec::new();
+    let mut seen = HashSet::new();
+
+    for note in notes {
+        let tick = quantise(note.tick)?;
+        let duration = quantise(note.tick + note.length)? - tick;
+        if duration == 0 {
+            dropped.push(DroppedNote {
+                note,
+                reason: DropReason::TooShort,
+            });
+            continue;
+        }
+
+        // percussion is split into an instrument per drum, everything else by track and channel
+        let (part_key, def) = if note.channel == PERCUSSION_CHANNEL {
+            let def = get_def_by_drum(note.pitch);
+            (def.map(|def| String::from(def.id)), def)
+        } else {
+            let def = get_def_by_program(program(note.track, note.channel).unwrap_or(0));
+            (Some(format!("{}:{}", note.track, note.channel)), def)
+        };
+        let (part_key, def) = match (part_key, def) {
+            (Some(part_key), Some(def)) => (part_key, def),
+            _ => continue,
+        };
+
+        let index = match part_keys.iter().position(|key| key == &part_key) {
+            Some(index) => index,
+            None => {
+                part_keys.push(part_key);
+                parts.push(Part {
+                    def,
+                    tones: Vec::new(),
+                });
+                parts.len() - 1
+            }
+        };
+
+        // notes below middle C go on the lower stave of keyboard instruments
+        let stave = if def.staves.len() > 1 && note.pitch < 60 {
+            1
+        } else {
+            0
+        };
+
+        if !seen.insert((index, stave, tick, note.pitch)) {
+            dropped.push(DroppedNote {
+                note,
+                reason: DropReason::Duplicate,
+            });
+            continue;
+        }
+
+        if !is_exact(note.tick) || !is_exact(note.tick + note.length) {
+            snapped.push(note.clone());
+        }
+
+        length = length.max(tick + duration);
+        parts[index].tones.push(PlannedTone {
+            stave,
+            tick,
+            duration,
+            pitch: note.pitch,
+            velocity: note.velocity,
+        });
+    }
+
+    Ok(Plan {
+        title,
+        length,
+        parts,
+        tempos,
+        time_signatures,
+        key_signatures,
+        snapped,
+        dropped,
+    })
+}
+
+impl Engine {
+    /// Create a flow, with a player and instrument for each part, from a MIDI file
+    fn create_midi_flow(&mut self, file: &MidiFile) -> Result<MidiImportReport> {
+        let flow_key = self.create_flow();
+        let subdivisions = match self.state.score.flows.by_key.get(&flow_key) {
+            Some(flow) => flow.subdivisions,
+            None => return self.fail(Error::UnknownFlow(flow_key)),
+        };
+        let plan = match plan(file, subdivisions) {
+            Ok(plan) => plan,
+            Err(error) => return self.fail(error),
+        };
+
+        if let Some(title) = &plan.title {
+            self.rename_flow(&flow_key, title)?;
+        }
+        self.set_flow_length(&flow_key, plan.length)?;
+
+        // time signatures are added in order so that none are moved to fill bars
+        for (tick, (beats, beat_type)) in plan.time_signatures {
+            self.create_time_signature(
+                &flow_key,
+                tick,
+                beats,
+                beat_type,
+                TimeSignatureDrawType::Normal,
+                None,
+            )?;
+        }
+        for (tick, bpm) in plan.tempos {
+            self.create_absolute_tempo(
+                &flow_key,
+                tick,
+                "",
+                NoteDuration::Quarter,
+                0,
+                bpm.round() as u32,
+                false,
+                false,
+                true,
+            )?;
+        }
+        for (tick, (offset, minor)) in plan.key_signatures {
+            let mode = if minor {
+                KeySignatureMode::Minor
+            } else {
+                KeySignatureMode::Major
+            };
+            self.create_key_signature(&flow_key, tick, mode, offset)?;
+        }
+
+        for part in plan.parts.iter() {
+            let player_key = self.create_player(PlayerType::Solo);
+            let instrument_key = self.create_instrument(part.def.id)?;
+            self.assign_instrument(&player_key, &instrument_key)?;
+
+            // the first track of each of the instruments staves
+            let flow = &self.state.score.flows.by_key[&flow_key];
+            let track_keys: Vec<Option<String>> = self.state.score.instruments[&instrument_key]
+                .staves
+                .iter()
+                .map(|stave_key| {
+                    flow.staves
+                        .get(stave_key)
+                        .and_then(|stave| stave.tracks.first().cloned())
+                })
+                .collect();
+
+            for tone in part.tones.iter() {
+                let track_key = match track_keys.get(tone.stave).cloned().flatten() {
+                    Some(track_key) => track_key,
+                    None => return self.fail(Error::UnknownStave(instrument_key)),
+                };
+                self.create_tone(
+                    &flow_key,
+                    &track_key,
+                    tone.tick,
+                    tone.duration,
+                    tone.pitch,
+                    tone.velocity,
+                    Articulation::None,
+                )?;
+            }
+        }
+
+        Ok(MidiImportReport {
+            flow_key,
+            snapped: plan.snapped,
+            dropped: plan.dropped,
+        })
+    }
+}
+
+impl Engine {
+    /// Import a standard MIDI file as a new flow, creating a player for each part.
+    ///
+    /// Returns a report of the notes that had to be moved onto the flow's ticks or left out.
+    pub fn import_midi(&mut self, bytes: &[u8]) -> Result<MidiImportReport> {
+        let file = parse(bytes)?;
+
+        self.begin_transaction(Some(String::from("Import MIDI")));
+        // the listener is sent the whole state once we are done rather than every step
+        self.patches.resync();
+        let report = self.create_midi_flow(&file);
+        self.commit()?;
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A type 1 file at 96 ticks per quarter with a conductor and a piano track
+    fn file() -> Vec<u8> {
+        let conductor: Vec<u8> = vec![
+            0x00, 0xFF, 0x03, 0x06, b'S', b'k', b'e', b't', b'c', b'h', // name
+            0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20, // 120 bpm
+            0x00, 0xFF, 0x58, 0x04, 0x03, 0x02, 0x18, 0x08, // 3/4
+            0x00, 0xFF, 0x2F, 0x00,
+        ];
+        let piano: Vec<u8> = vec![
+            0x00, 0xC0, 0x00, // piano
+            0x00, 0x90, 0x3C, 0x64, // C4 on
+            0x60, 0x3C, 0x00, // off a quarter later, running status
+            0x00, 0x90, 0x30, 0x50, // C3 on
+            0x00, 0x90, 0x3E, 0x64, // D4 on
+            0x02, 0x80, 0x30, 0x00, // C3 off far too soon
+            0x35, 0x80, 0x3E, 0x00, // D4 off a little after an eighth
+            0x00, 0x90, 0x40, 0x64, // E4 on and never off
+            0x00, 0xFF, 0x2F, 0x00,
+        ];
+
+        let mut out = Vec::new();
+        out.extend(b"MThd");
+        out.extend(&[0, 0, 0, 6, 0, 1, 0, 2, 0, 96]);
+        for track in [conductor, piano].iter() {
+            out.extend(b"MTrk");
+            out.extend(&(track.len() as u32).to_be_bytes());
+            out.extend(track);
+        }
+        out
+    }
+
+    #[test]
+    fn test_parse() {
+        let file = parse(&file()).unwrap();
+        assert_eq!(file.division, 96);
+        assert_eq!(file.tracks.len(), 2);
+        assert_eq!(file.tracks[0][1], (0, MidiEvent::Tempo(500_000)));
+        assert_eq!(
+            file.tracks[1][2],
+            (
+                96,
+                MidiEvent::NoteOff {
+                    channel: 0,
+                    pitch: 60
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(parse(b"RIFF").is_err());
+        let mut bytes = file();
+        bytes.truncate(30);
+        assert!(parse(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_plan() {
+        let plan = plan(&parse(&file()).unwrap(), 16).unwrap();
+        assert_eq!(plan.title, Some(String::from("Sketch")));
+        assert_eq!(plan.tempos[&0], 120.0);
+        assert_eq!(plan.time_signatures[&0], (3, NoteDuration::Quarter));
+
+        assert_eq!(plan.parts.len(), 1);
+        let part = &plan.parts[0];
+        assert_eq!(part.def.id, "keyboard.piano");
+        assert_eq!(part.tones.len(), 2);
+        assert_eq!((part.tones[0].tick, part.tones[0].duration), (0, 16));
+        // D4 is snapped to the nearest tick, on the upper stave
+        assert_eq!((part.tones[1].tick, part.tones[1].duration), (16, 9));
+        assert_eq!(part.tones[1].stave, 0);
+
+        assert_eq!(plan.snapped.len(), 1);
+        assert_eq!(plan.snapped[0].pitch, 62);
+
+        let mut reasons: Vec<(u8, DropReason)> = plan
+            .dropped
+            .iter()
+            .map(|dropped| (dropped.note.pitch, dropped.reason.clone()))
+            .collect();
+        reasons.sort_by_key(|(pitch, _)| *pitch);
+        assert_eq!(
+            reasons,
+            vec![(48, DropReason::TooShort), (64, DropReason::Unterminated)]
+        );
+    }
+
+    #[test]
+    fn test_plan_too_long() {
+        // a single tempo a long way in, at one tick per quarter
+        let track: Vec<u8> = vec![
+            0xFF, 0xFF, 0xFF, 0x7F, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20, // 120 bpm
+            0x00, 0xFF, 0x2F, 0x00,
+        ];
+        let mut bytes = Vec::new();
+        bytes.extend(b"MThd");
+        bytes.extend(&[0, 0, 0, 6, 0, 0, 0, 1, 0, 1]);
+        bytes.extend(b"MTrk");
+        bytes.extend(&(track.len() as u32).to_be_bytes());
+        bytes.extend(&track);
+
+        let file = parse(&bytes).unwrap();
+        assert!(plan(&file, 8).is_ok());
+        assert!(matches!(plan(&file, 32), Err(Error::InvalidFile(_))));
+    }
+}
@@ -0,0 +1,66 @@
+pub mod export;
+pub mod import;
+
+use crate::state::score::instrument::defs::InstrumentType;
+
+/// MIDI ticks per quarter note in the files we write.
+/// This divides evenly by all the usual subdivisions, including triplets and quintuplets.
+pub const PPQ: u16 = 960;
+
+/// The channel reserved for percussion in General MIDI
+pub const PERCUSSION_CHANNEL: u8 = 9;
+
+/// Give an instrument a channel. Melodic instruments take the next channel in turn,
+/// skipping the percussion channel, and percussive instruments share the percussion channel.
+pub fn assign_channel(instrument_type: &InstrumentType, next: &mut u8) -> u8 {
+    match instrument_type {
+        InstrumentType::Percussive => PERCUSSION_CHANNEL,
+        InstrumentType::Melodic => {
+            let channel = *next;
+            *next = (*next + 1) % 16;
+            if *next == PERCUSSION_CHANNEL {
+                *next += 1;
+            }
+            channel
+        }
+    }
+}
+
+/// Write a variable length quantity, 7 bits per byte with the top bit set on all but the last
+pub fn write_vlq(out: &mut Vec<u8>, value: u32) {
+    let mut bytes = vec![(value & 0x7F) as u8];
+    let mut value = value >> 7;
+    while value > 0 {
+        bytes.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.reverse();
+    out.extend(bytes);
+}
+
+/// Write a chunk with its 4 byte id and length
+pub fn write_chunk(out: &mut Vec<u8>, id: &[u8; 4], data: &[u8]) {
+    out.extend(id);
+    out.extend(&(data.len() as u32).to_be_bytes());
+    out.extend(data);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vlq() {
+        let cases: [(u32, Vec<u8>); 4] = [
+            (0, vec![0x00]),
+            (0x7F, vec![0x7F]),
+            (0x80, vec![0x81, 0x00]),
+            (0x0FFF_FFFF, vec![0xFF, 0xFF, 0xFF, 0x7F]),
+        ];
+        for (value, expected) in cases.iter() {
+            let mut out = Vec::new();
+            write_vlq(&mut out, *value);
+            assert_eq!(&out, expected);
+        }
+    }
+}
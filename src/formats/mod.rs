@@ -0,0 +1,36 @@
+pub mod abc;
+pub mod lilypond;
+pub mod midi;
+pub mod musicxml;
+pub mod plan;
+pub mod xml;
+pub mod zip;
+
+use crate::state::score::config::AutoCountStyle;
+use crate::utils::pitch::Accidental;
+
+/// Names in the score use tokens for accidentals (see `Accidental::to_token`),
+/// swap them for the plain unicode symbols when writing to other formats.
+pub fn untokenize(text: &str) -> String {
+    let accidentals = [
+        (Accidental::DoubleSharp, "\u{1D12A}"),
+        (Accidental::Sharp, "\u{266F}"),
+        (Accidental::Natural, "\u{266E}"),
+        (Accidental::Flat, "\u{266D}"),
+        (Accidental::DoubleFlat, "\u{1D12B}"),
+    ];
+    let mut out = String::from(text);
+    for (accidental, symbol) in accidentals.iter() {
+        out = out.replace(accidental.to_token(), symbol);
+    }
+    out
+}
+
+/// Instruments are counted in the style set for their player type, eg. Violin II
+pub fn part_name(name: &str, count: Option<u8>, style: AutoCountStyle) -> String {
+    let name = untokenize(name);
+    match count {
+        Some(count) => format!("{} {}", name, style.format(count)),
+        None => name,
+    }
+}
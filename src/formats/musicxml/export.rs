@@ -0,0 +1,721 @@
+use crate::error::{Error, Result};
+use crate::formats::midi::assign_channel;
+use crate::formats::musicxml::{alter, clef_sign, note_type, DOCTYPE};
+use crate::formats::xml::XmlWriter;
+use crate::formats::{part_name, untokenize};
+use crate::state::entries::barline::BarlineType;
+use crate::state::entries::clef::{Clef, ClefDrawType};
+use crate::state::entries::key_signature::KeySignatureMode;
+use crate::state::entries::time_signature::{TimeSignature, TimeSignatureDrawType};
+use crate::state::entries::tone::{Articulation, Tone};
+use crate::state::entries::tuplet::Tuplet;
+use crate::state::entries::Entry;
+use crate::state::score::flow::Flow;
+use crate::state::score::instrument::defs::{get_program, InstrumentType};
+use crate::state::score::instrument::Instrument;
+use crate::state::score::notation::{measures, notate, Notation};
+use crate::state::score::stave::Stave;
+use crate::state::score::track::Track;
+use crate::state::score::Score;
+use crate::state::Engine;
+use crate::utils::pitch::Pitch;
+use std::collections::HashMap;
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+/// An engine track written as a MusicXML voice
+struct Voice<'a> {
+    number: usize,
+    staff: usize, // 1 based index of the stave in the part
+    track: &'a Track,
+    notations: Vec<Notation>,
+    tuplets: HashMap<String, &'a Tuplet>,
+}
+
+/// Each instrument is written as a part, with all of its staves
+struct Part<'a> {
+    id: String,
+    instrument: &'a Instrument,
+    name: String,
+    short_name: String,
+    staves: Vec<&'a Stave>,
+    voices: Vec<Voice<'a>>,
+}
+
+/// The entries in a track from start up to (but not including) end, in order
+fn entries_between(track: &Track, start: u32, end: u32) -> Vec<&Entry> {
+    let mut entries: Vec<&Entry> = track
+        .entries
+        .by_key
+        .values()
+        .filter(|entry| entry.tick() >= start && entry.tick() < end)
+        .collect();
+    entries.sort_by_key(|entry| entry.tick());
+    entries
+}
+
+/// Collect the instruments in the flow in score order, with their notated voices
+fn parts<'a>(score: &'a Score, flow: &'a Flow) -> Vec<Part<'a>> {
+    let mut parts = Vec::new();
+    for player_key in score.players.order.iter() {
+        if !flow.players.contains(player_key) {
+            continue;
+        }
+        let player = match score.players.by_key.get(player_key) {
+            Some(player) => player,
+            None => continue,
+        };
+        let style = score.config.auto_count.style(player.player_type);
+        for instrument_key in player.instruments.iter() {
+            let instrument = match score.instruments.get(instrument_key) {
+                Some(instrument) => instrument,
+                None => continue,
+            };
+
+            let staves: Vec<&Stave> = instrument
+                .staves
+                .iter()
+                .filter_map(|stave_key| flow.staves.get(stave_key))
+                .collect();
+
+            let mut voices = Vec::new();
+            for (i, stave) in staves.iter().enumerate() {
+                for track_key in stave.tracks.iter() {
+                    if let Some(track) = flow.tracks.get(track_key) {
+                        voices.push(Voice {
+                            number: voices.len() + 1,
+                            staff: i + 1,
+                            track,
+                            notations: notate(track, &flow.master, flow.length, flow.subdivisions),
+                            tuplets: track
+                                .get_tuplets()
+                                .into_iter()
+                                .map(|tuplet| (tuplet.key.clone(), tuplet))
+                                .collect(),
+                        });
+                    }
+                }
+            }
+
+            parts.push(Part {
+                id: format!("P{}", parts.len() + 1),
+                instrument,
+                name: part_name(&instrument.long_name, instrument.count, style),
+                short_name: part_name(&instrument.short_name, instrument.count, style),
+                staves,
+                voices,
+            });
+        }
+    }
+    parts
+}
+
+fn write_header(xml: &mut XmlWriter, score: &Score, flow: &Flow, number: usize) {
+    let meta = &score.meta;
+
+    if !meta.title.is_empty() {
+        xml.open("work", &[]);
+        xml.text("work-title", &[], &meta.title);
+        xml.close("work");
+    }
+    xml.text("movement-number", &[], &number.to_string());
+    if !flow.title.is_empty() {
+        xml.text("movement-title", &[], &flow.title);
+    }
+
+    xml.open("identification", &[]);
+    let creators = [
+        ("composer", &meta.composer),
+        ("arranger", &meta.arranger),
+        ("lyricist", &meta.lyricist),
+    ];
+    for (kind, name) in creators.iter() {
+        if !name.is_empty() {
+            xml.text("creator", &[("type", kind)], name);
+        }
+    }
+    if !meta.copyright.is_empty() {
+        xml.text("rights", &[], &meta.copyright);
+    }
+    xml.open("encoding", &[]);
+    xml.text("software", &[], "Solo Composer");
+    xml.close("encoding");
+    xml.close("identification");
+
+    // there is nowhere else for a subtitle to go
+    if !meta.subtitle.is_empty() {
+        xml.open("credit", &[("page", "1")]);
+        xml.text("credit-type", &[], "subtitle");
+        xml.text("credit-words", &[], &meta.subtitle);
+        xml.close("credit");
+    }
+}
+
+fn write_part_list(xml: &mut XmlWriter, parts: &[Part]) {
+    xml.open("part-list", &[]);
+    let mut next_channel = 0;
+    for part in parts.iter() {
+        let instrument = part.instrument;
+        let id = format!("{}-I1", part.id);
+        let channel = assign_channel(&instrument.instrument_type, &mut next_channel);
+
+        xml.open("score-part", &[("id", &part.id)]);
+        xml.text("part-name", &[], &part.name);
+        xml.text("part-abbreviation", &[], &part.short_name);
+
+        xml.open("score-instrument", &[("id", &id)]);
+        xml.text("instrument-name", &[], &untokenize(&instrument.long_name));
+        xml.text("instrument-sound", &[], &instrument.id);
+        xml.close("score-instrument");
+
+        xml.open("midi-instrument", &[("id", &id)]);
+        xml.text("midi-channel", &[], &(channel + 1).to_string());
+        if let Some(program) = get_program(&instrument.id) {
+            xml.text("midi-program", &[], &(program + 1).to_string());
+        }
+        let volume = f64::from(instrument.volume.min(127)) / 127.0 * 100.0;
+        xml.text("volume", &[], &format!("{:.2}", volume));
+        xml.close("midi-instrument");
+
+        xml.close("score-part");
+    }
+    xml.close("part-list");
+}
+
+fn write_time(xml: &mut XmlWriter, time_signature: &TimeSignature) {
+    let attributes: &[(&str, &str)] = match time_signature.draw_type {
+        TimeSignatureDrawType::Hidden => &[("print-object", "no")],
+        TimeSignatureDrawType::Normal => &[],
+        TimeSignatureDrawType::CommonTime => &[("symbol", "common")],
+        TimeSignatureDrawType::SplitCommonTime => &[("symbol", "cut")],
+    };
+    xml.open("time", attributes);
+    if time_signature.beats == 0 {
+        xml.empty("senza-misura", &[]);
+    } else {
+        xml.text("beats", &[], &time_signature.beats.to_string());
+        xml.text(
+            "beat-type",
+            &[],
+            &time_signature.beat_type.to_int().to_string(),
+        );
+    }
+    xml.close("time");
+}
+
+fn write_clef(xml: &mut XmlWriter, clef: &Clef, stave: &Stave, number: Option<usize>) {
+    let number = number.map(|number| number.to_string());
+    match &number {
+        Some(number) => xml.open("clef", &[("number", number)]),
+        None => xml.open("clef", &[]),
+    }
+    xml.text("sign", &[], clef_sign(clef.draw_as));
+
+    // the offset is in half spaces from the middle of the stave
+    if let ClefDrawType::G | ClefDrawType::F | ClefDrawType::C = clef.draw_as {
+        let lines = stave.lines.iter().filter(|line| **line == 1).count() as i8;
+        let line = (lines + 1) / 2 + clef.offset / 2;
+        xml.text("line", &[], &line.to_string());
+    }
+    xml.close("clef");
+}
+
+fn write_attributes(
+    xml: &mut XmlWriter,
+    flow: &Flow,
+    part: &Part,
+    first: bool,
+    start: u32,
+    end: u32,
+) {
+    let master = entries_between(&flow.master, start, end);
+    let key_signature = master.iter().find_map(|entry| match entry {
+        Entry::KeySignature(key_signature) => Some(key_signature),
+        _ => None,
+    });
+    let time_signature = master.iter().find_map(|entry| match entry {
+        Entry::TimeSignature(time_signature) => Some(time_signature),
+        _ => None,
+    });
+    let clefs: Vec<(usize, &Stave, &Clef)> = part
+        .staves
+        .iter()
+        .enumerate()
+        .filter_map(|(i, stave)| {
+            entries_between(&stave.master, start, end)
+                .into_iter()
+                .find_map(|entry| match entry {
+                    Entry::Clef(clef) => Some((i, *stave, clef)),
+                    _ => None,
+                })
+        })
+        .collect();
+
+    if !first && key_signature.is_none() && time_signature.is_none() && clefs.is_empty() {
+        return;
+    }
+
+    xml.open("attributes", &[]);
+    if first {
+        xml.text("divisions", &[], &flow.subdivisions.to_string());
+    }
+    if let Some(key_signature) = key_signature {
+        let mode = match key_signature.mode {
+            KeySignatureMode::Major => "major",
+            KeySignatureMode::Minor => "minor",
+            KeySignatureMode::Open => "none",
+        };
+        xml.open("key", &[]);
+        xml.text("fifths", &[], &key_signature.offset.to_string());
+        xml.text("mode", &[], mode);
+        xml.close("key");
+    }
+    if let Some(time_signature) = time_signature {
+        write_time(xml, time_signature);
+    }
+    let multi_stave = part.staves.len() > 1;
+    if first && multi_stave {
+        xml.text("staves", &[], &part.staves.len().to_string());
+    }
+    for (i, stave, clef) in clefs {
+        write_clef(
+            xml,
+            clef,
+            stave,
+            if multi_stave { Some(i + 1) } else { None },
+        );
+    }
+    if first {
+        for (i, stave) in part.staves.iter().enumerate() {
+            let lines = stave.lines.iter().filter(|line| **line == 1).count();
+            if lines != 5 {
+                let number = (i + 1).to_string();
+                xml.open("staff-details", &[("number", &number)]);
+                xml.text("staff-lines", &[], &lines.to_string());
+                xml.close("staff-details");
+            }
+        }
+    }
+    xml.close("attributes");
+}
+
+/// Tempo markings are written once, in the first part
+fn write_directions(xml: &mut XmlWriter, flow: &Flow, start: u32, end: u32) {
+    for entry in entries_between(&flow.master, start, end) {
+        let tempo = match entry {
+            Entry::AbsoluteTempo(tempo) => tempo,
+            _ => continue,
+        };
+
+        xml.open("direction", &[("placement", "above")]);
+        if tempo.text_visible && !tempo.text.is_empty() {
+            xml.open("direction-type", &[]);
+            xml.text("words", &[], &tempo.text);
+            xml.close("direction-type");
+        }
+
+        let parentheses = if tempo.parenthesis_visible {
+            "yes"
+        } else {
+            "no"
+        };
+        let print_object = if tempo.bpm_visible { "yes" } else { "no" };
+        xml.open("direction-type", &[]);
+        xml.open(
+            "metronome",
+            &[("parentheses", parentheses), ("print-object", print_object)],
+        );
+        xml.text("beat-unit", &[], note_type(&tempo.beat_type));
+        for _ in 0..tempo.dotted {
+            xml.empty("beat-unit-dot", &[]);
+        }
+        xml.text("per-minute", &[], &tempo.bpm.to_string());
+        xml.close("metronome");
+        xml.close("direction-type");
+
+        if tempo.tick > start {
+            xml.text(
+                "offset",
+                &[("sound", "yes")],
+                &(tempo.tick - start).to_string(),
+            );
+        }
+        xml.empty("sound", &[("tempo", &tempo.normalized_bpm.to_string())]);
+        xml.close("direction");
+    }
+}
+
+/// Repeats starting at the start of the measure
+fn write_left_barline(xml: &mut XmlWriter, master: &Track, start: u32) {
+    for entry in entries_between(master, start, start + 1) {
+        if let Entry::Barline(barline) = entry {
+            if let BarlineType::StartRepeat | BarlineType::EndStartRepeat = barline.barline_type {
+                xml.open("barline", &[("location", "left")]);
+                xml.text("bar-style", &[], "heavy-light");
+                xml.empty("repeat", &[("direction", "forward")]);
+                xml.close("barline");
+            }
+        }
+    }
+}
+
+/// Barlines at the end of the measure
+fn write_right_barline(xml: &mut XmlWriter, master: &Track, end: u32) {
+    for entry in entries_between(master, end, end + 1) {
+        let barline = match entry {
+            Entry::Barline(barline) => barline,
+            _ => continue,
+        };
+        let (style, repeat) = match barline.barline_type {
+            BarlineType::Double => ("light-light", false),
+            BarlineType::Final => ("light-heavy", false),
+            BarlineType::EndRepeat | BarlineType::EndStartRepeat => ("light-heavy", true),
+            BarlineType::Normal | BarlineType::StartRepeat => continue,
+        };
+        xml.open("barline", &[("location", "right")]);
+        xml.text("bar-style", &[], style);
+        if repeat {
+            xml.empty("repeat", &[("direction", "backward")]);
+        }
+        xml.close("barline");
+    }
+}
+
+fn write_pitch(xml: &mut XmlWriter, pitch: &Pitch, percussive: bool) {
+    let pitch = pitch.written();
+
+    if percussive {
+        xml.open("unpitched", &[]);
+        xml.text("display-step", &[], pitch.letter());
+        xml.text("display-octave", &[], &pitch.octave().to_string());
+        xml.close("unpitched");
+    } else {
+        xml.open("pitch", &[]);
+        xml.text("step", &[], pitch.letter());
+        if alter(pitch.accidental) != 0 {
+            xml.text("alter", &[], &alter(pitch.accidental).to_string());
+        }
+        xml.text("octave", &[], &pitch.octave().to_string());
+        xml.close("pitch");
+    }
+}
+
+fn articulation_name(articulation: Articulation) -> Option<&'static str> {
+    match articulation {
+        Articulation::None => None,
+        Articulation::Staccato => Some("staccato"),
+        Articulation::Staccatissimo => Some("staccatissimo"),
+        Articulation::Tenuto => Some("tenuto"),
+        Articulation::StaccatoTenuto => Some("detached-legato"),
+    }
+}
+
+/// Write a notation as a rest, a note or the notes of a chord
+fn write_notation(xml: &mut XmlWriter, part: &Part, voice: &Voice, index: usize, bar_length: u32) {
+    let notation = &voice.notations[index];
+    let prev = index.checked_sub(1).and_then(|i| voice.notations.get(i));
+    let next = voice.notations.get(index + 1);
+
+    let tied_from = prev.is_some_and(|prev| prev.tie);
+    let tuplet = notation
+        .tuplet
+        .as_ref()
+        .and_then(|tuplet_key| voice.tuplets.get(tuplet_key));
+    let tuplet_start = tuplet.is_some() && prev.map(|prev| &prev.tuplet) != Some(&notation.tuplet);
+    let tuplet_stop = tuplet.is_some() && next.map(|next| &next.tuplet) != Some(&notation.tuplet);
+
+    let mut tones: Vec<Option<&Tone>> = notation
+        .tones
+        .iter()
+        .filter_map(|tone_key| match voice.track.entries.by_key.get(tone_key) {
+            Some(Entry::Tone(tone)) => Some(Some(tone)),
+            _ => None,
+        })
+        .collect();
+    tones.sort_by_key(|tone| tone.map(|tone| tone.pitch.int));
+    if tones.is_empty() {
+        tones.push(None);
+    }
+
+    let percussive = matches!(part.instrument.instrument_type, InstrumentType::Percussive);
+    let measure_rest = notation.is_rest() && notation.length == bar_length;
+    let duration = notation.length.to_string();
+    let voice_number = voice.number.to_string();
+    let staff = voice.staff.to_string();
+
+    for (i, tone) in tones.into_iter().enumerate() {
+        xml.open("note", &[]);
+        if i > 0 {
+            xml.empty("chord", &[]);
+        }
+        match tone {
+            Some(tone) => write_pitch(xml, &tone.pitch, percussive),
+            None if measure_rest => xml.empty("rest", &[("measure", "yes")]),
+            None => xml.empty("rest", &[]),
+        }
+        xml.text("duration", &[], &duration);
+        if tone.is_some() {
+            if tied_from {
+                xml.empty("tie", &[("type", "stop")]);
+            }
+            if notation.tie {
+                xml.empty("tie", &[("type", "start")]);
+            }
+        }
+        xml.text("voice", &[], &voice_number);
+        if !measure_rest {
+            xml.text("type", &[], note_type(&notation.duration.base));
+            for _ in 0..notation.duration.dots {
+                xml.empty("dot", &[]);
+            }
+        }
+        if let Some(tuplet) = tuplet {
+            xml.open("time-modification", &[]);
+            xml.text("actual-notes", &[], &tuplet.actual.to_string());
+            xml.text("normal-notes", &[], &tuplet.normal.to_string());
+            xml.close("time-modification");
+        }
+        if part.staves.len() > 1 {
+            xml.text("staff", &[], &staff);
+        }
+
+        let articulation = match tone {
+            Some(tone) if !tied_from => articulation_name(tone.articulation),
+            _ => None,
+        };
+        let tied = tone.is_some() && (tied_from || notation.tie);
+        let tuplet_marks = i == 0 && (tuplet_start || tuplet_stop);
+        if tied || tuplet_marks || articulation.is_some() {
+            xml.open("notations", &[]);
+            if tone.is_some() {
+                if tied_from {
+                    xml.empty("tied", &[("type", "stop")]);
+                }
+                if notation.tie {
+                    xml.empty("tied", &[("type", "start")]);
+                }
+            }
+            if i == 0 && tuplet_start {
+                xml.empty("tuplet", &[("type", "start"), ("bracket", "yes")]);
+            }
+            if i == 0 && tuplet_stop {
+                xml.empty("tuplet", &[("type", "stop")]);
+            }
+            if let Some(articulation) = articulation {
+                xml.open("articulations", &[]);
+                xml.empty(articulation, &[]);
+                xml.close("articulations");
+            }
+            xml.close("notations");
+        }
+        xml.close("note");
+    }
+}
+
+fn write_part(xml: &mut XmlWriter, flow: &Flow, part: &Part, conductor: bool) {
+    xml.open("part", &[("id", &part.id)]);
+
+    let mut cursors = vec![0; part.voices.len()];
+    let bars = measures(&flow.master, flow.length, flow.subdivisions);
+    // a pickup at the start of the flow is an uncounted measure 0
+    let pickup = match flow.master.get_time_signature_at_tick(0) {
+        Some(time_signature) => time_signature.pickup > 0,
+        None => false,
+    };
+    for (i, (start, end)) in bars.into_iter().enumerate() {
+        if pickup && i == 0 {
+            xml.open("measure", &[("number", "0"), ("implicit", "yes")]);
+        } else {
+            let number = if pickup { i } else { i + 1 }.to_string();
+            xml.open("measure", &[("number", &number)]);
+        }
+        write_left_barline(xml, &flow.master, start);
+        write_attributes(xml, flow, part, i == 0, start, end);
+        if conductor {
+            write_directions(xml, flow, start, end);
+        }
+
+        // each voice fills the bar, so step back to the start of the bar for the next
+        for (j, voice) in part.voices.iter().enumerate() {
+            if j > 0 {
+                xml.open("backup", &[]);
+                xml.text("duration", &[], &(end - start).to_string());
+                xml.close("backup");
+            }
+            while let Some(notation) = voice.notations.get(cursors[j]) {
+                if notation.tick >= end {
+                    break;
+                }
+                write_notation(xml, part, voice, cursors[j], end - start);
+                cursors[j] += 1;
+            }
+        }
+
+        write_right_barline(xml, &flow.master, end);
+        xml.close("measure");
+    }
+
+    xml.close("part");
+}
+
+/// Write a flow as a MusicXML partwise document, `number` is the movement number of the flow.
+pub fn export(score: &Score, flow: &Flow, number: usize) -> String {
+    let parts = parts(score, flow);
+
+    let mut xml = XmlWriter::new();
+    xml.raw("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>");
+    xml.raw(DOCTYPE);
+    xml.open("score-partwise", &[("version", "4.0")]);
+    write_header(&mut xml, score, flow, number);
+    write_part_list(&mut xml, &parts);
+    for (i, part) in parts.iter().enumerate() {
+        write_part(&mut xml, flow, part, i == 0);
+    }
+    xml.close("score-partwise");
+    xml.finish()
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl Engine {
+    /// Export a flow as a MusicXML document
+    pub fn export_musicxml(&self, flow_key: &str) -> Result<String> {
+        let score = &self.state.score;
+        let flow = match score.flows.by_key.get(flow_key) {
+            Some(flow) => flow,
+            None => return Err(Error::UnknownFlow(String::from(flow_key))),
+        };
+        let number = score
+            .flows
+            .order
+            .iter()
+            .position(|key| key == flow_key)
+            .unwrap_or(0);
+        Ok(export(score, flow, number + 1))
+    }
+}
+
+impl Engine {
+    /// Export every flow as MusicXML, one document per flow with each flow as a movement
+    pub fn export_score_musicxml(&self) -> Vec<String> {
+        let score = &self.state.score;
+        score
+            .flows
+            .order
+            .iter()
+            .filter_map(|flow_key| score.flows.by_key.get(flow_key))
+            .enumerate()
+            .map(|(i, flow)| export(score, flow, i + 1))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::score::config::AutoCountStyle;
+
+    fn score() -> Score {
+        serde_json::from_str(include_str!("../../../tests/fixtures/score_v2.json")).unwrap()
+    }
+
+    #[test]
+    fn test_export_flow() {
+        let score = score();
+        let flow = &score.flows.by_key["flow0000000001"];
+        let xml = export(&score, flow, 1);
+
+        assert!(xml.contains("<score-partwise version=\"4.0\">"));
+        assert!(xml.contains("<work-title>Sonata</work-title>"));
+        assert!(xml.contains("<movement-title>Allegro</movement-title>"));
+        assert!(xml.contains("<creator type=\"composer\">A. Composer</creator>"));
+        assert!(xml.contains("<rights>(c) 2020</rights>"));
+        assert!(xml.contains("<part-name>Violin</part-name>"));
+        assert!(xml.contains("<divisions>16</divisions>"));
+        assert!(xml.contains("<sign>G</sign>"));
+        assert!(xml.contains("<line>2</line>"));
+        assert!(xml.contains("<sound tempo=\"120\"/>"));
+
+        // the A then the Bb (stored with an invalid spelling), then a half rest
+        assert!(xml.contains("<step>A</step>"));
+        assert!(xml.contains("<step>B</step>"));
+        assert!(xml.contains("<alter>-1</alter>"));
+        assert!(xml.contains("<staccato/>"));
+        assert!(xml.contains("<type>half</type>"));
+        assert_eq!(xml.matches("<measure ").count(), 1);
+    }
+
+    #[test]
+    fn test_part_names() {
+        let mut score = score();
+        for instrument in score.instruments.values_mut() {
+            instrument.count = Some(2);
+        }
+        let flow = &score.flows.by_key["flow0000000001"];
+        let xml = export(&score, flow, 1);
+        assert!(xml.contains("<part-name>Violin II</part-name>"));
+
+        score.config.auto_count.solo = AutoCountStyle::Arabic;
+        score.config.auto_count.section = AutoCountStyle::Arabic;
+        let flow = &score.flows.by_key["flow0000000001"];
+        let xml = export(&score, flow, 1);
+        assert!(xml.contains("<part-name>Violin 2</part-name>"));
+    }
+
+    #[test]
+    fn test_ties_across_barlines() {
+        let mut score = score();
+        let flow = score.flows.by_key.get_mut("flow0000000001").unwrap();
+        flow.master.remove("EKHo8SEqk5s1ss");
+        flow.master.insert(TimeSignature::new(
+            String::from("time"),
+            0,
+            3,
+            crate::utils::duration::NoteDuration::Quarter,
+            TimeSignatureDrawType::Normal,
+            None,
+        ));
+        for entry in flow
+            .tracks
+            .values_mut()
+            .flat_map(|track| track.entries.by_key.values_mut())
+        {
+            if let Entry::Tone(tone) = entry {
+                if tone.tick == 16 {
+                    tone.duration.int = 48; // over the barline
+                }
+            }
+        }
+
+        let flow = &score.flows.by_key["flow0000000001"];
+        let xml = export(&score, flow, 1);
+        assert_eq!(xml.matches("<measure ").count(), 2);
+        assert!(xml.contains("<beats>3</beats>"));
+        assert_eq!(xml.matches("<tie type=\"start\"/>").count(), 1);
+        assert_eq!(xml.matches("<tie type=\"stop\"/>").count(), 1);
+    }
+
+    #[test]
+    fn test_pickup() {
+        let mut score = score();
+        let flow = score.flows.by_key.get_mut("flow0000000001").unwrap();
+        flow.master.remove("EKHo8SEqk5s1ss");
+        let mut time_signature = TimeSignature::new(
+            String::from("time"),
+            0,
+            3,
+            crate::utils::duration::NoteDuration::Quarter,
+            TimeSignatureDrawType::Normal,
+            None,
+        );
+        if let Entry::TimeSignature(time_signature) = &mut time_signature {
+            time_signature.pickup = 16;
+        }
+        flow.master.insert(time_signature);
+
+        let flow = &score.flows.by_key["flow0000000001"];
+        let xml = export(&score, flow, 1);
+        assert!(xml.contains("<measure number=\"0\" implicit=\"yes\">"));
+        assert!(xml.contains("<measure number=\"1\">"));
+        assert_eq!(xml.matches("<measure ").count(), 2);
+    }
+}
@@ -0,0 +1,1325 @@
+use crate::error::{Error, Result};
+use crate::formats::midi::PERCUSSION_CHANNEL;
+use crate::formats::musicxml::from_note_type;
+use crate::formats::plan::{
+    Fraction, ImportReport, ImportedMeta, Movement, Plan, PlannedClef, PlannedPart, PlannedTempo,
+    PlannedTimeSignature, PlannedTone, PlannedTuplet, Warnings,
+};
+use crate::formats::untokenize;
+use crate::formats::zip::{is_zip, unzip};
+use crate::state::entries::clef::ClefDrawType;
+use crate::state::entries::key_signature::KeySignatureMode;
+use crate::state::entries::time_signature::TimeSignatureDrawType;
+use crate::state::entries::tone::Articulation;
+use crate::state::score::instrument::defs::{
+    get_def, get_def_by_drum, get_def_by_program, InstrumentDef, INSTRUMENT_DEFS,
+};
+use crate::state::Engine;
+use crate::utils::duration::NoteDuration;
+use roxmltree::{Document, Node, ParsingOptions};
+use std::collections::HashMap;
+use std::ops::{Add, Sub};
+use std::str::FromStr;
+
+/// MusicXML treats a dynamics of 100 (forte) as a velocity of 90
+const DEFAULT_VELOCITY: u8 = 90;
+
+fn invalid(message: &str) -> Error {
+    Error::InvalidFile(format!("musicxml {}", message))
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|child| child.has_tag_name(name))
+}
+
+fn children<'a, 'input: 'a>(
+    node: Node<'a, 'input>,
+    name: &'a str,
+) -> impl Iterator<Item = Node<'a, 'input>> + 'a {
+    node.children()
+        .filter(move |child| child.has_tag_name(name))
+}
+
+fn child_text<'a>(node: Node<'a, '_>, name: &str) -> Option<&'a str> {
+    child(node, name)
+        .and_then(|child| child.text())
+        .map(|text| text.trim())
+        .filter(|text| !text.is_empty())
+}
+
+fn child_number<T: FromStr>(node: Node, name: &str) -> Option<T> {
+    child_text(node, name).and_then(|text| text.parse().ok())
+}
+
+fn from_beat_type(beat_type: u32) -> Option<NoteDuration> {
+    match beat_type {
+        1 => Some(NoteDuration::Whole),
+        2 => Some(NoteDuration::Half),
+        4 => Some(NoteDuration::Quarter),
+        8 => Some(NoteDuration::Eighth),
+        16 => Some(NoteDuration::Sixteenth),
+        32 => Some(NoteDuration::ThirtySecond),
+        _ => None,
+    }
+}
+
+/// The MIDI number of a note from its step, alteration and octave
+fn to_midi(step: &str, alter: f64, octave: i32) -> Option<u8> {
+    let pitch_class = match step {
+        "C" => 0,
+        "D" => 2,
+        "E" => 4,
+        "F" => 5,
+        "G" => 7,
+        "A" => 9,
+        "B" => 11,
+        _ => return None,
+    };
+    let int = (octave + 1) * 12 + pitch_class + alter.round() as i32;
+    if (0..128).contains(&int) {
+        Some(int as u8)
+    } else {
+        None
+    }
+}
+
+/// Files are usually UTF-8 but may be UTF-16 with a byte order mark
+fn decode(bytes: &[u8]) -> Result<String> {
+    let utf16 = |big_endian: bool| {
+        let units: Vec<u16> = bytes[2..]
+            .chunks_exact(2)
+            .map(|pair| match big_endian {
+                true => u16::from_be_bytes([pair[0], pair[1]]),
+                false => u16::from_le_bytes([pair[0], pair[1]]),
+            })
+            .collect();
+        String::from_utf16(&units).map_err(|_| invalid("is not valid UTF-16"))
+    };
+
+    match bytes {
+        [0xFE, 0xFF, ..] => utf16(true),
+        [0xFF, 0xFE, ..] => utf16(false),
+        [0xEF, 0xBB, 0xBF, rest @ ..] => {
+            String::from_utf8(rest.to_vec()).map_err(|_| invalid("is not valid UTF-8"))
+        }
+        _ => String::from_utf8(bytes.to_vec()).map_err(|_| invalid("is not valid UTF-8")),
+    }
+}
+
+fn parse_xml(text: &str) -> Result<Document<'_>> {
+    let options = ParsingOptions {
+        allow_dtd: true,
+        ..ParsingOptions::default()
+    };
+    Document::parse_with_options(text, options).map_err(|error| invalid(&error.to_string()))
+}
+
+/// The score documents to import. A compressed file names its main document in
+/// `META-INF/container.xml`, which may be an opus listing several scores.
+fn documents(bytes: &[u8], warnings: &mut Warnings) -> Result<Vec<String>> {
+    if !is_zip(bytes) {
+        return Ok(vec![decode(bytes)?]);
+    }
+
+    let files = unzip(bytes)?;
+    let find = |path: &str| {
+        files
+            .iter()
+            .find(|(name, _)| name == path)
+            .map(|(_, contents)| contents)
+    };
+
+    let container = find("META-INF/container.xml")
+        .map(|bytes| decode(bytes))
+        .transpose()?;
+    let root_path = match &container {
+        Some(container) => {
+            let container = parse_xml(container)?;
+            container
+                .descendants()
+                .find(|node| node.has_tag_name("rootfile"))
+                .and_then(|node| node.attribute("full-path"))
+                .map(String::from)
+        }
+        // without a container take the first MusicXML file
+        None => files
+            .iter()
+            .map(|(name, _)| name)
+            .find(|name| {
+                !name.starts_with("META-INF/")
+                    && (name.ends_with(".xml") || name.ends_with(".musicxml"))
+            })
+            .cloned(),
+    };
+    let root_path = root_path.ok_or_else(|| invalid("archive has no score"))?;
+    let root = decode(find(&root_path).ok_or_else(|| invalid("archive is missing its score"))?)?;
+
+    let hrefs: Vec<String> = {
+        let document = parse_xml(&root)?;
+        if !document.root_element().has_tag_name("opus") {
+            return Ok(vec![root]);
+        }
+        for _ in document
+            .descendants()
+            .filter(|node| node.has_tag_name("opus-link"))
+        {
+            warnings.push("opus-link", None, None, "linked opuses are not imported");
+        }
+        document
+            .descendants()
+            .filter(|node| node.has_tag_name("score"))
+            .filter_map(|node| {
+                node.attributes()
+                    .find(|attribute| attribute.name() == "href")
+                    .map(|attribute| String::from(attribute.value()))
+            })
+            .collect()
+    };
+
+    // scores are found relative to the opus
+    let directory = match root_path.rfind('/') {
+        Some(i) => &root_path[..i + 1],
+        None => "",
+    };
+    let mut documents = Vec::new();
+    for href in hrefs {
+        match find(&format!("{}{}", directory, href)).or_else(|| find(&href)) {
+            Some(bytes) => documents.push(decode(bytes)?),
+            None => warnings.push("score", None, None, "a score in the opus is missing"),
+        }
+    }
+    Ok(documents)
+}
+
+/// What we know about a part from the part list
+struct PartInfo {
+    id: String,
+    name: String,
+    abbreviation: String,
+    sound: Option<String>,
+    program: Option<u8>, // 1 based, as in the file
+    channel: Option<u8>, // 1 based, as in the file
+    unpitched: Option<u8>,
+}
+
+fn read_part_info(node: Node) -> PartInfo {
+    let midi = child(node, "midi-instrument");
+    PartInfo {
+        id: String::from(node.attribute("id").unwrap_or("")),
+        name: String::from(child_text(node, "part-name").unwrap_or("")),
+        abbreviation: String::from(child_text(node, "part-abbreviation").unwrap_or("")),
+        sound: child(node, "score-instrument")
+            .and_then(|instrument| child_text(instrument, "instrument-sound"))
+            .map(String::from),
+        program: midi.and_then(|midi| child_number(midi, "midi-program")),
+        channel: midi.and_then(|midi| child_number(midi, "midi-channel")),
+        unpitched: midi.and_then(|midi| child_number(midi, "midi-unpitched")),
+    }
+}
+
+/// Lower case letters and numbers only, with flats as 'b', for comparing names
+fn normalize(name: &str) -> String {
+    untokenize(name)
+        .replace('\u{266D}', "b")
+        .replace('\u{266F}', "#")
+        .to_lowercase()
+        .chars()
+        .filter(|c| c.is_alphanumeric() || *c == ' ' || *c == '#')
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+/// Find the def for a part, by its sound id, then its name, then its MIDI program
+fn find_def(info: &PartInfo) -> Option<&'static InstrumentDef> {
+    if let Some(sound) = &info.sound {
+        let def = INSTRUMENT_DEFS
+            .iter()
+            .find(|def| def.id == sound || sound.starts_with(&format!("{}.", def.id)));
+        if def.is_some() {
+            return def;
+        }
+    }
+
+    // the longest name found in the part name, so "Bass Clarinet" wins over "Clarinet"
+    let name = normalize(&info.name);
+    if !name.is_empty() {
+        let def = INSTRUMENT_DEFS
+            .iter()
+            .filter(|def| name.contains(&normalize(def.long_name)))
+            .max_by_key(|def| def.long_name.len());
+        if def.is_some() {
+            return def;
+        }
+    }
+
+    let abbreviation = normalize(&info.abbreviation);
+    if !abbreviation.is_empty() {
+        let def = INSTRUMENT_DEFS
+            .iter()
+            .find(|def| normalize(def.short_name) == abbreviation);
+        if def.is_some() {
+            return def;
+        }
+    }
+
+    match (info.channel, info.program) {
+        (Some(channel), _) if channel == PERCUSSION_CHANNEL + 1 => {
+            get_def_by_drum(info.unpitched.map_or(38, |key| key.saturating_sub(1)))
+        }
+        (_, Some(program)) => get_def_by_program(program.saturating_sub(1)),
+        _ => None,
+    }
+}
+
+struct NoteEvent {
+    staff: usize,
+    duration: Fraction,
+    pitch: Option<u8>, // none for a rest
+    velocity: u8,
+    articulation: Articulation,
+    tie_start: bool,
+    tie_stop: bool,
+    tuplet_start: Option<(u8, u8, u8)>, // number, actual, normal
+    tuplet_stop: Option<u8>,
+}
+
+enum Event {
+    Note(NoteEvent),
+    Time(PlannedTimeSignature),
+    Key(i8, KeySignatureMode),
+    Clef(usize, PlannedClef),
+    Tempo(PlannedTempo),
+}
+
+/// The music of a measure, positioned from the start of the measure
+struct MeasureContent {
+    number: String,
+    length: Fraction,
+    events: Vec<(Fraction, Event)>,
+}
+
+/// Things that carry on from measure to measure within a part
+struct PartState<'a> {
+    name: &'a str,
+    divisions: u64,
+    transpose: i32,
+    velocity: u8,
+}
+
+fn read_time(
+    node: Node,
+    warnings: &mut Warnings,
+    part: &str,
+    measure: &str,
+) -> Option<PlannedTimeSignature> {
+    let draw_type = if node.attribute("print-object") == Some("no") {
+        TimeSignatureDrawType::Hidden
+    } else {
+        match node.attribute("symbol") {
+            Some("common") => TimeSignatureDrawType::CommonTime,
+            Some("cut") => TimeSignatureDrawType::SplitCommonTime,
+            _ => TimeSignatureDrawType::Normal,
+        }
+    };
+
+    if child(node, "senza-misura").is_some() {
+        return Some(PlannedTimeSignature {
+            start: Fraction::zero(),
+            beats: 0,
+            beat_type: NoteDuration::Quarter,
+            draw_type,
+            groupings: None,
+        });
+    }
+
+    if children(node, "beats").count() > 1 {
+        warnings.push(
+            "time",
+            Some(part),
+            Some(measure),
+            "composite time signatures use the first part only",
+        );
+    }
+
+    // beats such as 3+2 set the groupings
+    let beats = child_text(node, "beats")?;
+    let groups: Vec<u8> = beats
+        .split('+')
+        .filter_map(|beat| beat.trim().parse().ok())
+        .collect();
+    let total: u32 = groups.iter().map(|beat| u32::from(*beat)).sum();
+    let beat_type = child_number(node, "beat-type").and_then(from_beat_type);
+    match beat_type {
+        Some(beat_type) if total > 0 && total <= u32::from(u8::MAX) => Some(PlannedTimeSignature {
+            start: Fraction::zero(),
+            beats: total as u8,
+            beat_type,
+            draw_type,
+            groupings: if groups.len() > 1 { Some(groups) } else { None },
+        }),
+        _ => {
+            warnings.push(
+                "time",
+                Some(part),
+                Some(measure),
+                "unsupported time signature",
+            );
+            None
+        }
+    }
+}
+
+fn read_clef(node: Node) -> Option<(usize, PlannedClef)> {
+    let staff = node
+        .attribute("number")
+        .and_then(|number| number.parse().ok())
+        .unwrap_or(1);
+    let line: i8 = child_number(node, "line").unwrap_or(0);
+    let octave: i8 = child_number(node, "clef-octave-change").unwrap_or(0);
+    let hidden = node.attribute("print-object") == Some("no");
+
+    // the pitch of the line the clef sits on and its default line
+    let (draw_as, pitch, default_line) = match child_text(node, "sign")? {
+        "G" => (ClefDrawType::G, 67i16, 2),
+        "F" => (ClefDrawType::F, 53, 4),
+        "C" => (ClefDrawType::C, 60, 3),
+        "percussion" => (ClefDrawType::Percussion, 60, 3),
+        "none" => (ClefDrawType::Hidden, 67, 2),
+        _ => return None,
+    };
+    let line = if line == 0 { default_line } else { line };
+
+    Some((
+        staff,
+        PlannedClef {
+            stave: staff.saturating_sub(1),
+            start: Fraction::zero(),
+            pitch: (pitch + i16::from(octave) * 12).clamp(0, 127) as u8,
+            offset: (line - 3) * 2,
+            draw_as: if hidden {
+                ClefDrawType::Hidden
+            } else {
+                draw_as
+            },
+        },
+    ))
+}
+
+fn read_attributes(
+    node: Node,
+    position: Fraction,
+    state: &mut PartState,
+    events: &mut Vec<(Fraction, Event)>,
+    warnings: &mut Warnings,
+    measure: &str,
+) {
+    let part = state.name;
+    for child_node in node.children().filter(|node| node.is_element()) {
+        match child_node.tag_name().name() {
+            "divisions" => {
+                if let Some(divisions) = child_node.text().and_then(|text| text.trim().parse().ok())
+                {
+                    state.divisions = u64::max(divisions, 1);
+                }
+            }
+            "key" => {
+                // keys for a single stave are not supported, nor are non-traditional keys
+                if child_node
+                    .attribute("number")
+                    .is_some_and(|number| number != "1")
+                {
+                    continue;
+                }
+                match child_number::<i8>(child_node, "fifths") {
+                    Some(fifths) => {
+                        let mode = match child_text(child_node, "mode") {
+                            Some("minor") => KeySignatureMode::Minor,
+                            Some("none") => KeySignatureMode::Open,
+                            _ => KeySignatureMode::Major,
+                        };
+                        events.push((position, Event::Key(fifths.clamp(-7, 7), mode)));
+                    }
+                    None => warnings.push(
+                        "key",
+                        Some(part),
+                        Some(measure),
+                        "non-traditional key signatures are not supported",
+                    ),
+                }
+            }
+            "time" => {
+                if let Some(time_signature) = read_time(child_node, warnings, part, measure) {
+                    events.push((position, Event::Time(time_signature)));
+                }
+            }
+            "clef" => match read_clef(child_node) {
+                Some((staff, clef)) => events.push((position, Event::Clef(staff, clef))),
+                None => warnings.push("clef", Some(part), Some(measure), "unsupported clef"),
+            },
+            "transpose" => {
+                let chromatic: i32 = child_number(child_node, "chromatic").unwrap_or(0);
+                let octave: i32 = child_number(child_node, "octave-change").unwrap_or(0);
+                state.transpose = chromatic + octave * 12;
+            }
+            "staves" | "instruments" | "part-symbol" | "staff-details" | "footnote" | "level" => (),
+            name => warnings.push(name, Some(part), Some(measure), "not supported"),
+        }
+    }
+}
+
+fn read_note(node: Node, state: &PartState, warnings: &mut Warnings, measure: &str) -> NoteEvent {
+    let part = state.name;
+    let pitch = if let Some(pitch) = child(node, "pitch") {
+        let step = child_text(pitch, "step").unwrap_or("");
+        let alter = child_number(pitch, "alter").unwrap_or(0.0);
+        let octave = child_number(pitch, "octave").unwrap_or(4);
+        to_midi(step, alter, octave)
+            .map(|int| (i32::from(int) + state.transpose).clamp(0, 127) as u8)
+    } else if let Some(unpitched) = child(node, "unpitched") {
+        let step = child_text(unpitched, "display-step").unwrap_or("B");
+        let octave = child_number(unpitched, "display-octave").unwrap_or(4);
+        to_midi(step, 0.0, octave)
+    } else {
+        None
+    };
+
+    let velocity = match node
+        .attribute("dynamics")
+        .and_then(|value| value.parse::<f64>().ok())
+    {
+        Some(dynamics) => (dynamics * f64::from(DEFAULT_VELOCITY) / 100.0)
+            .round()
+            .clamp(1.0, 127.0) as u8,
+        None => state.velocity,
+    };
+
+    let mut note = NoteEvent {
+        staff: child_number(node, "staff").unwrap_or(1).max(1),
+        duration: Fraction::new(child_number(node, "duration").unwrap_or(0), state.divisions),
+        pitch,
+        velocity,
+        articulation: Articulation::None,
+        tie_start: false,
+        tie_stop: false,
+        tuplet_start: None,
+        tuplet_stop: None,
+    };
+
+    for tie in children(node, "tie") {
+        match tie.attribute("type") {
+            Some("start") => note.tie_start = true,
+            Some("stop") => note.tie_stop = true,
+            _ => (),
+        }
+    }
+
+    let modification = child(node, "time-modification");
+    let actual = modification.and_then(|node| child_number(node, "actual-notes"));
+    let normal = modification.and_then(|node| child_number(node, "normal-notes"));
+
+    for notations in children(node, "notations") {
+        for notation in notations.children().filter(|node| node.is_element()) {
+            match notation.tag_name().name() {
+                "tied" => match notation.attribute("type") {
+                    Some("start") => note.tie_start = true,
+                    Some("stop") => note.tie_stop = true,
+                    _ => (),
+                },
+                "tuplet" => {
+                    let number = notation
+                        .attribute("number")
+                        .and_then(|number| number.parse().ok())
+                        .unwrap_or(1);
+                    match (notation.attribute("type"), actual, normal) {
+                        (Some("start"), Some(actual), Some(normal)) => {
+                            note.tuplet_start = Some((number, actual, normal))
+                        }
+                        (Some("stop"), _, _) => note.tuplet_stop = Some(number),
+                        _ => (),
+                    }
+                }
+                "articulations" => {
+                    for articulation in notation.children().filter(|node| node.is_element()) {
+                        note.articulation = match articulation.tag_name().name() {
+                            "staccato" => Articulation::Staccato,
+                            "staccatissimo" | "spiccato" => Articulation::Staccatissimo,
+                            "tenuto" => Articulation::Tenuto,
+                            "detached-legato" => Articulation::StaccatoTenuto,
+                            name => {
+                                warnings.push(
+                                    name,
+                                    Some(part),
+                                    Some(measure),
+                                    "articulation not supported",
+                                );
+                                continue;
+                            }
+                        }
+                    }
+                }
+                "footnote" | "level" => (),
+                name => warnings.push(name, Some(part), Some(measure), "not supported"),
+            }
+        }
+    }
+
+    for name in ["lyric", "notehead-text", "play"].iter() {
+        if child(node, name).is_some() {
+            warnings.push(name, Some(part), Some(measure), "not supported");
+        }
+    }
+
+    note
+}
+
+/// Read a tempo from a direction, the text of the direction becomes the tempo text
+fn read_direction(
+    node: Node,
+    state: &mut PartState,
+    warnings: &mut Warnings,
+    measure: &str,
+) -> Option<PlannedTempo> {
+    let part = state.name;
+    let mut words = Vec::new();
+    let mut metronome = None;
+
+    for direction_type in children(node, "direction-type") {
+        for element in direction_type.children().filter(|node| node.is_element()) {
+            match element.tag_name().name() {
+                "words" => words.extend(element.text().map(|text| text.trim())),
+                "metronome" => {
+                    let beat_type = child_text(element, "beat-unit").and_then(from_note_type);
+                    let bpm = child_text(element, "per-minute").and_then(|text| {
+                        text.split(|c: char| !c.is_ascii_digit() && c != '.')
+                            .find(|text| !text.is_empty())
+                            .and_then(|text| text.parse::<f64>().ok())
+                    });
+                    match (beat_type, bpm) {
+                        (Some(beat_type), Some(bpm))
+                            if children(element, "beat-unit").count() == 1 =>
+                        {
+                            metronome = Some((
+                                beat_type,
+                                children(element, "beat-unit-dot").count() as u8,
+                                bpm.round() as u32,
+                                element.attribute("parentheses") == Some("yes"),
+                                element.attribute("print-object") != Some("no"),
+                            ))
+                        }
+                        _ => warnings.push(
+                            "metronome",
+                            Some(part),
+                            Some(measure),
+                            "only metronome marks with a single beat unit are supported",
+                        ),
+                    }
+                }
+                name => warnings.push(name, Some(part), Some(measure), "not supported"),
+            }
+        }
+    }
+
+    let sound = child(node, "sound");
+    if let Some(dynamics) = sound
+        .and_then(|sound| sound.attribute("dynamics"))
+        .and_then(|value| value.parse::<f64>().ok())
+    {
+        state.velocity = (dynamics * f64::from(DEFAULT_VELOCITY) / 100.0)
+            .round()
+            .clamp(1.0, 127.0) as u8;
+    }
+    let sound_tempo = sound
+        .and_then(|sound| sound.attribute("tempo"))
+        .and_then(|value| value.parse::<f64>().ok())
+        .filter(|tempo| *tempo > 0.0);
+
+    let text = words.join(" ");
+    match (metronome, sound_tempo) {
+        (Some((beat_type, dotted, bpm, parenthesis_visible, bpm_visible)), _) => {
+            Some(PlannedTempo {
+                start: Fraction::zero(),
+                text_visible: !text.is_empty(),
+                text,
+                beat_type,
+                dotted,
+                bpm,
+                parenthesis_visible,
+                bpm_visible,
+            })
+        }
+        (None, Some(tempo)) => Some(PlannedTempo {
+            start: Fraction::zero(),
+            text_visible: !text.is_empty(),
+            text,
+            beat_type: NoteDuration::Quarter,
+            dotted: 0,
+            bpm: tempo.round() as u32,
+            parenthesis_visible: false,
+            bpm_visible: false,
+        }),
+        (None, None) => {
+            if !text.is_empty() {
+                warnings.push(
+                    "words",
+                    Some(part),
+                    Some(measure),
+                    "text directions are not supported",
+                );
+            }
+            None
+        }
+    }
+}
+
+fn read_measure(node: Node, state: &mut PartState, warnings: &mut Warnings) -> MeasureContent {
+    let number = String::from(node.attribute("number").unwrap_or(""));
+    let part = state.name;
+    let mut events = Vec::new();
+    let mut position = Fraction::zero();
+    let mut last_start = Fraction::zero();
+    let mut length = Fraction::zero();
+
+    for element in node.children().filter(|node| node.is_element()) {
+        match element.tag_name().name() {
+            "attributes" => {
+                read_attributes(element, position, state, &mut events, warnings, &number)
+            }
+            "note" => {
+                if child(element, "grace").is_some() {
+                    warnings.push(
+                        "grace",
+                        Some(part),
+                        Some(&number),
+                        "grace notes are left out",
+                    );
+                    continue;
+                }
+                if child(element, "cue").is_some() {
+                    warnings.push("cue", Some(part), Some(&number), "cue notes are left out");
+                    continue;
+                }
+                let note = read_note(element, state, warnings, &number);
+                let duration = note.duration;
+                if child(element, "chord").is_some() {
+                    events.push((last_start, Event::Note(note)));
+                } else {
+                    events.push((position, Event::Note(note)));
+                    last_start = position;
+                    position = position.add(duration);
+                }
+            }
+            "backup" => {
+                let duration = Fraction::new(
+                    child_number(element, "duration").unwrap_or(0),
+                    state.divisions,
+                );
+                position = position.sub(duration);
+            }
+            "forward" => {
+                let duration = Fraction::new(
+                    child_number(element, "duration").unwrap_or(0),
+                    state.divisions,
+                );
+                position = position.add(duration);
+            }
+            "direction" => {
+                // directions may be offset from where they are written
+                let offset: i64 = child_number(element, "offset").unwrap_or(0);
+                let offset = Fraction::new(offset.unsigned_abs(), state.divisions);
+                let at = if offset.is_zero() {
+                    position
+                } else if child_number::<i64>(element, "offset").unwrap_or(0) < 0 {
+                    position.sub(offset)
+                } else {
+                    position.add(offset)
+                };
+                if let Some(tempo) = read_direction(element, state, warnings, &number) {
+                    events.push((at, Event::Tempo(tempo)));
+                }
+            }
+            "sound" => {
+                let tempo = element
+                    .attribute("tempo")
+                    .and_then(|value| value.parse::<f64>().ok());
+                if let Some(tempo) = tempo.filter(|tempo| *tempo > 0.0) {
+                    events.push((
+                        position,
+                        Event::Tempo(PlannedTempo {
+                            start: Fraction::zero(),
+                            text: String::new(),
+                            beat_type: NoteDuration::Quarter,
+                            dotted: 0,
+                            bpm: tempo.round() as u32,
+                            parenthesis_visible: false,
+                            text_visible: false,
+                            bpm_visible: false,
+                        }),
+                    ));
+                }
+            }
+            "barline" => {
+                if child(element, "repeat").is_some() || child(element, "ending").is_some() {
+                    warnings.push(
+                        "barline",
+                        Some(part),
+                        Some(&number),
+                        "repeats and endings are not supported",
+                    );
+                }
+            }
+            "print" | "bookmark" | "link" | "grouping" | "listening" => (),
+            name => warnings.push(name, Some(part), Some(&number), "not supported"),
+        }
+        length = length.max(position);
+    }
+
+    MeasureContent {
+        number,
+        length,
+        events,
+    }
+}
+
+/// Lay out the measures of a score and collect its parts
+fn read_score(document: &Document, warnings: &mut Warnings) -> Result<(ImportedMeta, Movement)> {
+    let root = document.root_element();
+
+    // each part's measures, as the element holding the music of that measure
+    let mut part_measures: HashMap<String, Vec<Node>> = HashMap::new();
+    match root.tag_name().name() {
+        "score-partwise" => {
+            for part in children(root, "part") {
+                let id = String::from(part.attribute("id").unwrap_or(""));
+                part_measures
+                    .entry(id)
+                    .or_default()
+                    .extend(children(part, "measure"));
+            }
+        }
+        "score-timewise" => {
+            for measure in children(root, "measure") {
+                for part in children(measure, "part") {
+                    let id = String::from(part.attribute("id").unwrap_or(""));
+                    part_measures.entry(id).or_default().push(part);
+                }
+            }
+        }
+        name => return Err(invalid(&format!("root {} is not a score", name))),
+    }
+
+    let part_list = child(root, "part-list").ok_or_else(|| invalid("has no part list"))?;
+    let infos: Vec<PartInfo> = children(part_list, "score-part")
+        .map(read_part_info)
+        .collect();
+
+    // read every part, measure by measure
+    let mut contents: Vec<(&PartInfo, Vec<MeasureContent>)> = Vec::new();
+    for info in infos.iter() {
+        let nodes = match part_measures.get(&info.id) {
+            Some(nodes) => nodes,
+            None => continue,
+        };
+        let name = if info.name.is_empty() {
+            &info.id
+        } else {
+            &info.name
+        };
+        let mut state = PartState {
+            name,
+            divisions: 1,
+            transpose: 0,
+            velocity: DEFAULT_VELOCITY,
+        };
+
+        // in timewise files the measure number is on the parent
+        let measures = nodes
+            .iter()
+            .map(|node| {
+                let mut measure = read_measure(*node, &mut state, warnings);
+                if measure.number.is_empty() {
+                    if let Some(number) = node
+                        .parent_element()
+                        .and_then(|parent| parent.attribute("number"))
+                    {
+                        measure.number = String::from(number);
+                    }
+                }
+                measure
+            })
+            .collect();
+        contents.push((info, measures));
+    }
+    if contents.is_empty() {
+        return Err(invalid("has no parts"));
+    }
+
+    // bars are laid out from the time signatures of the first part and the longest part of each
+    let count = contents
+        .iter()
+        .map(|(_, measures)| measures.len())
+        .max()
+        .unwrap_or(0);
+    let mut starts = Vec::with_capacity(count); // where each measure starts in the flow
+    let mut origins = Vec::with_capacity(count); // where the music of each measure starts
+    let mut time_signatures: Vec<PlannedTimeSignature> = Vec::new();
+    let mut bar_length: Option<Fraction> = None;
+    let mut cursor = Fraction::zero();
+    let first_name = contents[0].0.name.clone();
+
+    for i in 0..count {
+        let mut number = String::new();
+        if let Some(measure) = contents[0].1.get(i) {
+            number = measure.number.clone();
+            for (_, event) in measure.events.iter() {
+                if let Event::Time(time_signature) = event {
+                    bar_length = match time_signature.beats {
+                        0 => None,
+                        beats => Some(Fraction::new(
+                            4 * u64::from(beats),
+                            u64::from(time_signature.beat_type.to_int()),
+                        )),
+                    };
+                    time_signatures.push(PlannedTimeSignature {
+                        start: cursor,
+                        beats: time_signature.beats,
+                        beat_type: time_signature.beat_type.clone(),
+                        draw_type: time_signature.draw_type,
+                        groupings: time_signature.groupings.clone(),
+                    });
+                }
+            }
+        }
+
+        let actual = contents
+            .iter()
+            .filter_map(|(_, measures)| measures.get(i))
+            .map(|measure| measure.length)
+            .max()
+            .unwrap_or_else(Fraction::zero);
+
+        let (length, offset) = match bar_length {
+            Some(bar_length) if actual.is_zero() => (bar_length, Fraction::zero()),
+            Some(bar_length) if actual < bar_length && i == 0 => {
+                warnings.push(
+                    "measure",
+                    Some(&first_name),
+                    Some(&number),
+                    "the pickup bar is written as a full bar",
+                );
+                (bar_length, bar_length.sub(actual))
+            }
+            Some(bar_length) if actual < bar_length => {
+                warnings.push(
+                    "measure",
+                    Some(&first_name),
+                    Some(&number),
+                    "short measures are filled with rests",
+                );
+                (bar_length, Fraction::zero())
+            }
+            Some(bar_length) if actual > bar_length => {
+                warnings.push(
+                    "measure",
+                    Some(&first_name),
+                    Some(&number),
+                    "long measures are filled to whole bars",
+                );
+                let mut length = bar_length;
+                while length < actual {
+                    length = length.add(bar_length);
+                }
+                (length, Fraction::zero())
+            }
+            Some(bar_length) => (bar_length, Fraction::zero()),
+            None => (actual, Fraction::zero()),
+        };
+
+        starts.push(cursor);
+        origins.push(cursor.add(offset));
+        cursor = cursor.add(length);
+    }
+
+    // signatures and directions at the start of a measure go at the start of the bar,
+    // even in a pickup, while notes keep their place
+    let place = |i: usize, position: Fraction, event: &Event| {
+        if position.is_zero() && !matches!(event, Event::Note(_)) {
+            starts[i]
+        } else {
+            origins[i].add(position)
+        }
+    };
+
+    let mut key_signatures = Vec::new();
+    let mut tempos: Vec<PlannedTempo> = Vec::new();
+    let mut parts = Vec::new();
+
+    for (p, (info, measures)) in contents.into_iter().enumerate() {
+        let name = if info.name.is_empty() {
+            info.id.clone()
+        } else {
+            info.name.clone()
+        };
+        let def = match find_def(info) {
+            Some(def) => def,
+            None => {
+                warnings.push(
+                    "score-part",
+                    Some(&name),
+                    None,
+                    "no matching instrument, imported as a piano",
+                );
+                get_def("keyboard.piano").ok_or_else(|| invalid("has no piano"))?
+            }
+        };
+        let staves = def.staves.len().max(1);
+
+        let mut tones: Vec<PlannedTone> = Vec::new();
+        let mut tuplets = Vec::new();
+        let mut clefs = Vec::new();
+        let mut open_ties: HashMap<(usize, u8), usize> = HashMap::new();
+        let mut open_tuplets: HashMap<(usize, u8), (Fraction, u8, u8)> = HashMap::new();
+
+        for (i, measure) in measures.into_iter().enumerate() {
+            for (position, event) in measure.events {
+                let start = place(i, position, &event);
+                match event {
+                    Event::Note(note) => {
+                        let stave = note.staff - 1;
+                        if stave >= staves {
+                            warnings.push(
+                                "staff",
+                                Some(&name),
+                                Some(&measure.number),
+                                "notes on extra staves are moved to the last stave",
+                            );
+                        }
+                        let stave = stave.min(staves - 1);
+                        let end = start.add(note.duration);
+
+                        if let Some((number, actual, normal)) = note.tuplet_start {
+                            open_tuplets.insert((stave, number), (start, actual, normal));
+                        }
+
+                        if let Some(pitch) = note.pitch.filter(|_| !note.duration.is_zero()) {
+                            // tied notes carry on the tone they are tied from
+                            let tied = open_ties.remove(&(stave, pitch)).filter(|&index| {
+                                note.tie_stop
+                                    && tones[index].start.add(tones[index].duration) == start
+                            });
+                            let index = match tied {
+                                Some(index) => {
+                                    tones[index].duration = end.sub(tones[index].start);
+                                    index
+                                }
+                                None => {
+                                    tones.push(PlannedTone {
+                                        stave,
+                                        start,
+                                        duration: note.duration,
+                                        pitch,
+                                        velocity: note.velocity,
+                                        articulation: note.articulation,
+                                    });
+                                    tones.len() - 1
+                                }
+                            };
+                            if note.tie_start {
+                                open_ties.insert((stave, pitch), index);
+                            }
+                        }
+
+                        if let Some(number) = note.tuplet_stop {
+                            if let Some((start, actual, normal)) =
+                                open_tuplets.remove(&(stave, number))
+                            {
+                                tuplets.push(PlannedTuplet {
+                                    stave,
+                                    start,
+                                    end,
+                                    actual,
+                                    normal,
+                                });
+                            }
+                        }
+                    }
+                    Event::Clef(staff, mut clef) => {
+                        if staff > staves {
+                            continue;
+                        }
+                        clef.start = start;
+                        clefs.push(clef);
+                    }
+                    Event::Key(offset, mode) if p == 0 => {
+                        key_signatures.push((start, offset, mode))
+                    }
+                    Event::Tempo(mut tempo) => {
+                        // tempos are often repeated in every part
+                        if !tempos.iter().any(|other| other.start == start) {
+                            tempo.start = start;
+                            tempos.push(tempo);
+                        }
+                    }
+                    Event::Key(..) | Event::Time(_) => (),
+                }
+            }
+        }
+
+        parts.push(PlannedPart {
+            id: info.id.clone(),
+            name,
+            def,
+            tones,
+            tuplets,
+            clefs,
+        });
+    }
+    tempos.sort_by_key(|tempo| tempo.start);
+
+    let mut meta = ImportedMeta::default();
+    let work_title = child(root, "work").and_then(|work| child_text(work, "work-title"));
+    let movement_title = child_text(root, "movement-title");
+    meta.title = work_title.or(movement_title).map(String::from);
+    if let Some(identification) = child(root, "identification") {
+        for creator in children(identification, "creator") {
+            let name = creator.text().map(|text| String::from(text.trim()));
+            match creator.attribute("type") {
+                Some("composer") => meta.composer = name,
+                Some("arranger") => meta.arranger = name,
+                Some("lyricist") | Some("poet") => meta.lyricist = name,
+                _ => (),
+            }
+        }
+        meta.copyright = child_text(identification, "rights").map(String::from);
+    }
+    for credit in children(root, "credit") {
+        if child_text(credit, "credit-type") == Some("subtitle") {
+            meta.subtitle = child_text(credit, "credit-words").map(String::from);
+        }
+    }
+
+    Ok((
+        meta,
+        Movement {
+            title: movement_title.or(work_title).map(String::from),
+            length: cursor,
+            time_signatures,
+            key_signatures,
+            tempos,
+            parts,
+        },
+    ))
+}
+
+/// Read a MusicXML file, or compressed .mxl archive, into flows to create
+pub fn plan(bytes: &[u8]) -> Result<Plan> {
+    let mut warnings = Warnings::default();
+    let mut meta = ImportedMeta::default();
+    let mut movements = Vec::new();
+
+    for text in documents(bytes, &mut warnings)? {
+        let document = parse_xml(&text)?;
+        let (document_meta, movement) = read_score(&document, &mut warnings)?;
+
+        // the first movement to say wins
+        meta.title = meta.title.or(document_meta.title);
+        meta.subtitle = meta.subtitle.or(document_meta.subtitle);
+        meta.composer = meta.composer.or(document_meta.composer);
+        meta.arranger = meta.arranger.or(document_meta.arranger);
+        meta.lyricist = meta.lyricist.or(document_meta.lyricist);
+        meta.copyright = meta.copyright.or(document_meta.copyright);
+        movements.push(movement);
+    }
+
+    Ok(Plan {
+        meta,
+        movements,
+        warnings: warnings.list,
+    })
+}
+
+impl Engine {
+    /// Import a MusicXML file (partwise, timewise or compressed .mxl), each score
+    /// in the file becomes a new flow with a player for each part.
+    ///
+    /// Returns the new flows and a list of warnings for anything that couldn't be imported.
+    pub fn import_musicxml(&mut self, bytes: &[u8]) -> Result<ImportReport> {
+        let plan = plan(bytes)?;
+
+        self.begin_transaction(Some(String::from("Import MusicXML")));
+        // the listener is sent the whole state once we are done rather than every step
+        self.patches.resync();
+        let report = self.create_planned_flows(&plan);
+        self.commit()?;
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::plan::from_quarters;
+    use crate::formats::zip::tests::archive;
+
+    const PARTWISE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE score-partwise PUBLIC "-//Recordare//DTD MusicXML 4.0 Partwise//EN" "http://www.musicxml.org/dtds/partwise.dtd">
+<score-partwise version="4.0">
+  <work><work-title>Sonata</work-title></work>
+  <movement-title>Allegro</movement-title>
+  <identification><creator type="composer">A. Composer</creator></identification>
+  <part-list>
+    <score-part id="P1"><part-name>Violin</part-name></score-part>
+  </part-list>
+  <part id="P1">
+    <measure number="0" implicit="yes">
+      <attributes>
+        <divisions>6</divisions>
+        <key><fifths>-1</fifths><mode>minor</mode></key>
+        <time><beats>2</beats><beat-type>4</beat-type></time>
+        <clef><sign>G</sign><line>2</line></clef>
+      </attributes>
+      <direction><direction-type><words>Allegro</words><metronome><beat-unit>quarter</beat-unit><per-minute>120</per-minute></metronome></direction-type></direction>
+      <note><pitch><step>A</step><octave>4</octave></pitch><duration>6</duration><type>quarter</type></note>
+    </measure>
+    <measure number="1">
+      <note><pitch><step>B</step><alter>-1</alter><octave>4</octave></pitch><duration>6</duration><tie type="start"/><type>quarter</type><notations><articulations><staccato/></articulations></notations></note>
+      <note><pitch><step>B</step><alter>-1</alter><octave>4</octave></pitch><duration>6</duration><tie type="stop"/><type>quarter</type><notations><tied type="stop"/></notations></note>
+    </measure>
+    <measure number="2">
+      <note><pitch><step>C</step><octave>5</octave></pitch><duration>2</duration><time-modification><actual-notes>3</actual-notes><normal-notes>2</normal-notes></time-modification><notations><tuplet type="start"/></notations></note>
+      <note><pitch><step>D</step><octave>5</octave></pitch><duration>2</duration><time-modification><actual-notes>3</actual-notes><normal-notes>2</normal-notes></time-modification></note>
+      <note><pitch><step>E</step><octave>5</octave></pitch><duration>2</duration><time-modification><actual-notes>3</actual-notes><normal-notes>2</normal-notes></time-modification><notations><tuplet type="stop"/><fermata/></notations></note>
+      <note><rest/><duration>6</duration></note>
+      <note><grace/><pitch><step>F</step><octave>5</octave></pitch><type>eighth</type></note>
+    </measure>
+  </part>
+</score-partwise>"#;
+
+    const TIMEWISE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<score-timewise version="4.0">
+  <part-list>
+    <score-part id="P1"><part-name>Violin</part-name></score-part>
+  </part-list>
+  <measure number="1">
+    <part id="P1">
+      <attributes><divisions>1</divisions><time><beats>2</beats><beat-type>4</beat-type></time></attributes>
+      <note><pitch><step>A</step><octave>4</octave></pitch><duration>2</duration></note>
+    </part>
+  </measure>
+  <measure number="2">
+    <part id="P1">
+      <note><pitch><step>B</step><octave>4</octave></pitch><duration>1</duration></note>
+      <note><rest/><duration>1</duration></note>
+    </part>
+  </measure>
+</score-timewise>"#;
+
+    #[test]
+    fn test_plan_partwise() {
+        let plan = plan(PARTWISE.as_bytes()).unwrap();
+        assert_eq!(plan.meta.title, Some(String::from("Sonata")));
+        assert_eq!(plan.meta.composer, Some(String::from("A. Composer")));
+        assert_eq!(plan.movements.len(), 1);
+
+        let movement = &plan.movements[0];
+        assert_eq!(movement.title, Some(String::from("Allegro")));
+        // the pickup is written as a full bar
+        assert_eq!(movement.length, Fraction::new(6, 1));
+        assert_eq!(movement.time_signatures.len(), 1);
+        assert_eq!(movement.time_signatures[0].beats, 2);
+        assert_eq!(movement.key_signatures[0].1, -1);
+        assert_eq!(movement.key_signatures[0].2, KeySignatureMode::Minor);
+        assert_eq!(movement.tempos.len(), 1);
+        assert_eq!(movement.tempos[0].bpm, 120);
+        assert_eq!(movement.tempos[0].text, "Allegro");
+        assert_eq!(movement.subdivisions(), Some(48));
+
+        let part = &movement.parts[0];
+        assert_eq!(part.def.id, "strings.violin");
+        assert_eq!(part.clefs.len(), 1);
+        assert_eq!(part.clefs[0].pitch, 67);
+        assert_eq!(part.clefs[0].offset, -2);
+
+        let tones: Vec<(Fraction, Fraction, u8)> = part
+            .tones
+            .iter()
+            .map(|tone| (tone.start, tone.duration, tone.pitch))
+            .collect();
+        assert_eq!(
+            tones,
+            vec![
+                (Fraction::new(1, 1), Fraction::new(1, 1), 69),
+                (Fraction::new(2, 1), Fraction::new(2, 1), 70),
+                (Fraction::new(4, 1), Fraction::new(1, 3), 72),
+                (Fraction::new(13, 3), Fraction::new(1, 3), 74),
+                (Fraction::new(14, 3), Fraction::new(1, 3), 76),
+            ]
+        );
+        assert!(matches!(part.tones[1].articulation, Articulation::Staccato));
+
+        assert_eq!(part.tuplets.len(), 1);
+        assert_eq!(part.tuplets[0].start, Fraction::new(4, 1));
+        assert_eq!(part.tuplets[0].end, Fraction::new(5, 1));
+        assert_eq!(
+            from_quarters((part.tuplets[0].end - part.tuplets[0].start) / 2),
+            Some(NoteDuration::Eighth)
+        );
+
+        let elements: Vec<&str> = plan
+            .warnings
+            .iter()
+            .map(|warning| warning.element.as_str())
+            .collect();
+        assert!(elements.contains(&"grace"));
+        assert!(elements.contains(&"fermata"));
+        assert!(elements.contains(&"measure"));
+    }
+
+    #[test]
+    fn test_plan_timewise_and_compressed() {
+        let plan = plan(TIMEWISE.as_bytes()).unwrap();
+        let movement = &plan.movements[0];
+        assert_eq!(movement.length, Fraction::new(4, 1));
+        assert_eq!(movement.subdivisions(), Some(16));
+        let starts: Vec<Fraction> = movement.parts[0]
+            .tones
+            .iter()
+            .map(|tone| tone.start)
+            .collect();
+        assert_eq!(starts, vec![Fraction::zero(), Fraction::new(2, 1)]);
+        assert!(plan.warnings.is_empty());
+
+        let container = br#"<?xml version="1.0" encoding="UTF-8"?>
+<container><rootfiles><rootfile full-path="music/score.xml"/></rootfiles></container>"#;
+        let files: [(&str, &[u8]); 3] = [
+            ("mimetype", b"application/vnd.recordare.musicxml"),
+            ("META-INF/container.xml", container),
+            ("music/score.xml", PARTWISE.as_bytes()),
+        ];
+        let plan = super::plan(&archive(&files, 8)).unwrap();
+        assert_eq!(plan.movements.len(), 1);
+        assert_eq!(plan.movements[0].parts[0].tones.len(), 5);
+    }
+
+    #[test]
+    fn test_find_def() {
+        let info = |name: &str, sound: Option<&str>, program: Option<u8>| PartInfo {
+            id: String::from("P1"),
+            name: String::from(name),
+            abbreviation: String::new(),
+            sound: sound.map(String::from),
+            program,
+            channel: Some(1),
+            unpitched: None,
+        };
+        let id = |info: PartInfo| find_def(&info).map(|def| def.id);
+        assert_eq!(
+            id(info("", Some("strings.viola"), None)),
+            Some("strings.viola")
+        );
+        assert_eq!(id(info("Violin 1", None, None)), Some("strings.violin"));
+        assert!(id(info("Something else", None, None)).is_none());
+    }
+}
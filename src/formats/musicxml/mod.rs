@@ -0,0 +1,55 @@
+pub mod export;
+pub mod import;
+
+use crate::state::entries::clef::ClefDrawType;
+use crate::utils::duration::NoteDuration;
+use crate::utils::pitch::Accidental;
+
+pub const DOCTYPE: &str = "<!DOCTYPE score-partwise PUBLIC \"-//Recordare//DTD MusicXML 4.0 Partwise//EN\" \"http://www.musicxml.org/dtds/partwise.dtd\">";
+
+/// The MusicXML name of a note value, as used by `<type>` and `<beat-unit>`
+pub fn note_type(duration: &NoteDuration) -> &'static str {
+    match duration {
+        NoteDuration::Whole => "whole",
+        NoteDuration::Half => "half",
+        NoteDuration::Quarter => "quarter",
+        NoteDuration::Eighth => "eighth",
+        NoteDuration::Sixteenth => "16th",
+        NoteDuration::ThirtySecond => "32nd",
+    }
+}
+
+/// The note value for a MusicXML `<type>`, if we can write it
+pub fn from_note_type(name: &str) -> Option<NoteDuration> {
+    match name {
+        "whole" => Some(NoteDuration::Whole),
+        "half" => Some(NoteDuration::Half),
+        "quarter" => Some(NoteDuration::Quarter),
+        "eighth" => Some(NoteDuration::Eighth),
+        "16th" => Some(NoteDuration::Sixteenth),
+        "32nd" => Some(NoteDuration::ThirtySecond),
+        _ => None,
+    }
+}
+
+/// The number of semitones an accidental alters the natural note by
+pub fn alter(accidental: Accidental) -> i8 {
+    match accidental {
+        Accidental::DoubleSharp => 2,
+        Accidental::Sharp => 1,
+        Accidental::Natural => 0,
+        Accidental::Flat => -1,
+        Accidental::DoubleFlat => -2,
+    }
+}
+
+/// The MusicXML clef sign for the way a clef is drawn
+pub fn clef_sign(draw_as: ClefDrawType) -> &'static str {
+    match draw_as {
+        ClefDrawType::Hidden => "none",
+        ClefDrawType::G => "G",
+        ClefDrawType::F => "F",
+        ClefDrawType::C => "C",
+        ClefDrawType::Percussion => "percussion",
+    }
+}
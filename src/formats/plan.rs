@@ -0,0 +1,513 @@
+use crate::error::{Error, Result};
+use crate::state::entries::clef::ClefDrawType;
+use crate::state::entries::key_signature::KeySignatureMode;
+use crate::state::entries::time_signature::TimeSignatureDrawType;
+use crate::state::entries::tone::Articulation;
+use crate::state::score::instrument::defs::InstrumentDef;
+use crate::state::score::player::PlayerType;
+use crate::state::Engine;
+use crate::utils::duration::NoteDuration;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::ops::{Add, Div, Mul, Sub};
+
+/// The largest number of subdivisions that is a multiple of 4 and fits in a u8
+const MAX_SUBDIVISIONS: u64 = 252;
+
+/// The subdivisions used when the file's rhythms can't be represented exactly.
+/// This allows triplets, quintuplets and 32nds.
+const FALLBACK_SUBDIVISIONS: u8 = 240;
+
+pub fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+pub fn lcm(a: u64, b: u64) -> u64 {
+    a / gcd(a, b) * b
+}
+
+/// A position or duration in quarter notes. Files don't share the flow's ticks
+/// so everything is kept as an exact fraction until the subdivisions are known.
+#[derive(Debug, Copy, Clone)]
+pub struct Fraction {
+    pub numerator: u64,
+    pub denominator: u64,
+}
+
+impl Fraction {
+    pub fn new(numerator: u64, denominator: u64) -> Self {
+        let denominator = denominator.max(1);
+        let divisor = gcd(numerator, denominator).max(1);
+        Self {
+            numerator: numerator / divisor,
+            denominator: denominator / divisor,
+        }
+    }
+
+    pub fn zero() -> Self {
+        Self::new(0, 1)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.numerator == 0
+    }
+
+    /// Convert to ticks of a flow, rounding to the nearest tick
+    pub fn to_ticks(self, subdivisions: u8) -> u32 {
+        let scaled = self.numerator * u64::from(subdivisions);
+        ((scaled + self.denominator / 2) / self.denominator) as u32
+    }
+}
+
+impl Add for Fraction {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        let denominator = lcm(self.denominator, other.denominator);
+        Self::new(
+            self.numerator * (denominator / self.denominator)
+                + other.numerator * (denominator / other.denominator),
+            denominator,
+        )
+    }
+}
+
+/// Subtract, stopping at zero
+impl Sub for Fraction {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        let denominator = lcm(self.denominator, other.denominator);
+        Self::new(
+            (self.numerator * (denominator / self.denominator))
+                .saturating_sub(other.numerator * (denominator / other.denominator)),
+            denominator,
+        )
+    }
+}
+
+impl Mul for Fraction {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        Self::new(
+            self.numerator * other.numerator,
+            self.denominator * other.denominator,
+        )
+    }
+}
+
+impl Div<u64> for Fraction {
+    type Output = Self;
+
+    fn div(self, value: u64) -> Self {
+        Self::new(
+            self.numerator,
+            self.denominator.saturating_mul(value.max(1)),
+        )
+    }
+}
+
+impl PartialEq for Fraction {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Fraction {}
+
+impl PartialOrd for Fraction {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Fraction {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let left = u128::from(self.numerator) * u128::from(other.denominator);
+        let right = u128::from(other.numerator) * u128::from(self.denominator);
+        left.cmp(&right)
+    }
+}
+
+/// Something in the file we couldn't bring in, counted rather than repeated
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct ImportWarning {
+    pub element: String,
+    pub part: Option<String>,
+    pub measure: Option<String>, // the first measure it was found in
+    pub count: u32,
+    pub message: String,
+}
+
+#[derive(Default)]
+pub struct Warnings {
+    pub list: Vec<ImportWarning>,
+}
+
+impl Warnings {
+    pub fn push(
+        &mut self,
+        element: &str,
+        part: Option<&str>,
+        measure: Option<&str>,
+        message: &str,
+    ) {
+        let existing = self.list.iter_mut().find(|warning| {
+            warning.element == element
+                && warning.part.as_deref() == part
+                && warning.message == message
+        });
+        match existing {
+            Some(warning) => warning.count += 1,
+            None => self.list.push(ImportWarning {
+                element: String::from(element),
+                part: part.map(String::from),
+                measure: measure.map(String::from),
+                count: 1,
+                message: String::from(message),
+            }),
+        }
+    }
+}
+
+pub struct PlannedTone {
+    pub stave: usize,
+    pub start: Fraction,
+    pub duration: Fraction,
+    pub pitch: u8,
+    pub velocity: u8,
+    pub articulation: Articulation,
+}
+
+pub struct PlannedTuplet {
+    pub stave: usize,
+    pub start: Fraction,
+    pub end: Fraction,
+    pub actual: u8,
+    pub normal: u8,
+}
+
+pub struct PlannedClef {
+    pub stave: usize,
+    pub start: Fraction,
+    pub pitch: u8,
+    pub offset: i8,
+    pub draw_as: ClefDrawType,
+}
+
+pub struct PlannedTempo {
+    pub start: Fraction,
+    pub text: String,
+    pub beat_type: NoteDuration,
+    pub dotted: u8,
+    pub bpm: u32,
+    pub parenthesis_visible: bool,
+    pub text_visible: bool,
+    pub bpm_visible: bool,
+}
+
+pub struct PlannedTimeSignature {
+    pub start: Fraction,
+    pub beats: u8,
+    pub beat_type: NoteDuration,
+    pub draw_type: TimeSignatureDrawType,
+    pub groupings: Option<Vec<u8>>,
+}
+
+/// The music for one instrument, `id` is used to share the instrument between movements
+pub struct PlannedPart {
+    pub id: String,
+    pub name: String,
+    pub def: &'static InstrumentDef,
+    pub tones: Vec<PlannedTone>,
+    pub tuplets: Vec<PlannedTuplet>,
+    pub clefs: Vec<PlannedClef>,
+}
+
+/// A piece from the file, which becomes a flow
+pub struct Movement {
+    pub title: Option<String>,
+    pub length: Fraction,
+    pub time_signatures: Vec<PlannedTimeSignature>,
+    pub key_signatures: Vec<(Fraction, i8, KeySignatureMode)>,
+    pub tempos: Vec<PlannedTempo>,
+    pub parts: Vec<PlannedPart>,
+}
+
+impl Movement {
+    /// The ticks per quarter needed to place everything exactly, if there is one
+    pub fn subdivisions(&self) -> Option<u8> {
+        let mut fractions = vec![self.length];
+        fractions.extend(self.time_signatures.iter().map(|entry| entry.start));
+        fractions.extend(self.key_signatures.iter().map(|entry| entry.0));
+        fractions.extend(self.tempos.iter().map(|entry| entry.start));
+        for part in self.parts.iter() {
+            for tone in part.tones.iter() {
+                fractions.push(tone.start);
+                fractions.push(tone.duration);
+            }
+            fractions.extend(part.clefs.iter().map(|clef| clef.start));
+            fractions.extend(part.tuplets.iter().map(|tuplet| tuplet.start));
+        }
+
+        let mut subdivisions = 16;
+        for fraction in fractions {
+            subdivisions = lcm(subdivisions, fraction.denominator);
+            if subdivisions > MAX_SUBDIVISIONS {
+                return None;
+            }
+        }
+        Some(subdivisions as u8)
+    }
+}
+
+/// Returned from the importers so the user can be told what was left out
+#[derive(Debug, Serialize)]
+pub struct ImportReport {
+    pub flow_keys: Vec<String>,
+    pub warnings: Vec<ImportWarning>,
+}
+
+#[derive(Default)]
+pub struct ImportedMeta {
+    pub title: Option<String>,
+    pub subtitle: Option<String>,
+    pub composer: Option<String>,
+    pub arranger: Option<String>,
+    pub lyricist: Option<String>,
+    pub copyright: Option<String>,
+}
+
+/// Everything needed to create the flows, worked out before touching the score
+pub struct Plan {
+    pub meta: ImportedMeta,
+    pub movements: Vec<Movement>,
+    pub warnings: Vec<ImportWarning>,
+}
+
+/// The note value lasting a number of quarters, if there is one
+pub fn from_quarters(quarters: Fraction) -> Option<NoteDuration> {
+    match (quarters.numerator, quarters.denominator) {
+        (4, 1) => Some(NoteDuration::Whole),
+        (2, 1) => Some(NoteDuration::Half),
+        (1, 1) => Some(NoteDuration::Quarter),
+        (1, 2) => Some(NoteDuration::Eighth),
+        (1, 4) => Some(NoteDuration::Sixteenth),
+        (1, 8) => Some(NoteDuration::ThirtySecond),
+        _ => None,
+    }
+}
+
+impl Engine {
+    /// Fill in the score's details from the file, where they haven't been set already
+    pub(crate) fn import_meta(&mut self, meta: &ImportedMeta) {
+        let current = self.state.score.meta.clone();
+        type Setter = fn(&mut Engine, String);
+        let fields: [(&str, &Option<String>, Setter); 6] = [
+            (&current.title, &meta.title, Engine::set_title),
+            (&current.subtitle, &meta.subtitle, Engine::set_subtitle),
+            (&current.composer, &meta.composer, Engine::set_composer),
+            (&current.arranger, &meta.arranger, Engine::set_arranger),
+            (&current.lyricist, &meta.lyricist, Engine::set_lyricist),
+            (&current.copyright, &meta.copyright, Engine::set_copyright),
+        ];
+        for (current, value, set) in fields.iter() {
+            if let Some(value) = value {
+                if current.is_empty() {
+                    set(self, value.clone());
+                }
+            }
+        }
+    }
+
+    /// Create a flow from a movement. Parts with the same id in earlier movements
+    /// share their instrument.
+    pub(crate) fn create_planned_flow(
+        &mut self,
+        movement: &Movement,
+        instruments: &mut HashMap<String, String>,
+        warnings: &mut Warnings,
+    ) -> Result<String> {
+        let flow_key = self.create_flow();
+
+        let subdivisions = match movement.subdivisions() {
+            Some(subdivisions) => subdivisions,
+            None => {
+                warnings.push(
+                    "note",
+                    None,
+                    None,
+                    "rhythms too fine for the flow are rounded",
+                );
+                FALLBACK_SUBDIVISIONS
+            }
+        };
+        self.set_flow_subdivisions(&flow_key, subdivisions)?;
+        if let Some(title) = &movement.title {
+            self.rename_flow(&flow_key, title)?;
+        }
+        let length = movement.length.to_ticks(subdivisions).max(1);
+        self.set_flow_length(&flow_key, length)?;
+
+        // time signatures are added in order so that none are moved to fill bars
+        for time_signature in movement.time_signatures.iter() {
+            let tick = time_signature.start.to_ticks(subdivisions);
+            if tick < length {
+                self.create_time_signature(
+                    &flow_key,
+                    tick,
+                    time_signature.beats,
+                    time_signature.beat_type.clone(),
+                    time_signature.draw_type,
+                    time_signature.groupings.clone(),
+                )?;
+            }
+        }
+        for (start, offset, mode) in movement.key_signatures.iter() {
+            let tick = start.to_ticks(subdivisions);
+            if tick < length {
+                self.create_key_signature(&flow_key, tick, *mode, *offset)?;
+            }
+        }
+        for tempo in movement.tempos.iter() {
+            let tick = tempo.start.to_ticks(subdivisions);
+            if tick < length && tempo.bpm > 0 {
+                self.create_absolute_tempo(
+                    &flow_key,
+                    tick,
+                    &tempo.text,
+                    tempo.beat_type.clone(),
+                    tempo.dotted,
+                    tempo.bpm,
+                    tempo.parenthesis_visible,
+                    tempo.text_visible,
+                    tempo.bpm_visible,
+                )?;
+            }
+        }
+
+        for part in movement.parts.iter() {
+            let instrument_key = match instruments.get(&part.id) {
+                Some(instrument_key) => instrument_key.clone(),
+                None => {
+                    let player_key = self.create_player(PlayerType::Solo);
+                    let instrument_key = self.create_instrument(part.def.id)?;
+                    self.assign_instrument(&player_key, &instrument_key)?;
+                    instruments.insert(part.id.clone(), instrument_key.clone());
+                    instrument_key
+                }
+            };
+
+            // the first track of each of the instruments staves
+            let stave_keys = self.state.score.instruments[&instrument_key].staves.clone();
+            let flow = &self.state.score.flows.by_key[&flow_key];
+            let track_keys: Vec<Option<String>> = stave_keys
+                .iter()
+                .map(|stave_key| {
+                    flow.staves
+                        .get(stave_key)
+                        .and_then(|stave| stave.tracks.first().cloned())
+                })
+                .collect();
+
+            for clef in part.clefs.iter() {
+                let tick = clef.start.to_ticks(subdivisions);
+                if let Some(stave_key) = stave_keys.get(clef.stave) {
+                    if tick < length {
+                        self.create_clef(
+                            &flow_key,
+                            stave_key,
+                            tick,
+                            clef.pitch,
+                            clef.offset,
+                            clef.draw_as,
+                        )?;
+                    }
+                }
+            }
+
+            for tone in part.tones.iter() {
+                let track_key = match track_keys.get(tone.stave).cloned().flatten() {
+                    Some(track_key) => track_key,
+                    None => return self.fail(Error::UnknownStave(instrument_key)),
+                };
+                let tick = tone.start.to_ticks(subdivisions);
+                let duration = tone.duration.to_ticks(subdivisions).max(1);
+                if tick >= length {
+                    continue;
+                }
+                self.create_tone(
+                    &flow_key,
+                    &track_key,
+                    tick,
+                    duration.min(length - tick),
+                    tone.pitch,
+                    tone.velocity,
+                    tone.articulation,
+                )?;
+            }
+
+            // only tuplets of a single note value that fit the flow's ticks can be created
+            let mut created: Vec<(usize, u32, u32)> = Vec::new();
+            for tuplet in part.tuplets.iter() {
+                let start = tuplet.start.to_ticks(subdivisions);
+                let end = tuplet.end.to_ticks(subdivisions);
+                let base =
+                    from_quarters(tuplet.end.sub(tuplet.start).div(u64::from(tuplet.normal)));
+                let track_key = track_keys.get(tuplet.stave).cloned().flatten();
+                let overlaps = created.iter().any(|(stave, other_start, other_end)| {
+                    *stave == tuplet.stave && *other_start < end && start < *other_end
+                });
+                let fits = end <= length
+                    && tuplet.actual > 0
+                    && tuplet.normal > 0
+                    && (end - start) % u32::from(tuplet.actual) == 0;
+                match (base, track_key) {
+                    (Some(base), Some(track_key)) if fits && !overlaps => {
+                        self.create_tuplet(
+                            &flow_key,
+                            &track_key,
+                            start,
+                            tuplet.actual,
+                            tuplet.normal,
+                            base,
+                        )?;
+                        created.push((tuplet.stave, start, end));
+                    }
+                    _ => warnings.push(
+                        "tuplet",
+                        Some(&part.name),
+                        None,
+                        "tuplet could not be created, its notes are kept",
+                    ),
+                }
+            }
+        }
+
+        Ok(flow_key)
+    }
+
+    /// Create a flow for each movement in the plan, with the score's details from the file
+    pub(crate) fn create_planned_flows(&mut self, plan: &Plan) -> Result<ImportReport> {
+        let mut warnings = Warnings {
+            list: plan.warnings.clone(),
+        };
+        self.import_meta(&plan.meta);
+
+        let mut instruments = HashMap::new();
+        let mut flow_keys = Vec::new();
+        for movement in plan.movements.iter() {
+            flow_keys.push(self.create_planned_flow(movement, &mut instruments, &mut warnings)?);
+        }
+
+        Ok(ImportReport {
+            flow_keys,
+            warnings: warnings.list,
+        })
+    }
+}
@@ -0,0 +1,152 @@
+use crate::error::{Error, Result};
+use miniz_oxide::inflate::decompress_to_vec;
+
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4B50;
+const CENTRAL_DIRECTORY_HEADER: u32 = 0x0201_4B50;
+const LOCAL_FILE_HEADER: u32 = 0x0403_4B50;
+
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+
+fn invalid(message: &str) -> Error {
+    Error::InvalidFile(format!("zip {}", message))
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> Result<u16> {
+    match bytes.get(at..at + 2) {
+        Some(bytes) => Ok(u16::from_le_bytes([bytes[0], bytes[1]])),
+        None => Err(invalid("ended unexpectedly")),
+    }
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> Result<u32> {
+    match bytes.get(at..at + 4) {
+        Some(bytes) => Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+        None => Err(invalid("ended unexpectedly")),
+    }
+}
+
+/// Returns true if the bytes look like a zip archive
+pub fn is_zip(bytes: &[u8]) -> bool {
+    bytes.len() >= 4 && u32_at(bytes, 0).ok() == Some(LOCAL_FILE_HEADER)
+}
+
+/// Read every file in a zip archive as (path, contents), in the order of the central directory.
+///
+/// Only stored and deflated files are supported, which is all MusicXML archives use.
+pub fn unzip(bytes: &[u8]) -> Result<Vec<(String, Vec<u8>)>> {
+    // the end record is at least 22 bytes and may be followed by a comment
+    let end = (0..bytes.len().saturating_sub(21))
+        .rev()
+        .find(|&i| u32_at(bytes, i).ok() == Some(END_OF_CENTRAL_DIRECTORY))
+        .ok_or_else(|| invalid("has no central directory"))?;
+
+    let count = u16_at(bytes, end + 10)?;
+    let mut at = u32_at(bytes, end + 16)? as usize;
+
+    let mut files = Vec::new();
+    for _ in 0..count {
+        if u32_at(bytes, at)? != CENTRAL_DIRECTORY_HEADER {
+            return Err(invalid("has a broken central directory"));
+        }
+        let method = u16_at(bytes, at + 10)?;
+        let compressed_size = u32_at(bytes, at + 20)? as usize;
+        let name_length = u16_at(bytes, at + 28)? as usize;
+        let extra_length = u16_at(bytes, at + 30)? as usize;
+        let comment_length = u16_at(bytes, at + 32)? as usize;
+        let offset = u32_at(bytes, at + 42)? as usize;
+        let name = bytes
+            .get(at + 46..at + 46 + name_length)
+            .ok_or_else(|| invalid("ended unexpectedly"))?;
+        let name = String::from_utf8_lossy(name).into_owned();
+        at += 46 + name_length + extra_length + comment_length;
+
+        // the local header has its own name and extra field before the data
+        if u32_at(bytes, offset)? != LOCAL_FILE_HEADER {
+            return Err(invalid("has a broken file header"));
+        }
+        let start = offset
+            + 30
+            + u16_at(bytes, offset + 26)? as usize
+            + u16_at(bytes, offset + 28)? as usize;
+        let data = bytes
+            .get(start..start + compressed_size)
+            .ok_or_else(|| invalid("ended unexpectedly"))?;
+
+        let contents = match method {
+            STORED => data.to_vec(),
+            DEFLATED => decompress_to_vec(data).map_err(|_| invalid("has a corrupt file"))?,
+            _ => return Err(invalid("uses an unsupported compression method")),
+        };
+        files.push((name, contents));
+    }
+
+    Ok(files)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Build an archive, deflating the files if asked
+    pub fn archive(files: &[(&str, &[u8])], method: u16) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut directory = Vec::new();
+        for (name, data) in files {
+            let offset = out.len() as u32;
+            let size = data.len() as u32;
+            let data = match method {
+                DEFLATED => miniz_oxide::deflate::compress_to_vec(data, 6),
+                _ => data.to_vec(),
+            };
+            let mut header = Vec::new();
+            header.extend(&20u16.to_le_bytes()); // version
+            header.extend(&0u16.to_le_bytes()); // flags
+            header.extend(&method.to_le_bytes());
+            header.extend(&[0; 8]); // time, date and crc
+            header.extend(&(data.len() as u32).to_le_bytes());
+            header.extend(&size.to_le_bytes());
+            header.extend(&(name.len() as u16).to_le_bytes());
+            header.extend(&0u16.to_le_bytes()); // extra
+
+            out.extend(&LOCAL_FILE_HEADER.to_le_bytes());
+            out.extend(&header);
+            out.extend(name.as_bytes());
+            out.extend(&data);
+
+            directory.extend(&CENTRAL_DIRECTORY_HEADER.to_le_bytes());
+            directory.extend(&20u16.to_le_bytes()); // version made by
+            directory.extend(&header);
+            directory.extend(&[0; 10]); // comment, disk, attributes
+            directory.extend(&offset.to_le_bytes());
+            directory.extend(name.as_bytes());
+        }
+        let directory_offset = out.len() as u32;
+        out.extend(&directory);
+        out.extend(&END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+        out.extend(&[0; 4]); // disks
+        out.extend(&(files.len() as u16).to_le_bytes());
+        out.extend(&(files.len() as u16).to_le_bytes());
+        out.extend(&(directory.len() as u32).to_le_bytes());
+        out.extend(&directory_offset.to_le_bytes());
+        out.extend(&0u16.to_le_bytes()); // comment
+        out
+    }
+
+    #[test]
+    fn test_unzip() {
+        let files: [(&str, &[u8]); 2] = [
+            ("mimetype", b"application/vnd.recordare.musicxml"),
+            ("score.xml", b"<score-partwise></score-partwise>"),
+        ];
+        for method in [STORED, DEFLATED].iter() {
+            let bytes = archive(&files, *method);
+            assert!(is_zip(&bytes));
+            let unzipped = unzip(&bytes).unwrap();
+            assert_eq!(unzipped.len(), 2);
+            assert_eq!(unzipped[1].0, "score.xml");
+            assert_eq!(unzipped[1].1, files[1].1);
+        }
+        assert!(unzip(b"not a zip file at all, not even close").is_err());
+    }
+}
@@ -1,7 +1,9 @@
-mod state;
-mod utils;
-
-use wasm_bindgen::prelude::*;
+pub mod error;
+pub mod formats;
+pub mod state;
+pub mod utils;
+#[cfg(feature = "wasm")]
+mod wasm;
 
 #[macro_use]
 extern crate serde;
@@ -14,8 +16,3 @@ extern crate lazy_static;
 
 #[macro_use]
 extern crate maplit;
-
-#[wasm_bindgen(start)]
-pub fn run() {
-    utils::set_panic_hook();
-}
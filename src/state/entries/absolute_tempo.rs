@@ -1,11 +1,14 @@
+use crate::error::{Error, Result};
 use crate::state::entries::Entry;
+use crate::state::patch::flow_path;
 use crate::state::Engine;
 use crate::utils::duration::NoteDuration;
 use crate::utils::measurements::{BoundingBox, Padding, Spaces};
 use crate::utils::shortid;
+#[cfg(feature = "wasm")]
 use wasm_bindgen::prelude::*;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct AbsoluteTempo {
     pub key: String,
     pub tick: u32,
@@ -55,8 +58,14 @@ impl AbsoluteTempo {
     /// Get a tempo normalized in quarters per minute
     ///
     /// Example h. = 100 => 300bpm
-    fn normalize_bpm(subdivisions: u8, beat_type: &NoteDuration, dotted: u8, bpm: u32) -> f64 {
-        let duration = beat_type.to_ticks(subdivisions) as f64;
+    pub(crate) fn normalize_bpm(
+        subdivisions: u8,
+        beat_type: &NoteDuration,
+        dotted: u8,
+        bpm: u32,
+    ) -> f64 {
+        // not rounded to whole ticks, a beat can be shorter than a tick
+        let duration = f64::from(subdivisions) * 4.0 / f64::from(beat_type.to_int());
         let mut addition = 0.0;
         for i in 1..dotted + 1 {
             let slice = f64::from(2.0).powf(f64::from(i));
@@ -75,7 +84,7 @@ impl AbsoluteTempo {
     }
 }
 
-#[wasm_bindgen]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 impl Engine {
     pub fn create_absolute_tempo(
         &mut self,
@@ -88,23 +97,21 @@ impl Engine {
         parenthesis_visible: bool,
         text_visible: bool,
         bpm_visible: bool,
-    ) -> JsValue {
+    ) -> Result<String> {
         // we want to be able to return this at the end
         let key = shortid();
 
-        let flow = match self
-            .state
-            .score
-            .flows
-            .by_key
-            .get_mut(&String::from(flow_key))
-        {
+        let flow = match self.state.score.flows.by_key.get_mut(flow_key) {
             Some(flow) => flow,
-            None => return JsValue::UNDEFINED,
+            None => return self.fail(Error::UnknownFlow(String::from(flow_key))),
         };
 
-        // TODO: we want to also find relative tempoes etc.
-        // if there is already a absolute tempo at this tick we remove it
+        if tick >= flow.length {
+            return self.fail(Error::InvalidTick(tick));
+        }
+
+        // if there is already a absolute tempo at this tick we remove it, gradual
+        // tempos are kept as they start from whatever tempo is at their tick
         let old_key = match flow.master.get_absolute_tempo_at_tick(tick) {
             Some(tempo) => Some(tempo.key.clone()),
             None => None,
@@ -133,13 +140,12 @@ impl Engine {
 
         // we are now done with the entry, insert it back in
         flow.master.insert(entry);
-        self.state
-            .ticks
-            .insert(String::from(flow_key), flow.calc_ticks());
-        self.state.score.meta.set_modified();
-        self.emit();
+        self.patches
+            .replace(format!("{}.master", flow_path(flow_key)), &flow.master);
+        self.update_tempo_map(flow_key);
+        self.record("Create absolute tempo");
 
-        JsValue::from_str(key.as_str())
+        Ok(key)
     }
 }
 
@@ -1,9 +1,10 @@
 use crate::state::entries::Entry;
 use crate::utils::measurements::{BoundingBox, Padding, Spaces};
+#[cfg(feature = "wasm")]
 use wasm_bindgen::prelude::*;
 
-#[wasm_bindgen]
-#[derive(Debug, Serialize_repr, Deserialize_repr)]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Debug, Serialize_repr, Deserialize_repr, Copy, Clone)]
 #[repr(u8)]
 pub enum BarlineType {
     Double,
@@ -14,7 +15,7 @@ pub enum BarlineType {
     StartRepeat,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Barline {
     pub key: String,
     pub tick: u32,
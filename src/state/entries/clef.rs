@@ -1,9 +1,14 @@
+use crate::error::{Error, Result};
 use crate::state::entries::Entry;
+use crate::state::patch::flow_path;
+use crate::state::Engine;
 use crate::utils::measurements::{BoundingBox, Padding, Spaces};
 use crate::utils::pitch::{Accidental, Pitch};
+use crate::utils::shortid;
+#[cfg(feature = "wasm")]
 use wasm_bindgen::prelude::*;
 
-#[wasm_bindgen]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 #[derive(Debug, Serialize_repr, Deserialize_repr, Copy, Clone)]
 #[repr(u8)]
 pub enum ClefDrawType {
@@ -14,7 +19,7 @@ pub enum ClefDrawType {
     Percussion,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Clef {
     pub key: String,
     pub tick: u32,
@@ -42,3 +47,54 @@ impl Clef {
         }
     }
 }
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl Engine {
+    /// Create a clef on a stave, replacing any clef already at the tick
+    pub fn create_clef(
+        &mut self,
+        flow_key: &str,
+        stave_key: &str,
+        tick: u32,
+        pitch: u8,
+        offset: i8,
+        draw_as: ClefDrawType,
+    ) -> Result<String> {
+        // we want to be able to return this at the end
+        let key = shortid();
+
+        let flow = match self.state.score.flows.by_key.get_mut(flow_key) {
+            Some(flow) => flow,
+            None => return self.fail(Error::UnknownFlow(String::from(flow_key))),
+        };
+
+        if tick >= flow.length {
+            return self.fail(Error::InvalidTick(tick));
+        }
+
+        let stave = match flow.staves.get_mut(stave_key) {
+            Some(stave) => stave,
+            None => return self.fail(Error::UnknownStave(String::from(stave_key))),
+        };
+
+        let old_key = stave.master.entries.by_tick.get(&tick).and_then(|keys| {
+            keys.iter()
+                .find(|key| matches!(stave.master.entries.by_key.get(*key), Some(Entry::Clef(_))))
+                .cloned()
+        });
+        if let Some(old_key) = old_key {
+            stave.master.remove(&old_key);
+        }
+
+        stave
+            .master
+            .insert(Clef::new(key.clone(), tick, pitch, offset, draw_as));
+        self.patches.replace(
+            format!("{}.staves.{}.master", flow_path(flow_key), stave_key),
+            &stave.master,
+        );
+        self.record("Create clef");
+
+        Ok(key)
+    }
+}
@@ -0,0 +1,245 @@
+use crate::error::{Error, Result};
+use crate::state::entries::Entry;
+use crate::state::patch::flow_path;
+use crate::state::Engine;
+use crate::utils::shortid;
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Debug, Serialize_repr, Deserialize_repr, Copy, Clone, PartialEq)]
+#[repr(u8)]
+pub enum GradualTempoType {
+    Ritardando,  // rit.
+    Accelerando, // accel.
+    ATempo,      // back to the tempo of the last tempo mark
+    TempoPrimo,  // back to the tempo at the start of the flow
+}
+
+/// How the tempo moves from the start to the target
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Debug, Serialize_repr, Deserialize_repr, Copy, Clone, PartialEq)]
+#[repr(u8)]
+pub enum TempoCurve {
+    Linear,
+    EaseIn,  // changes slowly at first
+    EaseOut, // changes quickly at first
+}
+
+impl TempoCurve {
+    /// How far through the change the tempo is, `t` being how far through the ticks (0 - 1)
+    pub fn progress(&self, t: f64) -> f64 {
+        match self {
+            TempoCurve::Linear => t,
+            TempoCurve::EaseIn => t * t,
+            TempoCurve::EaseOut => 1.0 - (1.0 - t) * (1.0 - t),
+        }
+    }
+}
+
+/// The tempo reached by a rit. or accel.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub enum TempoTarget {
+    Percent(u8), // of the tempo at the start of the change
+    Bpm(u32),    // quarters per minute
+}
+
+/// A change of tempo spread over `duration` ticks. The tempo then stays at the
+/// target until the next tempo mark.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct GradualTempo {
+    pub key: String,
+    pub tick: u32,
+    pub duration: u32,
+    pub tempo_type: GradualTempoType,
+    pub target: Option<TempoTarget>, // only a rit. or accel. has its own target
+    pub curve: TempoCurve,
+}
+
+impl GradualTempo {
+    pub fn new(
+        key: String,
+        tick: u32,
+        duration: u32,
+        tempo_type: GradualTempoType,
+        target: Option<TempoTarget>,
+        curve: TempoCurve,
+    ) -> Entry {
+        Entry::GradualTempo(Self {
+            key,
+            tick,
+            duration,
+            tempo_type,
+            target: match tempo_type {
+                GradualTempoType::Ritardando | GradualTempoType::Accelerando => target,
+                _ => None,
+            },
+            curve,
+        })
+    }
+
+    /// The text written above the stave
+    pub fn text(&self) -> &str {
+        match self.tempo_type {
+            GradualTempoType::Ritardando => "rit.",
+            GradualTempoType::Accelerando => "accel.",
+            GradualTempoType::ATempo => "a tempo",
+            GradualTempoType::TempoPrimo => "tempo primo",
+        }
+    }
+
+    /// The tempo at the end of the change, in quarters per minute
+    ///
+    /// `current` is the tempo as the change starts, `last` the tempo of the last
+    /// tempo mark and `first` the tempo at the start of the flow.
+    pub fn target_bpm(&self, current: f64, last: f64, first: f64) -> f64 {
+        match (self.tempo_type, self.target) {
+            (GradualTempoType::ATempo, _) => last,
+            (GradualTempoType::TempoPrimo, _) => first,
+            (_, Some(TempoTarget::Percent(percent))) => current * f64::from(percent) / 100.0,
+            (_, Some(TempoTarget::Bpm(bpm))) => f64::from(bpm),
+            (_, None) => current,
+        }
+    }
+}
+
+impl Engine {
+    /// Create a rit., accel., a tempo or tempo primo lasting `duration` ticks.
+    ///
+    /// A rit. or accel. needs a target, either a percentage of the tempo it starts
+    /// from or a bpm in quarters per minute. An a tempo or tempo primo ignores the
+    /// target and can have no duration to return to the tempo at once.
+    pub fn create_gradual_tempo(
+        &mut self,
+        flow_key: &str,
+        tick: u32,
+        duration: u32,
+        tempo_type: GradualTempoType,
+        curve: TempoCurve,
+        target: Option<TempoTarget>,
+    ) -> Result<String> {
+        // we want to be able to return this at the end
+        let key = shortid();
+
+        if let GradualTempoType::Ritardando | GradualTempoType::Accelerando = tempo_type {
+            match target {
+                Some(TempoTarget::Percent(percent)) if percent > 0 => {}
+                Some(TempoTarget::Bpm(bpm)) if bpm > 0 => {}
+                Some(_) => {
+                    return self.fail(Error::InvalidTempo(String::from(
+                        "the target must be above zero",
+                    )))
+                }
+                None => {
+                    return self.fail(Error::InvalidTempo(String::from(
+                        "a rit. or accel. needs a target",
+                    )))
+                }
+            }
+            if duration == 0 {
+                return self.fail(Error::InvalidTempo(String::from(
+                    "a rit. or accel. needs a duration",
+                )));
+            }
+        }
+
+        let flow = match self.state.score.flows.by_key.get_mut(flow_key) {
+            Some(flow) => flow,
+            None => return self.fail(Error::UnknownFlow(String::from(flow_key))),
+        };
+
+        if tick >= flow.length {
+            return self.fail(Error::InvalidTick(tick));
+        }
+
+        // if there is already a gradual tempo at this tick we remove it
+        let old_key = flow
+            .master
+            .entries
+            .by_tick
+            .get(&tick)
+            .into_iter()
+            .flatten()
+            .find(|key| {
+                matches!(
+                    flow.master.entries.by_key.get(*key),
+                    Some(Entry::GradualTempo(_))
+                )
+            })
+            .cloned();
+        if let Some(old_key) = old_key {
+            flow.master.remove(&old_key);
+        }
+
+        flow.master.insert(GradualTempo::new(
+            key.clone(),
+            tick,
+            duration,
+            tempo_type,
+            target,
+            curve,
+        ));
+        self.patches
+            .replace(format!("{}.master", flow_path(flow_key)), &flow.master);
+        self.update_tempo_map(flow_key);
+        self.record("Create gradual tempo");
+
+        Ok(key)
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl Engine {
+    pub fn remove_gradual_tempo(&mut self, flow_key: &str, entry_key: &str) -> Result<()> {
+        let flow = match self.state.score.flows.by_key.get_mut(flow_key) {
+            Some(flow) => flow,
+            None => return self.fail(Error::UnknownFlow(String::from(flow_key))),
+        };
+
+        match flow.master.entries.by_key.get(entry_key) {
+            Some(Entry::GradualTempo(_)) => {
+                flow.master.remove(entry_key);
+            }
+            _ => return self.fail(Error::UnknownEntry(String::from(entry_key))),
+        };
+
+        self.patches
+            .replace(format!("{}.master", flow_path(flow_key)), &flow.master);
+        self.update_tempo_map(flow_key);
+        self.record("Remove gradual tempo");
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_target_bpm() {
+        let rit = GradualTempo {
+            key: String::from("a"),
+            tick: 0,
+            duration: 16,
+            tempo_type: GradualTempoType::Ritardando,
+            target: Some(TempoTarget::Percent(75)),
+            curve: TempoCurve::Linear,
+        };
+        assert_eq!(rit.target_bpm(120.0, 100.0, 80.0), 90.0);
+
+        let a_tempo = GradualTempo {
+            tempo_type: GradualTempoType::ATempo,
+            ..rit.clone()
+        };
+        assert_eq!(a_tempo.target_bpm(120.0, 100.0, 80.0), 100.0);
+        assert_eq!(a_tempo.text(), "a tempo");
+    }
+
+    #[test]
+    fn test_curves() {
+        assert_eq!(TempoCurve::Linear.progress(0.5), 0.5);
+        assert_eq!(TempoCurve::EaseIn.progress(0.5), 0.25);
+        assert_eq!(TempoCurve::EaseOut.progress(0.5), 0.75);
+    }
+}
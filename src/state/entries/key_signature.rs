@@ -0,0 +1,248 @@
+use crate::error::{Error, Result};
+use crate::state::entries::Entry;
+use crate::state::patch::flow_path;
+use crate::state::Engine;
+use crate::utils::measurements::{BoundingBox, Padding, Spaces};
+use crate::utils::shortid;
+use crate::utils::spelling::Key;
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Debug, Serialize_repr, Deserialize_repr, Copy, Clone, PartialEq)]
+#[repr(u8)]
+pub enum KeySignatureMode {
+    Major,
+    Minor,
+    Open, // atonal, no key signature is drawn
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct KeySignature {
+    pub key: String,
+    pub tick: u32,
+    pub mode: KeySignatureMode,
+    pub offset: i8, // number of sharps (+) or flats (-)
+}
+
+impl KeySignature {
+    pub fn new(key: String, tick: u32, mode: KeySignatureMode, offset: i8) -> Entry {
+        Entry::KeySignature(Self {
+            key,
+            tick,
+            mode,
+            offset: match mode {
+                KeySignatureMode::Open => 0,
+                _ => offset,
+            },
+        })
+    }
+
+    /// The key used to spell tones under this key signature
+    pub fn to_key(&self) -> Key {
+        Key::new(self.offset, self.mode == KeySignatureMode::Minor)
+    }
+
+    pub fn metrics(&self) -> BoundingBox {
+        BoundingBox {
+            width: Spaces(self.offset.abs() as f32),
+            height: Spaces(4.0),
+            padding: Padding(Spaces(0.0), Spaces(1.0), Spaces(0.0), Spaces(0.0)),
+        }
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl Engine {
+    pub fn create_key_signature(
+        &mut self,
+        flow_key: &str,
+        tick: u32,
+        mode: KeySignatureMode,
+        offset: i8,
+    ) -> Result<String> {
+        // we want to be able to return this at the end
+        let key = shortid();
+
+        if !(-7..=7).contains(&offset) {
+            return self.fail(Error::InvalidKeySignature(offset));
+        }
+
+        let flow = match self.state.score.flows.by_key.get_mut(flow_key) {
+            Some(flow) => flow,
+            None => return self.fail(Error::UnknownFlow(String::from(flow_key))),
+        };
+
+        if tick >= flow.length {
+            return self.fail(Error::InvalidTick(tick));
+        }
+
+        // if there is already a key signature at this tick we remove it
+        let old_key = flow
+            .master
+            .get_key_signature_at_tick(tick)
+            .map(|key_signature| key_signature.key.clone());
+        if let Some(old_key) = old_key {
+            flow.master.remove(&old_key);
+        }
+
+        flow.master
+            .insert(KeySignature::new(key.clone(), tick, mode, offset));
+        self.patches
+            .replace(format!("{}.master", flow_path(flow_key)), &flow.master);
+        if flow.respell() {
+            self.patches
+                .replace(format!("{}.tracks", flow_path(flow_key)), &flow.tracks);
+        }
+        self.record("Create key signature");
+
+        Ok(key)
+    }
+
+    pub fn remove_key_signature(&mut self, flow_key: &str, entry_key: &str) -> Result<()> {
+        let flow = match self.state.score.flows.by_key.get_mut(flow_key) {
+            Some(flow) => flow,
+            None => return self.fail(Error::UnknownFlow(String::from(flow_key))),
+        };
+
+        match flow.master.entries.by_key.get(entry_key) {
+            Some(Entry::KeySignature(_)) => {
+                flow.master.remove(entry_key);
+            }
+            _ => return self.fail(Error::UnknownEntry(String::from(entry_key))),
+        };
+
+        self.patches
+            .replace(format!("{}.master", flow_path(flow_key)), &flow.master);
+        if flow.respell() {
+            self.patches
+                .replace(format!("{}.tracks", flow_path(flow_key)), &flow.tracks);
+        }
+        self.record("Remove key signature");
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::entries::tone::Articulation;
+    use crate::state::listener::{SystemClock, Update};
+    use crate::state::score::track::Track;
+    use crate::utils::pitch::Accidental;
+
+    /// A flow four quarters long with a Bb in the third quarter
+    fn setup() -> (Engine, String, String) {
+        let mut engine = Engine::create(Box::new(SystemClock), Box::new(|_: Update| ()));
+        let flow_key = engine.score().flows.order[0].clone();
+        engine.set_flow_length(&flow_key, 64).unwrap();
+        let track = Track::new();
+        let track_key = track.key.clone();
+        let flow = engine.state.score.flows.by_key.get_mut(&flow_key).unwrap();
+        flow.tracks.insert(track_key.clone(), track);
+        let tone_key = engine
+            .create_tone(&flow_key, &track_key, 32, 16, 70, 100, Articulation::None)
+            .unwrap();
+        (engine, flow_key, tone_key)
+    }
+
+    fn accidental(engine: &Engine, flow_key: &str, tone_key: &str) -> Accidental {
+        let flow = &engine.score().flows.by_key[flow_key];
+        match flow
+            .tracks
+            .values()
+            .find_map(|track| track.entries.by_key.get(tone_key))
+        {
+            Some(Entry::Tone(tone)) => tone.pitch.accidental.clone(),
+            _ => panic!("expected a tone"),
+        }
+    }
+
+    #[test]
+    fn test_create_key_signature() {
+        let (mut engine, flow_key, tone_key) = setup();
+        engine
+            .create_key_signature(&flow_key, 0, KeySignatureMode::Major, 4)
+            .unwrap();
+        assert_eq!(accidental(&engine, &flow_key, &tone_key), Accidental::Sharp);
+
+        // a key signature at the same tick replaces the old one
+        let key = engine
+            .create_key_signature(&flow_key, 0, KeySignatureMode::Major, -1)
+            .unwrap();
+        let master = &engine.score().flows.by_key[&flow_key].master;
+        assert_eq!(master.get_key_signature_at_tick(0).unwrap().key, key);
+        assert!(master.get_key_signature_after_tick(0, 64).is_none());
+        assert_eq!(accidental(&engine, &flow_key, &tone_key), Accidental::Flat);
+    }
+
+    #[test]
+    fn test_get_key_signature() {
+        let (mut engine, flow_key, _) = setup();
+        let first = engine
+            .create_key_signature(&flow_key, 0, KeySignatureMode::Major, 2)
+            .unwrap();
+        let second = engine
+            .create_key_signature(&flow_key, 32, KeySignatureMode::Minor, -3)
+            .unwrap();
+
+        let master = &engine.score().flows.by_key[&flow_key].master;
+        assert!(master.get_key_signature_at_tick(16).is_none());
+        assert_eq!(
+            master.get_key_signature_on_or_before_tick(16).unwrap().key,
+            first
+        );
+        assert_eq!(
+            master.get_key_signature_on_or_before_tick(32).unwrap().key,
+            second
+        );
+        assert_eq!(
+            master.get_key_signature_after_tick(0, 64).unwrap().key,
+            second
+        );
+        // key signatures past the end of the flow are ignored
+        assert!(master.get_key_signature_after_tick(0, 32).is_none());
+    }
+
+    #[test]
+    fn test_invalid_key_signature() {
+        let (mut engine, flow_key, _) = setup();
+        for offset in &[-8, 8] {
+            assert_eq!(
+                engine.create_key_signature(&flow_key, 0, KeySignatureMode::Major, *offset),
+                Err(Error::InvalidKeySignature(*offset))
+            );
+        }
+        assert_eq!(
+            engine.create_key_signature(&flow_key, 64, KeySignatureMode::Major, 0),
+            Err(Error::InvalidTick(64))
+        );
+        assert!(engine.score().flows.by_key[&flow_key]
+            .master
+            .get_key_signature_on_or_before_tick(64)
+            .is_none());
+    }
+
+    #[test]
+    fn test_remove_key_signature() {
+        let (mut engine, flow_key, tone_key) = setup();
+        assert_eq!(accidental(&engine, &flow_key, &tone_key), Accidental::Flat);
+        let key = engine
+            .create_key_signature(&flow_key, 16, KeySignatureMode::Major, 4)
+            .unwrap();
+        assert_eq!(accidental(&engine, &flow_key, &tone_key), Accidental::Sharp);
+
+        // the following tones go back to their spelling without it
+        engine.remove_key_signature(&flow_key, &key).unwrap();
+        assert_eq!(accidental(&engine, &flow_key, &tone_key), Accidental::Flat);
+        assert!(engine.score().flows.by_key[&flow_key]
+            .master
+            .get_key_signature_on_or_before_tick(64)
+            .is_none());
+        assert_eq!(
+            engine.remove_key_signature(&flow_key, &key),
+            Err(Error::UnknownEntry(key))
+        );
+    }
+}
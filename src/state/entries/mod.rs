@@ -1,22 +1,31 @@
 pub mod absolute_tempo;
 pub mod barline;
 pub mod clef;
+pub mod gradual_tempo;
+pub mod key_signature;
 pub mod time_signature;
 pub mod tone;
+pub mod tuplet;
 
 use absolute_tempo::AbsoluteTempo;
 use barline::Barline;
 use clef::Clef;
+use gradual_tempo::GradualTempo;
+use key_signature::KeySignature;
 use time_signature::TimeSignature;
 use tone::Tone;
+use tuplet::Tuplet;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub enum Entry {
     Barline(Barline),
     Clef(Clef),
+    KeySignature(KeySignature),
     TimeSignature(TimeSignature),
     Tone(Tone),
+    Tuplet(Tuplet),
     AbsoluteTempo(AbsoluteTempo),
+    GradualTempo(GradualTempo),
 }
 
 impl Entry {
@@ -25,9 +34,12 @@ impl Entry {
         match self {
             Entry::Barline(barline) => barline.key.clone(),
             Entry::Clef(clef) => clef.key.clone(),
+            Entry::KeySignature(key_signature) => key_signature.key.clone(),
             Entry::TimeSignature(time_signature) => time_signature.key.clone(),
             Entry::Tone(tone) => tone.key.clone(),
+            Entry::Tuplet(tuplet) => tuplet.key.clone(),
             Entry::AbsoluteTempo(tempo) => tempo.key.clone(),
+            Entry::GradualTempo(tempo) => tempo.key.clone(),
         }
     }
 
@@ -36,9 +48,12 @@ impl Entry {
         match self {
             Entry::Barline(barline) => barline.tick,
             Entry::Clef(clef) => clef.tick,
+            Entry::KeySignature(key_signature) => key_signature.tick,
             Entry::TimeSignature(time_signature) => time_signature.tick,
             Entry::Tone(tone) => tone.tick,
+            Entry::Tuplet(tuplet) => tuplet.tick,
             Entry::AbsoluteTempo(tempo) => tempo.tick,
+            Entry::GradualTempo(tempo) => tempo.tick,
         }
     }
 
@@ -50,9 +65,12 @@ impl Entry {
         match self {
             Entry::Barline(barline) => barline.tick = tick,
             Entry::Clef(clef) => clef.tick = tick,
+            Entry::KeySignature(key_signature) => key_signature.tick = tick,
             Entry::TimeSignature(time_signature) => time_signature.tick = tick,
             Entry::Tone(tone) => tone.tick = tick,
+            Entry::Tuplet(tuplet) => tuplet.tick = tick,
             Entry::AbsoluteTempo(tempo) => tempo.tick = tick,
+            Entry::GradualTempo(tempo) => tempo.tick = tick,
         }
     }
 }
@@ -1,8 +1,12 @@
+use crate::error::{Error, Result};
 use crate::state::entries::Entry;
+use crate::state::patch::flow_path;
 use crate::state::Engine;
-use crate::utils::duration::NoteDuration;
+use crate::utils::duration::{NoteDuration, NOTE_DURATIONS};
 use crate::utils::measurements::{BoundingBox, Padding, Spaces};
 use crate::utils::shortid;
+use std::convert::TryFrom;
+#[cfg(feature = "wasm")]
 use wasm_bindgen::prelude::*;
 
 enum TimeSignatureType {
@@ -12,8 +16,8 @@ enum TimeSignatureType {
     Open,
 }
 
-#[wasm_bindgen]
-#[derive(Debug, Serialize_repr, Deserialize_repr)]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Debug, Serialize_repr, Deserialize_repr, Copy, Clone)]
 #[repr(u8)]
 pub enum TimeSignatureDrawType {
     Hidden,          // always hidden
@@ -22,7 +26,17 @@ pub enum TimeSignatureDrawType {
     SplitCommonTime, // '¢'
 }
 
-#[derive(Serialize, Deserialize)]
+/// How the bars after a time signature change are kept full
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Debug, Serialize_repr, Deserialize_repr, Copy, Clone, PartialEq)]
+#[repr(u8)]
+pub enum Reflow {
+    Pad,      // fill the last bar, moving everything after it later
+    Truncate, // cut the last bar back to a full bar, shortening or removing the tones past it
+    Shift,    // cut the last bar back to a full bar, moving the tones past it earlier
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct TimeSignature {
     pub key: String,
     pub tick: u32,
@@ -30,6 +44,7 @@ pub struct TimeSignature {
     pub beat_type: NoteDuration,
     pub draw_type: TimeSignatureDrawType,
     pub groupings: Vec<u8>, // optional, we can always revert to defaults
+    pub pickup: u32,        // ticks before the first barline, 0 if it starts with a full bar
 }
 
 impl TimeSignature {
@@ -51,6 +66,7 @@ impl TimeSignature {
                 None => TimeSignature::groupings(beats),
             },
             draw_type,
+            pickup: 0,
         })
     }
 
@@ -96,18 +112,38 @@ impl TimeSignature {
 
     /// Get the number of ticks per the time signatures bar
     pub fn ticks_per_bar(&self, subdivisions: u8) -> u32 {
-        (self.ticks_per_beat(subdivisions) * self.beats) as u32
+        self.ticks_per_beat(subdivisions) * u32::from(self.beats)
     }
 
     /// Get the number of ticks per the time signatures beat type
-    pub fn ticks_per_beat(&self, subdivisions: u8) -> u8 {
+    pub fn ticks_per_beat(&self, subdivisions: u8) -> u32 {
         self.beat_type.to_ticks(subdivisions)
     }
 
+    /// Get the number of ticks from where the first full bar would start if the pickup
+    /// were a full bar, so that beats and groupings line up from the first barline.
+    fn position(&self, tick: u32, subdivisions: u8) -> u32 {
+        match (self.kind(), self.pickup) {
+            (TimeSignatureType::Open, _) | (_, 0) => tick - self.tick,
+            _ => tick - self.tick + self.ticks_per_bar(subdivisions) - self.pickup,
+        }
+    }
+
     pub fn distance_from_barline(&self, tick: u32, subdivisions: u8) -> u32 {
         match self.kind() {
             TimeSignatureType::Open => tick - self.tick,
-            _ => (tick - self.tick) % self.ticks_per_bar(subdivisions),
+            _ => self.position(tick, subdivisions) % self.ticks_per_bar(subdivisions),
+        }
+    }
+
+    /// Returns the tick of the next barline after a tick, open time signatures have no barlines
+    pub fn next_barline(&self, tick: u32, subdivisions: u8) -> Option<u32> {
+        match self.kind() {
+            TimeSignatureType::Open => None,
+            _ => Some(
+                tick + self.ticks_per_bar(subdivisions)
+                    - self.distance_from_barline(tick, subdivisions),
+            ),
         }
     }
 
@@ -118,8 +154,8 @@ impl TimeSignature {
 
     /// Return true if a tick is on an arbitrary beat type
     pub fn is_on_beat_type(&self, tick: u32, subdivisions: u8, beat_type: &NoteDuration) -> bool {
-        let ticks_per_beat = beat_type.to_ticks(subdivisions) as u32;
-        ((tick - self.tick) % ticks_per_beat) == 0
+        let ticks_per_beat = beat_type.to_ticks(subdivisions);
+        (self.position(tick, subdivisions) % ticks_per_beat) == 0
     }
 
     // Returns true is the tick is on a beat group boundry
@@ -129,7 +165,7 @@ impl TimeSignature {
             _ => {
                 let ticks_per_beat = self.ticks_per_beat(subdivisions);
                 let bar_length = self.ticks_per_bar(subdivisions);
-                let distance_from_first_beat = (tick - self.tick) % bar_length;
+                let distance_from_first_beat = self.position(tick, subdivisions) % bar_length;
 
                 if distance_from_first_beat == 0 {
                     return true;
@@ -137,7 +173,7 @@ impl TimeSignature {
 
                 let mut offset: u32 = 0;
                 for group in &self.groupings {
-                    offset += (group * ticks_per_beat) as u32;
+                    offset += u32::from(*group) * ticks_per_beat;
                     if distance_from_first_beat == offset {
                         return true;
                     }
@@ -148,6 +184,32 @@ impl TimeSignature {
         }
     }
 
+    /// Returns true if a tick is on a multiple of `step` ticks counted from the first barline
+    pub fn is_on_step(&self, tick: u32, subdivisions: u8, step: u32) -> bool {
+        self.position(tick, subdivisions) % step == 0
+    }
+
+    /// The pickup as a number of beats, using shorter beats than the time signature's
+    /// when it isn't a whole number of them, eg. a dotted quarter pickup in 4/4 is 3/8.
+    pub fn pickup_beats(&self, subdivisions: u8) -> Option<(u8, NoteDuration)> {
+        if self.pickup == 0 {
+            return None;
+        }
+        NOTE_DURATIONS
+            .iter()
+            .filter(|beat_type| beat_type.to_int() >= self.beat_type.to_int())
+            .find_map(|beat_type| {
+                let ticks = beat_type.to_ticks(subdivisions);
+                if ticks > 0 && self.pickup % ticks == 0 {
+                    u8::try_from(self.pickup / ticks)
+                        .ok()
+                        .map(|beats| (beats, beat_type.clone()))
+                } else {
+                    None
+                }
+            })
+    }
+
     pub fn metrics(&self) -> BoundingBox {
         BoundingBox {
             width: Spaces(0.75),
@@ -157,7 +219,7 @@ impl TimeSignature {
     }
 }
 
-#[wasm_bindgen]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 impl Engine {
     pub fn create_time_signature(
         &mut self,
@@ -167,21 +229,19 @@ impl Engine {
         beat_type: NoteDuration,
         draw_type: TimeSignatureDrawType,
         groupings: Option<Vec<u8>>,
-    ) -> JsValue {
+    ) -> Result<String> {
         // we want to be able to return this at the end
         let key = shortid();
 
-        let flow = match self
-            .state
-            .score
-            .flows
-            .by_key
-            .get_mut(&String::from(flow_key))
-        {
+        let flow = match self.state.score.flows.by_key.get_mut(flow_key) {
             Some(flow) => flow,
-            None => return JsValue::UNDEFINED,
+            None => return self.fail(Error::UnknownFlow(String::from(flow_key))),
         };
 
+        if tick >= flow.length {
+            return self.fail(Error::InvalidTick(tick));
+        }
+
         // if there is already a time isg at this tick we remove it
         let old_key = match flow.master.get_time_signature_at_tick(tick) {
             Some(time_signature) => Some(time_signature.key.clone()),
@@ -195,57 +255,383 @@ impl Engine {
             None => (),
         };
 
-        // we insert the new time sig
-        let entry = TimeSignature::new(key.clone(), tick, beats, beat_type, draw_type, groupings);
+        // we insert the new time sig and make full bars up to the next one
+        flow.master.insert(TimeSignature::new(
+            key.clone(),
+            tick,
+            beats,
+            beat_type,
+            draw_type,
+            groupings,
+        ));
+        flow.reflow(tick, Reflow::Pad);
+        self.patches.replace(flow_path(flow_key), flow);
+        self.update_timing(flow_key, tick);
+        self.record("Create time signature");
 
-        // extract the time sig itself out the Entry, we need it's methods to work with
-        let time_signature = match &entry {
-            Entry::TimeSignature(time_signature) => time_signature,
-            _ => return JsValue::UNDEFINED, // will never happen, something has gone horribly wrong!
+        Ok(key)
+    }
+
+    /// Change a time signature, keeping its bars full up to the next time signature
+    pub fn update_time_signature(
+        &mut self,
+        flow_key: &str,
+        entry_key: &str,
+        beats: u8,
+        beat_type: NoteDuration,
+        draw_type: TimeSignatureDrawType,
+        groupings: Option<Vec<u8>>,
+        reflow: Reflow,
+    ) -> Result<()> {
+        let flow = match self.state.score.flows.by_key.get_mut(flow_key) {
+            Some(flow) => flow,
+            None => return self.fail(Error::UnknownFlow(String::from(flow_key))),
         };
 
-        // we want to create full bars when we insert time sigs,
-        // however open time sigs don't have bar lengths in reality so ignore this step
-        match time_signature.kind() {
-            TimeSignatureType::Open => (),
-            _ => {
-                let bar_length = time_signature.ticks_per_bar(flow.subdivisions) as u32;
+        let subdivisions = flow.subdivisions;
+        let tick = match flow.master.entries.by_key.get_mut(entry_key) {
+            Some(Entry::TimeSignature(time_signature)) => {
+                // the pickup must still be shorter than a bar
+                let bar_length = beat_type.to_ticks(subdivisions) * u32::from(beats);
+                let pickup = time_signature.pickup;
+                if pickup > 0 && pickup >= bar_length {
+                    return self.fail(Error::InvalidTick(pickup));
+                }
+                time_signature.beats = beats;
+                time_signature.beat_type = beat_type;
+                time_signature.draw_type = draw_type;
+                time_signature.groupings =
+                    groupings.unwrap_or_else(|| TimeSignature::groupings(beats));
+                time_signature.tick
+            }
+            _ => return self.fail(Error::UnknownEntry(String::from(entry_key))),
+        };
 
-                // calculate how may ticks we have filled of the last bar before
-                let overflow = match flow.master.get_time_signature_after_tick(tick, flow.length) {
-                    Some(next_time_signature) => (next_time_signature.tick - tick) % bar_length,
-                    None => ((flow.length - tick) % bar_length),
-                };
+        flow.reflow(tick, reflow);
+        self.patches.replace(flow_path(flow_key), flow);
+        self.update_timing(flow_key, tick);
+        self.record("Update time signature");
 
-                if overflow > 0 {
-                    // add aditional ticks to flow length to make full bars
-                    flow.length += bar_length - overflow;
-                }
+        Ok(())
+    }
 
-                // offset all remaining time sigs by remainder
-                for i in tick + 1..flow.length {
-                    let key = match flow.master.get_time_signature_at_tick(i) {
-                        Some(time_signature) => Some(time_signature.key.clone()),
-                        None => None,
-                    };
-                    match key {
-                        Some(key) => {
-                            flow.master.r#move(&key, i + (bar_length - overflow));
-                        }
-                        None => (),
-                    }
+    /// Start a time signature with a pickup (anacrusis) of `pickup` ticks, 0 for a full
+    /// bar. The bars after the pickup are kept full up to the next time signature.
+    pub fn set_time_signature_pickup(
+        &mut self,
+        flow_key: &str,
+        entry_key: &str,
+        pickup: u32,
+        reflow: Reflow,
+    ) -> Result<()> {
+        let flow = match self.state.score.flows.by_key.get_mut(flow_key) {
+            Some(flow) => flow,
+            None => return self.fail(Error::UnknownFlow(String::from(flow_key))),
+        };
+
+        let subdivisions = flow.subdivisions;
+        let tick = match flow.master.entries.by_key.get_mut(entry_key) {
+            Some(Entry::TimeSignature(time_signature)) => {
+                // open time has no bars to pick up into
+                if pickup > 0
+                    && (time_signature.beats == 0
+                        || pickup >= time_signature.ticks_per_bar(subdivisions))
+                {
+                    return self.fail(Error::InvalidTick(pickup));
                 }
+                time_signature.pickup = pickup;
+                time_signature.tick
             }
+            _ => return self.fail(Error::UnknownEntry(String::from(entry_key))),
+        };
+
+        flow.reflow(tick, reflow);
+        self.patches.replace(flow_path(flow_key), flow);
+        self.update_timing(flow_key, tick);
+        self.record("Set time signature pickup");
+
+        Ok(())
+    }
+
+    /// Remove a time signature, the time signature before it then runs on to the
+    /// next and its bars are kept full. The first time signature of the flow is
+    /// replaced with open time.
+    pub fn remove_time_signature(
+        &mut self,
+        flow_key: &str,
+        entry_key: &str,
+        reflow: Reflow,
+    ) -> Result<()> {
+        let flow = match self.state.score.flows.by_key.get_mut(flow_key) {
+            Some(flow) => flow,
+            None => return self.fail(Error::UnknownFlow(String::from(flow_key))),
+        };
+
+        let tick = match flow.master.entries.by_key.get(entry_key) {
+            Some(Entry::TimeSignature(time_signature)) => time_signature.tick,
+            _ => return self.fail(Error::UnknownEntry(String::from(entry_key))),
+        };
+
+        flow.master.remove(entry_key);
+        if tick == 0 {
+            flow.master.insert(TimeSignature::new(
+                shortid(),
+                0,
+                0,
+                NoteDuration::Quarter,
+                TimeSignatureDrawType::Hidden,
+                None,
+            ));
+        } else {
+            flow.reflow(tick - 1, reflow);
+        }
+        self.patches.replace(flow_path(flow_key), flow);
+        self.update_timing(flow_key, tick);
+        self.record("Remove time signature");
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::entries::tone::{Articulation, Tone};
+    use crate::state::listener::{SystemClock, Update};
+    use crate::state::score::flow::Flow;
+    use crate::state::score::track::Track;
+    use crate::utils::duration::Duration;
+    use crate::utils::pitch::{Accidental, Pitch};
+    use crate::utils::velocity::Velocity;
+
+    fn tone(key: &str, tick: u32, duration: u32) -> Entry {
+        Tone::new(
+            String::from(key),
+            tick,
+            Duration::new(duration),
+            Pitch::new(60, Accidental::Natural),
+            Velocity::new(100),
+            Articulation::None,
+            None,
+        )
+    }
+
+    /// A bar of 4/4 then 3/4 with tones at the end of the 3/4 bars,
+    /// returns the engine, the flow and the 3/4 time signature
+    fn setup() -> (Engine, String, String) {
+        let mut engine = Engine::create(Box::new(SystemClock), Box::new(|_: Update| ()));
+        let flow_key = engine.score().flows.order[0].clone();
+        engine.set_flow_length(&flow_key, 128).unwrap();
+        engine
+            .create_time_signature(
+                &flow_key,
+                0,
+                4,
+                NoteDuration::Quarter,
+                TimeSignatureDrawType::Normal,
+                None,
+            )
+            .unwrap();
+        let key = engine
+            .create_time_signature(
+                &flow_key,
+                64,
+                3,
+                NoteDuration::Quarter,
+                TimeSignatureDrawType::Normal,
+                None,
+            )
+            .unwrap();
+
+        let mut track = Track::new();
+        track.insert(tone("a", 120, 16));
+        track.insert(tone("b", 136, 8));
+        let flow = engine.state.score.flows.by_key.get_mut(&flow_key).unwrap();
+        flow.tracks.insert(String::from("track"), track);
+
+        (engine, flow_key, key)
+    }
+
+    fn flow<'a>(engine: &'a Engine, flow_key: &str) -> &'a Flow {
+        &engine.score().flows.by_key[flow_key]
+    }
+
+    fn tone_at(engine: &Engine, flow_key: &str, key: &str) -> Option<(u32, u32)> {
+        match flow(engine, flow_key).tracks["track"]
+            .entries
+            .by_key
+            .get(key)
+        {
+            Some(Entry::Tone(tone)) => Some((tone.tick, tone.duration.int)),
+            _ => None,
         }
+    }
+
+    #[test]
+    fn test_create_pads_bars() {
+        let (engine, flow_key, _) = setup();
+        // 64 ticks of 3/4 is padded to two full bars
+        assert_eq!(flow(&engine, &flow_key).length, 160);
+        assert_eq!(engine.state.ticks[&flow_key].list.len(), 161);
+    }
+
+    #[test]
+    fn test_update_time_signature() {
+        let (mut engine, flow_key, key) = setup();
+        engine
+            .update_time_signature(
+                &flow_key,
+                &key,
+                5,
+                NoteDuration::Eighth,
+                TimeSignatureDrawType::Normal,
+                None,
+                Reflow::Truncate,
+            )
+            .unwrap();
+        // 96 ticks of 5/8 is cut back to two bars
+        assert_eq!(flow(&engine, &flow_key).length, 144);
+        assert_eq!(tone_at(&engine, &flow_key, "a"), Some((120, 16)));
+        assert_eq!(tone_at(&engine, &flow_key, "b"), Some((136, 8)));
+
+        engine.undo();
+        engine
+            .update_time_signature(
+                &flow_key,
+                &key,
+                2,
+                NoteDuration::Quarter,
+                TimeSignatureDrawType::Normal,
+                None,
+                Reflow::Truncate,
+            )
+            .unwrap();
+        // 96 ticks is already three bars of 2/4
+        assert_eq!(flow(&engine, &flow_key).length, 160);
+
+        engine.undo();
+        engine
+            .update_time_signature(
+                &flow_key,
+                &key,
+                5,
+                NoteDuration::Quarter,
+                TimeSignatureDrawType::Normal,
+                None,
+                Reflow::Pad,
+            )
+            .unwrap();
+        assert_eq!(flow(&engine, &flow_key).length, 224);
+    }
+
+    /// No tone in the track starts while another is sounding, other than in a chord
+    fn assert_no_overlaps(engine: &Engine, flow_key: &str) {
+        let tones: Vec<(u32, u32)> = flow(engine, flow_key).tracks["track"]
+            .entries
+            .by_key
+            .values()
+            .filter_map(|entry| match entry {
+                Entry::Tone(tone) => Some((tone.tick, tone.tick + tone.duration.int)),
+                _ => None,
+            })
+            .collect();
+        for (start, end) in tones.iter() {
+            assert!(tones
+                .iter()
+                .all(|(other, _)| *other <= *start || *other >= *end));
+        }
+    }
+
+    #[test]
+    fn test_remove_time_signature() {
+        let (mut engine, flow_key, key) = setup();
+        // 160 ticks of 4/4 has half a bar over
+        engine
+            .remove_time_signature(&flow_key, &key, Reflow::Truncate)
+            .unwrap();
+        assert_eq!(flow(&engine, &flow_key).length, 128);
+        assert_eq!(tone_at(&engine, &flow_key, "a"), Some((120, 8)));
+        assert_eq!(tone_at(&engine, &flow_key, "b"), None);
+        assert_eq!(engine.state.ticks[&flow_key].list.len(), 129);
+        assert_no_overlaps(&engine, &flow_key);
+
+        let (mut engine, flow_key, key) = setup();
+        engine
+            .remove_time_signature(&flow_key, &key, Reflow::Shift)
+            .unwrap();
+        assert_eq!(flow(&engine, &flow_key).length, 128);
+        // the a is cut short by the b moved in after it
+        assert_eq!(tone_at(&engine, &flow_key, "a"), Some((112, 8)));
+        assert_eq!(tone_at(&engine, &flow_key, "b"), Some((120, 8)));
+        assert_no_overlaps(&engine, &flow_key);
+
+        // the first time signature becomes open time
+        let first = flow(&engine, &flow_key)
+            .master
+            .get_time_signature_at_tick(0)
+            .unwrap()
+            .key
+            .clone();
+        engine
+            .remove_time_signature(&flow_key, &first, Reflow::Pad)
+            .unwrap();
+        let flow = flow(&engine, &flow_key);
+        assert_eq!(flow.master.get_time_signature_at_tick(0).unwrap().beats, 0);
+        assert_eq!(flow.length, 128);
+    }
+
+    #[test]
+    fn test_pickup() {
+        let (mut engine, flow_key, _) = setup();
+        let key = flow(&engine, &flow_key)
+            .master
+            .get_time_signature_at_tick(0)
+            .unwrap()
+            .key
+            .clone();
+        engine
+            .set_time_signature_pickup(&flow_key, &key, 16, Reflow::Pad)
+            .unwrap();
+
+        // a quarter pickup then a bar of 4/4, padded to fit before the 3/4
+        let bars = flow(&engine, &flow_key).bars();
+        assert_eq!(bars, vec![(0, 16), (16, 80), (80, 128), (128, 176)]);
+        let master = &flow(&engine, &flow_key).master;
+        assert_eq!(master.get_time_signature_at_tick(80).unwrap().beats, 3);
+
+        // the pickup is bar 0, counted as the last beat of a bar
+        let ticks = &engine.state.ticks[&flow_key].list;
+        assert_eq!((ticks[0].bar, ticks[0].beat), (0, 4));
+        assert!(ticks[0].is_first_beat);
+        assert!(ticks[8].is_quaver_beat && !ticks[8].is_beat);
+        assert_eq!((ticks[16].bar, ticks[16].beat), (1, 1));
+        assert!(ticks[16].is_first_beat);
+        assert_eq!((ticks[80].bar, ticks[80].beat), (2, 1));
+        assert_eq!((ticks[128].bar, ticks[128].beat), (3, 1));
 
-        // we are now done with the entry, insert it back in
-        flow.master.insert(entry);
-        self.state
-            .ticks
-            .insert(String::from(flow_key), flow.calc_ticks());
-        self.state.score.meta.set_modified();
-        self.emit();
+        // the pickup must be shorter than a bar, and the bar longer than the pickup
+        assert!(engine
+            .set_time_signature_pickup(&flow_key, &key, 64, Reflow::Pad)
+            .is_err());
+        assert!(engine
+            .update_time_signature(
+                &flow_key,
+                &key,
+                1,
+                NoteDuration::Eighth,
+                TimeSignatureDrawType::Normal,
+                None,
+                Reflow::Pad,
+            )
+            .is_err());
 
-        JsValue::from_str(key.as_str())
+        // deleting the pickup bar leaves full bars
+        engine.delete_bars(&flow_key, 0, 1).unwrap();
+        let time_signature = flow(&engine, &flow_key)
+            .master
+            .get_time_signature_at_tick(0)
+            .unwrap();
+        assert_eq!(time_signature.pickup, 0);
+        assert_eq!(flow(&engine, &flow_key).length, 160);
     }
 }
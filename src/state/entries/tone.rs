@@ -1,12 +1,15 @@
+use crate::error::{Error, Result};
 use crate::state::entries::Entry;
 use crate::state::Engine;
 use crate::utils::duration::Duration;
 use crate::utils::pitch::{Accidental, Pitch};
 use crate::utils::shortid;
+use crate::utils::spelling::{is_valid, next_enharmonic};
 use crate::utils::velocity::Velocity;
+#[cfg(feature = "wasm")]
 use wasm_bindgen::prelude::*;
 
-#[wasm_bindgen]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 #[derive(Debug, Serialize_repr, Deserialize_repr, Copy, Clone)]
 #[repr(u8)]
 pub enum Articulation {
@@ -19,7 +22,7 @@ pub enum Articulation {
 
 /// These represent the audiable tones of the music.
 /// They are never directly drawn in the score.
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Tone {
     pub key: String,
     pub tick: u32,
@@ -27,6 +30,7 @@ pub struct Tone {
     pub pitch: Pitch, // the pitch that the clef sits on
     pub velocity: Velocity,
     pub articulation: Articulation,
+    pub spelling: Option<Accidental>, // user defined spelling, otherwise spelled from the key
 }
 
 impl Tone {
@@ -37,6 +41,7 @@ impl Tone {
         pitch: Pitch,
         velocity: Velocity,
         articulation: Articulation,
+        spelling: Option<Accidental>,
     ) -> Entry {
         Entry::Tone(Self {
             key,
@@ -45,11 +50,12 @@ impl Tone {
             pitch,
             velocity,
             articulation,
+            spelling,
         })
     }
 }
 
-#[wasm_bindgen]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 impl Engine {
     /// Create a tone
     pub fn create_tone(
@@ -61,24 +67,22 @@ impl Engine {
         pitch: u8,
         velocity: u8,
         articulation: Articulation,
-    ) -> JsValue {
+    ) -> Result<String> {
         // we want to be able to return this at the end
         let key = shortid();
 
-        let flow = match self
-            .state
-            .score
-            .flows
-            .by_key
-            .get_mut(&String::from(flow_key))
-        {
+        let flow = match self.state.score.flows.by_key.get_mut(flow_key) {
             Some(flow) => flow,
-            None => return JsValue::UNDEFINED,
+            None => return self.fail(Error::UnknownFlow(String::from(flow_key))),
         };
 
+        if tick >= flow.length {
+            return self.fail(Error::InvalidTick(tick));
+        }
+
         let track = match flow.tracks.get_mut(track_key) {
             Some(track) => track,
-            None => return JsValue::UNDEFINED,
+            None => return self.fail(Error::UnknownTrack(String::from(track_key))),
         };
 
         // we are now done with the entry, insert it back in
@@ -89,12 +93,17 @@ impl Engine {
             Pitch::new(pitch, Accidental::default(pitch)),
             Velocity::new(velocity),
             articulation,
+            None,
         ));
+        self.patches.set_entry(flow_key, track, &key);
+        self.patches.set_tick_index(flow_key, track, &[tick]);
+        for entry_key in track.respell_around(&flow.master, tick) {
+            self.patches.set_entry(flow_key, track, &entry_key);
+        }
 
-        self.state.score.meta.set_modified();
-        self.emit();
+        self.record("Create tone");
 
-        JsValue::from_str(key.as_str())
+        Ok(key)
     }
 
     /// Update the tone
@@ -107,111 +116,212 @@ impl Engine {
         duration: u32,
         pitch: u8,
         articulation: Articulation,
-    ) {
-        let flow = match self
-            .state
-            .score
-            .flows
-            .by_key
-            .get_mut(&String::from(flow_key))
-        {
+    ) -> Result<()> {
+        let flow = match self.state.score.flows.by_key.get_mut(flow_key) {
             Some(flow) => flow,
-            None => return (),
+            None => return self.fail(Error::UnknownFlow(String::from(flow_key))),
         };
 
+        if tick >= flow.length {
+            return self.fail(Error::InvalidTick(tick));
+        }
+
         let track = match flow.tracks.get_mut(track_key) {
             Some(track) => track,
-            None => return (),
+            None => return self.fail(Error::UnknownTrack(String::from(track_key))),
+        };
+
+        let old_tick = match track.entries.by_key.get(entry_key) {
+            Some(Entry::Tone(tone)) => tone.tick,
+            _ => return self.fail(Error::UnknownEntry(String::from(entry_key))),
         };
 
         // move the entry to the new start tick
         track.r#move(entry_key, tick);
 
         // update pitch and duration
-        let tone = match track.entries.by_key.get_mut(entry_key) {
-            Some(entry) => match entry {
-                Entry::Tone(tone) => tone,
-                _ => return (),
-            },
-            None => return (),
-        };
-        tone.pitch = Pitch::new(pitch, Accidental::default(pitch));
-        tone.duration = Duration::new(duration);
-        tone.articulation = articulation;
-
-        self.state.score.meta.set_modified();
-        self.emit();
+        if let Some(Entry::Tone(tone)) = track.entries.by_key.get_mut(entry_key) {
+            // a user defined spelling sticks as long as it still makes sense for the pitch
+            let spelling = tone.spelling.filter(|&spelling| is_valid(pitch, spelling));
+            tone.pitch = Pitch::new(
+                pitch,
+                spelling.unwrap_or_else(|| Accidental::default(pitch)),
+            );
+            tone.spelling = spelling;
+            tone.duration = Duration::new(duration);
+            tone.articulation = articulation;
+        }
+        self.patches.set_entry(flow_key, track, entry_key);
+        self.patches
+            .set_tick_index(flow_key, track, &[old_tick, tick]);
+        // the tones around where it was and where it is now
+        let mut changed = track.respell_around(&flow.master, old_tick);
+        changed.extend(track.respell_around(&flow.master, tick));
+        for entry_key in changed {
+            self.patches.set_entry(flow_key, track, &entry_key);
+        }
+
+        self.record("Update tone");
+
+        Ok(())
     }
 
     /// Remove the tone
-    pub fn remove_tone(&mut self, flow_key: &str, track_key: &str, entry_key: &str) {
-        let flow = match self
-            .state
-            .score
-            .flows
-            .by_key
-            .get_mut(&String::from(flow_key))
-        {
+    pub fn remove_tone(&mut self, flow_key: &str, track_key: &str, entry_key: &str) -> Result<()> {
+        let flow = match self.state.score.flows.by_key.get_mut(flow_key) {
             Some(flow) => flow,
-            None => return (),
+            None => return self.fail(Error::UnknownFlow(String::from(flow_key))),
         };
 
         let track = match flow.tracks.get_mut(track_key) {
             Some(track) => track,
-            None => return (),
+            None => return self.fail(Error::UnknownTrack(String::from(track_key))),
         };
 
-        track.remove(entry_key);
+        let tick = match track.remove(entry_key) {
+            Some(entry) => entry.tick(),
+            None => return self.fail(Error::UnknownEntry(String::from(entry_key))),
+        };
+        self.patches.set_entry(flow_key, track, entry_key);
+        self.patches.set_tick_index(flow_key, track, &[tick]);
+        for entry_key in track.respell_around(&flow.master, tick) {
+            self.patches.set_entry(flow_key, track, &entry_key);
+        }
+
+        self.record("Remove tone");
 
-        self.state.score.meta.set_modified();
-        self.emit();
+        Ok(())
     }
 
     /// Slice a tone
-    pub fn slice_tone(&mut self, flow_key: &str, track_key: &str, entry_key: &str, slice_at: u32) {
-        let flow = match self
-            .state
-            .score
-            .flows
-            .by_key
-            .get_mut(&String::from(flow_key))
-        {
+    pub fn slice_tone(
+        &mut self,
+        flow_key: &str,
+        track_key: &str,
+        entry_key: &str,
+        slice_at: u32,
+    ) -> Result<()> {
+        let flow = match self.state.score.flows.by_key.get_mut(flow_key) {
             Some(flow) => flow,
-            None => return (),
+            None => return self.fail(Error::UnknownFlow(String::from(flow_key))),
         };
 
         let track = match flow.tracks.get_mut(track_key) {
             Some(track) => track,
-            None => return (),
+            None => return self.fail(Error::UnknownTrack(String::from(track_key))),
+        };
+
+        // the slice must fall strictly inside the tone
+        match track.entries.by_key.get(entry_key) {
+            Some(Entry::Tone(tone)) => {
+                if slice_at <= tone.tick || slice_at >= tone.tick + tone.duration.int {
+                    return self.fail(Error::InvalidTick(slice_at));
+                }
+            }
+            _ => return self.fail(Error::UnknownEntry(String::from(entry_key))),
         };
 
         let old_tone = match track.remove(entry_key) {
-            Some(entry) => match entry {
-                Entry::Tone(tone) => tone,
-                _ => return (),
-            },
-            None => return (),
+            Some(Entry::Tone(tone)) => tone,
+            _ => return self.fail(Error::UnknownEntry(String::from(entry_key))),
         };
 
         track.insert(Tone::new(
-            old_tone.key,
+            old_tone.key.clone(),
             old_tone.tick,
             Duration::new(slice_at - old_tone.tick),
             old_tone.pitch,
             old_tone.velocity,
             old_tone.articulation,
+            old_tone.spelling,
         ));
 
+        let new_key = shortid();
         track.insert(Tone::new(
-            shortid(),
+            new_key.clone(),
             slice_at,
             Duration::new(old_tone.duration.int - (slice_at - old_tone.tick)),
             old_tone.pitch,
             old_tone.velocity,
             old_tone.articulation,
+            old_tone.spelling,
         ));
+        self.patches.set_entry(flow_key, track, &old_tone.key);
+        self.patches.set_entry(flow_key, track, &new_key);
+        self.patches
+            .set_tick_index(flow_key, track, &[old_tone.tick, slice_at]);
+        for entry_key in track.respell_around(&flow.master, slice_at) {
+            self.patches.set_entry(flow_key, track, &entry_key);
+        }
+
+        self.record("Slice tone");
+
+        Ok(())
+    }
+
+    /// Respell a tone as the next enharmonic equivalent, eg. C# -> Db, along with the
+    /// tones either side of it. The spelling is kept through later edits until it is reset.
+    pub fn respell_tone(&mut self, flow_key: &str, track_key: &str, entry_key: &str) -> Result<()> {
+        let flow = match self.state.score.flows.by_key.get_mut(flow_key) {
+            Some(flow) => flow,
+            None => return self.fail(Error::UnknownFlow(String::from(flow_key))),
+        };
+
+        let track = match flow.tracks.get_mut(track_key) {
+            Some(track) => track,
+            None => return self.fail(Error::UnknownTrack(String::from(track_key))),
+        };
+
+        let tick = match track.entries.by_key.get_mut(entry_key) {
+            Some(Entry::Tone(tone)) => {
+                let spelling = next_enharmonic(tone.pitch.int, tone.pitch.accidental);
+                tone.pitch.accidental = spelling;
+                tone.spelling = Some(spelling);
+                tone.tick
+            }
+            _ => return self.fail(Error::UnknownEntry(String::from(entry_key))),
+        };
+        self.patches.set_entry(flow_key, track, entry_key);
+        for entry_key in track.respell_around(&flow.master, tick) {
+            self.patches.set_entry(flow_key, track, &entry_key);
+        }
+
+        self.record("Respell tone");
+
+        Ok(())
+    }
+
+    /// Remove a user defined spelling so the tone is spelled from its key again
+    pub fn reset_tone_spelling(
+        &mut self,
+        flow_key: &str,
+        track_key: &str,
+        entry_key: &str,
+    ) -> Result<()> {
+        let flow = match self.state.score.flows.by_key.get_mut(flow_key) {
+            Some(flow) => flow,
+            None => return self.fail(Error::UnknownFlow(String::from(flow_key))),
+        };
+
+        let track = match flow.tracks.get_mut(track_key) {
+            Some(track) => track,
+            None => return self.fail(Error::UnknownTrack(String::from(track_key))),
+        };
+
+        let tick = match track.entries.by_key.get_mut(entry_key) {
+            Some(Entry::Tone(tone)) => {
+                tone.spelling = None;
+                tone.tick
+            }
+            _ => return self.fail(Error::UnknownEntry(String::from(entry_key))),
+        };
+        self.patches.set_entry(flow_key, track, entry_key);
+        for entry_key in track.respell_around(&flow.master, tick) {
+            self.patches.set_entry(flow_key, track, &entry_key);
+        }
+
+        self.record("Reset tone spelling");
 
-        self.state.score.meta.set_modified();
-        self.emit();
+        Ok(())
     }
 }
@@ -0,0 +1,323 @@
+use crate::error::{Error, Result};
+use crate::state::entries::tone::Articulation;
+use crate::state::entries::Entry;
+use crate::state::Engine;
+use crate::utils::duration::NoteDuration;
+use crate::utils::shortid;
+use std::convert::TryFrom;
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+/// Tuplets play `actual` notes of the base duration in the time of `normal` notes,
+/// eg. 3:2 over an eighth is a triplet lasting a quarter.
+///
+/// The tones within a tuplet sit on the normal tick grid so the flow's subdivisions
+/// need to divide by the tuplet, eg. 48 subdivisions for triplets.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Tuplet {
+    pub key: String,
+    pub tick: u32,
+    pub actual: u8,
+    pub normal: u8,
+    pub base: NoteDuration,
+}
+
+impl Tuplet {
+    pub fn new(key: String, tick: u32, actual: u8, normal: u8, base: NoteDuration) -> Entry {
+        Entry::Tuplet(Self {
+            key,
+            tick,
+            actual,
+            normal,
+            base,
+        })
+    }
+
+    /// The number of ticks the whole tuplet lasts
+    pub fn length(&self, subdivisions: u8) -> u32 {
+        self.base.to_ticks(subdivisions) * u32::from(self.normal)
+    }
+
+    /// Lengthen the tuplet by `ticks`, keeping its ratio, eg. a 3:2 eighth note triplet
+    /// lengthened by a quarter becomes 6:4. Returns false if it can't be written so.
+    pub fn lengthen(&mut self, ticks: u32, subdivisions: u8) -> bool {
+        let base = self.base.to_ticks(subdivisions);
+        if self.normal == 0 || base == 0 || !ticks.is_multiple_of(base) {
+            return false;
+        }
+        let normal = u32::from(self.normal) + ticks / base;
+        let actual = normal * u32::from(self.actual);
+        if !actual.is_multiple_of(u32::from(self.normal)) {
+            return false;
+        }
+        match (
+            u8::try_from(normal),
+            u8::try_from(actual / u32::from(self.normal)),
+        ) {
+            (Ok(normal), Ok(actual)) => {
+                self.normal = normal;
+                self.actual = actual;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The number of ticks of each note in the tuplet, if the subdivisions can represent it
+    pub fn ticks_per_value(&self, subdivisions: u8) -> Option<u32> {
+        let length = self.length(subdivisions);
+        if self.actual == 0 || length == 0 || !length.is_multiple_of(u32::from(self.actual)) {
+            None
+        } else {
+            Some(length / u32::from(self.actual))
+        }
+    }
+}
+
+/// A tone within a tuplet, starting on the `index` note of the tuplet and
+/// lasting `count` notes.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Debug, Copy, Clone)]
+pub struct TupletTone {
+    pub index: u8,
+    pub count: u8,
+    pub pitch: u8,
+    pub velocity: u8,
+    pub articulation: Articulation,
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl TupletTone {
+    #[cfg_attr(feature = "wasm", wasm_bindgen(constructor))]
+    pub fn new(index: u8, count: u8, pitch: u8, velocity: u8, articulation: Articulation) -> Self {
+        Self {
+            index,
+            count,
+            pitch,
+            velocity,
+            articulation,
+        }
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl Engine {
+    /// Create a tuplet, eg. (3, 2, Eighth) for eighth note triplets
+    pub fn create_tuplet(
+        &mut self,
+        flow_key: &str,
+        track_key: &str,
+        tick: u32,
+        actual: u8,
+        normal: u8,
+        base: NoteDuration,
+    ) -> Result<String> {
+        // we want to be able to return this at the end
+        let key = shortid();
+
+        let flow = match self.state.score.flows.by_key.get_mut(flow_key) {
+            Some(flow) => flow,
+            None => return self.fail(Error::UnknownFlow(String::from(flow_key))),
+        };
+
+        let subdivisions = flow.subdivisions;
+        let track = match flow.tracks.get_mut(track_key) {
+            Some(track) => track,
+            None => return self.fail(Error::UnknownTrack(String::from(track_key))),
+        };
+
+        let entry = Tuplet::new(key.clone(), tick, actual, normal, base);
+        let tuplet = match &entry {
+            Entry::Tuplet(tuplet) => tuplet,
+            _ => return self.fail(Error::UnknownEntry(key)), // will never happen
+        };
+
+        let length = tuplet.length(subdivisions);
+        if normal == 0 || tuplet.ticks_per_value(subdivisions).is_none() {
+            return self.fail(Error::InvalidTuplet(actual, normal));
+        }
+        let end = match tick.checked_add(length) {
+            Some(end) if end <= flow.length => end,
+            _ => return self.fail(Error::InvalidTick(tick)),
+        };
+
+        // tuplets in the same track can't overlap
+        let overlaps = track
+            .get_tuplets()
+            .iter()
+            .any(|other| other.tick < end && tick < other.tick + other.length(subdivisions));
+        if overlaps {
+            return self.fail(Error::InvalidTuplet(actual, normal));
+        }
+
+        track.insert(entry);
+        self.patches.set_entry(flow_key, track, &key);
+        self.patches.set_tick_index(flow_key, track, &[tick]);
+        self.record("Create tuplet");
+
+        Ok(key)
+    }
+
+    /// Create a tone inside a tuplet, see `TupletTone` for where it is placed
+    pub fn create_tuplet_tone(
+        &mut self,
+        flow_key: &str,
+        track_key: &str,
+        tuplet_key: &str,
+        tone: TupletTone,
+    ) -> Result<String> {
+        let flow = match self.state.score.flows.by_key.get(flow_key) {
+            Some(flow) => flow,
+            None => return self.fail(Error::UnknownFlow(String::from(flow_key))),
+        };
+
+        let track = match flow.tracks.get(track_key) {
+            Some(track) => track,
+            None => return self.fail(Error::UnknownTrack(String::from(track_key))),
+        };
+
+        let tuplet = match track.entries.by_key.get(tuplet_key) {
+            Some(Entry::Tuplet(tuplet)) => tuplet,
+            _ => return self.fail(Error::UnknownEntry(String::from(tuplet_key))),
+        };
+
+        let TupletTone {
+            index,
+            count,
+            pitch,
+            velocity,
+            articulation,
+        } = tone;
+        if count == 0 || u16::from(index) + u16::from(count) > u16::from(tuplet.actual) {
+            return self.fail(Error::InvalidIndex(usize::from(index)));
+        }
+
+        let (actual, normal, tuplet_tick) = (tuplet.actual, tuplet.normal, tuplet.tick);
+        let ticks_per_value = match tuplet.ticks_per_value(flow.subdivisions) {
+            Some(ticks_per_value) => ticks_per_value,
+            None => return self.fail(Error::InvalidTuplet(actual, normal)),
+        };
+
+        let tick = tuplet_tick + u32::from(index) * ticks_per_value;
+        let duration = u32::from(count) * ticks_per_value;
+        self.create_tone(
+            flow_key,
+            track_key,
+            tick,
+            duration,
+            pitch,
+            velocity,
+            articulation,
+        )
+    }
+
+    /// Remove a tuplet, the tones within it are left where they are
+    pub fn remove_tuplet(
+        &mut self,
+        flow_key: &str,
+        track_key: &str,
+        entry_key: &str,
+    ) -> Result<()> {
+        let flow = match self.state.score.flows.by_key.get_mut(flow_key) {
+            Some(flow) => flow,
+            None => return self.fail(Error::UnknownFlow(String::from(flow_key))),
+        };
+
+        let track = match flow.tracks.get_mut(track_key) {
+            Some(track) => track,
+            None => return self.fail(Error::UnknownTrack(String::from(track_key))),
+        };
+
+        let tick = match track.entries.by_key.get(entry_key) {
+            Some(Entry::Tuplet(tuplet)) => tuplet.tick,
+            _ => return self.fail(Error::UnknownEntry(String::from(entry_key))),
+        };
+        track.remove(entry_key);
+        self.patches.set_entry(flow_key, track, entry_key);
+        self.patches.set_tick_index(flow_key, track, &[tick]);
+        self.record("Remove tuplet");
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::listener::{SystemClock, Update};
+    use crate::state::score::flow::Flow;
+    use crate::state::score::track::Track;
+
+    #[test]
+    fn test_ticks_per_value() {
+        let entry = Tuplet::new(String::from("a"), 0, 3, 2, NoteDuration::Eighth);
+        let tuplet = match &entry {
+            Entry::Tuplet(tuplet) => tuplet,
+            _ => unreachable!(),
+        };
+        assert_eq!(tuplet.ticks_per_value(16), None);
+        assert_eq!(tuplet.ticks_per_value(48), Some(16));
+    }
+
+    #[test]
+    fn test_rescale_flow() {
+        let mut flow = Flow::new();
+        let mut track = Track::new();
+        track.insert(Tuplet::new(
+            String::from("a"),
+            8,
+            3,
+            2,
+            NoteDuration::Eighth,
+        ));
+        flow.tracks.insert(track.key.clone(), track);
+
+        assert!(flow.rescale(48).is_ok());
+        assert_eq!(flow.length, 48);
+        let track = flow.tracks.values().next().unwrap();
+        assert_eq!(track.get_tuplets()[0].tick, 24);
+
+        // triplets can't be represented in 16ths so nothing changes
+        assert!(flow.rescale(16).is_err());
+        assert_eq!(flow.subdivisions, 48);
+    }
+
+    #[test]
+    fn test_create_tuplet() {
+        let mut engine = Engine::create(Box::new(SystemClock), Box::new(|_: Update| ()));
+        let flow_key = engine.score().flows.order[0].clone();
+        engine.set_flow_subdivisions(&flow_key, 48).unwrap();
+        let track = Track::new();
+        let track_key = track.key.clone();
+        let flow = engine.state.score.flows.by_key.get_mut(&flow_key).unwrap();
+        flow.tracks.insert(track_key.clone(), track);
+
+        assert_eq!(
+            engine.create_tuplet(&flow_key, &track_key, u32::MAX, 3, 2, NoteDuration::Eighth),
+            Err(Error::InvalidTick(u32::MAX))
+        );
+        let tuplet = engine
+            .create_tuplet(&flow_key, &track_key, 0, 3, 2, NoteDuration::Eighth)
+            .unwrap();
+
+        // the second and third notes of the triplet
+        let tone = TupletTone::new(1, 2, 60, 100, Articulation::None);
+        let key = engine
+            .create_tuplet_tone(&flow_key, &track_key, &tuplet, tone)
+            .unwrap();
+        match engine.score().flows.by_key[&flow_key].tracks[&track_key]
+            .entries
+            .by_key
+            .get(&key)
+        {
+            Some(Entry::Tone(tone)) => assert_eq!((tone.tick, tone.duration.int), (16, 32)),
+            _ => panic!("expected a tone"),
+        }
+
+        let tone = TupletTone::new(2, 2, 60, 100, Articulation::None);
+        assert_eq!(
+            engine.create_tuplet_tone(&flow_key, &track_key, &tuplet, tone),
+            Err(Error::InvalidIndex(2))
+        );
+    }
+}
@@ -0,0 +1,196 @@
+use crate::state::score::Score;
+use crate::state::{Engine, State};
+use std::collections::VecDeque;
+use std::mem;
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+/// The maximum number of steps kept in the undo stack
+const HISTORY_LIMIT: usize = 100;
+
+#[derive(Clone)]
+struct HistoryEntry {
+    label: String,
+    score: Score,
+}
+
+/// Snapshot based undo/redo stacks.
+///
+/// We keep a copy of the score as of the last committed step so that each step
+/// can be reverted exactly. Derived data such as the tick lists and tempo maps
+/// isn't kept, it is rebuilt from the score when a step is restored.
+pub struct History {
+    undo: VecDeque<HistoryEntry>,
+    redo: Vec<HistoryEntry>,
+    present: Score,
+}
+
+impl History {
+    pub fn new(state: &State) -> Self {
+        Self {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            present: state.score.clone(),
+        }
+    }
+
+    /// Record a new step. The previously committed score becomes the undo target
+    pub fn push(&mut self, label: &str, state: &State) {
+        let previous = mem::replace(&mut self.present, state.score.clone());
+        self.undo.push_back(HistoryEntry {
+            label: String::from(label),
+            score: previous,
+        });
+        if self.undo.len() > HISTORY_LIMIT {
+            self.undo.pop_front();
+        }
+        self.redo.clear();
+    }
+
+    /// Step back, returning the state to restore
+    pub fn undo(&mut self) -> Option<State> {
+        let entry = self.undo.pop_back()?;
+        let next = mem::replace(&mut self.present, entry.score);
+        self.redo.push(HistoryEntry {
+            label: entry.label,
+            score: next,
+        });
+        Some(self.restore())
+    }
+
+    /// Step forward again, returning the state to restore
+    pub fn redo(&mut self) -> Option<State> {
+        let entry = self.redo.pop()?;
+        let previous = mem::replace(&mut self.present, entry.score);
+        self.undo.push_back(HistoryEntry {
+            label: entry.label,
+            score: previous,
+        });
+        Some(self.restore())
+    }
+
+    /// The state as of the last recorded step, rebuilt from its score
+    pub fn restore(&self) -> State {
+        State::new(self.present.clone())
+    }
+
+    pub fn undo_label(&self) -> Option<&str> {
+        self.undo.back().map(|entry| entry.label.as_str())
+    }
+
+    pub fn redo_label(&self) -> Option<&str> {
+        self.redo.last().map(|entry| entry.label.as_str())
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl Engine {
+    pub fn undo(&mut self) {
+        if self.transaction.is_some() {
+            return;
+        }
+        if let Some(state) = self.history.undo() {
+            self.state = state;
+            self.patches.resync();
+            self.emit();
+        }
+    }
+
+    pub fn redo(&mut self) {
+        if self.transaction.is_some() {
+            return;
+        }
+        if let Some(state) = self.history.redo() {
+            self.state = state;
+            self.patches.resync();
+            self.emit();
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.history.undo_label().is_some()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.history.redo_label().is_some()
+    }
+
+    /// The label of the step that will be reverted by `undo()`
+    pub fn undo_label(&self) -> Option<String> {
+        self.history.undo_label().map(String::from)
+    }
+
+    /// The label of the step that will be reapplied by `redo()`
+    pub fn redo_label(&self) -> Option<String> {
+        self.history.redo_label().map(String::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(title: &str) -> State {
+        let mut score = Score::new(0.0);
+        score.meta.title = String::from(title);
+        State::new(score)
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        let mut history = History::new(&state("a"));
+        history.push("Set title", &state("b"));
+        history.push("Set composer", &state("c"));
+        assert_eq!(history.undo_label(), Some("Set composer"));
+        assert_eq!(history.redo_label(), None);
+
+        let undone = history.undo().unwrap();
+        assert_eq!(undone.score.meta.title, "b");
+        // the derived data is rebuilt for the restored score
+        for key in undone.score.flows.order.iter() {
+            assert!(undone.ticks.contains_key(key));
+            assert!(undone.tempo_maps.contains_key(key));
+        }
+        assert_eq!(history.undo_label(), Some("Set title"));
+        assert_eq!(history.redo_label(), Some("Set composer"));
+
+        assert_eq!(history.undo().unwrap().score.meta.title, "a");
+        assert!(history.undo().is_none());
+        assert_eq!(history.present.meta.title, "a");
+
+        assert_eq!(history.redo().unwrap().score.meta.title, "b");
+        assert_eq!(history.undo_label(), Some("Set title"));
+        assert_eq!(history.redo_label(), Some("Set composer"));
+    }
+
+    #[test]
+    fn test_push_clears_redo() {
+        let mut history = History::new(&state("a"));
+        history.push("Set title", &state("b"));
+        history.undo();
+        assert_eq!(history.redo_label(), Some("Set title"));
+
+        history.push("Set subtitle", &state("c"));
+        assert_eq!(history.redo_label(), None);
+        assert!(history.redo().is_none());
+        assert_eq!(history.undo().unwrap().score.meta.title, "a");
+    }
+
+    #[test]
+    fn test_limit() {
+        let mut history = History::new(&state("0"));
+        for step in 1..=HISTORY_LIMIT + 5 {
+            history.push(&step.to_string(), &state(&step.to_string()));
+        }
+
+        // the oldest steps are dropped
+        let mut steps = 0;
+        let mut title = String::new();
+        while let Some(state) = history.undo() {
+            steps += 1;
+            title = state.score.meta.title;
+        }
+        assert_eq!(steps, HISTORY_LIMIT);
+        assert_eq!(title, "5");
+    }
+}
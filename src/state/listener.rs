@@ -0,0 +1,38 @@
+use crate::state::patch::PatchSet;
+use crate::state::State;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The time used to stamp the score when it is created and modified
+pub trait Clock {
+    /// Milliseconds since the unix epoch
+    fn now(&self) -> f64;
+}
+
+/// The system time, for native builds
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> f64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0.0, |duration| duration.as_secs_f64() * 1000.0)
+    }
+}
+
+/// What the listener is sent after each change, depending on the `ListenerMode`
+pub enum Update<'a> {
+    State(&'a State),
+    Patches(&'a PatchSet),
+}
+
+/// Notified whenever the state changes
+pub trait Listener {
+    fn notify(&mut self, update: Update);
+}
+
+/// Any closure taking an `Update` can listen, eg. `|_| ()` to ignore changes
+impl<F: FnMut(Update)> Listener for F {
+    fn notify(&mut self, update: Update) {
+        self(update)
+    }
+}
@@ -0,0 +1,123 @@
+use crate::error::{Error, Result};
+use crate::state::score::Score;
+use serde_json::Value;
+
+/// The version of the score document written by this engine
+pub const VERSION: u64 = 2;
+
+type Migration = fn(&mut Value);
+
+/// Each migration upgrades a document from version `i` to `i + 1`
+const MIGRATIONS: [Migration; VERSION as usize] = [v0_to_v1, v1_to_v2];
+
+/// Documents saved before versioning have no version field, we stamp them as v1
+fn v0_to_v1(_doc: &mut Value) {
+    // intentionally a no-op: v1 only added the version field, which `migrate()` sets
+    // once all the migrations have run, so a v0 document is already a valid v1 one.
+    // It is kept so that `MIGRATIONS[i]` always upgrades from version `i`.
+}
+
+/// Time signatures gained a pickup, those saved before started with a full bar
+fn v1_to_v2(doc: &mut Value) {
+    let flows = match doc
+        .pointer_mut("/flows/by_key")
+        .and_then(Value::as_object_mut)
+    {
+        Some(flows) => flows,
+        None => return,
+    };
+    for flow in flows.values_mut() {
+        let entries = match flow
+            .pointer_mut("/master/entries/by_key")
+            .and_then(Value::as_object_mut)
+        {
+            Some(entries) => entries,
+            None => continue,
+        };
+        for entry in entries.values_mut() {
+            if let Some(time_signature) = entry
+                .get_mut("TimeSignature")
+                .and_then(Value::as_object_mut)
+            {
+                time_signature
+                    .entry("pickup")
+                    .or_insert_with(|| Value::from(0));
+            }
+        }
+    }
+}
+
+/// Upgrade a saved document step by step to the current version and load it
+pub fn migrate(mut doc: Value) -> Result<Score> {
+    let version = match doc.get("version") {
+        Some(version) => version
+            .as_u64()
+            .ok_or_else(|| Error::SchemaMismatch(String::from("version is not a number")))?,
+        None => 0,
+    };
+
+    if version > VERSION {
+        return Err(Error::SchemaMismatch(format!(
+            "document version {} is newer than supported version {}",
+            version, VERSION
+        )));
+    }
+
+    for migration in MIGRATIONS.iter().skip(version as usize) {
+        migration(&mut doc);
+    }
+
+    match doc.as_object_mut() {
+        Some(object) => object.insert(String::from("version"), Value::from(VERSION)),
+        None => return Err(Error::SchemaMismatch(String::from("expected an object"))),
+    };
+
+    serde_json::from_value(doc).map_err(|e| Error::SchemaMismatch(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(json: &str) -> Value {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_v0() {
+        let score = migrate(fixture(include_str!("../../tests/fixtures/score_v0.json"))).unwrap();
+        assert_eq!(score.version, VERSION);
+        assert_eq!(score.meta.title, "Sonata");
+        assert_eq!(score.flows.order.len(), 1);
+        assert_eq!(score.players.order.len(), 1);
+    }
+
+    #[test]
+    fn test_v1() {
+        let score = migrate(fixture(include_str!("../../tests/fixtures/score_v1.json"))).unwrap();
+        assert_eq!(score.version, VERSION);
+        assert_eq!(score.meta.composer, "A. Composer");
+        let flow = &score.flows.by_key["flow0000000001"];
+        let time_signature = flow.master.get_time_signature_at_tick(0).unwrap();
+        assert_eq!(time_signature.pickup, 0);
+    }
+
+    #[test]
+    fn test_v2() {
+        let score = migrate(fixture(include_str!("../../tests/fixtures/score_v2.json"))).unwrap();
+        assert_eq!(score.version, VERSION);
+        assert_eq!(score.meta.title, "Sonata");
+    }
+
+    #[test]
+    fn test_newer_version() {
+        let mut doc = fixture(include_str!("../../tests/fixtures/score_v0.json"));
+        doc["version"] = Value::from(VERSION + 1);
+        assert!(migrate(doc).is_err());
+    }
+
+    #[test]
+    fn test_not_a_score() {
+        assert!(migrate(Value::from("score")).is_err());
+    }
+}
@@ -1,56 +1,228 @@
-mod entries;
-mod score;
+pub mod entries;
+mod history;
+pub mod listener;
+mod migrate;
+pub mod patch;
+pub mod score;
+mod transaction;
 
+use crate::error::{Error, Result};
+use crate::state::history::History;
+use crate::state::listener::{Clock, Listener, Update};
+use crate::state::migrate::migrate;
+use crate::state::patch::Patches;
 use crate::state::score::flow::TickList;
+use crate::state::score::tempo_map::TempoMap;
 use crate::state::score::Score;
+use crate::state::transaction::Transaction;
+use serde_json::Value;
 use std::collections::HashMap;
+#[cfg(feature = "wasm")]
 use wasm_bindgen::prelude::*;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct State {
-    score: Score,
+    pub score: Score,
     ticks: HashMap<String, TickList>,
+    tempo_maps: HashMap<String, TempoMap>,
 }
 
-#[wasm_bindgen]
+impl State {
+    pub fn new(score: Score) -> State {
+        let mut ticks = HashMap::new();
+        let mut tempo_maps = HashMap::new();
+        for (key, flow) in &score.flows.by_key {
+            ticks.insert(key.clone(), flow.calc_ticks());
+            tempo_maps.insert(key.clone(), flow.calc_tempo_map());
+        }
+        State {
+            score,
+            ticks,
+            tempo_maps,
+        }
+    }
+}
+
+/// The score and its history, with every edit to the score made through the engine.
+///
+/// The engine is plain Rust, the wasm bindings wrap it with a `Date` clock and a
+/// JavaScript function as the listener.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 pub struct Engine {
-    listener: js_sys::Function,
-    state: State,
+    clock: Box<dyn Clock>,
+    listener: Box<dyn Listener>,
+    pub(crate) state: State,
+    history: History,
+    transaction: Option<Transaction>,
+    pub(crate) patches: Patches,
 }
 
-#[wasm_bindgen]
 impl Engine {
-    #[wasm_bindgen(constructor)]
-    pub fn new(callback: js_sys::Function) -> Engine {
+    /// Create an engine with an empty score, the listener is sent the state straight away
+    pub fn create(clock: Box<dyn Clock>, listener: Box<dyn Listener>) -> Engine {
+        let state = State::new(Score::new(clock.now()));
         let mut engine = Engine {
-            listener: callback,
-            state: State {
-                score: Score::new(),
-                ticks: HashMap::new(),
-            },
+            clock,
+            listener,
+            history: History::new(&state),
+            transaction: None,
+            patches: Patches::new(),
+            state,
         };
-        for (key, flow) in &engine.state.score.flows.by_key {
-            engine.state.ticks.insert(key.clone(), flow.calc_ticks());
-        }
         engine.emit();
         engine
     }
 
-    pub fn export(&self) -> JsValue {
-        JsValue::from_serde(&self.state.score).unwrap()
+    pub fn score(&self) -> &Score {
+        &self.state.score
     }
 
-    pub fn import(&mut self, state: JsValue) {
-        self.state.score = state.into_serde().unwrap();
-        for (key, flow) in &self.state.score.flows.by_key {
-            self.state.ticks.insert(key.clone(), flow.calc_ticks());
-        }
+    /// Replace the score, upgrading it from older file versions if needed.
+    /// This also clears the undo history.
+    pub fn load(&mut self, doc: Value) -> Result<()> {
+        self.state = State::new(migrate(doc)?);
+        self.history = History::new(&self.state);
+        self.transaction = None;
+        self.patches.resync();
         self.emit();
+        Ok(())
+    }
+
+    /// Mark the score as modified, record the change in the history and notify the listener.
+    /// Inside a transaction the history step and notification are deferred until `commit()`.
+    fn record(&mut self, label: &str) {
+        self.state.score.meta.set_modified(self.clock.now());
+        self.patches.replace(
+            String::from("score.meta.modified"),
+            &self.state.score.meta.modified,
+        );
+        match &mut self.transaction {
+            Some(transaction) => {
+                transaction.changed = true;
+                if transaction.label.is_none() {
+                    transaction.label = Some(String::from(label));
+                }
+            }
+            None => {
+                self.history.push(label, &self.state);
+                self.emit();
+            }
+        }
+    }
+
+    /// Recalculate the ticks of a flow from `from` onwards and its tempo map, after
+    /// its length or time signatures have changed. The ticks before `from` must be
+    /// unchanged by the edit.
+    pub(crate) fn update_timing(&mut self, flow_key: &str, from: u32) {
+        let flow = match self.state.score.flows.by_key.get(flow_key) {
+            Some(flow) => flow,
+            None => return,
+        };
+        let ticks = self.state.ticks.entry(String::from(flow_key)).or_default();
+        let start = flow.update_ticks(ticks, from);
+        self.patches.splice(
+            format!("ticks.{}.list", flow_key),
+            start,
+            &ticks.list[start..],
+        );
+        self.patches
+            .replace(format!("ticks.{}.width", flow_key), &ticks.width);
+        self.update_tempo_map(flow_key);
+    }
+
+    /// Recalculate the tempo map of a flow, after its tempos have changed
+    pub(crate) fn update_tempo_map(&mut self, flow_key: &str) {
+        let flow = match self.state.score.flows.by_key.get(flow_key) {
+            Some(flow) => flow,
+            None => return,
+        };
+        let tempo_map = flow.calc_tempo_map();
+        self.patches
+            .replace(format!("tempo_maps.{}", flow_key), &tempo_map);
+        self.state
+            .tempo_maps
+            .insert(String::from(flow_key), tempo_map);
+    }
+
+    /// Flag the open transaction (if any) as failed so that it is rolled back on commit.
+    ///
+    /// Returns the error so it can be used as `return self.fail(error)`
+    pub(crate) fn fail<T>(&mut self, error: Error) -> Result<T> {
+        if let Some(transaction) = &mut self.transaction {
+            if transaction.error.is_none() {
+                transaction.error = Some(error.clone());
+            }
+        }
+        Err(error)
+    }
+
+    /// Notify the listener with either the full state or the pending patches
+    fn emit(&mut self) {
+        let patch_set = self.patches.take(&self.state);
+        let update = if self.patches.enabled() {
+            Update::Patches(&patch_set)
+        } else {
+            Update::State(&self.state)
+        };
+        self.listener.notify(update);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::listener::SystemClock;
+    use crate::state::patch::ListenerMode;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct FixedClock(f64);
+
+    impl Clock for FixedClock {
+        fn now(&self) -> f64 {
+            self.0
+        }
     }
 
-    fn emit(&self) {
-        let this = JsValue::NULL;
-        let state = JsValue::from_serde(&self.state).unwrap();
-        let _ = self.listener.call1(&this, &state);
+    /// An engine that records what its listener was sent, as (is full state, patch count)
+    fn engine(clock: Box<dyn Clock>) -> (Engine, Rc<RefCell<Vec<(bool, usize)>>>) {
+        let updates = Rc::new(RefCell::new(Vec::new()));
+        let log = updates.clone();
+        let listener = move |update: Update| {
+            log.borrow_mut().push(match update {
+                Update::State(_) => (true, 0),
+                Update::Patches(patch_set) => (false, patch_set.patches.len()),
+            })
+        };
+        (Engine::create(clock, Box::new(listener)), updates)
+    }
+
+    #[test]
+    fn test_native_engine() {
+        let (mut engine, updates) = engine(Box::new(FixedClock(1000.0)));
+        assert_eq!(engine.score().meta.created, 1000.0);
+        assert_eq!(*updates.borrow(), vec![(true, 0)]);
+
+        engine.set_title(String::from("Sonata"));
+        assert_eq!(engine.score().meta.title, "Sonata");
+        assert_eq!(engine.undo_label(), Some(String::from("Set title")));
+
+        engine.set_listener_mode(ListenerMode::Patches);
+        engine.undo();
+        assert_eq!(engine.score().meta.title, "");
+        assert_eq!(updates.borrow().len(), 4);
+        assert_eq!(updates.borrow()[3], (false, 1));
+    }
+
+    #[test]
+    fn test_load() {
+        let (mut engine, _) = engine(Box::new(SystemClock));
+        assert!(engine.score().meta.created > 0.0);
+
+        let doc = serde_json::from_str(include_str!("../../tests/fixtures/score_v2.json")).unwrap();
+        engine.load(doc).unwrap();
+        assert_eq!(engine.score().meta.title, "Sonata");
+        assert!(!engine.can_undo());
+        assert!(engine.load(Value::Null).is_err());
     }
 }
@@ -0,0 +1,382 @@
+use crate::state::score::flow::Flow;
+use crate::state::score::track::Track;
+use crate::state::{Engine, State};
+use serde::Serialize;
+use serde_json::Value;
+use std::mem;
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+/// How the listener is notified of changes
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ListenerMode {
+    /// The listener receives the full state on every change
+    State,
+    /// The listener receives `{ version, patches }` describing each change
+    Patches,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum Op {
+    Add,
+    Replace,
+    Remove,
+    /// Replace the end of an array, the path ending with the index to replace from
+    Splice,
+}
+
+/// A JSON-Patch style change. Paths are dot separated from the root of the state,
+/// eg. `score.flows.by_key.X.tracks.Y.entries.by_key.Z`. An empty path replaces the whole state.
+#[derive(Serialize, Clone)]
+pub struct Patch {
+    pub op: Op,
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<Value>,
+}
+
+#[derive(Serialize)]
+pub struct PatchSet {
+    pub version: u32,
+    pub patches: Vec<Patch>,
+}
+
+/// Collects the patches produced by mutations until the listener is next notified.
+///
+/// Nothing is recorded unless the listener is in `ListenerMode::Patches`, so
+/// that the values are only serialized when someone wants them.
+pub struct Patches {
+    enabled: bool,
+    version: u32,
+    resync: bool,
+    list: Vec<Patch>,
+}
+
+impl Default for Patches {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Patches {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            version: 0,
+            resync: false,
+            list: Vec::new(),
+        }
+    }
+
+    fn push<T: Serialize>(&mut self, op: Op, path: String, value: Option<&T>) {
+        if !self.enabled || self.resync {
+            return;
+        }
+        let value = value.map(|value| serde_json::to_value(value).unwrap_or(Value::Null));
+        self.list.push(Patch { op, path, value });
+    }
+
+    pub fn add<T: Serialize>(&mut self, path: String, value: &T) {
+        self.push(Op::Add, path, Some(value));
+    }
+
+    pub fn replace<T: Serialize>(&mut self, path: String, value: &T) {
+        self.push(Op::Replace, path, Some(value));
+    }
+
+    pub fn remove(&mut self, path: String) {
+        self.push::<()>(Op::Remove, path, None);
+    }
+
+    /// Replace the items of an array from `start` onwards
+    pub fn splice<T: Serialize>(&mut self, path: String, start: usize, items: &[T]) {
+        self.push(Op::Splice, format!("{}.{}", path, start), Some(&items));
+    }
+
+    /// Record the players, staves and tracks of a flow as these change together
+    pub fn replace_flow_contents(&mut self, flow: &Flow) {
+        let path = flow_path(&flow.key);
+        self.replace(format!("{}.players", path), &flow.players);
+        self.replace(format!("{}.staves", path), &flow.staves);
+        self.replace(format!("{}.tracks", path), &flow.tracks);
+    }
+
+    /// Record the current value of an entry (or its removal)
+    pub fn set_entry(&mut self, flow_key: &str, track: &Track, entry_key: &str) {
+        let path = entry_path(flow_key, &track.key, entry_key);
+        match track.entries.by_key.get(entry_key) {
+            Some(entry) => self.add(path, entry),
+            None => self.remove(path),
+        };
+    }
+
+    /// Record the tracks tick index at the ticks where entries have been added,
+    /// moved or removed. Ticks left without any entries are removed from the index.
+    pub fn set_tick_index(&mut self, flow_key: &str, track: &Track, ticks: &[u32]) {
+        let mut ticks = ticks.to_vec();
+        ticks.sort_unstable();
+        ticks.dedup();
+        for tick in ticks {
+            let path = format!(
+                "{}.entries.by_tick.{}",
+                track_path(flow_key, &track.key),
+                tick
+            );
+            match track.entries.by_tick.get(&tick) {
+                Some(keys) => self.add(path, keys),
+                None => self.remove(path),
+            };
+        }
+    }
+
+    /// The whole state has changed (undo, redo, import), the listener should
+    /// replace everything it holds on the next notification.
+    pub fn resync(&mut self) {
+        self.resync = true;
+        self.list.clear();
+    }
+
+    /// Drop any pending patches, the state is back to what the listener last saw
+    pub fn clear(&mut self) {
+        self.resync = false;
+        self.list.clear();
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Take the pending patches and bump the version
+    pub fn take(&mut self, state: &State) -> PatchSet {
+        self.version += 1;
+        let patches = if self.resync && self.enabled {
+            vec![Patch {
+                op: Op::Replace,
+                path: String::new(),
+                value: Some(serde_json::to_value(state).unwrap_or(Value::Null)),
+            }]
+        } else {
+            mem::take(&mut self.list)
+        };
+        self.resync = false;
+        PatchSet {
+            version: self.version,
+            patches,
+        }
+    }
+}
+
+pub fn flow_path(flow_key: &str) -> String {
+    format!("score.flows.by_key.{}", flow_key)
+}
+
+pub fn track_path(flow_key: &str, track_key: &str) -> String {
+    format!("{}.tracks.{}", flow_path(flow_key), track_key)
+}
+
+pub fn entry_path(flow_key: &str, track_key: &str, entry_key: &str) -> String {
+    format!(
+        "{}.entries.by_key.{}",
+        track_path(flow_key, track_key),
+        entry_key
+    )
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl Engine {
+    /// Switch how the listener is notified. The listener is immediately sent
+    /// the full state so it has something to apply patches to.
+    pub fn set_listener_mode(&mut self, mode: ListenerMode) {
+        self.patches.enabled = mode == ListenerMode::Patches;
+        self.patches.resync();
+        self.emit();
+    }
+
+    /// The version of the last notification sent to the listener
+    pub fn version(&self) -> u32 {
+        self.patches.version()
+    }
+
+    /// Send the full state to the listener, eg. if the UI has missed a patch
+    pub fn resync(&mut self) {
+        self.patches.resync();
+        self.emit();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::entries::tone::Articulation;
+    use crate::state::history::History;
+    use crate::state::listener::{SystemClock, Update};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Apply a patch to the listener's copy of the state
+    fn apply(doc: &mut Value, patch: &Value) {
+        let path = patch["path"].as_str().unwrap();
+        if path.is_empty() {
+            *doc = patch["value"].clone();
+            return;
+        }
+        let mut parts: Vec<&str> = path.split('.').collect();
+        let last = parts.pop().unwrap();
+        let mut parent = doc;
+        for part in parts {
+            parent = &mut parent[part];
+        }
+        match patch["op"].as_str().unwrap() {
+            "add" | "replace" => parent[last] = patch["value"].clone(),
+            "remove" => {
+                parent.as_object_mut().unwrap().remove(last);
+            }
+            "splice" => {
+                let list = parent.as_array_mut().unwrap();
+                list.truncate(last.parse().unwrap());
+                list.extend(patch["value"].as_array().unwrap().iter().cloned());
+            }
+            op => panic!("unknown op {}", op),
+        }
+    }
+
+    /// An engine in patch mode with a flow and track, and the patch sets sent to its listener
+    fn setup() -> (Engine, String, String, Rc<RefCell<Vec<Value>>>) {
+        let sets = Rc::new(RefCell::new(Vec::new()));
+        let log = sets.clone();
+        let listener = move |update: Update| {
+            if let Update::Patches(patch_set) = update {
+                log.borrow_mut()
+                    .push(serde_json::to_value(patch_set).unwrap());
+            }
+        };
+        let mut engine = Engine::create(Box::new(SystemClock), Box::new(listener));
+        let flow_key = engine.score().flows.order[0].clone();
+        let track = Track::new();
+        let track_key = track.key.clone();
+        let flow = engine.state.score.flows.by_key.get_mut(&flow_key).unwrap();
+        flow.tracks.insert(track_key.clone(), track);
+        engine.history = History::new(&engine.state);
+        engine.set_listener_mode(ListenerMode::Patches);
+        (engine, flow_key, track_key, sets)
+    }
+
+    #[test]
+    fn test_version() {
+        let (mut engine, _, _, sets) = setup();
+        let start = engine.version();
+        engine.set_title(String::from("Sonata"));
+        engine.set_title(String::from("Symphony"));
+        assert_eq!(engine.version(), start + 2);
+        // every notification has the next version
+        let versions: Vec<u32> = sets
+            .borrow()
+            .iter()
+            .map(|set| set["version"].as_u64().unwrap() as u32)
+            .collect();
+        assert_eq!(versions, vec![start, start + 1, start + 2]);
+    }
+
+    #[test]
+    fn test_patches_match_state() {
+        let (mut engine, flow_key, track_key, sets) = setup();
+        let mut doc = Value::Null;
+        let mut ops = Vec::new();
+        let mut check = |engine: &Engine| {
+            for set in sets.borrow_mut().drain(..) {
+                for patch in set["patches"].as_array().unwrap() {
+                    ops.push(String::from(patch["op"].as_str().unwrap()));
+                    apply(&mut doc, patch);
+                }
+            }
+            assert_eq!(doc, serde_json::to_value(&engine.state).unwrap());
+        };
+        check(&engine);
+
+        let key = engine
+            .create_tone(&flow_key, &track_key, 0, 4, 61, 100, Articulation::None)
+            .unwrap();
+        check(&engine);
+        engine
+            .update_tone(&flow_key, &track_key, &key, 4, 8, 62, Articulation::None)
+            .unwrap();
+        check(&engine);
+        engine.slice_tone(&flow_key, &track_key, &key, 8).unwrap();
+        check(&engine);
+        engine.remove_tone(&flow_key, &track_key, &key).unwrap();
+        check(&engine);
+        engine.set_flow_length(&flow_key, 32).unwrap();
+        check(&engine);
+        engine.set_flow_length(&flow_key, 8).unwrap();
+        check(&engine);
+
+        for op in &["add", "replace", "remove", "splice"] {
+            assert!(ops.iter().any(|item| item == op), "no {} patch", op);
+        }
+    }
+
+    #[test]
+    fn test_tick_index() {
+        let (mut engine, flow_key, track_key, sets) = setup();
+        engine
+            .create_tone(&flow_key, &track_key, 0, 4, 60, 100, Articulation::None)
+            .unwrap();
+        let key = engine
+            .create_tone(&flow_key, &track_key, 8, 4, 62, 100, Articulation::None)
+            .unwrap();
+        sets.borrow_mut().clear();
+        engine
+            .update_tone(&flow_key, &track_key, &key, 4, 4, 62, Articulation::None)
+            .unwrap();
+
+        // only the ticks the tone has moved between are sent
+        let index = format!("{}.entries.by_tick", track_path(&flow_key, &track_key));
+        let patches: Vec<(String, String)> = sets.borrow()[0]["patches"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|patch| patch["path"].as_str().unwrap().starts_with(&index))
+            .map(|patch| {
+                (
+                    String::from(patch["op"].as_str().unwrap()),
+                    String::from(patch["path"].as_str().unwrap()),
+                )
+            })
+            .collect();
+        assert_eq!(
+            patches,
+            vec![
+                (String::from("add"), format!("{}.4", index)),
+                (String::from("remove"), format!("{}.8", index)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resync() {
+        let (mut engine, _, _, sets) = setup();
+        engine.set_title(String::from("Sonata"));
+        engine.undo();
+        engine.resync();
+
+        let sets = sets.borrow();
+        // switching mode, undo and resync send the whole state
+        for index in &[0, 2, 3] {
+            let patches = sets[*index]["patches"].as_array().unwrap();
+            assert_eq!(patches.len(), 1);
+            assert_eq!(patches[0]["op"], "replace");
+            assert_eq!(patches[0]["path"], "");
+            assert_eq!(
+                patches[0]["value"],
+                serde_json::to_value(&engine.state).unwrap()
+            );
+        }
+        assert_eq!(sets[1]["patches"][0]["path"], "score.meta.title");
+    }
+}
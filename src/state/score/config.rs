@@ -1,20 +1,59 @@
+use crate::state::score::player::PlayerType;
 use crate::state::Engine;
+#[cfg(feature = "wasm")]
 use wasm_bindgen::prelude::*;
 
-#[wasm_bindgen]
-#[derive(Serialize_repr, Deserialize_repr)]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Serialize_repr, Deserialize_repr, Copy, Clone)]
 #[repr(u8)]
 pub enum AutoCountStyle {
     Arabic,
     Roman,
 }
 
-#[derive(Serialize, Deserialize)]
+impl AutoCountStyle {
+    /// Write an instrument count in the style, eg. 4 => "IV"
+    pub fn format(&self, count: u8) -> String {
+        match self {
+            AutoCountStyle::Arabic => count.to_string(),
+            AutoCountStyle::Roman => {
+                const NUMERALS: [(u8, &str); 9] = [
+                    (100, "C"),
+                    (90, "XC"),
+                    (50, "L"),
+                    (40, "XL"),
+                    (10, "X"),
+                    (9, "IX"),
+                    (5, "V"),
+                    (4, "IV"),
+                    (1, "I"),
+                ];
+                let mut out = String::new();
+                let mut remaining = count;
+                for (value, numeral) in NUMERALS.iter() {
+                    while remaining >= *value {
+                        out.push_str(numeral);
+                        remaining -= value;
+                    }
+                }
+                out
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct AutoCount {
     pub solo: AutoCountStyle,
     pub section: AutoCountStyle,
 }
 
+impl Default for AutoCount {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl AutoCount {
     pub fn new() -> AutoCount {
         AutoCount {
@@ -22,13 +61,27 @@ impl AutoCount {
             section: AutoCountStyle::Roman,
         }
     }
+
+    /// The style instruments are counted in for a type of player
+    pub fn style(&self, player_type: PlayerType) -> AutoCountStyle {
+        match player_type {
+            PlayerType::Solo => self.solo,
+            PlayerType::Section => self.section,
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Config {
     pub auto_count: AutoCount,
 }
 
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Config {
     pub fn new() -> Config {
         Config {
@@ -37,16 +90,22 @@ impl Config {
     }
 }
 
-#[wasm_bindgen]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 impl Engine {
     pub fn set_auto_count_style_solo(&mut self, value: AutoCountStyle) {
         self.state.score.config.auto_count.solo = value;
-        self.state.score.meta.set_modified();
-        self.emit();
+        self.patches.replace(
+            String::from("score.config.auto_count.solo"),
+            &self.state.score.config.auto_count.solo,
+        );
+        self.record("Set solo auto count style");
     }
     pub fn set_auto_count_style_section(&mut self, value: AutoCountStyle) {
         self.state.score.config.auto_count.section = value;
-        self.state.score.meta.set_modified();
-        self.emit();
+        self.patches.replace(
+            String::from("score.config.auto_count.section"),
+            &self.state.score.config.auto_count.section,
+        );
+        self.record("Set section auto count style");
     }
 }
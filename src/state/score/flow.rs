@@ -1,16 +1,23 @@
+use crate::error::{Error, Result};
+use crate::state::entries::absolute_tempo::AbsoluteTempo;
 use crate::state::entries::clef::Clef;
-use crate::state::entries::time_signature::{TimeSignature, TimeSignatureDrawType};
+use crate::state::entries::time_signature::{Reflow, TimeSignature, TimeSignatureDrawType};
+use crate::state::entries::Entry;
+use crate::state::patch::flow_path;
 use crate::state::score::instrument::defs::get_def;
 use crate::state::score::instrument::Instrument;
+use crate::state::score::notation::measures;
 use crate::state::score::stave::Stave;
+use crate::state::score::tempo_map::TempoMap;
 use crate::state::score::track::Track;
 use crate::state::Engine;
 use crate::utils::duration::NoteDuration;
 use crate::utils::shortid;
 use std::collections::{HashMap, HashSet};
+#[cfg(feature = "wasm")]
 use wasm_bindgen::prelude::*;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Tick {
     pub tick: u32,
     pub bar: u32,
@@ -24,12 +31,18 @@ pub struct Tick {
     pub is_grouping_boundry: bool,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct TickList {
     pub list: Vec<Tick>,
     pub width: f32,
 }
 
+impl Default for TickList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl TickList {
     pub fn new() -> Self {
         Self {
@@ -41,9 +54,18 @@ impl TickList {
         self.width += tick.width;
         self.list.push(tick);
     }
+
+    /// Drop the ticks from `index` onwards
+    pub fn truncate(&mut self, index: usize) {
+        self.list.truncate(index);
+        self.width = match self.list.last() {
+            Some(tick) => tick.x + tick.width,
+            None => 0.0,
+        };
+    }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Flow {
     pub key: String,
     pub title: String,
@@ -56,6 +78,12 @@ pub struct Flow {
     pub tracks: HashMap<String, Track>,
 }
 
+impl Default for Flow {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Flow {
     pub fn new() -> Flow {
         let mut flow = Flow {
@@ -111,15 +139,185 @@ impl Flow {
         }
     }
 
+    /// Re-spell the tones in every track, eg. after the key signatures have changed.
+    ///
+    /// Returns true if any tone has changed.
+    pub fn respell(&mut self) -> bool {
+        let mut changed = false;
+        for track in self.tracks.values_mut() {
+            changed |= !track.respell(&self.master).is_empty();
+        }
+        changed
+    }
+
+    /// Change the number of subdivisions per quarter, moving every entry onto the new
+    /// tick grid. Nothing is changed unless everything falls exactly on the new grid,
+    /// the error listing the entries that don't.
+    pub fn rescale(&mut self, subdivisions: u8) -> Result<()> {
+        let from = u64::from(self.subdivisions);
+        let to = u64::from(subdivisions);
+        let scale = |tick: u32| {
+            let scaled = u64::from(tick) * to;
+            if scaled.is_multiple_of(from) {
+                Some((scaled / from) as u32)
+            } else {
+                None
+            }
+        };
+
+        // beat types must also be a whole number of ticks for the bars and beats to be found
+        let beat_fits = |beat_type: &NoteDuration| beat_type.to_exact_ticks(subdivisions).is_some();
+        let fits = |entry: &Entry| {
+            scale(entry.tick()).is_some()
+                && match entry {
+                    Entry::Tone(tone) => scale(tone.duration.int).is_some(),
+                    Entry::GradualTempo(tempo) => scale(tempo.duration).is_some(),
+                    Entry::TimeSignature(time_signature) => {
+                        scale(time_signature.pickup).is_some()
+                            && beat_fits(&time_signature.beat_type)
+                    }
+                    Entry::AbsoluteTempo(tempo) => beat_fits(&tempo.beat_type),
+                    Entry::Tuplet(tuplet) => {
+                        beat_fits(&tuplet.base) && tuplet.ticks_per_value(subdivisions).is_some()
+                    }
+                    _ => true,
+                }
+        };
+
+        let mut unfit: Vec<String> = std::iter::once(&self.master)
+            .chain(self.staves.values().map(|stave| &stave.master))
+            .chain(self.tracks.values())
+            .flat_map(|track| track.entries.by_key.values())
+            .filter(|entry| !fits(entry))
+            .map(|entry| entry.key())
+            .collect();
+        if !unfit.is_empty() {
+            unfit.sort();
+            return Err(Error::UnrepresentableEntries(subdivisions, unfit));
+        }
+        if scale(self.length).is_none() {
+            return Err(Error::InvalidSubdivisions(subdivisions));
+        }
+
+        let scale = |tick: u32| scale(tick).unwrap_or(tick);
+        self.master.rescale(&scale);
+        for stave in self.staves.values_mut() {
+            stave.master.rescale(&scale);
+        }
+        for track in self.tracks.values_mut() {
+            track.rescale(&scale);
+        }
+        self.length = scale(self.length);
+        self.subdivisions = subdivisions;
+
+        for entry in self.master.entries.by_key.values_mut() {
+            if let Entry::AbsoluteTempo(tempo) = entry {
+                tempo.normalized_bpm = AbsoluteTempo::normalize_bpm(
+                    subdivisions,
+                    &tempo.beat_type,
+                    tempo.dotted,
+                    tempo.bpm,
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Add `count` ticks at `at`, moving everything on or after it later
+    pub fn insert_ticks(&mut self, at: u32, count: u32) {
+        let subdivisions = self.subdivisions;
+        self.master.insert_ticks(at, count, subdivisions);
+        for stave in self.staves.values_mut() {
+            stave.master.insert_ticks(at, count, subdivisions);
+        }
+        for track in self.tracks.values_mut() {
+            track.insert_ticks(at, count, subdivisions);
+        }
+        self.length += count;
+    }
+
+    /// Take out the ticks from `from` to `to`, moving everything after them earlier,
+    /// see `Track::remove_ticks()` for what happens to the entries in between.
+    pub fn remove_ticks(&mut self, from: u32, to: u32, shift: bool) {
+        let subdivisions = self.subdivisions;
+        self.master.remove_ticks(from, to, shift, subdivisions);
+        for stave in self.staves.values_mut() {
+            stave.master.remove_ticks(from, to, shift, subdivisions);
+        }
+        for track in self.tracks.values_mut() {
+            track.remove_ticks(from, to, shift, subdivisions);
+        }
+        self.length -= to - from;
+    }
+
+    /// Make the bars of the time signature playing at `tick` full bars up to the
+    /// next time signature (or the end of the flow). Open time has no bars to fill.
+    pub fn reflow(&mut self, tick: u32, reflow: Reflow) {
+        let (start, bar_length, pickup) =
+            match self.master.get_time_signature_on_or_before_tick(tick) {
+                Some(time_signature) if time_signature.beats > 0 => (
+                    time_signature.tick,
+                    time_signature.ticks_per_bar(self.subdivisions),
+                    time_signature.pickup,
+                ),
+                _ => return,
+            };
+        let end = match self
+            .master
+            .get_time_signature_after_tick(start, self.length)
+        {
+            Some(next) => next.tick,
+            None => self.length,
+        };
+
+        // there must be a full bar after the pickup left to shrink to
+        if end - start < pickup + bar_length {
+            self.insert_ticks(end, start + pickup + bar_length - end);
+            return;
+        }
+        let overflow = (end - start - pickup) % bar_length;
+        if overflow == 0 {
+            return;
+        }
+        match reflow {
+            Reflow::Pad => self.insert_ticks(end, bar_length - overflow),
+            Reflow::Truncate => self.remove_ticks(end - overflow, end, false),
+            Reflow::Shift => self.remove_ticks(end - overflow, end, true),
+        }
+    }
+
+    /// The bars of the flow as (start, end) ticks
+    pub fn bars(&self) -> Vec<(u32, u32)> {
+        measures(&self.master, self.length, self.subdivisions)
+    }
+
     /// Calculate the timestamp parts, and the drawn tick widths for the tick track
     pub fn calc_ticks(&self) -> TickList {
-        let crotchet_width = 72.0;
         let mut ticks = TickList::new();
+        self.update_ticks(&mut ticks, 0);
+        ticks
+    }
 
-        let mut bar: u32 = 0;
-        let mut result: Option<&TimeSignature> = None;
+    /// Recalculate the tick list from `from` onwards, after an edit that leaves the
+    /// ticks before it unchanged. The last tick of the old list is always recalculated
+    /// as it is drawn differently at the end of the flow.
+    ///
+    /// Returns the index of the first recalculated tick.
+    pub fn update_ticks(&self, ticks: &mut TickList, from: u32) -> usize {
+        let crotchet_width = 72.0;
+        let from = from.min(ticks.list.len().saturating_sub(1) as u32);
+        ticks.truncate(from as usize);
 
-        for tick in 0..self.length + 1 {
+        // the bar count carries on from the tick before
+        let mut bar: u32 = match ticks.list.last() {
+            Some(tick) => tick.bar,
+            None => 0,
+        };
+        let mut result: Option<&TimeSignature> =
+            self.master.get_time_signature_on_or_before_tick(from);
+
+        for tick in from..self.length + 1 {
             match self.master.get_time_signature_at_tick(tick) {
                 Some(time_signature) => {
                     result = Some(time_signature);
@@ -129,7 +327,7 @@ impl Flow {
 
             let time_signature = match result {
                 Some(time_signature) => time_signature,
-                None => return ticks,
+                None => return from as usize,
             };
 
             let ticks_per_quarter = NoteDuration::Quarter.to_ticks(self.subdivisions);
@@ -146,10 +344,13 @@ impl Flow {
             let beat = (f64::from(distance_from_barline) / f64::from(ticks_per_quarter)).floor()
                 as u32
                 + 1;
-            let sixteenth = f64::from(distance_from_barline % u32::from(ticks_per_quarter))
+            let sixteenth = f64::from(distance_from_barline % ticks_per_quarter)
                 / f64::from(ticks_per_sixteenth);
 
-            if distance_from_barline == 0 {
+            // a pickup starts a bar at its time signature, the pickup at the start
+            // of the flow being bar 0
+            let is_first_beat = distance_from_barline == 0 || tick == time_signature.tick;
+            if is_first_beat && !(tick == 0 && time_signature.pickup > 0) {
                 bar = bar + 1;
             }
 
@@ -161,7 +362,7 @@ impl Flow {
                 x: ticks.width,
                 width: tick_width,
                 is_beat: time_signature.is_on_beat(tick, self.subdivisions),
-                is_first_beat: distance_from_barline == 0,
+                is_first_beat,
                 is_quaver_beat: time_signature.is_on_beat_type(
                     tick,
                     self.subdivisions,
@@ -171,16 +372,27 @@ impl Flow {
             });
         }
 
-        ticks
+        from as usize
+    }
+
+    /// Calculate the conversion between ticks and seconds from the tempo marks
+    pub fn calc_tempo_map(&self) -> TempoMap {
+        TempoMap::new(&self.master, self.length, self.subdivisions)
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Flows {
     pub order: Vec<String>,
     pub by_key: HashMap<String, Flow>,
 }
 
+impl Default for Flows {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Flows {
     pub fn new() -> Flows {
         let flow = Flow::new();
@@ -192,9 +404,9 @@ impl Flows {
     }
 }
 
-#[wasm_bindgen]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 impl Engine {
-    pub fn create_flow(&mut self) -> JsValue {
+    pub fn create_flow(&mut self) -> String {
         let mut flow = Flow::new();
         let flow_key = flow.key.clone(); // return value
 
@@ -205,126 +417,585 @@ impl Engine {
 
         // add stave / tracks for each instrument in the score
         // we do this for every player so we can loop the instruments directly
-        for (_instrument_key, instrument) in &self.state.score.instruments {
+        for instrument in self.state.score.instruments.values() {
             flow.add_instrument(instrument);
         }
 
-        self.state.ticks.insert(flow.key.clone(), flow.calc_ticks());
+        let ticks = flow.calc_ticks();
+        let tempo_map = flow.calc_tempo_map();
+        self.patches.add(flow_path(&flow.key), &flow);
+        self.patches.add(format!("ticks.{}", flow.key), &ticks);
+        self.patches
+            .add(format!("tempo_maps.{}", flow.key), &tempo_map);
+        self.state.ticks.insert(flow.key.clone(), ticks);
+        self.state.tempo_maps.insert(flow.key.clone(), tempo_map);
         self.state.score.flows.order.push(flow.key.clone());
         self.state.score.flows.by_key.insert(flow.key.clone(), flow);
-        self.state.score.meta.set_modified();
-        self.emit();
+        self.patches.replace(
+            String::from("score.flows.order"),
+            &self.state.score.flows.order,
+        );
+        self.record("Create flow");
 
-        JsValue::from_str(&flow_key)
+        flow_key
     }
 
-    pub fn rename_flow(&mut self, flow_key: &str, name: &str) {
+    pub fn rename_flow(&mut self, flow_key: &str, name: &str) -> Result<()> {
         match self.state.score.flows.by_key.get_mut(flow_key) {
             Some(flow) => {
                 flow.title = String::from(name);
+                self.patches
+                    .replace(format!("{}.title", flow_path(flow_key)), &flow.title);
             }
-            None => return (),
+            None => return self.fail(Error::UnknownFlow(String::from(flow_key))),
         };
-        self.state.score.meta.set_modified();
-        self.emit();
+        self.record("Rename flow");
+
+        Ok(())
     }
 
-    pub fn set_flow_length(&mut self, flow_key: &str, length: u32) {
+    pub fn set_flow_length(&mut self, flow_key: &str, length: u32) -> Result<()> {
         match self.state.score.flows.by_key.get_mut(flow_key) {
             Some(flow) => {
                 flow.length = length;
-                self.state.ticks.insert(flow.key.clone(), flow.calc_ticks());
+                self.patches
+                    .replace(format!("{}.length", flow_path(flow_key)), &flow.length);
             }
-            None => return (),
+            None => return self.fail(Error::UnknownFlow(String::from(flow_key))),
+        };
+        // the ticks up to the old end, or the new if shorter, are unchanged
+        self.update_timing(flow_key, length);
+
+        self.record("Set flow length");
+
+        Ok(())
+    }
+
+    /// Set the number of ticks per quarter, eg. 48 to allow triplets or 240 to allow
+    /// triplets and quintuplets. It must be a multiple of 4 so sixteenths fall on a tick,
+    /// and time signatures, tempos and tuplets must have beat types of a whole number of ticks.
+    pub fn set_flow_subdivisions(&mut self, flow_key: &str, subdivisions: u8) -> Result<()> {
+        if subdivisions == 0 || subdivisions % 4 != 0 {
+            return self.fail(Error::InvalidSubdivisions(subdivisions));
+        }
+
+        let flow = match self.state.score.flows.by_key.get_mut(flow_key) {
+            Some(flow) => flow,
+            None => return self.fail(Error::UnknownFlow(String::from(flow_key))),
+        };
+
+        if let Err(error) = flow.rescale(subdivisions) {
+            return self.fail(error);
+        }
+
+        self.patches.replace(flow_path(flow_key), flow);
+        self.update_timing(flow_key, 0);
+        self.record("Set flow subdivisions");
+
+        Ok(())
+    }
+
+    /// Insert `count` empty bars before the bar at `at_bar` (counting from 0), or at the
+    /// end of the flow if `at_bar` is the number of bars. The new bars are in the time
+    /// signature at that point, a bar of open time being a quarter.
+    pub fn insert_bars(&mut self, flow_key: &str, at_bar: u32, count: u32) -> Result<()> {
+        let flow = match self.state.score.flows.by_key.get_mut(flow_key) {
+            Some(flow) => flow,
+            None => return self.fail(Error::UnknownFlow(String::from(flow_key))),
+        };
+
+        let bars = flow.bars();
+        let at = match bars.get(at_bar as usize) {
+            Some((start, _)) => *start,
+            None if at_bar as usize == bars.len() => flow.length,
+            None => return self.fail(Error::InvalidIndex(at_bar as usize)),
+        };
+
+        let (bar_length, time_signature_key) = match flow
+            .master
+            .get_time_signature_on_or_before_tick(at.min(flow.length.saturating_sub(1)))
+        {
+            Some(time_signature) if time_signature.beats > 0 => (
+                time_signature.ticks_per_bar(flow.subdivisions),
+                time_signature.key.clone(),
+            ),
+            Some(time_signature) => (
+                NoteDuration::Quarter.to_ticks(flow.subdivisions),
+                time_signature.key.clone(),
+            ),
+            None => (
+                NoteDuration::Quarter.to_ticks(flow.subdivisions),
+                String::new(),
+            ),
         };
 
-        self.state.score.meta.set_modified();
-        self.emit();
+        flow.insert_ticks(at, bar_length * count);
+        // the new bars start with the time signature they are sized by
+        if let Some(Entry::TimeSignature(time_signature)) =
+            flow.master.entries.by_key.get(&time_signature_key)
+        {
+            if time_signature.tick == at + bar_length * count {
+                flow.master.r#move(&time_signature_key, at);
+            }
+        }
+
+        self.patches.replace(flow_path(flow_key), flow);
+        self.update_timing(flow_key, at);
+        self.record("Insert bars");
+
+        Ok(())
     }
 
-    pub fn reorder_flow(&mut self, old_index: u8, new_index: u8) {
+    /// Delete `count` bars from the bar at `from_bar` (counting from 0), moving
+    /// everything after them earlier. Tones sounding into the deleted bars are
+    /// shortened and time signatures, keys and tempos within them carry on after.
+    pub fn delete_bars(&mut self, flow_key: &str, from_bar: u32, count: u32) -> Result<()> {
+        let flow = match self.state.score.flows.by_key.get_mut(flow_key) {
+            Some(flow) => flow,
+            None => return self.fail(Error::UnknownFlow(String::from(flow_key))),
+        };
+
+        let bars = flow.bars();
+        let last = (from_bar + count) as usize;
+        // a flow needs at least one bar
+        if count == 0 || last > bars.len() || (from_bar == 0 && last == bars.len()) {
+            return self.fail(Error::InvalidIndex(last));
+        }
+
+        let (from, to) = (bars[from_bar as usize].0, bars[last - 1].1);
+        let deleted: Vec<String> = flow
+            .master
+            .entries
+            .by_key
+            .values()
+            .filter(|entry| entry.tick() >= from && entry.tick() < to)
+            .map(|entry| entry.key())
+            .collect();
+        flow.remove_ticks(from, to, false);
+        // a time signature carried on from the deleted bars lost its pickup with them
+        for key in deleted.iter() {
+            if let Some(Entry::TimeSignature(time_signature)) =
+                flow.master.entries.by_key.get_mut(key)
+            {
+                time_signature.pickup = 0;
+            }
+        }
+        self.patches.replace(flow_path(flow_key), flow);
+        self.update_timing(flow_key, from);
+        self.record("Delete bars");
+
+        Ok(())
+    }
+
+    pub fn reorder_flow(&mut self, old_index: u8, new_index: u8) -> Result<()> {
+        let len = self.state.score.flows.order.len();
+        if old_index as usize >= len {
+            return self.fail(Error::InvalidIndex(old_index as usize));
+        }
+        if new_index as usize >= len {
+            return self.fail(Error::InvalidIndex(new_index as usize));
+        }
+
         let removed = self.state.score.flows.order.remove(old_index as usize);
         self.state
             .score
             .flows
             .order
             .insert(new_index as usize, removed);
-        self.state.score.meta.set_modified();
-        self.emit();
+        self.patches.replace(
+            String::from("score.flows.order"),
+            &self.state.score.flows.order,
+        );
+        self.record("Reorder flow");
+
+        Ok(())
     }
 
-    pub fn remove_flow(&mut self, flow_key: &str) {
+    pub fn remove_flow(&mut self, flow_key: &str) -> Result<()> {
+        if self.state.score.flows.by_key.remove(flow_key).is_none() {
+            return self.fail(Error::UnknownFlow(String::from(flow_key)));
+        }
         self.state.score.flows.order.retain(|e| e != flow_key);
-        self.state.score.flows.by_key.remove(flow_key);
         self.state.ticks.remove(flow_key);
-        self.state.score.meta.set_modified();
-        self.emit();
+        self.state.tempo_maps.remove(flow_key);
+        self.patches.replace(
+            String::from("score.flows.order"),
+            &self.state.score.flows.order,
+        );
+        self.patches.remove(flow_path(flow_key));
+        self.patches.remove(format!("ticks.{}", flow_key));
+        self.patches.remove(format!("tempo_maps.{}", flow_key));
+        self.record("Remove flow");
+
+        Ok(())
     }
 
     /**
      * Assign a player to a flow
      */
-    pub fn assign_player(&mut self, flow_key: &str, player_key: &str) {
+    pub fn assign_player(&mut self, flow_key: &str, player_key: &str) -> Result<()> {
+        // get all the insturments assigned to the player
+        let instrument_keys = match self.state.score.players.by_key.get(player_key) {
+            Some(player) => &player.instruments,
+            None => return self.fail(Error::UnknownPlayer(String::from(player_key))),
+        };
+
         // add the player_key to the flow
         let flow = match self.state.score.flows.by_key.get_mut(flow_key) {
             Some(flow) => flow,
-            None => return (),
+            None => return self.fail(Error::UnknownFlow(String::from(flow_key))),
         };
 
         flow.players.insert(String::from(player_key));
 
-        // get all the insturments assigned to the player
-        let instrument_keys = match self.state.score.players.by_key.get(player_key) {
-            Some(player) => &player.instruments,
-            None => return (),
-        };
-
         // add staves and tracks to this flow
         for instrument_key in instrument_keys {
-            let instrument = match self.state.score.instruments.get(instrument_key) {
-                Some(instrument) => instrument,
-                None => return (),
-            };
-            flow.add_instrument(instrument);
+            if let Some(instrument) = self.state.score.instruments.get(instrument_key) {
+                flow.add_instrument(instrument);
+            }
         }
 
-        self.state.score.meta.set_modified();
-        self.emit();
+        self.patches.replace_flow_contents(flow);
+        self.record("Assign player");
+
+        Ok(())
     }
 
-    pub fn unassign_player(&mut self, flow_key: &str, player_key: &str) {
+    pub fn unassign_player(&mut self, flow_key: &str, player_key: &str) -> Result<()> {
+        // get all the insturments assigned to the player
+        let instrument_keys = match self.state.score.players.by_key.get(player_key) {
+            Some(player) => &player.instruments,
+            None => return self.fail(Error::UnknownPlayer(String::from(player_key))),
+        };
+
         // remove the player_key from the flow
         let flow = match self.state.score.flows.by_key.get_mut(flow_key) {
             Some(flow) => flow,
-            None => return (),
+            None => return self.fail(Error::UnknownFlow(String::from(flow_key))),
         };
         flow.players.remove(player_key);
 
-        // get all the insturments assigned to the player
-        let instrument_keys = match self.state.score.players.by_key.get(player_key) {
-            Some(player) => &player.instruments,
-            None => return (),
-        };
-
         // delete staves and tracks in this flow
         for instrument_key in instrument_keys {
             let stave_keys = match self.state.score.instruments.get(instrument_key) {
                 Some(instrument) => &instrument.staves,
-                None => return (),
+                None => continue,
             };
             for stave_key in stave_keys {
-                let stave = match flow.staves.get(stave_key) {
-                    Some(stave) => stave,
-                    None => return (),
-                };
-                for track_key in &stave.tracks {
-                    flow.tracks.remove(track_key);
+                if let Some(stave) = flow.staves.remove(stave_key) {
+                    for track_key in &stave.tracks {
+                        flow.tracks.remove(track_key);
+                    }
                 }
-                flow.staves.remove(stave_key);
             }
         }
 
-        self.state.score.meta.set_modified();
-        self.emit();
+        self.patches.replace_flow_contents(flow);
+        self.record("Unassign player");
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::entries::tone::{Articulation, Tone};
+    use crate::state::listener::{SystemClock, Update};
+    use crate::state::patch::ListenerMode;
+    use crate::utils::duration::Duration;
+    use crate::utils::pitch::{Accidental, Pitch};
+    use crate::utils::velocity::Velocity;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn tone(key: &str, tick: u32, duration: u32) -> Entry {
+        Tone::new(
+            String::from(key),
+            tick,
+            Duration::new(duration),
+            Pitch::new(60, Accidental::Natural),
+            Velocity::new(100),
+            Articulation::None,
+            None,
+        )
+    }
+
+    /// Two bars of 4/4 with a tone tied over the barline and one in the second bar
+    fn setup() -> (Engine, String) {
+        let mut engine = Engine::create(Box::new(SystemClock), Box::new(|_: Update| ()));
+        let flow_key = engine.score().flows.order[0].clone();
+        engine.set_flow_length(&flow_key, 128).unwrap();
+        engine
+            .create_time_signature(
+                &flow_key,
+                0,
+                4,
+                NoteDuration::Quarter,
+                TimeSignatureDrawType::Normal,
+                None,
+            )
+            .unwrap();
+
+        let mut track = Track::new();
+        track.insert(tone("a", 48, 32));
+        track.insert(tone("b", 96, 16));
+        let flow = engine.state.score.flows.by_key.get_mut(&flow_key).unwrap();
+        flow.tracks.insert(String::from("track"), track);
+
+        (engine, flow_key)
+    }
+
+    fn tones(engine: &Engine, flow_key: &str) -> Vec<(u32, u32)> {
+        let mut tones: Vec<(u32, u32)> = engine.score().flows.by_key[flow_key].tracks["track"]
+            .entries
+            .by_key
+            .values()
+            .filter_map(|entry| match entry {
+                Entry::Tone(tone) => Some((tone.tick, tone.duration.int)),
+                _ => None,
+            })
+            .collect();
+        tones.sort();
+        tones
+    }
+
+    #[test]
+    fn test_insert_bars() {
+        let (mut engine, flow_key) = setup();
+        engine.insert_bars(&flow_key, 1, 1).unwrap();
+        let flow = &engine.score().flows.by_key[&flow_key];
+        assert_eq!(flow.length, 192);
+        assert_eq!(flow.bars().len(), 3);
+        // the tone sounding across the new bar is held through it
+        assert_eq!(tones(&engine, &flow_key), vec![(48, 96), (160, 16)]);
+        assert_eq!(engine.state.ticks[&flow_key].list.len(), 193);
+
+        // new bars take the time signature they are inserted before
+        engine
+            .create_time_signature(
+                &flow_key,
+                64,
+                3,
+                NoteDuration::Quarter,
+                TimeSignatureDrawType::Normal,
+                None,
+            )
+            .unwrap();
+        engine.insert_bars(&flow_key, 1, 2).unwrap();
+        let flow = &engine.score().flows.by_key[&flow_key];
+        assert_eq!(flow.master.get_time_signature_at_tick(64).unwrap().beats, 3);
+        assert_eq!(flow.length, 304);
+
+        assert!(engine.insert_bars(&flow_key, 9, 1).is_err());
+    }
+
+    #[test]
+    fn test_delete_bars() {
+        let (mut engine, flow_key) = setup();
+        engine.delete_bars(&flow_key, 1, 1).unwrap();
+        assert_eq!(engine.score().flows.by_key[&flow_key].length, 64);
+        assert_eq!(tones(&engine, &flow_key), vec![(48, 16)]);
+
+        let (mut engine, flow_key) = setup();
+        engine.delete_bars(&flow_key, 0, 1).unwrap();
+        let flow = &engine.score().flows.by_key[&flow_key];
+        assert_eq!(flow.length, 64);
+        // the time signature carries on from the deleted bar
+        assert_eq!(flow.master.get_time_signature_at_tick(0).unwrap().beats, 4);
+        assert_eq!(tones(&engine, &flow_key), vec![(0, 16), (32, 16)]);
+
+        assert!(engine.delete_bars(&flow_key, 0, 1).is_err());
+    }
+
+    #[test]
+    fn test_set_flow_subdivisions() {
+        let (mut engine, flow_key) = setup();
+        let flow = engine.state.score.flows.by_key.get_mut(&flow_key).unwrap();
+        let track = flow.tracks.get_mut("track").unwrap();
+        track.insert(tone("d", 2, 4));
+        track.insert(tone("c", 64, 6));
+        assert_eq!(
+            engine.set_flow_subdivisions(&flow_key, 4),
+            Err(Error::UnrepresentableEntries(
+                4,
+                vec![String::from("c"), String::from("d")]
+            ))
+        );
+        assert_eq!(engine.score().flows.by_key[&flow_key].subdivisions, 16);
+
+        let (mut engine, flow_key) = setup();
+        let tempo = engine
+            .create_absolute_tempo(
+                &flow_key,
+                0,
+                "",
+                NoteDuration::ThirtySecond,
+                0,
+                480,
+                false,
+                false,
+                true,
+            )
+            .unwrap();
+        engine.set_flow_subdivisions(&flow_key, 8).unwrap();
+        let flow = &engine.score().flows.by_key[&flow_key];
+        assert_eq!(flow.length, 64);
+        assert_eq!(tones(&engine, &flow_key), vec![(24, 16), (48, 8)]);
+        // a beat of a single tick keeps its tempo
+        match flow.master.entries.by_key.get(&tempo) {
+            Some(Entry::AbsoluteTempo(tempo)) => assert_eq!(tempo.normalized_bpm, 60.0),
+            _ => panic!("expected a tempo"),
+        }
+        assert_eq!(engine.flow_duration_seconds(&flow_key), Ok(8.0));
+        assert_ticks(&engine, &flow_key);
+    }
+
+    #[test]
+    fn test_rescale_beat_types() {
+        let (mut engine, flow_key) = setup();
+        let time_signature = engine
+            .create_time_signature(
+                &flow_key,
+                64,
+                4,
+                NoteDuration::ThirtySecond,
+                TimeSignatureDrawType::Normal,
+                None,
+            )
+            .unwrap();
+        let tempo = engine
+            .create_absolute_tempo(
+                &flow_key,
+                64,
+                "",
+                NoteDuration::ThirtySecond,
+                0,
+                480,
+                false,
+                false,
+                true,
+            )
+            .unwrap();
+        let mut keys = vec![time_signature, tempo];
+        keys.sort();
+
+        // a thirty-second is half a tick in 4 subdivisions and a tick and a half in 12
+        for subdivisions in &[4, 12] {
+            assert_eq!(
+                engine.set_flow_subdivisions(&flow_key, *subdivisions),
+                Err(Error::UnrepresentableEntries(*subdivisions, keys.clone()))
+            );
+            assert_eq!(engine.score().flows.by_key[&flow_key].subdivisions, 16);
+        }
+        assert_ticks(&engine, &flow_key);
+    }
+
+    #[test]
+    fn test_rescale_pickup() {
+        let (mut engine, flow_key) = setup();
+        let time_signature = engine.score().flows.by_key[&flow_key]
+            .master
+            .get_time_signature_at_tick(0)
+            .unwrap()
+            .key
+            .clone();
+        engine
+            .set_time_signature_pickup(&flow_key, &time_signature, 16, Reflow::Pad)
+            .unwrap();
+        assert_eq!(
+            engine.score().flows.by_key[&flow_key].bars(),
+            vec![(0, 16), (16, 80), (80, 144)]
+        );
+
+        // a quarter pickup is still a quarter
+        engine.set_flow_subdivisions(&flow_key, 8).unwrap();
+        let flow = &engine.score().flows.by_key[&flow_key];
+        assert_eq!(flow.master.get_time_signature_at_tick(0).unwrap().pickup, 8);
+        assert_eq!(flow.bars(), vec![(0, 8), (8, 40), (40, 72)]);
+        assert_ticks(&engine, &flow_key);
+
+        // a pickup of half a thirty-second can't be written in thirty-seconds
+        engine.set_flow_subdivisions(&flow_key, 16).unwrap();
+        engine
+            .set_time_signature_pickup(&flow_key, &time_signature, 1, Reflow::Pad)
+            .unwrap();
+        assert_eq!(
+            engine.set_flow_subdivisions(&flow_key, 8),
+            Err(Error::UnrepresentableEntries(8, vec![time_signature]))
+        );
+    }
+
+    /// The ticks kept by the engine are the same as calculating them from scratch
+    fn assert_ticks(engine: &Engine, flow_key: &str) {
+        let ticks = serde_json::to_value(&engine.state.ticks[flow_key]).unwrap();
+        let calculated = serde_json::to_value(engine.score().flows.by_key[flow_key].calc_ticks());
+        assert_eq!(ticks, calculated.unwrap());
+    }
+
+    #[test]
+    fn test_update_ticks() {
+        let (mut engine, flow_key) = setup();
+        let time_signature = engine
+            .create_time_signature(
+                &flow_key,
+                64,
+                3,
+                NoteDuration::Quarter,
+                TimeSignatureDrawType::Normal,
+                None,
+            )
+            .unwrap();
+        assert_ticks(&engine, &flow_key);
+        engine.insert_bars(&flow_key, 1, 2).unwrap();
+        assert_ticks(&engine, &flow_key);
+        engine
+            .set_time_signature_pickup(&flow_key, &time_signature, 8, Reflow::Pad)
+            .unwrap();
+        assert_ticks(&engine, &flow_key);
+        engine.delete_bars(&flow_key, 0, 1).unwrap();
+        assert_ticks(&engine, &flow_key);
+        engine.set_flow_length(&flow_key, 400).unwrap();
+        assert_ticks(&engine, &flow_key);
+        engine.set_flow_length(&flow_key, 100).unwrap();
+        assert_ticks(&engine, &flow_key);
+        engine
+            .remove_time_signature(&flow_key, &time_signature, Reflow::Shift)
+            .unwrap();
+        assert_ticks(&engine, &flow_key);
+        engine.set_flow_subdivisions(&flow_key, 8).unwrap();
+        assert_ticks(&engine, &flow_key);
+    }
+
+    #[test]
+    fn test_ticks_patch() {
+        let paths = Rc::new(RefCell::new(Vec::new()));
+        let log = paths.clone();
+        let listener = move |update: Update| {
+            if let Update::Patches(patch_set) = update {
+                let mut log = log.borrow_mut();
+                log.extend(patch_set.patches.iter().map(|patch| patch.path.clone()));
+            }
+        };
+        let mut engine = Engine::create(Box::new(SystemClock), Box::new(listener));
+        engine.set_listener_mode(ListenerMode::Patches);
+        let flow_key = engine.score().flows.order[0].clone();
+        engine.set_flow_length(&flow_key, 128).unwrap();
+        engine
+            .create_time_signature(
+                &flow_key,
+                64,
+                4,
+                NoteDuration::Quarter,
+                TimeSignatureDrawType::Normal,
+                None,
+            )
+            .unwrap();
+
+        // only the ticks from the edit onwards are sent
+        let paths = paths.borrow();
+        assert!(paths.contains(&format!("ticks.{}.list.16", flow_key)));
+        assert!(paths.contains(&format!("ticks.{}.list.64", flow_key)));
+        assert!(!paths.contains(&format!("ticks.{}", flow_key)));
     }
 }
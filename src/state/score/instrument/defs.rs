@@ -1,9 +1,10 @@
 use crate::state::entries::clef::ClefDrawType;
 use crate::state::score::player::PlayerType;
 use std::collections::HashMap;
+#[cfg(feature = "wasm")]
 use wasm_bindgen::prelude::*;
 
-#[wasm_bindgen]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 #[derive(Serialize_repr, Deserialize_repr, Hash, Eq, PartialEq)]
 #[repr(u8)]
 pub enum Expression {
@@ -15,7 +16,7 @@ pub enum Expression {
     Mute, // may have to convert to MuteStaccato etc. later for mixed expressions
 }
 
-#[wasm_bindgen]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 #[derive(Serialize_repr, Deserialize_repr, Copy, Clone)]
 #[repr(u8)]
 pub enum InstrumentType {
@@ -776,20 +777,82 @@ lazy_static! {
     };
 }
 
-pub fn get_def(id: &str) -> Option<&InstrumentDef> {
+pub fn get_def(id: &str) -> Option<&'static InstrumentDef> {
     INSTRUMENT_DEFS.iter().find(|&def| def.id == id)
 }
 
-/// Get patches for a given id
-#[wasm_bindgen]
-pub fn get_patches(id: &str, player_type: PlayerType) -> JsValue {
-    let def = match get_def(id) {
-        Some(def) => def,
-        None => return JsValue::UNDEFINED,
-    };
+/// General MIDI programs (zero based) for the melodic defs, the first is used for playback
+const GM_PROGRAMS: [(&str, &[u8]); 26] = [
+    ("keyboard.piano", &[0, 1, 2, 3, 4, 5, 6, 7]),
+    ("keyboard.celesta", &[8]),
+    ("pitched-percussion.glockenspiel", &[9, 10, 14]),
+    ("pitched-percussion.vibraphone", &[11]),
+    ("pitched-percussion.marimba", &[12]),
+    ("pitched-percussion.xylophone", &[13]),
+    ("guitar.acoustic", &[24, 25, 26, 27, 28]),
+    ("guitar.distortion", &[29, 30, 31]),
+    ("guitar.bass", &[32, 33, 34, 35, 36, 37, 38, 39]),
+    ("strings.violin", &[40, 44, 45, 48, 49, 50, 51, 110]),
+    ("strings.viola", &[41]),
+    ("strings.violoncello", &[42]),
+    ("strings.contrabass", &[43]),
+    ("pitched-percussion.harp", &[46]),
+    ("pitched-percussion.timpani", &[47]),
+    ("brass.trumpet.b-flat", &[56, 59, 61, 62, 63]),
+    ("brass.trombone", &[57]),
+    ("brass.tuba", &[58]),
+    ("brass.horn.f", &[60]),
+    ("woodwinds.alto-sxophone", &[64, 65, 66, 67]),
+    ("woodwinds.oboe", &[68]),
+    ("woodwinds.english-horn", &[69]),
+    ("woodwinds.bassoon", &[70]),
+    ("woodwinds.clarinet.b-flat", &[71]),
+    ("woodwinds.piccolo", &[72]),
+    ("woodwinds.flute", &[73, 74, 75, 76, 77, 78, 79]),
+];
+
+/// General MIDI percussion keys for the unpitched defs
+const GM_DRUMS: [(&str, &[u8]); 4] = [
+    ("unpitched-percussion.kick", &[35, 36]),
+    ("unpitched-percussion.snare", &[37, 38, 39, 40]),
+    ("unpitched-percussion.hi-hat", &[42, 44, 46]),
+    ("unpitched-percussion.crash-cymbal", &[49, 52, 55, 57]),
+];
+
+/// Guess the def for a General MIDI program, falling back to the piano
+pub fn get_def_by_program(program: u8) -> Option<&'static InstrumentDef> {
+    let id = GM_PROGRAMS
+        .iter()
+        .find(|(_, programs)| programs.contains(&program))
+        .map_or("keyboard.piano", |(id, _)| id);
+    get_def(id)
+}
 
+/// Guess the def for a key on the General MIDI percussion channel, falling back to the snare
+pub fn get_def_by_drum(key: u8) -> Option<&'static InstrumentDef> {
+    let id = GM_DRUMS
+        .iter()
+        .find(|(_, keys)| keys.contains(&key))
+        .map_or("unpitched-percussion.snare", |(id, _)| id);
+    get_def(id)
+}
+
+/// The General MIDI program used to play a def
+pub fn get_program(id: &str) -> Option<u8> {
+    GM_PROGRAMS
+        .iter()
+        .find(|(def_id, _)| *def_id == id)
+        .and_then(|(_, programs)| programs.first().cloned())
+}
+
+/// Get patches for a given id
+pub fn get_patches(
+    id: &str,
+    player_type: PlayerType,
+) -> Option<&'static HashMap<Expression, &'static str>> {
+    let def = get_def(id)?;
     match player_type {
-        PlayerType::Solo => JsValue::from_serde(&def.solo_patches).unwrap(),
-        PlayerType::Section => JsValue::from_serde(&def.section_patches).unwrap(),
+        PlayerType::Solo => Some(&def.solo_patches),
+        PlayerType::Section => Some(&def.section_patches),
     }
 }
@@ -1,13 +1,15 @@
 pub mod defs;
 pub mod utils;
 
+use crate::error::{Error, Result};
 use crate::state::score::instrument::defs::{get_def, InstrumentType};
 use crate::state::score::instrument::utils::calc_counts;
 use crate::state::Engine;
 use crate::utils::shortid;
+#[cfg(feature = "wasm")]
 use wasm_bindgen::prelude::*;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Instrument {
     pub key: String,
     pub id: String,
@@ -21,13 +23,13 @@ pub struct Instrument {
     pub mute: bool,
 }
 
-#[wasm_bindgen]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 impl Engine {
     /// Create an instrument
-    pub fn create_instrument(&mut self, id: &str) -> JsValue {
-        let def = match get_def(&id) {
+    pub fn create_instrument(&mut self, id: &str) -> Result<String> {
+        let def = match get_def(id) {
             Some(def) => def,
-            None => return JsValue::UNDEFINED,
+            None => return self.fail(Error::UnknownDef(String::from(id))),
         };
         let instrument = Instrument {
             key: shortid(),
@@ -46,104 +48,146 @@ impl Engine {
             mute: false,
         };
         let return_value = instrument.key.clone();
+        self.patches
+            .add(format!("score.instruments.{}", instrument.key), &instrument);
         self.state
             .score
             .instruments
             .insert(instrument.key.clone(), instrument);
 
-        self.state.score.meta.set_modified();
-        self.emit();
+        self.record("Create instrument");
 
-        JsValue::from_serde(&return_value).unwrap()
+        Ok(return_value)
     }
 
     /// Reorder the instruments
-    pub fn reorder_instrument(&mut self, player_key: &str, old_index: u8, new_index: u8) {
+    pub fn reorder_instrument(
+        &mut self,
+        player_key: &str,
+        old_index: u8,
+        new_index: u8,
+    ) -> Result<()> {
         match self.state.score.players.by_key.get_mut(player_key) {
             Some(player) => {
+                let len = player.instruments.len();
+                if old_index as usize >= len {
+                    return self.fail(Error::InvalidIndex(old_index as usize));
+                }
+                if new_index as usize >= len {
+                    return self.fail(Error::InvalidIndex(new_index as usize));
+                }
                 let removed = player.instruments.remove(old_index as usize);
                 player.instruments.insert(new_index as usize, removed);
+                self.patches.replace(
+                    format!("score.players.by_key.{}.instruments", player_key),
+                    &player.instruments,
+                );
             }
-            None => (),
+            None => return self.fail(Error::UnknownPlayer(String::from(player_key))),
         }
 
         calc_counts(self);
-        self.state.score.meta.set_modified();
-        self.emit();
+        self.record("Reorder instrument");
+
+        Ok(())
     }
 
     /// Remove an instrument
-    pub fn remove_instrument(&mut self, player_key: &str, instrument_key: &str) {
-        // remove from the player entry
-        match self.state.score.players.by_key.get_mut(player_key) {
-            Some(player) => {
-                player.instruments.retain(|e| e != instrument_key);
-            }
-            None => (),
-        };
+    pub fn remove_instrument(&mut self, player_key: &str, instrument_key: &str) -> Result<()> {
+        if !self.state.score.players.by_key.contains_key(player_key) {
+            return self.fail(Error::UnknownPlayer(String::from(player_key)));
+        }
+        if let Err(error) = self.delete_instrument(player_key, instrument_key) {
+            return self.fail(error);
+        }
 
-        let stave_keys = match self.state.score.instruments.get(instrument_key) {
-            Some(instrument) => &instrument.staves,
-            None => return (),
+        calc_counts(self);
+        self.record("Remove instrument");
+
+        Ok(())
+    }
+
+    /// Remove an instrument from the player and all flows without recording a history step
+    pub(crate) fn delete_instrument(
+        &mut self,
+        player_key: &str,
+        instrument_key: &str,
+    ) -> Result<()> {
+        let stave_keys = match self.state.score.instruments.remove(instrument_key) {
+            Some(instrument) => instrument.staves,
+            None => return Err(Error::UnknownInstrument(String::from(instrument_key))),
         };
 
-        for flow_key in &self.state.score.flows.order {
-            let flow = match self.state.score.flows.by_key.get_mut(flow_key) {
-                Some(flow) => flow,
-                None => return (),
-            };
+        // remove from the player entry
+        if let Some(player) = self.state.score.players.by_key.get_mut(player_key) {
+            player.instruments.retain(|e| e != instrument_key);
+            self.patches.replace(
+                format!("score.players.by_key.{}.instruments", player_key),
+                &player.instruments,
+            );
+        }
 
+        for flow in self.state.score.flows.by_key.values_mut() {
             if flow.players.contains(player_key) {
-                for stave_key in stave_keys {
-                    let stave = match flow.staves.get(stave_key) {
-                        Some(stave) => stave,
-                        None => return (),
-                    };
-                    for track_key in &stave.tracks {
-                        flow.tracks.remove(track_key);
+                for stave_key in &stave_keys {
+                    if let Some(stave) = flow.staves.remove(stave_key) {
+                        for track_key in &stave.tracks {
+                            flow.tracks.remove(track_key);
+                        }
                     }
-                    flow.staves.remove(stave_key);
                 }
+                self.patches.replace_flow_contents(flow);
             }
         }
 
-        self.state.score.instruments.remove(instrument_key);
-
-        calc_counts(self);
-        self.state.score.meta.set_modified();
-        self.emit();
+        Ok(())
     }
 
-    pub fn set_instrument_volume(&mut self, instrument_key: &str, value: u8) {
+    pub fn set_instrument_volume(&mut self, instrument_key: &str, value: u8) -> Result<()> {
         match self.state.score.instruments.get_mut(instrument_key) {
             Some(instrument) => {
                 instrument.volume = value;
+                self.patches.replace(
+                    format!("score.instruments.{}.volume", instrument_key),
+                    &instrument.volume,
+                );
             }
-            None => return (),
+            None => return self.fail(Error::UnknownInstrument(String::from(instrument_key))),
         };
-        self.state.score.meta.set_modified();
-        self.emit();
+        self.record("Set instrument volume");
+
+        Ok(())
     }
 
-    pub fn set_instrument_solo(&mut self, instrument_key: &str, value: bool) {
+    pub fn set_instrument_solo(&mut self, instrument_key: &str, value: bool) -> Result<()> {
         match self.state.score.instruments.get_mut(instrument_key) {
             Some(instrument) => {
                 instrument.solo = value;
+                self.patches.replace(
+                    format!("score.instruments.{}.solo", instrument_key),
+                    &instrument.solo,
+                );
             }
-            None => return (),
+            None => return self.fail(Error::UnknownInstrument(String::from(instrument_key))),
         };
-        self.state.score.meta.set_modified();
-        self.emit();
+        self.record("Set instrument solo");
+
+        Ok(())
     }
 
-    pub fn set_instrument_mute(&mut self, instrument_key: &str, value: bool) {
+    pub fn set_instrument_mute(&mut self, instrument_key: &str, value: bool) -> Result<()> {
         match self.state.score.instruments.get_mut(instrument_key) {
             Some(instrument) => {
                 instrument.mute = value;
+                self.patches.replace(
+                    format!("score.instruments.{}.mute", instrument_key),
+                    &instrument.mute,
+                );
             }
-            None => return (),
+            None => return self.fail(Error::UnknownInstrument(String::from(instrument_key))),
         };
-        self.state.score.meta.set_modified();
-        self.emit();
+        self.record("Set instrument mute");
+
+        Ok(())
     }
 }
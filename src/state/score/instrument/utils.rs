@@ -1,9 +1,8 @@
-use crate::state::score::instrument::defs::INSTRUMENT_DEFS;
+use crate::state::score::instrument::defs::{InstrumentDef, INSTRUMENT_DEFS};
 use crate::state::score::instrument::Instrument;
 use crate::state::score::player::PlayerType;
 use crate::state::Engine;
 use std::collections::{HashMap, HashSet};
-use wasm_bindgen::prelude::*;
 
 fn append_instruments<'a>(
     map: &mut HashMap<String, Vec<String>>,
@@ -41,41 +40,20 @@ fn insert_counts(engine: &mut Engine, map: &HashMap<String, Vec<String>>) {
         }
     }
 }
-#[derive(Serialize)]
-struct FullPathReturn<'a> {
-    path: &'a Vec<&'a str>,
-    id: &'a str,
-}
-
-/// Get a full path to def from partial path
-#[wasm_bindgen]
-pub fn get_full_path_from_partial(selection: &JsValue) -> JsValue {
-    let selection: Vec<String> = selection.into_serde().unwrap();
-
-    let def = INSTRUMENT_DEFS.iter().find(|&def| {
+/// Get the first def matching a partial path
+pub fn get_full_path_from_partial(selection: &[String]) -> Option<&'static InstrumentDef> {
+    INSTRUMENT_DEFS.iter().find(|&def| {
         for (i, step) in selection.iter().enumerate() {
-            if String::from(step) != def.path[i] {
+            if def.path.get(i) != Some(&step.as_str()) {
                 return false; // we have a mismatched path -- this isn't what we're looking for
             }
         }
         true // even if we have a partial match only the first def we encounter is what we want
-    });
-
-    match def {
-        Some(def) => JsValue::from_serde(&FullPathReturn {
-            path: &&def.path,
-            id: def.id,
-        })
-        .unwrap(),
-        None => JsValue::UNDEFINED,
-    }
+    })
 }
 
 /// Get a tree of instruments from a (possibly incomplete) path
-#[wasm_bindgen]
-pub fn def_tree(selection: &JsValue) -> JsValue {
-    let selection: Vec<String> = selection.into_serde().unwrap();
-
+pub fn def_tree(selection: &[String]) -> [Vec<&'static str>; 3] {
     let mut ignore: HashSet<&str> = HashSet::new();
     let mut tree: [Vec<&str>; 3] = [Vec::new(), Vec::new(), Vec::new()];
     for def in INSTRUMENT_DEFS.iter() {
@@ -84,14 +62,13 @@ pub fn def_tree(selection: &JsValue) -> JsValue {
                 if !tree[i].contains(step) {
                     tree[i].push(step);
                 }
-                if step.to_string() != selection[i] {
+                if selection.get(i).map(|s| s.as_str()) != Some(*step) {
                     ignore.insert(def.id);
                 }
             }
         }
     }
-
-    JsValue::from_serde(&tree).unwrap()
+    tree
 }
 
 /**
@@ -129,4 +106,9 @@ pub fn calc_counts(engine: &mut Engine) {
 
     insert_counts(engine, &instruments_solo);
     insert_counts(engine, &instruments_section);
+
+    engine.patches.replace(
+        String::from("score.instruments"),
+        &engine.state.score.instruments,
+    );
 }
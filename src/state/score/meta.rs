@@ -1,8 +1,8 @@
 use crate::state::Engine;
-use js_sys::Date;
+#[cfg(feature = "wasm")]
 use wasm_bindgen::prelude::*;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Meta {
     pub title: String,
     pub subtitle: String,
@@ -15,7 +15,8 @@ pub struct Meta {
 }
 
 impl Meta {
-    pub fn new() -> Meta {
+    /// A new meta created at `now`, in milliseconds since the unix epoch
+    pub fn new(now: f64) -> Meta {
         Meta {
             title: String::from(""),
             subtitle: String::from(""),
@@ -23,46 +24,64 @@ impl Meta {
             arranger: String::from(""),
             lyricist: String::from(""),
             copyright: String::from(""),
-            created: Date::now(),
-            modified: Date::now(),
+            created: now,
+            modified: now,
         }
     }
 
-    pub fn set_modified(&mut self) {
-        self.modified = Date::now();
+    pub fn set_modified(&mut self, now: f64) {
+        self.modified = now;
     }
 }
 
-#[wasm_bindgen]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 impl Engine {
     pub fn set_title(&mut self, value: String) {
         self.state.score.meta.title = value;
-        self.state.score.meta.set_modified();
-        self.emit();
+        self.patches.replace(
+            String::from("score.meta.title"),
+            &self.state.score.meta.title,
+        );
+        self.record("Set title");
     }
     pub fn set_subtitle(&mut self, value: String) {
         self.state.score.meta.subtitle = value;
-        self.state.score.meta.set_modified();
-        self.emit();
+        self.patches.replace(
+            String::from("score.meta.subtitle"),
+            &self.state.score.meta.subtitle,
+        );
+        self.record("Set subtitle");
     }
     pub fn set_composer(&mut self, value: String) {
         self.state.score.meta.composer = value;
-        self.state.score.meta.set_modified();
-        self.emit();
+        self.patches.replace(
+            String::from("score.meta.composer"),
+            &self.state.score.meta.composer,
+        );
+        self.record("Set composer");
     }
     pub fn set_arranger(&mut self, value: String) {
         self.state.score.meta.arranger = value;
-        self.state.score.meta.set_modified();
-        self.emit();
+        self.patches.replace(
+            String::from("score.meta.arranger"),
+            &self.state.score.meta.arranger,
+        );
+        self.record("Set arranger");
     }
     pub fn set_lyricist(&mut self, value: String) {
         self.state.score.meta.lyricist = value;
-        self.state.score.meta.set_modified();
-        self.emit();
+        self.patches.replace(
+            String::from("score.meta.lyricist"),
+            &self.state.score.meta.lyricist,
+        );
+        self.record("Set lyricist");
     }
     pub fn set_copyright(&mut self, value: String) {
         self.state.score.meta.copyright = value;
-        self.state.score.meta.set_modified();
-        self.emit();
+        self.patches.replace(
+            String::from("score.meta.copyright"),
+            &self.state.score.meta.copyright,
+        );
+        self.record("Set copyright");
     }
 }
@@ -1,12 +1,16 @@
-mod config;
-mod engrave;
+pub mod config;
+pub mod engrave;
 pub mod flow;
-mod instrument;
+pub mod instrument;
 mod meta;
-mod player;
-mod stave;
-mod track;
+pub mod notation;
+pub mod player;
+pub mod stave;
+pub mod tempo_map;
+pub mod track;
+pub mod validate;
 
+use crate::state::migrate::VERSION;
 use crate::state::score::config::Config;
 use crate::state::score::engrave::{Engrave, LayoutType};
 use crate::state::score::flow::Flows;
@@ -15,8 +19,9 @@ use crate::state::score::meta::Meta;
 use crate::state::score::player::Players;
 use std::collections::HashMap;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Score {
+    pub version: u64,
     pub meta: Meta,
     pub config: Config,
     pub engrave: HashMap<String, Engrave>,
@@ -26,7 +31,7 @@ pub struct Score {
 }
 
 impl Score {
-    pub fn new() -> Score {
+    pub fn new(now: f64) -> Score {
         let mut engrave = HashMap::new();
 
         let score = Engrave::new(LayoutType::Score, String::from("Score"));
@@ -35,7 +40,8 @@ impl Score {
         engrave.insert(part.key.clone(), part);
 
         Score {
-            meta: Meta::new(),
+            version: VERSION,
+            meta: Meta::new(now),
             config: Config::new(),
             engrave: engrave,
             flows: Flows::new(),
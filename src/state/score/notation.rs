@@ -0,0 +1,550 @@
+use crate::error::{Error, Result};
+use crate::state::entries::time_signature::{TimeSignature, TimeSignatureDrawType};
+use crate::state::entries::tuplet::Tuplet;
+use crate::state::entries::Entry;
+use crate::state::score::track::Track;
+use crate::state::Engine;
+use crate::utils::duration::{NoteDuration, WrittenDuration};
+use std::collections::BTreeMap;
+
+/// A written note, chord or rest.
+///
+/// Tones that can't be written as a single note value are split into several
+/// notations joined by ties.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct Notation {
+    pub tick: u32,
+    pub length: u32, // number of ticks
+    pub duration: WrittenDuration,
+    pub tones: Vec<String>,     // the tones sounding, empty for a rest
+    pub tie: bool,              // tied to the next notation
+    pub tuplet: Option<String>, // the tuplet this is written within
+}
+
+impl Notation {
+    pub fn is_rest(&self) -> bool {
+        self.tones.is_empty()
+    }
+}
+
+/// The time signature in effect at a tick. Before the first time signature we
+/// count in open time with quarter note beats.
+fn time_signature_at(master: &Track, tick: u32) -> TimeSignature {
+    match master.get_time_signature_on_or_before_tick(tick) {
+        Some(time_signature) => time_signature.clone(),
+        None => TimeSignature {
+            key: String::new(),
+            tick: 0,
+            beats: 0,
+            beat_type: NoteDuration::Quarter,
+            draw_type: TimeSignatureDrawType::Hidden,
+            groupings: Vec::new(),
+            pickup: 0,
+        },
+    }
+}
+
+/// Returns true if a span can be written as a single note value without hiding
+/// the beat groupings of the bar.
+fn is_clear(
+    start: u32,
+    end: u32,
+    time_signature: &TimeSignature,
+    subdivisions: u8,
+    rest: bool,
+) -> bool {
+    let is_grouping_boundry = |tick| time_signature.is_on_grouping_boundry(tick, subdivisions);
+    let is_on_beat = |tick| time_signature.is_on_beat(tick, subdivisions);
+
+    // notes may cover whole groups, rests fill each group on its own
+    if (start + 1..end).any(is_grouping_boundry)
+        && (rest || !is_grouping_boundry(start) || !is_grouping_boundry(end))
+    {
+        return false;
+    }
+
+    // rests that cross a beat must cover whole beats
+    if rest && (start + 1..end).any(is_on_beat) {
+        return is_on_beat(start) && is_on_beat(end);
+    }
+
+    true
+}
+
+/// Find the most significant tick to split a span at, prefering grouping
+/// boundries, then beats, then ever smaller divisions of the beat.
+fn split_point(
+    start: u32,
+    end: u32,
+    time_signature: &TimeSignature,
+    subdivisions: u8,
+) -> Option<u32> {
+    let mut inner = start + 1..end;
+
+    if let Some(tick) = inner
+        .clone()
+        .find(|&tick| time_signature.is_on_grouping_boundry(tick, subdivisions))
+    {
+        return Some(tick);
+    }
+
+    let mut step = time_signature.ticks_per_beat(subdivisions);
+    if let Some(tick) = inner.find(|&tick| time_signature.is_on_step(tick, subdivisions, step)) {
+        return Some(tick);
+    }
+    while step.is_multiple_of(2) {
+        step /= 2;
+        if let Some(tick) =
+            (start + 1..end).find(|&tick| time_signature.is_on_step(tick, subdivisions, step))
+        {
+            return Some(tick);
+        }
+    }
+
+    // the beat can't be divided any further so take the longest value we can
+    WrittenDuration::longest_within(end - start - 1, subdivisions)
+        .map(|duration| start + duration.to_ticks(subdivisions))
+}
+
+/// Split a span within a single bar into written values
+fn split_bar(
+    start: u32,
+    end: u32,
+    time_signature: &TimeSignature,
+    subdivisions: u8,
+    rest: bool,
+    out: &mut Vec<(u32, u32, WrittenDuration)>,
+) {
+    let max_dots = if rest { 1 } else { 2 };
+    if let Some(duration) = WrittenDuration::from_ticks(end - start, subdivisions, max_dots) {
+        if is_clear(start, end, time_signature, subdivisions, rest) {
+            out.push((start, end - start, duration));
+            return;
+        }
+    }
+
+    match split_point(start, end, time_signature, subdivisions) {
+        Some(tick) => {
+            split_bar(start, tick, time_signature, subdivisions, rest, out);
+            split_bar(tick, end, time_signature, subdivisions, rest, out);
+        }
+        None => {
+            // this can't be written with plain note values, write the closest we can
+            let duration = WrittenDuration::longest_within(end - start, subdivisions).unwrap_or(
+                WrittenDuration {
+                    base: NoteDuration::ThirtySecond,
+                    dots: 0,
+                },
+            );
+            out.push((start, end - start, duration));
+        }
+    }
+}
+
+/// Split a span within a tuplet into written values. Each value is written as
+/// though it were `actual / normal` times longer, eg. a third of a quarter is an eighth.
+fn split_tuplet(
+    start: u32,
+    end: u32,
+    tuplet: &Tuplet,
+    ticks_per_value: u32,
+    subdivisions: u8,
+    rest: bool,
+    out: &mut Vec<(u32, u32, WrittenDuration)>,
+) {
+    let actual = u32::from(tuplet.actual);
+    let normal = u32::from(tuplet.normal);
+    let length = end - start;
+    let written = length * actual / normal;
+
+    let max_dots = if rest { 1 } else { 2 };
+    if (length * actual).is_multiple_of(normal) {
+        if let Some(duration) = WrittenDuration::from_ticks(written, subdivisions, max_dots) {
+            out.push((start, length, duration));
+            return;
+        }
+    }
+
+    match (start + 1..end).find(|tick| (tick - tuplet.tick).is_multiple_of(ticks_per_value)) {
+        Some(tick) => {
+            split_tuplet(
+                start,
+                tick,
+                tuplet,
+                ticks_per_value,
+                subdivisions,
+                rest,
+                out,
+            );
+            split_tuplet(tick, end, tuplet, ticks_per_value, subdivisions, rest, out);
+        }
+        None => {
+            let duration =
+                WrittenDuration::longest_within(written, subdivisions).unwrap_or(WrittenDuration {
+                    base: NoteDuration::ThirtySecond,
+                    dots: 0,
+                });
+            out.push((start, length, duration));
+        }
+    }
+}
+
+/// Split a span of ticks into written values (tick, length, duration), breaking
+/// at barlines and the beat groupings of the active time signatures.
+///
+/// Rests are grouped more strictly than notes and a rest that fills a whole bar
+/// is always written as a whole rest.
+pub fn split(
+    start: u32,
+    end: u32,
+    master: &Track,
+    length: u32,
+    subdivisions: u8,
+    rest: bool,
+) -> Vec<(u32, u32, WrittenDuration)> {
+    let mut out = Vec::new();
+    let mut tick = start;
+
+    while tick < end {
+        let time_signature = time_signature_at(master, tick);
+        let barline = time_signature.next_barline(tick, subdivisions);
+
+        // nothing is written across a barline or a change of time signature
+        let mut stop = end;
+        if let Some(barline) = barline {
+            stop = stop.min(barline);
+        }
+        if let Some(next) = master.get_time_signature_after_tick(tick, length) {
+            stop = stop.min(next.tick);
+        }
+
+        let is_whole_bar =
+            barline == Some(stop) && time_signature.distance_from_barline(tick, subdivisions) == 0;
+        if rest && is_whole_bar {
+            out.push((
+                tick,
+                stop - tick,
+                WrittenDuration {
+                    base: NoteDuration::Whole,
+                    dots: 0,
+                },
+            ));
+        } else {
+            split_bar(tick, stop, &time_signature, subdivisions, rest, &mut out);
+        }
+
+        tick = stop;
+    }
+
+    out
+}
+
+/// The bars of a flow as (start, end) ticks. Open time has no barlines so
+/// runs on as one bar until the next time signature.
+pub fn measures(master: &Track, length: u32, subdivisions: u8) -> Vec<(u32, u32)> {
+    let mut out = Vec::new();
+    let mut tick = 0;
+
+    while tick < length {
+        let time_signature = time_signature_at(master, tick);
+        let mut stop = length;
+        if let Some(barline) = time_signature.next_barline(tick, subdivisions) {
+            stop = stop.min(barline);
+        }
+        if let Some(next) = master.get_time_signature_after_tick(tick, length) {
+            stop = stop.min(next.tick);
+        }
+        out.push((tick, stop));
+        tick = stop;
+    }
+
+    out
+}
+
+/// Work out the written notes and rests for a track as a single voice.
+///
+/// Tones starting on the same tick are written as a chord, lasting as long as the
+/// longest tone but cut short by the next tone to start. Anything within a tuplet
+/// is written in the tuplet's values.
+pub fn notate(track: &Track, master: &Track, length: u32, subdivisions: u8) -> Vec<Notation> {
+    let mut chords: BTreeMap<u32, (u32, Vec<String>)> = BTreeMap::new();
+    for entry in track.entries.by_key.values() {
+        if let Entry::Tone(tone) = entry {
+            let chord = chords.entry(tone.tick).or_insert((0, Vec::new()));
+            chord.0 = chord.0.max(tone.tick + tone.duration.int);
+            chord.1.push(tone.key.clone());
+        }
+    }
+
+    let onsets: Vec<u32> = chords.keys().cloned().collect();
+    let mut out = Vec::new();
+    let mut cursor = 0;
+
+    // tuplets the subdivisions can't represent are ignored and written as normal
+    let tuplets: Vec<(&Tuplet, u32)> = track
+        .get_tuplets()
+        .into_iter()
+        .filter_map(|tuplet| {
+            tuplet
+                .ticks_per_value(subdivisions)
+                .map(|ticks_per_value| (tuplet, ticks_per_value))
+        })
+        .collect();
+
+    let push = |start: u32, end: u32, tones: Vec<String>, out: &mut Vec<Notation>| {
+        let rest = tones.is_empty();
+        let mut parts = Vec::new();
+        let mut cursor = start;
+
+        for (tuplet, ticks_per_value) in tuplets.iter() {
+            let tuplet_end = tuplet.tick + tuplet.length(subdivisions);
+            if tuplet_end <= cursor || tuplet.tick >= end {
+                continue;
+            }
+            if tuplet.tick > cursor {
+                for part in split(cursor, tuplet.tick, master, length, subdivisions, rest) {
+                    parts.push((part, None));
+                }
+                cursor = tuplet.tick;
+            }
+
+            let stop = end.min(tuplet_end);
+            let mut inner = Vec::new();
+            split_tuplet(
+                cursor,
+                stop,
+                tuplet,
+                *ticks_per_value,
+                subdivisions,
+                rest,
+                &mut inner,
+            );
+            for part in inner {
+                parts.push((part, Some(tuplet.key.clone())));
+            }
+            cursor = stop;
+        }
+
+        if cursor < end {
+            for part in split(cursor, end, master, length, subdivisions, rest) {
+                parts.push((part, None));
+            }
+        }
+
+        let last = parts.len().saturating_sub(1);
+        for (i, ((tick, length, duration), tuplet)) in parts.into_iter().enumerate() {
+            out.push(Notation {
+                tick,
+                length,
+                duration,
+                tones: tones.clone(),
+                tie: !rest && i < last,
+                tuplet,
+            });
+        }
+    };
+
+    for (i, (tick, (end, mut tones))) in chords.into_iter().enumerate() {
+        if tick >= length {
+            break;
+        }
+        if tick > cursor {
+            push(cursor, tick, Vec::new(), &mut out);
+        }
+
+        let next = onsets.get(i + 1).cloned().unwrap_or(length);
+        let end = end.min(next).min(length);
+        tones.sort();
+        push(tick, end, tones, &mut out);
+        cursor = end;
+    }
+
+    if cursor < length {
+        push(cursor, length, Vec::new(), &mut out);
+    }
+
+    out
+}
+
+impl Engine {
+    /// Get the written notes and rests for a track
+    pub fn get_notation(&self, flow_key: &str, track_key: &str) -> Result<Vec<Notation>> {
+        let flow = match self.state.score.flows.by_key.get(flow_key) {
+            Some(flow) => flow,
+            None => return Err(Error::UnknownFlow(String::from(flow_key))),
+        };
+
+        let track = match flow.tracks.get(track_key) {
+            Some(track) => track,
+            None => return Err(Error::UnknownTrack(String::from(track_key))),
+        };
+
+        Ok(notate(track, &flow.master, flow.length, flow.subdivisions))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::entries::tone::{Articulation, Tone};
+    use crate::utils::duration::Duration;
+    use crate::utils::pitch::{Accidental, Pitch};
+    use crate::utils::velocity::Velocity;
+
+    const SUBDIVISIONS: u8 = 4; // sixteenths
+
+    fn with_time_signature(beats: u8, beat_type: NoteDuration) -> Track {
+        let mut master = Track::new();
+        master.insert(TimeSignature::new(
+            String::from("time"),
+            0,
+            beats,
+            beat_type,
+            TimeSignatureDrawType::Normal,
+            None,
+        ));
+        master
+    }
+
+    fn tone(track: &mut Track, key: &str, tick: u32, duration: u32) {
+        track.insert(Tone::new(
+            String::from(key),
+            tick,
+            Duration::new(duration),
+            Pitch::new(60, Accidental::Natural),
+            Velocity::new(100),
+            Articulation::None,
+            None,
+        ));
+    }
+
+    fn written(parts: &[(u32, u32, WrittenDuration)]) -> Vec<(NoteDuration, u8)> {
+        parts
+            .iter()
+            .map(|(_, _, duration)| (duration.base.clone(), duration.dots))
+            .collect()
+    }
+
+    #[test]
+    fn test_whole_bar() {
+        let master = with_time_signature(4, NoteDuration::Quarter);
+        assert_eq!(
+            written(&split(0, 16, &master, 32, SUBDIVISIONS, false)),
+            vec![(NoteDuration::Whole, 0)]
+        );
+        // whole bar rests are always whole rests, even in 3/4
+        let master = with_time_signature(3, NoteDuration::Quarter);
+        assert_eq!(
+            written(&split(0, 12, &master, 24, SUBDIVISIONS, true)),
+            vec![(NoteDuration::Whole, 0)]
+        );
+    }
+
+    #[test]
+    fn test_tied_across_groupings() {
+        // a dotted half from beat 2 in 4/4 hides the middle of the bar
+        let master = with_time_signature(4, NoteDuration::Quarter);
+        assert_eq!(
+            written(&split(4, 16, &master, 32, SUBDIVISIONS, false)),
+            vec![(NoteDuration::Quarter, 0), (NoteDuration::Half, 0)]
+        );
+        // syncopated quarter within a group is fine
+        assert_eq!(
+            written(&split(2, 6, &master, 32, SUBDIVISIONS, false)),
+            vec![(NoteDuration::Quarter, 0)]
+        );
+    }
+
+    #[test]
+    fn test_tied_across_barline() {
+        let master = with_time_signature(4, NoteDuration::Quarter);
+        let parts = split(12, 24, &master, 32, SUBDIVISIONS, false);
+        assert_eq!(
+            written(&parts),
+            vec![(NoteDuration::Quarter, 0), (NoteDuration::Half, 0)]
+        );
+        assert_eq!(parts[1].0, 16);
+    }
+
+    #[test]
+    fn test_rests() {
+        let master = with_time_signature(4, NoteDuration::Quarter);
+        // beats 2 and 3 of 4/4 are written as two quarter rests
+        assert_eq!(
+            written(&split(4, 12, &master, 32, SUBDIVISIONS, true)),
+            vec![(NoteDuration::Quarter, 0), (NoteDuration::Quarter, 0)]
+        );
+        // an off beat rest fills up to the beat first
+        assert_eq!(
+            written(&split(2, 8, &master, 32, SUBDIVISIONS, true)),
+            vec![(NoteDuration::Eighth, 0), (NoteDuration::Quarter, 0)]
+        );
+    }
+
+    #[test]
+    fn test_compound() {
+        let master = with_time_signature(6, NoteDuration::Eighth);
+        // a full bar of 6/8 is a dotted half
+        assert_eq!(
+            written(&split(0, 12, &master, 24, SUBDIVISIONS, false)),
+            vec![(NoteDuration::Half, 1)]
+        );
+        // rests fill each dotted quarter group
+        assert_eq!(
+            written(&split(4, 12, &master, 24, SUBDIVISIONS, true)),
+            vec![(NoteDuration::Eighth, 0), (NoteDuration::Quarter, 1)]
+        );
+    }
+
+    #[test]
+    fn test_tuplets() {
+        let master = with_time_signature(2, NoteDuration::Quarter);
+        let mut track = Track::new();
+        track.insert(Tuplet::new(
+            String::from("triplet"),
+            0,
+            3,
+            2,
+            NoteDuration::Eighth,
+        ));
+        // at 48 subdivisions each triplet eighth is 16 ticks
+        tone(&mut track, "a", 0, 16);
+        tone(&mut track, "b", 16, 32);
+
+        let notation = notate(&track, &master, 96, 48);
+        assert_eq!(notation[0].duration.base, NoteDuration::Eighth);
+        assert_eq!(notation[0].tuplet, Some(String::from("triplet")));
+        assert_eq!(notation[1].duration.base, NoteDuration::Quarter);
+        assert_eq!(notation[1].tuplet, Some(String::from("triplet")));
+        assert_eq!(notation[2].tick, 48);
+        assert_eq!(notation[2].duration.base, NoteDuration::Quarter);
+        assert_eq!(notation[2].tuplet, None);
+    }
+
+    #[test]
+    fn test_notate() {
+        let master = with_time_signature(4, NoteDuration::Quarter);
+        let mut track = Track::new();
+        tone(&mut track, "a", 4, 16);
+        tone(&mut track, "b", 4, 4);
+
+        let notation = notate(&track, &master, 32, SUBDIVISIONS);
+        assert!(notation[0].is_rest());
+        assert_eq!(notation[0].length, 4);
+
+        // the chord is tied into the next bar
+        let tones = vec![String::from("a"), String::from("b")];
+        assert_eq!(notation[1].tones, tones);
+        assert!(notation[1].tie);
+        assert_eq!(notation[2].tones, tones);
+        assert!(notation[2].tie);
+        assert_eq!(notation[3].tones, tones);
+        assert_eq!(notation[3].tick, 16);
+        assert!(!notation[3].tie);
+
+        // and the rest of the flow is filled with rests
+        let rests = &notation[4..];
+        assert!(rests.iter().all(|notation| notation.is_rest()));
+        assert_eq!(rests.iter().map(|rest| rest.length).sum::<u32>(), 12);
+    }
+}
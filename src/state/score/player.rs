@@ -1,18 +1,21 @@
+use crate::error::{Error, Result};
+use crate::state::patch::flow_path;
 use crate::state::score::instrument::utils::calc_counts;
 use crate::state::Engine;
 use crate::utils::shortid;
 use std::collections::HashMap;
+#[cfg(feature = "wasm")]
 use wasm_bindgen::prelude::*;
 
-#[wasm_bindgen]
-#[derive(Serialize_repr, Deserialize_repr)]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Serialize_repr, Deserialize_repr, Copy, Clone)]
 #[repr(u8)]
 pub enum PlayerType {
     Solo,
     Section,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Player {
     pub key: String,
     pub player_type: PlayerType,
@@ -32,12 +35,18 @@ impl Player {
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Players {
     pub order: Vec<String>,
     pub by_key: HashMap<String, Player>,
 }
 
+impl Default for Players {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Players {
     pub fn new() -> Self {
         Self {
@@ -47,11 +56,11 @@ impl Players {
     }
 }
 
-#[wasm_bindgen]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
 impl Engine {
-    pub fn create_player(&mut self, player_type: PlayerType) -> JsValue {
+    pub fn create_player(&mut self, player_type: PlayerType) -> String {
         let player = Player::new(player_type);
-        let returner = JsValue::from_str(&player.key);
+        let player_key = player.key.clone();
 
         self.state.score.players.order.push(player.key.clone());
 
@@ -61,6 +70,8 @@ impl Engine {
             match flow {
                 Some(flow) => {
                     flow.players.insert(player.key.clone());
+                    self.patches
+                        .replace(format!("{}.players", flow_path(flow_key)), &flow.players);
                 }
                 None => {} // won't happen but we ignore if it does
             }
@@ -71,29 +82,40 @@ impl Engine {
             .players
             .by_key
             .insert(player.key.clone(), player);
-        self.state.score.meta.set_modified();
-        self.emit();
-
-        returner
+        self.patches.add(
+            format!("score.players.by_key.{}", player_key),
+            &self.state.score.players.by_key[&player_key],
+        );
+        self.patches.replace(
+            String::from("score.players.order"),
+            &self.state.score.players.order,
+        );
+        self.record("Create player");
+
+        player_key
     }
 
     /**
      * Assign instrument to player
      */
-    pub fn assign_instrument(&mut self, player_key: &str, instrument_key: &str) -> JsValue {
+    pub fn assign_instrument(&mut self, player_key: &str, instrument_key: &str) -> Result<String> {
         let player_key = String::from(player_key);
         let instrument_key = String::from(instrument_key);
 
+        let instrument = match self.state.score.instruments.get(&instrument_key) {
+            Some(instrument) => instrument,
+            None => return self.fail(Error::UnknownInstrument(instrument_key)),
+        };
+
         match self.state.score.players.by_key.get_mut(&player_key) {
             Some(player) => {
                 player.instruments.push(instrument_key.clone());
+                self.patches.replace(
+                    format!("score.players.by_key.{}.instruments", player_key),
+                    &player.instruments,
+                );
             }
-            None => return JsValue::UNDEFINED,
-        };
-
-        let instrument = match self.state.score.instruments.get(&instrument_key) {
-            Some(instrument) => instrument,
-            None => return JsValue::UNDEFINED,
+            None => return self.fail(Error::UnknownPlayer(player_key)),
         };
 
         // add empty staves to each flow that contains the player
@@ -102,6 +124,7 @@ impl Engine {
                 Some(flow) => {
                     if flow.players.contains(&player_key) {
                         flow.add_instrument(instrument);
+                        self.patches.replace_flow_contents(flow);
                     }
                 }
                 None => {} // won't happen but we ignore if it does
@@ -109,51 +132,69 @@ impl Engine {
         }
 
         calc_counts(self);
-        self.state.score.meta.set_modified();
-        self.emit();
+        self.record("Assign instrument");
 
-        JsValue::from_str(&player_key)
+        Ok(player_key)
     }
 
-    pub fn reorder_player(&mut self, old_index: u8, new_index: u8) {
+    pub fn reorder_player(&mut self, old_index: u8, new_index: u8) -> Result<()> {
+        let len = self.state.score.players.order.len();
+        if old_index as usize >= len {
+            return self.fail(Error::InvalidIndex(old_index as usize));
+        }
+        if new_index as usize >= len {
+            return self.fail(Error::InvalidIndex(new_index as usize));
+        }
+
         let removed = self.state.score.players.order.remove(old_index as usize);
         self.state
             .score
             .players
             .order
             .insert(new_index as usize, removed);
+        self.patches.replace(
+            String::from("score.players.order"),
+            &self.state.score.players.order,
+        );
 
         calc_counts(self);
-        self.state.score.meta.set_modified();
-        self.emit();
+        self.record("Reorder player");
+
+        Ok(())
     }
 
-    pub fn remove_player(&mut self, player_key: &str) {
+    pub fn remove_player(&mut self, player_key: &str) -> Result<()> {
         // delete all instruments that this player holds
         let instrument_keys = match self.state.score.players.by_key.get(player_key) {
             // we need to clone so instrument_keys isn't a ref to self and so we can use it later
             Some(player) => player.instruments.clone(),
-            None => return (),
+            None => return self.fail(Error::UnknownPlayer(String::from(player_key))),
         };
         for instrument_key in instrument_keys {
-            self.remove_instrument(player_key, &instrument_key);
+            // the player may reference an instrument that is already gone, that's fine
+            let _ = self.delete_instrument(player_key, &instrument_key);
         }
 
         // remove the player from each flow
-        for flow_key in &self.state.score.flows.order {
-            let flow = match self.state.score.flows.by_key.get_mut(flow_key) {
-                Some(flow) => flow,
-                None => return (),
-            };
+        for flow in self.state.score.flows.by_key.values_mut() {
             flow.players.remove(player_key);
+            self.patches
+                .replace(format!("{}.players", flow_path(&flow.key)), &flow.players);
         }
 
         // remove the player itself
         self.state.score.players.by_key.remove(player_key);
         self.state.score.players.order.retain(|e| e != player_key);
+        self.patches
+            .remove(format!("score.players.by_key.{}", player_key));
+        self.patches.replace(
+            String::from("score.players.order"),
+            &self.state.score.players.order,
+        );
 
         calc_counts(self);
-        self.state.score.meta.set_modified();
-        self.emit();
+        self.record("Remove player");
+
+        Ok(())
     }
 }
@@ -0,0 +1,418 @@
+use crate::error::{Error, Result};
+use crate::state::entries::gradual_tempo::{GradualTempo, TempoCurve};
+use crate::state::entries::Entry;
+use crate::state::score::track::Track;
+use crate::state::Engine;
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+/// The tempo until the first tempo mark, in quarters per minute
+pub const DEFAULT_BPM: f64 = 120.0;
+
+/// How many steps a tempo change is split into to work out how long it takes
+const STEPS: u32 = 64;
+
+/// A stretch of the flow from one tempo mark to the next. A gradual change moves
+/// from `bpm` to `target_bpm` over `ramp` ticks and then holds the target.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TempoSegment {
+    pub tick: u32,
+    pub seconds: f64, // time at the start of the segment
+    pub bpm: f64,     // quarters per minute
+    pub target_bpm: f64,
+    pub ramp: u32, // 0 for a steady tempo
+    pub curve: TempoCurve,
+}
+
+impl TempoSegment {
+    fn steady(tick: u32, seconds: f64, bpm: f64) -> TempoSegment {
+        TempoSegment {
+            tick,
+            seconds,
+            bpm,
+            target_bpm: bpm,
+            ramp: 0,
+            curve: TempoCurve::Linear,
+        }
+    }
+
+    /// The tempo `offset` ticks into the segment
+    pub fn bpm_at(&self, offset: f64) -> f64 {
+        let ramp = f64::from(self.ramp);
+        if offset >= ramp {
+            self.target_bpm
+        } else {
+            let progress = self.curve.progress(offset.max(0.0) / ramp);
+            self.bpm + (self.target_bpm - self.bpm) * progress
+        }
+    }
+
+    /// The seconds taken to play `offset` ticks into the segment
+    fn elapsed(&self, offset: f64, subdivisions: f64) -> f64 {
+        let seconds_per_tick = |bpm: f64| 60.0 / (bpm * subdivisions);
+        let ramped = offset.min(f64::from(self.ramp));
+        let held = (offset - ramped) * seconds_per_tick(self.target_bpm);
+        if ramped <= 0.0 {
+            return held;
+        }
+
+        // Simpson's rule across the change
+        let width = ramped / f64::from(STEPS);
+        let mut sum = seconds_per_tick(self.bpm_at(0.0)) + seconds_per_tick(self.bpm_at(ramped));
+        for i in 1..STEPS {
+            let weight = if i % 2 == 0 { 2.0 } else { 4.0 };
+            sum += weight * seconds_per_tick(self.bpm_at(f64::from(i) * width));
+        }
+        held + sum * width / 3.0
+    }
+
+    /// The ticks into the segment played after `seconds`, the inverse of `elapsed()`
+    fn offset(&self, seconds: f64, subdivisions: f64) -> f64 {
+        let ramp = f64::from(self.ramp);
+        let ramp_seconds = self.elapsed(ramp, subdivisions);
+        if seconds >= ramp_seconds {
+            return ramp + (seconds - ramp_seconds) * self.target_bpm * subdivisions / 60.0;
+        }
+
+        // time only moves forward, so a binary search finds the offset
+        let (mut low, mut high) = (0.0, ramp);
+        for _ in 0..48 {
+            let middle = (low + high) / 2.0;
+            if self.elapsed(middle, subdivisions) < seconds {
+                low = middle;
+            } else {
+                high = middle;
+            }
+        }
+        (low + high) / 2.0
+    }
+}
+
+enum TempoMark<'a> {
+    Absolute(f64),
+    Gradual(&'a GradualTempo),
+}
+
+/// Converts between ticks and seconds for a flow, following the tempo marks on its
+/// master track. Times before the first mark are at `DEFAULT_BPM`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TempoMap {
+    pub subdivisions: u8,
+    pub length: u32,
+    pub segments: Vec<TempoSegment>, // in tick order, the first always starts at 0
+}
+
+impl TempoMap {
+    pub fn new(master: &Track, length: u32, subdivisions: u8) -> TempoMap {
+        let mut marks: Vec<(u32, TempoMark)> = master
+            .entries
+            .by_key
+            .values()
+            .filter_map(|entry| match entry {
+                Entry::AbsoluteTempo(tempo) if tempo.normalized_bpm > 0.0 => {
+                    Some((tempo.tick, TempoMark::Absolute(tempo.normalized_bpm)))
+                }
+                Entry::GradualTempo(tempo) => Some((tempo.tick, TempoMark::Gradual(tempo))),
+                _ => None,
+            })
+            .collect();
+        // a change starting on a tempo mark starts from the new tempo
+        marks.sort_by_key(|(tick, mark)| (*tick, matches!(mark, TempoMark::Gradual(_))));
+
+        let first = marks
+            .iter()
+            .find_map(|(tick, mark)| match mark {
+                TempoMark::Absolute(bpm) if *tick == 0 => Some(*bpm),
+                _ => None,
+            })
+            .unwrap_or(DEFAULT_BPM);
+        let mut last = DEFAULT_BPM;
+
+        let mut map = TempoMap {
+            subdivisions,
+            length,
+            segments: vec![TempoSegment::steady(0, 0.0, DEFAULT_BPM)],
+        };
+        for (tick, mark) in marks {
+            let seconds = map.tick_to_seconds(tick);
+            let segment = match mark {
+                TempoMark::Absolute(bpm) => {
+                    last = bpm;
+                    TempoSegment::steady(tick, seconds, bpm)
+                }
+                TempoMark::Gradual(tempo) => {
+                    let current = map.bpm_at_tick(f64::from(tick));
+                    TempoSegment {
+                        tick,
+                        seconds,
+                        bpm: current,
+                        target_bpm: tempo.target_bpm(current, last, first),
+                        ramp: tempo.duration,
+                        curve: tempo.curve,
+                    }
+                }
+            };
+            match map.segments.last_mut() {
+                Some(previous) if previous.tick == tick => *previous = segment,
+                _ => map.segments.push(segment),
+            }
+        }
+        map
+    }
+
+    /// The segment playing at a tick
+    fn segment_at_tick(&self, tick: f64) -> &TempoSegment {
+        let i = self
+            .segments
+            .partition_point(|segment| f64::from(segment.tick) <= tick);
+        &self.segments[i.max(1) - 1]
+    }
+
+    /// The tempo in quarters per minute at a tick
+    pub fn bpm_at_tick(&self, tick: f64) -> f64 {
+        let segment = self.segment_at_tick(tick);
+        segment.bpm_at(tick - f64::from(segment.tick))
+    }
+
+    /// The time from the start of the flow to a tick. Ticks after the end of the
+    /// flow carry on at the last tempo.
+    pub fn tick_to_seconds(&self, tick: u32) -> f64 {
+        let tick = f64::from(tick);
+        let segment = self.segment_at_tick(tick);
+        let subdivisions = f64::from(self.subdivisions.max(1));
+        segment.seconds + segment.elapsed(tick - f64::from(segment.tick), subdivisions)
+    }
+
+    /// The tick playing at a time, with the fraction of the way to the next tick
+    /// so that a playback cursor can move smoothly.
+    pub fn seconds_to_tick(&self, seconds: f64) -> f64 {
+        let seconds = seconds.max(0.0);
+        let i = self
+            .segments
+            .partition_point(|segment| segment.seconds <= seconds);
+        let segment = &self.segments[i.max(1) - 1];
+        let subdivisions = f64::from(self.subdivisions.max(1));
+        f64::from(segment.tick) + segment.offset(seconds - segment.seconds, subdivisions)
+    }
+
+    /// How long the flow takes to play
+    pub fn duration(&self) -> f64 {
+        self.tick_to_seconds(self.length)
+    }
+
+    /// The tempos to play the flow at as (tick, bpm), gradual changes are broken
+    /// into steps of `step` ticks, each at the tempo that takes the same time to play.
+    pub fn changes(&self, step: u32) -> Vec<(u32, f64)> {
+        let step = step.max(1);
+        let subdivisions = f64::from(self.subdivisions.max(1));
+        let mut out: Vec<(u32, f64)> = Vec::new();
+        let mut push = |tick: u32, bpm: f64| match out.last() {
+            Some((_, last)) if (last - bpm).abs() < 1e-9 => (),
+            _ => out.push((tick, bpm)),
+        };
+
+        for (i, segment) in self.segments.iter().enumerate() {
+            let end = match self.segments.get(i + 1) {
+                Some(next) => next.tick,
+                None => self.length.max(segment.tick),
+            };
+            let ramp_end = segment.tick + segment.ramp;
+            let mut tick = segment.tick;
+            loop {
+                if tick >= end && tick > segment.tick {
+                    break;
+                }
+                if tick >= ramp_end {
+                    push(tick, segment.target_bpm);
+                    break;
+                }
+                let next = (tick + step).min(ramp_end).min(end.max(tick + 1));
+                let from = f64::from(tick - segment.tick);
+                let to = f64::from(next - segment.tick);
+                let seconds =
+                    segment.elapsed(to, subdivisions) - segment.elapsed(from, subdivisions);
+                push(tick, (to - from) * 60.0 / (seconds * subdivisions));
+                tick = next;
+            }
+        }
+        out
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl Engine {
+    /// The time in seconds from the start of a flow to a tick
+    pub fn tick_to_seconds(&self, flow_key: &str, tick: u32) -> Result<f64> {
+        match self.state.tempo_maps.get(flow_key) {
+            Some(tempo_map) => Ok(tempo_map.tick_to_seconds(tick)),
+            None => Err(Error::UnknownFlow(String::from(flow_key))),
+        }
+    }
+
+    /// The (fractional) tick playing at a time in seconds from the start of a flow
+    pub fn seconds_to_tick(&self, flow_key: &str, seconds: f64) -> Result<f64> {
+        match self.state.tempo_maps.get(flow_key) {
+            Some(tempo_map) => Ok(tempo_map.seconds_to_tick(seconds)),
+            None => Err(Error::UnknownFlow(String::from(flow_key))),
+        }
+    }
+
+    /// How long a flow takes to play in seconds
+    pub fn flow_duration_seconds(&self, flow_key: &str) -> Result<f64> {
+        match self.state.tempo_maps.get(flow_key) {
+            Some(tempo_map) => Ok(tempo_map.duration()),
+            None => Err(Error::UnknownFlow(String::from(flow_key))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::entries::absolute_tempo::AbsoluteTempo;
+    use crate::state::entries::gradual_tempo::{GradualTempoType, TempoTarget};
+    use crate::state::listener::{SystemClock, Update};
+    use crate::utils::duration::NoteDuration;
+
+    fn tempo(tick: u32, beat_type: NoteDuration, bpm: u32) -> Entry {
+        AbsoluteTempo::new(
+            4,
+            format!("tempo{}", tick),
+            tick,
+            String::new(),
+            beat_type,
+            0,
+            bpm,
+            false,
+            false,
+            true,
+        )
+    }
+
+    #[test]
+    fn test_default_tempo() {
+        let map = TempoMap::new(&Track::new(), 16, 4);
+        assert_eq!(map.tick_to_seconds(4), 0.5);
+        assert_eq!(map.duration(), 2.0);
+        assert_eq!(map.seconds_to_tick(0.25), 2.0);
+    }
+
+    #[test]
+    fn test_tempo_changes() {
+        let mut master = Track::new();
+        master.insert(tempo(0, NoteDuration::Quarter, 60));
+        // eighth = 240 is 120 quarters per minute
+        master.insert(tempo(8, NoteDuration::Eighth, 240));
+        let map = TempoMap::new(&master, 16, 4);
+
+        assert_eq!(map.segments.len(), 2);
+        assert_eq!(map.tick_to_seconds(8), 2.0);
+        assert_eq!(map.tick_to_seconds(12), 2.5);
+        assert_eq!(map.duration(), 3.0);
+        assert_eq!(map.seconds_to_tick(1.0), 4.0);
+        assert_eq!(map.seconds_to_tick(2.5), 12.0);
+        assert_eq!(map.seconds_to_tick(-1.0), 0.0);
+        // past the end carries on at the last tempo
+        assert_eq!(map.tick_to_seconds(20), 3.5);
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-6
+    }
+
+    #[test]
+    fn test_gradual_tempo() {
+        let mut master = Track::new();
+        master.insert(tempo(0, NoteDuration::Quarter, 120));
+        master.insert(GradualTempo::new(
+            String::from("rit"),
+            16,
+            16,
+            GradualTempoType::Ritardando,
+            Some(TempoTarget::Percent(50)),
+            TempoCurve::Linear,
+        ));
+        master.insert(GradualTempo::new(
+            String::from("a tempo"),
+            32,
+            0,
+            GradualTempoType::ATempo,
+            None,
+            TempoCurve::Linear,
+        ));
+        let map = TempoMap::new(&master, 48, 4);
+
+        // a linear rit. from 120 to 60 over 4 quarters takes 4 ln(2) seconds
+        let rit_end = 2.0 + 4.0 * 2f64.ln();
+        assert_eq!(map.tick_to_seconds(16), 2.0);
+        assert!(close(map.tick_to_seconds(32), rit_end));
+        assert!(close(map.duration(), rit_end + 2.0));
+        assert_eq!(map.bpm_at_tick(24.0), 90.0);
+        assert_eq!(map.bpm_at_tick(40.0), 120.0);
+        assert!(close(map.seconds_to_tick(map.tick_to_seconds(24)), 24.0));
+        assert!(close(map.seconds_to_tick(rit_end + 1.0), 40.0));
+
+        let changes = map.changes(4);
+        assert_eq!(changes.len(), 6);
+        assert_eq!(changes[0], (0, 120.0));
+        assert_eq!(changes[5], (32, 120.0));
+        assert!(changes[1..5]
+            .windows(2)
+            .all(|steps| steps[0].1 > steps[1].1));
+        // the steps take as long as the rit.
+        let stepped: f64 = changes[1..5].iter().map(|(_, bpm)| 60.0 / bpm).sum();
+        assert!(close(stepped, rit_end - 2.0));
+    }
+
+    #[test]
+    fn test_engine_tempo_map() {
+        let mut engine = Engine::create(Box::new(SystemClock), Box::new(|_: Update| ()));
+        let flow_key = engine.score().flows.order[0].clone();
+        engine.set_flow_length(&flow_key, 64).unwrap();
+        assert_eq!(engine.flow_duration_seconds(&flow_key), Ok(2.0));
+
+        engine
+            .create_absolute_tempo(
+                &flow_key,
+                32,
+                "",
+                NoteDuration::Quarter,
+                0,
+                60,
+                false,
+                false,
+                true,
+            )
+            .unwrap();
+        assert_eq!(engine.flow_duration_seconds(&flow_key), Ok(3.0));
+        assert_eq!(engine.tick_to_seconds(&flow_key, 48), Ok(2.0));
+        assert_eq!(engine.seconds_to_tick(&flow_key, 2.0), Ok(48.0));
+
+        engine
+            .create_gradual_tempo(
+                &flow_key,
+                48,
+                8,
+                GradualTempoType::Accelerando,
+                TempoCurve::EaseOut,
+                Some(TempoTarget::Bpm(120)),
+            )
+            .unwrap();
+        assert!(engine.flow_duration_seconds(&flow_key).unwrap() < 3.0);
+        assert!(engine
+            .create_gradual_tempo(
+                &flow_key,
+                0,
+                8,
+                GradualTempoType::Ritardando,
+                TempoCurve::Linear,
+                None,
+            )
+            .is_err());
+
+        engine.undo();
+        engine.undo();
+        assert_eq!(engine.flow_duration_seconds(&flow_key), Ok(2.0));
+        assert!(engine.tick_to_seconds("missing", 0).is_err());
+    }
+}
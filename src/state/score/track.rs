@@ -1,27 +1,61 @@
 use crate::state::entries::absolute_tempo::AbsoluteTempo;
+use crate::state::entries::key_signature::KeySignature;
 use crate::state::entries::time_signature::TimeSignature;
+use crate::state::entries::tone::Tone;
+use crate::state::entries::tuplet::Tuplet;
 use crate::state::entries::Entry;
 use crate::utils::shortid;
-use std::collections::HashMap;
+use crate::utils::spelling::{spell, Key};
+use std::collections::{BTreeMap, HashMap};
+use std::mem;
+use std::ops::Bound::{Excluded, Unbounded};
+use std::ops::RangeBounds;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Entries {
-    pub by_tick: HashMap<u32, Vec<String>>,
+    pub by_tick: BTreeMap<u32, Vec<String>>, // ordered, and only the ticks with entries
     pub by_key: HashMap<String, Entry>, // we can iterate the hashmap directly, so no order/by_key needed
 }
 
-#[derive(Serialize, Deserialize)]
+fn time_signature(entry: &Entry) -> Option<&TimeSignature> {
+    match entry {
+        Entry::TimeSignature(time_signature) => Some(time_signature),
+        _ => None,
+    }
+}
+
+fn tone(entry: &Entry) -> Option<&Tone> {
+    match entry {
+        Entry::Tone(tone) => Some(tone),
+        _ => None,
+    }
+}
+
+fn key_signature(entry: &Entry) -> Option<&KeySignature> {
+    match entry {
+        Entry::KeySignature(key_signature) => Some(key_signature),
+        _ => None,
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Track {
     pub key: String,
     pub entries: Entries,
 }
 
+impl Default for Track {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Track {
     pub fn new() -> Track {
         Track {
             key: shortid(),
             entries: Entries {
-                by_tick: HashMap::new(),
+                by_tick: BTreeMap::new(),
                 by_key: HashMap::new(),
             },
         }
@@ -38,6 +72,16 @@ impl Track {
         self.entries.by_key.insert(entry.key(), entry);
     }
 
+    /// Take a key out of the tick index, dropping the tick once it has no entries
+    fn unindex(&mut self, tick: u32, key: &str) {
+        if let Some(keys) = self.entries.by_tick.get_mut(&tick) {
+            keys.retain(|item| item != key);
+            if keys.is_empty() {
+                self.entries.by_tick.remove(&tick);
+            }
+        }
+    }
+
     /// Move an entry to a new tick
     pub fn r#move(&mut self, key: &str, new_tick: u32) {
         let entry = match self.entries.by_key.get_mut(key) {
@@ -51,12 +95,7 @@ impl Track {
         if old_tick != new_tick {
             entry.set_tick(new_tick);
             // move the entry key to the new tick
-            match self.entries.by_tick.get_mut(&old_tick) {
-                Some(keys) => {
-                    keys.retain(|item| item != key);
-                }
-                None => (),
-            };
+            self.unindex(old_tick, key);
             let tick = self.entries.by_tick.entry(new_tick).or_insert(Vec::new());
             tick.push(String::from(key));
         }
@@ -64,83 +103,541 @@ impl Track {
 
     /// remove an entry and return the removed entry
     pub fn remove(&mut self, key: &str) -> Option<Entry> {
-        let entry = match self.entries.by_key.get(key) {
-            Some(entry) => entry,
+        let tick = match self.entries.by_key.get(key) {
+            Some(entry) => entry.tick(),
             None => return None,
         };
 
-        match self.entries.by_tick.get_mut(&entry.tick()) {
-            Some(keys) => {
-                keys.retain(|item| item != key);
-            }
-            None => (),
-        };
+        self.unindex(tick, key);
         self.entries.by_key.remove(key)
     }
 
+    /// Returns the entries within a range of ticks, in tick order
+    pub fn entries_in_range<R: RangeBounds<u32>>(
+        &self,
+        range: R,
+    ) -> impl DoubleEndedIterator<Item = &Entry> {
+        self.entries
+            .by_tick
+            .range(range)
+            .flat_map(|(_, keys)| keys.iter())
+            .filter_map(move |key| self.entries.by_key.get(key))
+    }
+
+    /// Returns the first entry of a kind *after* a given tick, `kind` picking out the
+    /// entries of that kind, eg. `|entry| match entry { Entry::Clef(clef) => Some(clef), _ => None }`
+    pub fn next_of_kind<'a, T>(
+        &'a self,
+        tick: u32,
+        kind: impl Fn(&'a Entry) -> Option<&'a T>,
+    ) -> Option<&'a T> {
+        self.entries_in_range((Excluded(tick), Unbounded))
+            .find_map(kind)
+    }
+
+    /// Returns the last entry of a kind *on* or *before* a given tick, see `next_of_kind()`
+    pub fn prev_of_kind<'a, T>(
+        &'a self,
+        tick: u32,
+        kind: impl Fn(&'a Entry) -> Option<&'a T>,
+    ) -> Option<&'a T> {
+        self.entries_in_range(..=tick).rev().find_map(kind)
+    }
+
     /// Returns the time signature entry at a given tick if it exists
     pub fn get_time_signature_at_tick(&self, tick: u32) -> Option<&TimeSignature> {
-        let entry_keys = match self.entries.by_tick.get(&tick) {
-            Some(entries) => entries,
-            None => return None,
-        };
+        self.entries_in_range(tick..=tick).find_map(time_signature)
+    }
 
-        for key in entry_keys.iter() {
-            match self.entries.by_key.get(key) {
-                Some(entry) => match entry {
-                    Entry::TimeSignature(time_signature) => return Some(time_signature),
-                    _ => (),
-                },
-                None => (),
+    /// Returns the next time signature entry *after* a given tick if it exists
+    pub fn get_time_signature_after_tick(&self, tick: u32, length: u32) -> Option<&TimeSignature> {
+        self.next_of_kind(tick, time_signature)
+            .filter(|time_signature| time_signature.tick < length)
+    }
+
+    /// Returns the previous time signature entry *on* or *before* a given tick if it exists
+    pub fn get_time_signature_on_or_before_tick(&self, tick: u32) -> Option<&TimeSignature> {
+        self.prev_of_kind(tick, time_signature)
+    }
+
+    /// Returns the time signature entry at a given tick if it exists
+    pub fn get_absolute_tempo_at_tick(&self, tick: u32) -> Option<&AbsoluteTempo> {
+        self.entries_in_range(tick..=tick)
+            .find_map(|entry| match entry {
+                Entry::AbsoluteTempo(tempo) => Some(tempo),
+                _ => None,
+            })
+    }
+
+    /// Returns the key signature entry at a given tick if it exists
+    pub fn get_key_signature_at_tick(&self, tick: u32) -> Option<&KeySignature> {
+        self.entries_in_range(tick..=tick).find_map(key_signature)
+    }
+
+    /// Returns the previous key signature entry *on* or *before* a given tick if it exists
+    pub fn get_key_signature_on_or_before_tick(&self, tick: u32) -> Option<&KeySignature> {
+        self.prev_of_kind(tick, key_signature)
+    }
+
+    /// Returns the next key signature entry *after* a given tick if it exists
+    pub fn get_key_signature_after_tick(&self, tick: u32, length: u32) -> Option<&KeySignature> {
+        self.next_of_kind(tick, key_signature)
+            .filter(|key_signature| key_signature.tick < length)
+    }
+
+    /// Move every entry onto a new tick grid, durations and pickups are scaled along with them
+    pub fn rescale(&mut self, scale: &dyn Fn(u32) -> u32) {
+        let entries: Vec<Entry> = self
+            .entries
+            .by_key
+            .drain()
+            .map(|(_, entry)| entry)
+            .collect();
+        self.entries.by_tick.clear();
+        for mut entry in entries {
+            entry.set_tick(scale(entry.tick()));
+            match &mut entry {
+                Entry::Tone(tone) => tone.duration.int = scale(tone.duration.int),
+                Entry::GradualTempo(tempo) => tempo.duration = scale(tempo.duration),
+                Entry::TimeSignature(time_signature) => {
+                    time_signature.pickup = scale(time_signature.pickup)
+                }
+                _ => (),
             }
+            self.insert(entry);
         }
+    }
 
-        None
+    /// Rebuild the tick index after the entries' ticks have been changed in place
+    fn reindex(&mut self) {
+        self.entries.by_tick.clear();
+        for (key, entry) in self.entries.by_key.iter() {
+            self.entries
+                .by_tick
+                .entry(entry.tick())
+                .or_default()
+                .push(key.clone());
+        }
     }
 
-    /// Returns the next time signature entry *after* a given tick if it exists
-    pub fn get_time_signature_after_tick(&self, tick: u32, length: u32) -> Option<&TimeSignature> {
-        for i in tick + 1..length {
-            match self.get_time_signature_at_tick(i) {
-                Some(time_signature) => return Some(time_signature),
-                None => (),
-            };
+    /// Make room for `count` ticks at `at`, moving the entries on or after it later.
+    /// Tones, gradual tempos and tuplets sounding across `at` are lengthened to carry
+    /// on through the new ticks. Tuplets that can't be are removed, leaving their tones.
+    pub fn insert_ticks(&mut self, at: u32, count: u32, subdivisions: u8) {
+        let mut removed = Vec::new();
+        for (key, entry) in self.entries.by_key.iter_mut() {
+            let tick = entry.tick();
+            if tick >= at {
+                entry.set_tick(tick + count);
+                continue;
+            }
+            match entry {
+                Entry::Tone(tone) if tick + tone.duration.int > at => {
+                    tone.duration.int += count;
+                }
+                Entry::GradualTempo(tempo) if tick + tempo.duration > at => {
+                    tempo.duration += count;
+                }
+                Entry::Tuplet(tuplet) if tick + tuplet.length(subdivisions) > at => {
+                    let lengthened = tuplet.lengthen(count, subdivisions);
+                    if !lengthened {
+                        removed.push(key.clone());
+                    }
+                }
+                _ => (),
+            }
+        }
+        for key in removed {
+            self.entries.by_key.remove(&key);
+        }
+        self.reindex();
+    }
+
+    /// Take out the ticks from `from` to `to`, moving the entries after them earlier.
+    ///
+    /// Tones keep only the part of them outside the removed ticks, or if `shift`, tones
+    /// sounding past `from` are moved earlier to end at it, cutting short the tones
+    /// they now overlap. Tuplets that no longer fit
+    /// are removed, leaving their tones. Of the other entries in the removed ticks the
+    /// last of each kind is moved to `from`, so the clef, key, time signature and tempo
+    /// carry on, unless there is already one of that kind at `to`.
+    pub fn remove_ticks(&mut self, from: u32, to: u32, shift: bool, subdivisions: u8) {
+        let count = to - from;
+
+        let mut removed_kinds: Vec<_> = self
+            .entries
+            .by_key
+            .values()
+            .filter(|entry| !matches!(entry, Entry::Tone(_) | Entry::Tuplet(_)))
+            .filter(|entry| entry.tick() >= from && entry.tick() <= to)
+            .map(|entry| (entry.tick(), mem::discriminant(entry), entry.key()))
+            .collect();
+        removed_kinds.sort_by_key(|(tick, _, _)| u32::MAX - tick);
+        let mut kinds = Vec::new();
+        let mut carried = Vec::new();
+        for (tick, kind, key) in removed_kinds {
+            if !kinds.contains(&kind) {
+                kinds.push(kind);
+                if tick < to {
+                    carried.push(key);
+                }
+            }
         }
 
-        None
+        let mut removed = Vec::new();
+        let mut shifted = Vec::new();
+        for (key, entry) in self.entries.by_key.iter_mut() {
+            let tick = entry.tick();
+            if tick >= to {
+                entry.set_tick(tick - count);
+                continue;
+            }
+            match entry {
+                Entry::Tone(tone) => {
+                    let end = tick + tone.duration.int;
+                    if end <= from {
+                        continue;
+                    }
+                    if shift {
+                        tone.duration.int = tone.duration.int.min(from);
+                        tone.tick = from - tone.duration.int;
+                        shifted.push(key.clone());
+                    } else {
+                        tone.tick = tick.min(from);
+                        tone.duration.int = from.saturating_sub(tick) + end.saturating_sub(to);
+                    }
+                    if tone.duration.int == 0 {
+                        removed.push(key.clone());
+                    }
+                }
+                Entry::Tuplet(tuplet) => {
+                    if tick + tuplet.length(subdivisions) > from {
+                        removed.push(key.clone());
+                    }
+                }
+                _ => {
+                    if tick < from {
+                        continue;
+                    }
+                    if carried.contains(key) {
+                        entry.set_tick(from);
+                    } else {
+                        removed.push(key.clone());
+                    }
+                }
+            }
+        }
+        for key in removed {
+            self.entries.by_key.remove(&key);
+        }
+        self.trim_overlaps(&shifted);
+        self.reindex();
     }
 
-    /// Returns the previous time signature entry *on* or *before* a given tick if it exists
-    pub fn get_time_signature_on_or_before_tick(&self, tick: u32) -> Option<&TimeSignature> {
-        // tick + 1 as the top value is not inclusive and we want to include the current tick.
-        // ie 0..5 == 0,1,2,3,4;
-        for i in (0..tick + 1).rev() {
-            match self.get_time_signature_at_tick(i) {
-                Some(time_signature) => return Some(time_signature),
-                None => (),
-            };
+    /// Cut short the tones sounding past the start of a later tone, where either of
+    /// them is one of the tones in `keys`, so that moved tones don't overlap the rest
+    fn trim_overlaps(&mut self, keys: &[String]) {
+        if keys.is_empty() {
+            return;
+        }
+        let starts: Vec<(u32, bool)> = self
+            .entries
+            .by_key
+            .iter()
+            .filter_map(|(key, entry)| match entry {
+                Entry::Tone(tone) => Some((tone.tick, keys.contains(key))),
+                _ => None,
+            })
+            .collect();
+        for (key, entry) in self.entries.by_key.iter_mut() {
+            if let Entry::Tone(tone) = entry {
+                let moved = keys.contains(key);
+                let end = tone.tick + tone.duration.int;
+                let cut = starts
+                    .iter()
+                    .filter(|(tick, other)| (moved || *other) && *tick > tone.tick && *tick < end)
+                    .map(|(tick, _)| *tick)
+                    .min();
+                if let Some(cut) = cut {
+                    tone.duration.int = cut - tone.tick;
+                }
+            }
         }
+    }
 
-        None
+    /// Returns all the tuplets in the track, in order
+    pub fn get_tuplets(&self) -> Vec<&Tuplet> {
+        let mut tuplets: Vec<&Tuplet> = self
+            .entries
+            .by_key
+            .values()
+            .filter_map(|entry| match entry {
+                Entry::Tuplet(tuplet) => Some(tuplet),
+                _ => None,
+            })
+            .collect();
+        tuplets.sort_by_key(|tuplet| tuplet.tick);
+        tuplets
     }
 
-    /// Returns the time signature entry at a given tick if it exists
-    pub fn get_absolute_tempo_at_tick(&self, tick: u32) -> Option<&AbsoluteTempo> {
-        let entry_keys = match self.entries.by_tick.get(&tick) {
-            Some(entries) => entries,
-            None => return None,
-        };
+    /// Re-spell the tones in the track from the key signatures in the master track
+    /// and their melodic context. Tones with a user defined spelling keep it.
+    ///
+    /// Returns the keys of the tones whose spelling has changed.
+    pub fn respell(&mut self, master: &Track) -> Vec<String> {
+        self.respell_ticks(master, 0, u32::MAX)
+    }
+
+    /// Re-spell the tones at `tick` and the tones either side of it, which are all
+    /// the tones whose melodic context changes when a tone at `tick` is edited.
+    ///
+    /// Returns the keys of the tones whose spelling has changed.
+    pub fn respell_around(&mut self, master: &Track, tick: u32) -> Vec<String> {
+        let from = self
+            .entries_in_range(..tick)
+            .rev()
+            .find_map(tone)
+            .map_or(tick, |tone| tone.tick);
+        let to = self.next_of_kind(tick, tone).map_or(tick, |tone| tone.tick);
+        self.respell_ticks(master, from, to)
+    }
+
+    /// Re-spell the tones from `from` to `to`, see `respell()`
+    fn respell_ticks(&mut self, master: &Track, from: u32, to: u32) -> Vec<String> {
+        // the tones either side of the range are needed for the melodic context
+        let start = self
+            .entries_in_range(..from)
+            .rev()
+            .find_map(tone)
+            .map_or(from, |tone| tone.tick);
+        let end = self.next_of_kind(to, tone).map_or(to, |tone| tone.tick);
+        let mut tones: Vec<(u32, u8, String)> = self
+            .entries_in_range(start..=end)
+            .filter_map(tone)
+            .map(|tone| (tone.tick, tone.pitch.int, tone.key.clone()))
+            .collect();
+        tones.sort();
 
-        for key in entry_keys.iter() {
-            match self.entries.by_key.get(key) {
-                Some(entry) => match entry {
-                    Entry::AbsoluteTempo(tempo) => return Some(tempo),
-                    _ => (),
-                },
-                None => (),
+        let mut changed = Vec::new();
+        for (i, (tick, int, entry_key)) in tones.iter().enumerate() {
+            if *tick < from || *tick > to {
+                continue;
+            }
+            let prev = tones[..i].iter().rev().find(|t| t.0 < *tick).map(|t| t.1);
+            let next = tones[i + 1..].iter().find(|t| t.0 > *tick).map(|t| t.1);
+            let key = match master.get_key_signature_on_or_before_tick(*tick) {
+                Some(key_signature) => key_signature.to_key(),
+                None => Key::new(0, false),
+            };
+
+            if let Some(Entry::Tone(tone)) = self.entries.by_key.get_mut(entry_key) {
+                let accidental = match tone.spelling {
+                    Some(spelling) => spelling,
+                    None => spell(*int, &key, prev, next),
+                };
+                if tone.pitch.accidental != accidental {
+                    tone.pitch.accidental = accidental;
+                    changed.push(entry_key.clone());
+                }
             }
         }
 
-        None
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::entries::clef::{Clef, ClefDrawType};
+    use crate::state::entries::gradual_tempo::{
+        GradualTempo, GradualTempoType, TempoCurve, TempoTarget,
+    };
+    use crate::state::entries::time_signature::TimeSignatureDrawType;
+    use crate::state::entries::tone::Articulation;
+    use crate::utils::duration::{Duration, NoteDuration};
+    use crate::utils::pitch::{Accidental, Pitch};
+    use crate::utils::velocity::Velocity;
+
+    fn time_signature(key: &str, tick: u32, beats: u8) -> Entry {
+        TimeSignature::new(
+            String::from(key),
+            tick,
+            beats,
+            NoteDuration::Quarter,
+            TimeSignatureDrawType::Normal,
+            None,
+        )
+    }
+
+    fn track() -> Track {
+        let mut track = Track::new();
+        track.insert(time_signature("a", 0, 4));
+        track.insert(Clef::new(String::from("clef"), 32, 60, 0, ClefDrawType::C));
+        track.insert(time_signature("b", 64, 3));
+        track.insert(time_signature("c", 1_000_000, 2));
+        track
+    }
+
+    #[test]
+    fn test_entries_in_range() {
+        let track = track();
+        let keys: Vec<String> = track
+            .entries_in_range(0..64)
+            .map(|entry| entry.key())
+            .collect();
+        assert_eq!(keys, vec!["a", "clef"]);
+        let keys: Vec<String> = track
+            .entries_in_range(32..)
+            .rev()
+            .map(|entry| entry.key())
+            .collect();
+        assert_eq!(keys, vec!["c", "b", "clef"]);
+    }
+
+    #[test]
+    fn test_of_kind() {
+        let track = track();
+        assert_eq!(
+            track.get_time_signature_on_or_before_tick(63).unwrap().key,
+            "a"
+        );
+        assert_eq!(
+            track.get_time_signature_on_or_before_tick(64).unwrap().key,
+            "b"
+        );
+        assert_eq!(
+            track
+                .get_time_signature_after_tick(0, 2_000_000)
+                .unwrap()
+                .key,
+            "b"
+        );
+        assert_eq!(
+            track
+                .get_time_signature_after_tick(64, 2_000_000)
+                .unwrap()
+                .key,
+            "c"
+        );
+        // only within the flow
+        assert!(track.get_time_signature_after_tick(64, 1_000_000).is_none());
+        let clef = track.next_of_kind(0, |entry| match entry {
+            Entry::Clef(clef) => Some(clef),
+            _ => None,
+        });
+        assert_eq!(clef.unwrap().tick, 32);
+    }
+
+    fn accidental(track: &Track, key: &str) -> Accidental {
+        match track.entries.by_key.get(key) {
+            Some(Entry::Tone(tone)) => tone.pitch.accidental,
+            _ => panic!("expected a tone"),
+        }
+    }
+
+    #[test]
+    fn test_respell_around() {
+        let master = Track::new();
+        let mut track = Track::new();
+        for (key, tick, pitch) in &[("a", 0, 60), ("b", 4, 61), ("c", 8, 62), ("d", 12, 61)] {
+            track.insert(Tone::new(
+                String::from(*key),
+                *tick,
+                Duration::new(4),
+                Pitch::new(*pitch, Accidental::Natural),
+                Velocity::new(100),
+                Articulation::None,
+                None,
+            ));
+        }
+        track.insert(Tone::new(
+            String::from("far"),
+            64,
+            Duration::new(4),
+            Pitch::new(61, Accidental::DoubleSharp),
+            Velocity::new(100),
+            Articulation::None,
+            Some(Accidental::DoubleSharp),
+        ));
+        // C C# D Db
+        track.respell(&master);
+        assert_eq!(accidental(&track, "b"), Accidental::Sharp);
+        assert_eq!(accidental(&track, "d"), Accidental::Flat);
+
+        // C Db C C#, only the tones next to the edit are respelled
+        if let Some(Entry::Tone(tone)) = track.entries.by_key.get_mut("c") {
+            tone.pitch = Pitch::new(60, Accidental::Natural);
+        }
+        if let Some(Entry::Tone(tone)) = track.entries.by_key.get_mut("far") {
+            tone.spelling = None;
+        }
+        let mut changed = track.respell_around(&master, 8);
+        changed.sort();
+        assert_eq!(changed, vec!["b", "d"]);
+        assert_eq!(accidental(&track, "b"), Accidental::Flat);
+        assert_eq!(accidental(&track, "d"), Accidental::Sharp);
+        assert_eq!(accidental(&track, "far"), Accidental::DoubleSharp);
+    }
+
+    #[test]
+    fn test_insert_ticks() {
+        let mut track = Track::new();
+        track.insert(Tone::new(
+            String::from("tone"),
+            0,
+            Duration::new(72),
+            Pitch::new(60, Accidental::Natural),
+            Velocity::new(100),
+            Articulation::None,
+            None,
+        ));
+        track.insert(GradualTempo::new(
+            String::from("rit"),
+            12,
+            96,
+            GradualTempoType::Ritardando,
+            Some(TempoTarget::Percent(50)),
+            TempoCurve::Linear,
+        ));
+        track.insert(Tuplet::new(
+            String::from("tuplet"),
+            0,
+            3,
+            2,
+            NoteDuration::Eighth,
+        ));
+        track.insert(Clef::new(String::from("clef"), 24, 60, 0, ClefDrawType::C));
+
+        // a quarter at 48 subdivisions
+        track.insert_ticks(24, 48, 48);
+        let span = |key: &str| match track.entries.by_key.get(key) {
+            Some(Entry::Tone(tone)) => (tone.tick, tone.duration.int),
+            Some(Entry::GradualTempo(tempo)) => (tempo.tick, tempo.duration),
+            Some(Entry::Tuplet(tuplet)) => (tuplet.tick, tuplet.length(48)),
+            Some(entry) => (entry.tick(), 0),
+            None => panic!("{} was removed", key),
+        };
+        assert_eq!(span("tone"), (0, 120));
+        assert_eq!(span("rit"), (12, 144));
+        assert_eq!(span("tuplet"), (0, 96));
+        assert_eq!(span("clef"), (72, 0));
+        match track.entries.by_key.get("tuplet") {
+            Some(Entry::Tuplet(tuplet)) => assert_eq!((tuplet.actual, tuplet.normal), (6, 4)),
+            _ => panic!("expected a tuplet"),
+        }
+
+        // an eighth can't be added to a 6:4 eighth note tuplet and keep it 3:2
+        track.insert_ticks(30, 24, 48);
+        assert!(track.entries.by_key.get("tuplet").is_none());
+    }
+
+    #[test]
+    fn test_index_stays_sparse() {
+        let mut track = track();
+        track.r#move("clef", 48);
+        track.remove("b");
+        let ticks: Vec<u32> = track.entries.by_tick.keys().cloned().collect();
+        assert_eq!(ticks, vec![0, 48, 1_000_000]);
     }
 }
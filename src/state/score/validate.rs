@@ -0,0 +1,196 @@
+use crate::state::entries::Entry;
+use crate::state::score::flow::Flow;
+use crate::state::score::track::Track;
+use crate::state::score::Score;
+use crate::state::Engine;
+use std::collections::HashSet;
+
+/// Check a track's tick index agrees with its entries and that they fit in the flow
+fn validate_track(problems: &mut Vec<String>, flow: &Flow, track: &Track, name: &str) {
+    let mut indexed = HashSet::new();
+    for (tick, keys) in track.entries.by_tick.iter() {
+        for key in keys {
+            indexed.insert(key);
+            match track.entries.by_key.get(key) {
+                Some(entry) if entry.tick() != *tick => problems.push(format!(
+                    "{} entry '{}' is indexed at tick {} but is at tick {}",
+                    name,
+                    key,
+                    tick,
+                    entry.tick()
+                )),
+                Some(_) => (),
+                None => problems.push(format!(
+                    "{} indexes missing entry '{}' at tick {}",
+                    name, key, tick
+                )),
+            }
+        }
+    }
+
+    for (key, entry) in track.entries.by_key.iter() {
+        if !indexed.contains(key) {
+            problems.push(format!(
+                "{} entry '{}' is missing from the index",
+                name, key
+            ));
+        }
+        if entry.tick() >= flow.length.max(1) {
+            problems.push(format!(
+                "{} entry '{}' at tick {} is after the end of the flow",
+                name,
+                key,
+                entry.tick()
+            ));
+        }
+        match entry {
+            Entry::Tone(tone) => {
+                if tone.duration.int == 0 {
+                    problems.push(format!("{} tone '{}' has no duration", name, key));
+                }
+                if tone.pitch.int > 127 {
+                    problems.push(format!(
+                        "{} tone '{}' has pitch {} out of range",
+                        name, key, tone.pitch.int
+                    ));
+                }
+            }
+            Entry::TimeSignature(time_signature)
+                if time_signature.pickup > 0
+                    && time_signature.pickup >= time_signature.ticks_per_bar(flow.subdivisions) =>
+            {
+                problems.push(format!(
+                    "{} time signature '{}' has a pickup of a bar or more",
+                    name, key
+                ));
+            }
+            _ => (),
+        }
+    }
+}
+
+fn validate_flow(problems: &mut Vec<String>, score: &Score, flow: &Flow) {
+    let name = format!("flow '{}'", flow.key);
+    if flow.subdivisions == 0 {
+        problems.push(format!("{} has no subdivisions", name));
+    }
+    validate_track(problems, flow, &flow.master, &name);
+
+    for player_key in flow.players.iter() {
+        let player = match score.players.by_key.get(player_key) {
+            Some(player) => player,
+            None => {
+                problems.push(format!("{} has unknown player '{}'", name, player_key));
+                continue;
+            }
+        };
+        for instrument in player
+            .instruments
+            .iter()
+            .filter_map(|key| score.instruments.get(key))
+        {
+            for stave_key in instrument.staves.iter() {
+                let stave = match flow.staves.get(stave_key) {
+                    Some(stave) => stave,
+                    None => {
+                        problems.push(format!(
+                            "{} is missing stave '{}' of instrument '{}'",
+                            name, stave_key, instrument.key
+                        ));
+                        continue;
+                    }
+                };
+                let stave_name = format!("{} stave '{}'", name, stave_key);
+                validate_track(problems, flow, &stave.master, &stave_name);
+                for track_key in stave.tracks.iter() {
+                    match flow.tracks.get(track_key) {
+                        Some(track) => validate_track(
+                            problems,
+                            flow,
+                            track,
+                            &format!("{} track '{}'", name, track_key),
+                        ),
+                        None => problems
+                            .push(format!("{} is missing track '{}'", stave_name, track_key)),
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Check the score is consistent, eg. that everything referenced by key exists and the
+/// tick indexes agree with the entries. Returns a description of each problem found.
+pub fn validate(score: &Score) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    for flow_key in score.flows.order.iter() {
+        if !score.flows.by_key.contains_key(flow_key) {
+            problems.push(format!("flow '{}' is ordered but doesn't exist", flow_key));
+        }
+    }
+    for player_key in score.players.order.iter() {
+        if !score.players.by_key.contains_key(player_key) {
+            problems.push(format!(
+                "player '{}' is ordered but doesn't exist",
+                player_key
+            ));
+        }
+    }
+    for (player_key, player) in score.players.by_key.iter() {
+        if !score.players.order.contains(player_key) {
+            problems.push(format!("player '{}' is missing from the order", player_key));
+        }
+        for instrument_key in player.instruments.iter() {
+            if !score.instruments.contains_key(instrument_key) {
+                problems.push(format!(
+                    "player '{}' has unknown instrument '{}'",
+                    player_key, instrument_key
+                ));
+            }
+        }
+    }
+
+    for (flow_key, flow) in score.flows.by_key.iter() {
+        if !score.flows.order.contains(flow_key) {
+            problems.push(format!("flow '{}' is missing from the order", flow_key));
+        }
+        validate_flow(&mut problems, score, flow);
+    }
+
+    problems.sort();
+    problems
+}
+
+impl Engine {
+    /// Check the score is consistent, see `validate()`
+    pub fn validate(&self) -> Vec<String> {
+        validate(&self.state.score)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn score() -> Score {
+        serde_json::from_str(include_str!("../../../tests/fixtures/score_v2.json")).unwrap()
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut score = score();
+        assert_eq!(validate(&score), Vec::<String>::new());
+
+        let flow = score.flows.by_key.get_mut("flow0000000001").unwrap();
+        flow.length = 16;
+        score.players.order.push(String::from("missing"));
+        assert_eq!(
+            validate(&score),
+            vec![
+                "flow 'flow0000000001' track 'M3stg7RGucdvWd' entry 'tone0000000002' at tick 16 is after the end of the flow",
+                "player 'missing' is ordered but doesn't exist",
+            ]
+        );
+    }
+}
@@ -0,0 +1,171 @@
+use crate::error::{Error, Result};
+use crate::state::Engine;
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+/// Groups many edits so that they produce a single history step and a single
+/// listener callback.
+pub struct Transaction {
+    pub label: Option<String>,
+    pub depth: u8,
+    pub changed: bool,
+    /// The first error encountered during the transaction
+    pub error: Option<Error>,
+}
+
+impl Transaction {
+    pub fn new(label: Option<String>) -> Self {
+        Self {
+            label,
+            depth: 1,
+            changed: false,
+            error: None,
+        }
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl Engine {
+    /// Start batching edits. Transactions can be nested, only the outermost
+    /// `commit()` records the history step and notifies the listener.
+    pub fn begin_transaction(&mut self, label: Option<String>) {
+        match &mut self.transaction {
+            Some(transaction) => transaction.depth += 1,
+            None => self.transaction = Some(Transaction::new(label)),
+        }
+    }
+
+    /// Finish the current transaction
+    ///
+    /// If any of the edits failed the score is restored to its state before
+    /// the transaction began and the first error is returned.
+    pub fn commit(&mut self) -> Result<()> {
+        let mut transaction = match self.transaction.take() {
+            Some(transaction) => transaction,
+            None => return Err(Error::NoTransaction),
+        };
+
+        if transaction.depth > 1 {
+            transaction.depth -= 1;
+            let result = match &transaction.error {
+                Some(error) => Err(error.clone()),
+                None => Ok(()),
+            };
+            self.transaction = Some(transaction);
+            return result;
+        }
+
+        if let Some(error) = transaction.error {
+            self.state = self.history.restore();
+            self.patches.clear();
+            return Err(error);
+        }
+
+        if transaction.changed {
+            let label = transaction.label.unwrap_or_default();
+            self.history.push(&label, &self.state);
+            self.emit();
+        }
+
+        Ok(())
+    }
+
+    /// Abandon the current transaction (including any outer transactions),
+    /// restoring the score to its state before the transaction began.
+    pub fn rollback(&mut self) {
+        if self.transaction.take().is_some() {
+            self.state = self.history.restore();
+            self.patches.clear();
+        }
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        self.transaction.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::listener::{SystemClock, Update};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// An engine counting the updates sent to its listener
+    fn engine() -> (Engine, Rc<RefCell<usize>>) {
+        let updates = Rc::new(RefCell::new(0));
+        let count = updates.clone();
+        let listener = move |_: Update| *count.borrow_mut() += 1;
+        (
+            Engine::create(Box::new(SystemClock), Box::new(listener)),
+            updates,
+        )
+    }
+
+    #[test]
+    fn test_rollback_on_error() {
+        let (mut engine, updates) = engine();
+        engine.set_title(String::from("Sonata"));
+
+        engine.begin_transaction(None);
+        engine.set_composer(String::from("A. Composer"));
+        assert!(engine.rename_flow("missing", "Allegro").is_err());
+        engine.set_title(String::from("Symphony"));
+        assert_eq!(
+            engine.commit(),
+            Err(Error::UnknownFlow(String::from("missing")))
+        );
+
+        // back to the last recorded step
+        let score = serde_json::to_value(engine.score()).unwrap();
+        let present = serde_json::to_value(&engine.history.restore().score).unwrap();
+        assert_eq!(score, present);
+        assert_eq!(engine.score().meta.title, "Sonata");
+        assert_eq!(engine.score().meta.composer, "");
+        assert_eq!(engine.undo_label(), Some(String::from("Set title")));
+        assert_eq!(*updates.borrow(), 2);
+        assert!(!engine.in_transaction());
+    }
+
+    #[test]
+    fn test_commit() {
+        let (mut engine, updates) = engine();
+        engine.begin_transaction(Some(String::from("Set details")));
+        engine.set_title(String::from("Sonata"));
+        engine.set_composer(String::from("A. Composer"));
+        assert_eq!(*updates.borrow(), 1);
+        assert!(engine.commit().is_ok());
+
+        // one step and one update for the whole transaction
+        assert_eq!(*updates.borrow(), 2);
+        assert_eq!(engine.undo_label(), Some(String::from("Set details")));
+        engine.undo();
+        assert_eq!(engine.score().meta.title, "");
+        assert_eq!(engine.score().meta.composer, "");
+        assert!(!engine.can_undo());
+
+        assert_eq!(engine.commit(), Err(Error::NoTransaction));
+    }
+
+    #[test]
+    fn test_nested() {
+        let (mut engine, updates) = engine();
+        engine.begin_transaction(None);
+        engine.set_title(String::from("Sonata"));
+        engine.begin_transaction(Some(String::from("Inner")));
+        engine.set_composer(String::from("A. Composer"));
+        assert!(engine.commit().is_ok());
+
+        // the inner commit records nothing
+        assert!(engine.in_transaction());
+        assert!(!engine.can_undo());
+        assert_eq!(*updates.borrow(), 1);
+
+        assert!(engine.commit().is_ok());
+        assert_eq!(engine.undo_label(), Some(String::from("Set title")));
+        assert_eq!(*updates.borrow(), 2);
+        engine.undo();
+        assert!(!engine.can_undo());
+        assert_eq!(engine.score().meta.composer, "");
+    }
+}
@@ -1,7 +1,8 @@
+#[cfg(feature = "wasm")]
 use wasm_bindgen::prelude::*;
 
-#[wasm_bindgen]
-#[derive(Serialize_repr, Deserialize_repr)]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Debug, Serialize_repr, Deserialize_repr, Clone, PartialEq)]
 #[repr(u8)]
 pub enum NoteDuration {
     Whole,
@@ -12,6 +13,16 @@ pub enum NoteDuration {
     ThirtySecond,
 }
 
+/// All the note durations, longest first
+pub(crate) const NOTE_DURATIONS: [NoteDuration; 6] = [
+    NoteDuration::Whole,
+    NoteDuration::Half,
+    NoteDuration::Quarter,
+    NoteDuration::Eighth,
+    NoteDuration::Sixteenth,
+    NoteDuration::ThirtySecond,
+];
+
 impl NoteDuration {
     pub fn to_int(&self) -> u8 {
         match self {
@@ -24,9 +35,19 @@ impl NoteDuration {
         }
     }
 
-    pub fn to_ticks(&self, subdivisions: u8) -> u8 {
-        let beat_type = self.to_int();
-        (subdivisions as f32 / (beat_type as f32 / 4 as f32)) as u8
+    pub fn to_ticks(&self, subdivisions: u8) -> u32 {
+        u32::from(subdivisions) * 4 / u32::from(self.to_int())
+    }
+
+    /// The number of ticks of the note value, if it is a whole number of ticks
+    pub fn to_exact_ticks(&self, subdivisions: u8) -> Option<u32> {
+        let whole = u32::from(subdivisions) * 4;
+        let int = u32::from(self.to_int());
+        if whole > 0 && whole.is_multiple_of(int) {
+            Some(whole / int)
+        } else {
+            None
+        }
     }
 
     pub fn to_glyph(&self) -> &str {
@@ -41,12 +62,11 @@ impl NoteDuration {
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Duration {
-    // number of ticks in the duration
+    // number of ticks in the duration, how it is written is worked out from the
+    // time signature (see `state::score::notation`)
     pub int: u32,
-    // TODO: this should include a way of specifying how the duration should be written
-    // ie. if the user wants it written differently to the parsing algorithm
 }
 
 impl Duration {
@@ -54,3 +74,63 @@ impl Duration {
         Self { int }
     }
 }
+
+/// A single note value as it is written, eg. a dotted quarter
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct WrittenDuration {
+    pub base: NoteDuration,
+    pub dots: u8,
+}
+
+impl WrittenDuration {
+    /// Find the note value that is exactly `ticks` long using up to `max_dots` dots,
+    /// if there is one
+    pub fn from_ticks(ticks: u32, subdivisions: u8, max_dots: u8) -> Option<Self> {
+        for base in NOTE_DURATIONS.iter() {
+            for dots in 0..=max_dots {
+                let written = WrittenDuration {
+                    base: base.clone(),
+                    dots,
+                };
+                if written.is_ticks(ticks, subdivisions) {
+                    return Some(written);
+                }
+            }
+        }
+        None
+    }
+
+    /// The longest undotted note value that is no longer than `ticks`
+    pub fn longest_within(ticks: u32, subdivisions: u8) -> Option<Self> {
+        NOTE_DURATIONS
+            .iter()
+            .map(|base| WrittenDuration {
+                base: base.clone(),
+                dots: 0,
+            })
+            .find(|written| {
+                let length = written.to_ticks(subdivisions);
+                length > 0 && length <= ticks && written.is_ticks(length, subdivisions)
+            })
+    }
+
+    /// The number of ticks the written duration lasts, rounded down
+    pub fn to_ticks(&self, subdivisions: u8) -> u32 {
+        let (numerator, denominator) = self.fraction_of_whole();
+        u32::from(subdivisions) * 4 * numerator / denominator
+    }
+
+    /// Returns true if the written duration is exactly `ticks` long
+    fn is_ticks(&self, ticks: u32, subdivisions: u8) -> bool {
+        let (numerator, denominator) = self.fraction_of_whole();
+        ticks * denominator == u32::from(subdivisions) * 4 * numerator
+    }
+
+    /// The length as a fraction of a whole note, each dot adds half the previous value
+    fn fraction_of_whole(&self) -> (u32, u32) {
+        let dots = u32::from(self.dots);
+        let numerator = (1 << (dots + 1)) - 1;
+        let denominator = u32::from(self.base.to_int()) << dots;
+        (numerator, denominator)
+    }
+}
@@ -1,7 +1,7 @@
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct MM(pub f32);
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Spaces(pub f32);
 
 #[derive(Serialize, Deserialize)]
@@ -11,5 +11,5 @@ pub struct BoundingBox {
     pub padding: Padding<Spaces>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Padding<T>(pub T, pub T, pub T, pub T);
@@ -1,8 +1,8 @@
-extern crate web_sys;
-
 pub mod duration;
 pub mod measurements;
 pub mod pitch;
+pub mod spelling;
+#[cfg(feature = "wasm")]
 pub mod storage;
 pub mod text;
 pub mod velocity;
@@ -10,11 +10,8 @@ pub mod velocity;
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
 
-pub fn set_panic_hook() {
-    console_error_panic_hook::set_once();
-}
-
 // A macro to provide `println!(..)`-style syntax for `console.log` logging.
+#[cfg(feature = "wasm")]
 #[macro_export]
 macro_rules! log {
     ( $( $t:tt )* ) => {
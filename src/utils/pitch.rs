@@ -1,7 +1,9 @@
+use crate::utils::spelling::{is_valid, spell, Key};
+#[cfg(feature = "wasm")]
 use wasm_bindgen::prelude::*;
 
-#[wasm_bindgen]
-#[derive(Serialize_repr, Deserialize_repr, Copy, Clone)]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Debug, Serialize_repr, Deserialize_repr, Copy, Clone, PartialEq)]
 #[repr(u8)]
 pub enum Accidental {
     DoubleSharp,
@@ -12,14 +14,10 @@ pub enum Accidental {
 }
 
 impl Accidental {
-    // TODO: Make this better by working it out within the context of a key
-    /// When there is no user defined accidental, we work it out from the pitch
+    /// When there is no user defined accidental and no key, we work it out from the pitch alone.
+    /// See `utils::spelling` for spelling within a key.
     pub fn default(int: u8) -> Accidental {
-        let step = (int - 12) % 12;
-        match step {
-            0 | 2 | 4 | 5 | 7 | 9 | 10 => Accidental::Natural,
-            _ => Accidental::Sharp,
-        }
+        spell(int, &Key::new(0, false), None, None)
     }
 
     /// Convert an accidental to a token
@@ -48,6 +46,16 @@ impl Pitch {
         Self { int, accidental }
     }
 
+    /// The pitch as it is written, falling back to the default spelling if the
+    /// stored one isn't a real note name
+    pub fn written(&self) -> Pitch {
+        if is_valid(self.int, self.accidental) {
+            *self
+        } else {
+            Pitch::new(self.int, Accidental::default(self.int))
+        }
+    }
+
     /// gets the base note pitch (natural) for an accidental note
     /// ie 61 (C#) -> 60 (C), 61 (D flat) -> 62 (D)
     fn natural(&self) -> i8 {
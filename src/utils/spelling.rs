@@ -0,0 +1,260 @@
+use crate::utils::pitch::Accidental;
+
+/// Pitch classes of the natural letters C D E F G A B
+const NATURALS: [i8; 7] = [0, 2, 4, 5, 7, 9, 11];
+/// Letters in the order sharps are added to a key signature (F C G D A E B)
+const SHARPS: [usize; 7] = [3, 0, 4, 1, 5, 2, 6];
+/// Letters in the order flats are added to a key signature (B E A D G C F)
+const FLATS: [usize; 7] = [6, 2, 5, 1, 4, 0, 3];
+
+/// The diatonic spelling of a key, used to work out accidentals
+pub struct Key {
+    offset: i8,
+    minor: bool,
+    alterations: [i8; 7],
+    tonic_letter: usize,
+    tonic: i8,
+}
+
+impl Key {
+    /// Build a key from the number of sharps (+) or flats (-)
+    pub fn new(offset: i8, minor: bool) -> Self {
+        let mut alterations = [0; 7];
+        for i in 0..offset.unsigned_abs().min(7) as usize {
+            if offset > 0 {
+                alterations[SHARPS[i]] = 1;
+            } else {
+                alterations[FLATS[i]] = -1;
+            }
+        }
+
+        // each sharp moves the tonic up a fifth, a minor key's tonic is a sixth above the major
+        let major_letter = (i16::from(offset) * 4).rem_euclid(7) as usize;
+        let major_tonic = (i16::from(offset) * 7).rem_euclid(12) as i8;
+        let (tonic_letter, tonic) = if minor {
+            ((major_letter + 5) % 7, (major_tonic + 9) % 12)
+        } else {
+            (major_letter, major_tonic)
+        };
+
+        Self {
+            offset,
+            minor,
+            alterations,
+            tonic_letter,
+            tonic,
+        }
+    }
+
+    /// The pitch class of a letter in this key
+    fn pitch_class(&self, letter: usize) -> i8 {
+        (NATURALS[letter] + self.alterations[letter]).rem_euclid(12)
+    }
+
+    /// The letter that spells a pitch class diatonically in this key, if there is one
+    fn diatonic_letter(&self, pitch_class: i8) -> Option<usize> {
+        (0..7).find(|&letter| self.pitch_class(letter) == pitch_class)
+    }
+}
+
+/// The number of semitones a pitch class is from a natural letter, -6..6
+fn distance(pitch_class: i8, letter: usize) -> i8 {
+    let distance = (pitch_class - NATURALS[letter]).rem_euclid(12);
+    if distance > 6 {
+        distance - 12
+    } else {
+        distance
+    }
+}
+
+fn to_accidental(distance: i8) -> Option<Accidental> {
+    match distance {
+        2 => Some(Accidental::DoubleSharp),
+        1 => Some(Accidental::Sharp),
+        0 => Some(Accidental::Natural),
+        -1 => Some(Accidental::Flat),
+        -2 => Some(Accidental::DoubleFlat),
+        _ => None,
+    }
+}
+
+fn from_accidental(accidental: Accidental) -> i8 {
+    match accidental {
+        Accidental::DoubleSharp => 2,
+        Accidental::Sharp => 1,
+        Accidental::Natural => 0,
+        Accidental::Flat => -1,
+        Accidental::DoubleFlat => -2,
+    }
+}
+
+/// Returns true if the accidental spells the pitch as a real note name
+pub fn is_valid(int: u8, accidental: Accidental) -> bool {
+    let natural = ((int % 12) as i8 - from_accidental(accidental)).rem_euclid(12);
+    NATURALS.contains(&natural)
+}
+
+/// The next valid enharmonic spelling of a pitch after the current one
+pub fn next_enharmonic(int: u8, accidental: Accidental) -> Accidental {
+    let all = [
+        Accidental::DoubleSharp,
+        Accidental::Sharp,
+        Accidental::Natural,
+        Accidental::Flat,
+        Accidental::DoubleFlat,
+    ];
+    let valid: Vec<Accidental> = all.iter().cloned().filter(|&a| is_valid(int, a)).collect();
+    match valid.iter().position(|&a| a == accidental) {
+        Some(i) => valid[(i + 1) % valid.len()],
+        None => valid[0],
+    }
+}
+
+/// Work out how a pitch should be spelled within a key and its melodic context
+/// (the previous and next pitches in the track)
+pub fn spell(int: u8, key: &Key, prev: Option<u8>, next: Option<u8>) -> Accidental {
+    let pitch_class = (int % 12) as i8;
+
+    // diatonic notes are always spelled as in the key
+    if let Some(letter) = key.diatonic_letter(pitch_class) {
+        return to_accidental(key.alterations[letter]).unwrap_or(Accidental::Natural);
+    }
+
+    // minor keys raise the 7th (leading tone), and the 6th when rising towards it
+    if key.minor {
+        let raised = if pitch_class == (key.tonic + 11) % 12 {
+            Some((key.tonic_letter + 6) % 7)
+        } else if pitch_class == (key.tonic + 9) % 12 && next.is_some_and(|next| next > int) {
+            Some((key.tonic_letter + 5) % 7)
+        } else {
+            None
+        };
+        if let Some(accidental) =
+            raised.and_then(|letter| to_accidental(distance(pitch_class, letter)))
+        {
+            return accidental;
+        }
+    }
+
+    // a chromatic note is either the diatonic note below raised, or the note above lowered
+    let raised = key
+        .diatonic_letter((pitch_class + 11) % 12)
+        .map_or(2, |letter| distance(pitch_class, letter));
+    let lowered = key
+        .diatonic_letter((pitch_class + 1) % 12)
+        .map_or(-2, |letter| distance(pitch_class, letter));
+
+    let ascending = match (prev, next) {
+        (_, Some(next)) if Some(next) == int.checked_add(1) => Some(true),
+        (_, Some(next)) if next.checked_add(1) == Some(int) => Some(false),
+        (Some(prev), _) if prev.checked_add(1) == Some(int) => Some(true),
+        (Some(prev), _) if Some(prev) == int.checked_add(1) => Some(false),
+        _ => None,
+    };
+
+    let choice = match ascending {
+        // follow the line, unless that means a double accidental we can avoid
+        Some(true) if raised.abs() < 2 || lowered.abs() == 2 => raised,
+        Some(false) if lowered.abs() < 2 || raised.abs() == 2 => lowered,
+        _ => {
+            if raised.abs() != lowered.abs() {
+                if raised.abs() < lowered.abs() {
+                    raised
+                } else {
+                    lowered
+                }
+            } else if key.offset > 0 {
+                raised
+            } else if key.offset < 0 {
+                lowered
+            } else {
+                // without a key prefer C#, Eb, F#, G#, Bb
+                match pitch_class {
+                    3 | 10 => lowered,
+                    _ => raised,
+                }
+            }
+        }
+    };
+
+    to_accidental(choice).unwrap_or(Accidental::Natural)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diatonic() {
+        // Bb in F major
+        assert_eq!(
+            spell(70, &Key::new(-1, false), None, None),
+            Accidental::Flat
+        );
+        // F# in G major
+        assert_eq!(
+            spell(66, &Key::new(1, false), None, None),
+            Accidental::Sharp
+        );
+        // Cb in Gb major
+        assert_eq!(
+            spell(71, &Key::new(-6, false), None, None),
+            Accidental::Flat
+        );
+        // E# in C# major
+        assert_eq!(
+            spell(65, &Key::new(7, false), None, None),
+            Accidental::Sharp
+        );
+    }
+
+    #[test]
+    fn test_leading_tone() {
+        // G# in A minor
+        assert_eq!(spell(68, &Key::new(0, true), None, None), Accidental::Sharp);
+        // B natural in C minor
+        assert_eq!(
+            spell(71, &Key::new(-3, true), None, None),
+            Accidental::Natural
+        );
+    }
+
+    #[test]
+    fn test_chromatic_passing_notes() {
+        let key = Key::new(0, false);
+        // C C# D
+        assert_eq!(spell(61, &key, Some(60), Some(62)), Accidental::Sharp);
+        // D Db C
+        assert_eq!(spell(61, &key, Some(62), Some(60)), Accidental::Flat);
+    }
+
+    #[test]
+    fn test_avoid_double_accidentals() {
+        // G natural in B major rather than F double sharp
+        assert_eq!(
+            spell(67, &Key::new(5, false), None, None),
+            Accidental::Natural
+        );
+    }
+
+    #[test]
+    fn test_next_enharmonic() {
+        assert_eq!(next_enharmonic(61, Accidental::Sharp), Accidental::Flat);
+        assert_eq!(
+            next_enharmonic(61, Accidental::Flat),
+            Accidental::DoubleSharp
+        );
+        assert!(!is_valid(69, Accidental::Flat));
+    }
+
+    #[test]
+    fn test_top_of_range() {
+        let key = Key::new(0, false);
+        // D# / Eb at 255 has no semitone above it
+        assert_eq!(spell(255, &key, Some(254), None), Accidental::Sharp);
+        assert_eq!(spell(254, &key, None, Some(255)), Accidental::Natural);
+        assert_eq!(spell(255, &key, None, Some(254)), Accidental::Flat);
+        assert!(is_valid(255, Accidental::Sharp));
+        assert_eq!(next_enharmonic(255, Accidental::Sharp), Accidental::Flat);
+    }
+}
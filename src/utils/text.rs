@@ -1,6 +1,6 @@
 use crate::utils::measurements::{Padding, Spaces};
 
-#[derive(Serialize_repr, Deserialize_repr)]
+#[derive(Serialize_repr, Deserialize_repr, Clone)]
 #[repr(u8)]
 pub enum Justify {
     Start,
@@ -36,7 +36,7 @@ impl Align {
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Font {
     pub size: Spaces,
     pub font: String,
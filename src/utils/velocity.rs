@@ -1,6 +1,6 @@
 #[derive(Serialize, Deserialize, Copy, Clone)]
 pub struct Velocity {
-    int: u8,
+    pub int: u8,
 }
 
 impl Velocity {
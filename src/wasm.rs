@@ -0,0 +1,156 @@
+//! The JavaScript bindings.
+//!
+//! Edits with plain arguments are bound where they are defined, this wraps the rest of
+//! the engine: the browser clock and listener and anything passing JS values.
+
+use crate::error::{Error, Result};
+use crate::state::entries::gradual_tempo::{GradualTempoType, TempoCurve};
+use crate::state::listener::{Clock, Listener, Update};
+use crate::state::score::instrument::{defs, utils};
+use crate::state::score::player::PlayerType;
+use crate::state::Engine;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen(start)]
+pub fn run() {
+    console_error_panic_hook::set_once();
+}
+
+/// Errors are thrown as an `Error` with a `code` property so the UI can report
+/// what went wrong.
+impl From<Error> for JsValue {
+    fn from(error: Error) -> JsValue {
+        let js_error = js_sys::Error::new(&error.to_string());
+        let _ = js_sys::Reflect::set(
+            &js_error,
+            &JsValue::from_str("code"),
+            &JsValue::from_str(error.code()),
+        );
+        js_error.into()
+    }
+}
+
+/// Maps are written as plain objects, the UI reads the state as JSON.
+fn to_js<T: Serialize>(value: &T) -> JsValue {
+    value
+        .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+        .unwrap()
+}
+
+fn from_js<T: DeserializeOwned>(value: &JsValue) -> Result<T> {
+    serde_wasm_bindgen::from_value(value.clone()).map_err(|e| Error::SchemaMismatch(e.to_string()))
+}
+
+struct DateClock;
+
+impl Clock for DateClock {
+    fn now(&self) -> f64 {
+        js_sys::Date::now()
+    }
+}
+
+/// Calls a JS function with the state or patches
+struct FunctionListener(js_sys::Function);
+
+impl Listener for FunctionListener {
+    fn notify(&mut self, update: Update) {
+        let value = match update {
+            Update::State(state) => to_js(state),
+            Update::Patches(patch_set) => to_js(patch_set),
+        };
+        let _ = self.0.call1(&JsValue::NULL, &value);
+    }
+}
+
+#[wasm_bindgen]
+impl Engine {
+    #[wasm_bindgen(constructor)]
+    pub fn new(callback: js_sys::Function) -> Engine {
+        Engine::create(Box::new(DateClock), Box::new(FunctionListener(callback)))
+    }
+
+    pub fn export(&self) -> JsValue {
+        to_js(self.score())
+    }
+
+    /// Replace the score, upgrading it from older file versions if needed.
+    /// This also clears the undo history.
+    pub fn import(&mut self, state: JsValue) -> Result<()> {
+        self.load(from_js(&state)?)
+    }
+
+    #[wasm_bindgen(js_name = import_midi)]
+    pub fn import_midi_js(&mut self, bytes: &[u8]) -> Result<JsValue> {
+        Ok(to_js(&self.import_midi(bytes)?))
+    }
+
+    #[wasm_bindgen(js_name = import_musicxml)]
+    pub fn import_musicxml_js(&mut self, bytes: &[u8]) -> Result<JsValue> {
+        Ok(to_js(&self.import_musicxml(bytes)?))
+    }
+
+    #[wasm_bindgen(js_name = import_abc)]
+    pub fn import_abc_js(&mut self, text: &str) -> Result<JsValue> {
+        Ok(to_js(&self.import_abc(text)?))
+    }
+
+    #[wasm_bindgen(js_name = export_score_musicxml)]
+    pub fn export_score_musicxml_js(&self) -> JsValue {
+        to_js(&self.export_score_musicxml())
+    }
+
+    /// The target is `{ Percent: number }`, `{ Bpm: number }` or undefined
+    #[wasm_bindgen(js_name = create_gradual_tempo)]
+    pub fn create_gradual_tempo_js(
+        &mut self,
+        flow_key: &str,
+        tick: u32,
+        duration: u32,
+        tempo_type: GradualTempoType,
+        curve: TempoCurve,
+        target: &JsValue,
+    ) -> Result<String> {
+        let target = from_js(target)?;
+        self.create_gradual_tempo(flow_key, tick, duration, tempo_type, curve, target)
+    }
+
+    #[wasm_bindgen(js_name = get_notation)]
+    pub fn get_notation_js(&self, flow_key: &str, track_key: &str) -> Result<JsValue> {
+        Ok(to_js(&self.get_notation(flow_key, track_key)?))
+    }
+}
+
+/// Get patches for a given id
+#[wasm_bindgen]
+pub fn get_patches(id: &str, player_type: PlayerType) -> JsValue {
+    match defs::get_patches(id, player_type) {
+        Some(patches) => to_js(patches),
+        None => JsValue::UNDEFINED,
+    }
+}
+
+#[derive(Serialize)]
+struct FullPathReturn<'a> {
+    path: &'a Vec<&'a str>,
+    id: &'a str,
+}
+
+/// Get a full path to def from partial path
+#[wasm_bindgen]
+pub fn get_full_path_from_partial(selection: &JsValue) -> Result<JsValue> {
+    match utils::get_full_path_from_partial(&from_js::<Vec<String>>(selection)?) {
+        Some(def) => Ok(to_js(&FullPathReturn {
+            path: &def.path,
+            id: def.id,
+        })),
+        None => Ok(JsValue::UNDEFINED),
+    }
+}
+
+/// Get a tree of instruments from a (possibly incomplete) path
+#[wasm_bindgen]
+pub fn def_tree(selection: &JsValue) -> Result<JsValue> {
+    Ok(to_js(&utils::def_tree(&from_js::<Vec<String>>(selection)?)))
+}